@@ -0,0 +1,107 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// Writing cpio archives, for handing the kernel files we made up ourselves.
+//
+// Only the "newc" format, what `cpio -H newc` writes, as that is the only one the kernel will
+// unpack an initramfs from. Every header and every file is padded out to 4 bytes.
+
+// "newc", no checksums
+const MAGIC: &str = "070701";
+const TRAILER: &str = "TRAILER!!!";
+
+const S_IFDIR: u32 = 0o040_000;
+const S_IFREG: u32 = 0o100_000;
+
+pub const DIR_MODE: u32 = 0o755;
+pub const FILE_MODE: u32 = 0o644;
+
+pub struct CpioWriter {
+    data: Vec<u8>,
+    ino: u32,
+    mtime: u32,
+    // Directories already in the archive, so they only go in once
+    dirs: Vec<String>,
+}
+
+impl CpioWriter {
+    // Everything in the archive gets `mtime`, as seconds since the epoch
+    pub fn new(mtime: u32) -> Self {
+        Self {
+            data: Vec::new(),
+            ino: 1,
+            mtime,
+            dirs: Vec::new(),
+        }
+    }
+
+    // Add a directory, and any of the ones it is in that aren't already in the archive
+    pub fn dir(&mut self, path: &str) {
+        let path = path.trim_matches('/');
+        if path.is_empty() || self.dirs.iter().any(|dir| dir == path) {
+            return;
+        }
+
+        if let Some((parent, _)) = path.rsplit_once('/') {
+            self.dir(parent);
+        }
+
+        self.entry(path, S_IFDIR | DIR_MODE, 2, &[]);
+        self.dirs.push(path.to_string());
+    }
+
+    // Add a file, along with the directories it's in
+    pub fn file(&mut self, path: &str, mode: u32, data: &[u8]) {
+        let path = path.trim_start_matches('/');
+        if let Some((parent, _)) = path.rsplit_once('/') {
+            self.dir(parent);
+        }
+
+        self.entry(path, S_IFREG | (mode & 0o7777), 1, data);
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        // NOTE(aki): The trailer has to have an inode of 0, and a link count of 1
+        self.ino = 0;
+        self.entry(TRAILER, 0, 1, &[]);
+        self.data
+    }
+
+    fn entry(&mut self, path: &str, mode: u32, nlink: u32, data: &[u8]) {
+        let ino = self.ino;
+        if ino != 0 {
+            self.ino += 1;
+        }
+
+        let fields = [
+            ino,
+            mode,
+            0, // uid
+            0, // gid
+            nlink,
+            self.mtime,
+            data.len() as u32,
+            0, // devmajor
+            0, // devminor
+            0, // rdevmajor
+            0, // rdevminor
+            path.len() as u32 + 1,
+            0, // check
+        ];
+
+        self.data.extend_from_slice(MAGIC.as_bytes());
+        for field in fields {
+            self.data
+                .extend_from_slice(format!("{field:08X}").as_bytes());
+        }
+
+        self.data.extend_from_slice(path.as_bytes());
+        self.data.push(0);
+        self.pad();
+
+        self.data.extend_from_slice(data);
+        self.pad();
+    }
+
+    fn pad(&mut self) {
+        self.data.resize(self.data.len().next_multiple_of(4), 0);
+    }
+}
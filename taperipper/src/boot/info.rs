@@ -0,0 +1,229 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// Telling userspace how it was booted.
+//
+// What we booted from, what came off of it, and how the read went ends up in the generated
+// initramfs archive as `/etc/taperipper/boot-info`. It is a `KEY='value'` per line, so a
+// shell script can source it as is, and anything else only has to split on the first `=` and
+// strip the quotes.
+
+use core::fmt::{self, Write};
+
+use uefi::runtime::{self, Time};
+
+use crate::{
+    boot::initramfs::Initramfs,
+    tape::{
+        device::{TapeDevice, scsi::ScsiTape},
+        loader::Payloads,
+        recovery::FileErrors,
+        scsi::density::Density,
+        transport::Transport,
+    },
+};
+
+pub const PATH: &str = "/etc/taperipper/boot-info";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    Drive,
+    // A tape image on the ESP
+    Image,
+    // The kernel and initrd straight off of the ESP
+    Esp,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Drive => write!(f, "drive"),
+            Source::Image => write!(f, "image"),
+            Source::Esp => write!(f, "esp"),
+        }
+    }
+}
+
+pub struct BootInfo {
+    source: Source,
+    device: Option<String>,
+    // Vendor, product, and revision, from INQUIRY
+    inquiry: Option<[String; 3]>,
+    density: Option<Density>,
+    // How many reels it took
+    reels: u32,
+    errors: Option<FileErrors>,
+}
+
+impl BootInfo {
+    pub fn drive<T: Transport>(drive: &ScsiTape<T>) -> Self {
+        Self {
+            device: Some(drive.name().to_string()),
+            inquiry: drive.inquiry().map(|inquiry| {
+                [
+                    inquiry.vendor().to_string(),
+                    inquiry.product().to_string(),
+                    inquiry.revision().to_string(),
+                ]
+            }),
+            density: drive.format().and_then(|format| format.density),
+            ..Self::new(Source::Drive)
+        }
+    }
+
+    pub fn image(path: &str) -> Self {
+        Self {
+            device: Some(path.to_string()),
+            ..Self::new(Source::Image)
+        }
+    }
+
+    pub fn esp() -> Self {
+        Self::new(Source::Esp)
+    }
+
+    fn new(source: Source) -> Self {
+        Self {
+            source,
+            device: None,
+            inquiry: None,
+            density: None,
+            reels: 1,
+            errors: None,
+        }
+    }
+
+    // How the read went, once it's done
+    pub fn read(&mut self, reels: u32, errors: FileErrors) {
+        self.reels = reels;
+        self.errors = Some(errors);
+    }
+
+    // Put the boot-info file into the generated archive, stamped with the time we booted
+    pub fn add_to(&self, initramfs: &mut Initramfs, payloads: Option<&Payloads>) {
+        let time = runtime::get_time().ok();
+        if let Some(time) = &time {
+            initramfs.set_mtime(unix_time(time));
+        }
+
+        initramfs.add_file(PATH, self.render(payloads, time.as_ref()).into_bytes());
+    }
+
+    fn render(&self, payloads: Option<&Payloads>, time: Option<&Time>) -> String {
+        let mut info = Info::default();
+
+        info.add("TAPERIPPER_VERSION", env!("CARGO_PKG_VERSION"));
+        if let Some(time) = time {
+            info.add("BOOT_TIME", format_time(time));
+        }
+        info.add("BOOT_SOURCE", self.source);
+        if let Some(device) = &self.device {
+            info.add("BOOT_DEVICE", device);
+        }
+
+        if let Some([vendor, product, revision]) = &self.inquiry {
+            info.add("DRIVE_VENDOR", vendor);
+            info.add("DRIVE_PRODUCT", product);
+            info.add("DRIVE_REVISION", revision);
+        }
+        if let Some(density) = self.density {
+            info.add("DENSITY", density);
+        }
+
+        if let Some(errors) = &self.errors {
+            info.add("REELS", self.reels);
+            info.add("READ_ERRORS", errors.errors);
+            info.add("READ_RETRIES", errors.retries);
+            info.add("READ_RECOVERED", errors.recovered);
+            info.add("READ_HOLES", errors.holes);
+        }
+
+        let Some(payloads) = payloads else {
+            return info.0;
+        };
+
+        if let Some(volume) = &payloads.volume {
+            info.add("VOLUME", volume);
+        }
+        if let Some(digest) = payloads.initrd_digest {
+            info.add("INITRD_SHA256", digest.sha256_hex());
+        }
+
+        // NOTE(aki): Everything about a payload gets the same `PAYLOAD_<n>_` prefix, in the
+        // order they came off of the tape
+        info.add("PAYLOADS", payloads.digests.len());
+        for (idx, payload) in payloads.digests.iter().enumerate() {
+            let key = |name: &str| format!("PAYLOAD_{idx}_{name}");
+
+            info.add(&key("KIND"), payload.kind);
+            info.add(&key("NAME"), &payload.name);
+            info.add(&key("REEL"), payload.location.reel);
+            if let Some(file) = payload.location.file {
+                info.add(&key("FILE"), file);
+            }
+            info.add(&key("SIZE"), payload.digest.len);
+            info.add(&key("SHA256"), payload.digest.sha256_hex());
+            info.add(&key("CRC32"), format!("{:08x}", payload.digest.crc32));
+        }
+
+        info.0
+    }
+}
+
+#[derive(Default)]
+struct Info(String);
+
+impl Info {
+    fn add(&mut self, key: &str, value: impl fmt::Display) {
+        // NOTE(aki): Nothing gets expanded inside single quotes, a quote itself has to be
+        // closed, escaped, and opened again
+        let value = value.to_string().replace('\'', "'\\''");
+        let _ = writeln!(self.0, "{key}='{value}'");
+    }
+}
+
+// ISO 8601, with the offset if the firmware knows what time zone it is in
+fn format_time(time: &Time) -> String {
+    let mut stamp = format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        time.year(),
+        time.month(),
+        time.day(),
+        time.hour(),
+        time.minute(),
+        time.second()
+    );
+
+    if let Some(offset) = time.time_zone() {
+        let sign = if offset < 0 { '-' } else { '+' };
+        let offset = offset.unsigned_abs();
+        let _ = write!(stamp, "{sign}{:02}:{:02}", offset / 60, offset % 60);
+    }
+
+    stamp
+}
+
+// Seconds since the epoch
+//
+// NOTE(aki): Without a time zone the clock is in local time, which we have no way to get
+// back to UTC from, so it's taken as-is
+fn unix_time(time: &Time) -> u32 {
+    // Days from the epoch to the civil date, by way of 400 year eras starting in March
+    let (month, day) = (time.month() as i64, time.day() as i64);
+    let year = time.year() as i64 - (month <= 2) as i64;
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    let mut seconds = days * 86_400
+        + time.hour() as i64 * 3600
+        + time.minute() as i64 * 60
+        + time.second() as i64;
+
+    // The time zone is how far ahead of UTC the clock is, in minutes
+    if let Some(offset) = time.time_zone() {
+        seconds -= offset as i64 * 60;
+    }
+
+    seconds.clamp(0, u32::MAX as i64) as u32
+}
@@ -0,0 +1,195 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// Putting the initramfs the kernel gets together out of however many cpio archives we have.
+//
+// Linux will unpack an initramfs that is several cpio archives, compressed or not, back to
+// back, so rather than glue them all into one big buffer they're kept apart and copied
+// straight into the kernel's initrd buffer one after the other. Each archive has to start on
+// a 4 byte boundary, the gaps are filled with zeros, which the kernel skips over.
+//
+// They go to the kernel in layers, and within a layer in the order we found them:
+//  - Microcode, which has to come first and be uncompressed for the early loader to find it
+//  - The base initrd
+//  - Overlays, which are unpacked over the top of the base
+//  - An archive of files we generated, so nothing can clobber them
+
+use tracing::{debug, info, warn};
+
+use crate::{
+    boot::{
+        cpio::{self, CpioWriter},
+        initrd::InitrdSource,
+    },
+    platform,
+    tape::decompress::Format,
+};
+
+mod layer;
+
+pub use layer::Layer;
+
+// Extra archives that can be picked up off of the ESP
+pub const ESP_MICROCODE_PATH: &str = "EFI\\taperipper\\microcode.cpio";
+pub const ESP_OVERLAY_PATH: &str = "EFI\\taperipper\\overlay.cpio";
+
+// Where the list of what went into the initramfs ends up for userspace
+pub const LISTING_PATH: &str = "/etc/taperipper/initramfs";
+
+const ALIGN: usize = 4;
+
+// One of the archives that make up the initramfs
+pub struct Part {
+    pub layer: Layer,
+    // Where it came from, for the logs
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+impl Part {
+    pub fn new(layer: Layer, name: &str, data: Vec<u8>) -> Self {
+        Self {
+            layer,
+            name: name.to_string(),
+            data,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Initramfs {
+    parts: Vec<Part>,
+    // Files for the generated archive, by path
+    files: Vec<(String, Vec<u8>)>,
+    // When they were made, as seconds since the epoch
+    mtime: u32,
+}
+
+impl Initramfs {
+    pub fn new(parts: Vec<Part>) -> Self {
+        Self {
+            parts,
+            files: Vec::new(),
+            mtime: 0,
+        }
+    }
+
+    pub fn push(&mut self, part: Part) {
+        if part.layer == Layer::Microcode && Format::detect(&part.data).is_some() {
+            warn!(
+                name = part.name.as_str(),
+                "Microcode archive is compressed, the kernel won't find it early"
+            );
+        }
+
+        self.parts.push(part);
+    }
+
+    // Put a file into the generated archive, replacing anything already at `path`
+    pub fn add_file(&mut self, path: &str, data: Vec<u8>) {
+        self.files.retain(|(file, _)| file != path);
+        self.files.push((path.to_string(), data));
+    }
+
+    pub fn set_mtime(&mut self, mtime: u32) {
+        self.mtime = mtime;
+    }
+
+    // Pick up any extra archives on the ESP
+    //
+    // NOTE(aki): These aren't checked against anything, without Secure Boot anyone who can
+    // write to the ESP can just as well replace us, but with it they can't. So this is only
+    // ever done for tapes that aren't signed, a signed one has to carry its whole initramfs
+    pub fn add_from_esp(&mut self) {
+        for (layer, path) in [
+            (Layer::Microcode, ESP_MICROCODE_PATH),
+            (Layer::Overlay, ESP_OVERLAY_PATH),
+        ] {
+            if !platform::uefi::fs::exists(path) {
+                continue;
+            }
+            if let Ok(data) = platform::uefi::fs::read(path) {
+                self.push(Part::new(layer, path, data));
+            }
+        }
+
+        for path in overlay_paths() {
+            match platform::uefi::fs::read(&path) {
+                Ok(data) => self.push(Part::new(Layer::Overlay, &path, data)),
+                Err(_) => warn!(path = path.as_str(), "Skipping missing initrd overlay"),
+            }
+        }
+    }
+
+    // Put the parts in order and add the generated archive, `None` if there's nothing for the
+    // kernel at all
+    //
+    // NOTE(aki): An initramfs of nothing but our own files isn't going to boot anything, so
+    // without any other parts there is no generated archive either
+    pub fn build(mut self) -> Option<Box<dyn InitrdSource>> {
+        if self.parts.is_empty() {
+            return None;
+        }
+
+        // NOTE(aki): The sort is stable, so parts stay in the order we found them in a layer
+        self.parts.sort_by_key(|part| part.layer);
+
+        let listing = self.listing();
+        self.add_file(LISTING_PATH, listing.into_bytes());
+
+        let mut archive = CpioWriter::new(self.mtime);
+        for (path, data) in &self.files {
+            debug!(path = path.as_str(), size = data.len(), "Generated file");
+            archive.file(path, cpio::FILE_MODE, data);
+        }
+        self.parts
+            .push(Part::new(Layer::Generated, "taperipper", archive.finish()));
+
+        for part in &self.parts {
+            info!(
+                layer = %part.layer,
+                name = part.name.as_str(),
+                size = part.data.len(),
+                "Initramfs part"
+            );
+        }
+
+        Some(Box::new(self))
+    }
+
+    // A line per part, its layer, size, and where it came from
+    fn listing(&self) -> String {
+        self.parts
+            .iter()
+            .map(|part| format!("{} {} {}\n", part.layer, part.data.len(), part.name))
+            .collect()
+    }
+}
+
+impl InitrdSource for Initramfs {
+    fn size(&self) -> usize {
+        self.parts
+            .iter()
+            .map(|part| part.data.len().next_multiple_of(ALIGN))
+            .sum()
+    }
+
+    fn read_into(&mut self, buffer: &mut [u8]) -> Result<(), uefi::Error> {
+        let mut offset = 0;
+
+        for part in &self.parts {
+            let (data, padding) = buffer[offset..].split_at_mut(part.data.len());
+            data.copy_from_slice(&part.data);
+
+            let pad = part.data.len().next_multiple_of(ALIGN) - part.data.len();
+            padding[..pad].fill(0);
+
+            offset += part.data.len() + pad;
+        }
+
+        Ok(())
+    }
+}
+
+// Extra overlays to pull off of the ESP, as a comma separated list of paths
+pub fn overlay_paths() -> Vec<String> {
+    platform::uefi::variables::get_list("TAPERIPPER_INITRD_OVERLAYS")
+}
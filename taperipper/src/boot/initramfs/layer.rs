@@ -0,0 +1,23 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// Which layer of the initramfs an archive goes in.
+
+use core::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Layer {
+    Microcode,
+    Base,
+    Overlay,
+    Generated,
+}
+
+impl fmt::Display for Layer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Layer::Microcode => write!(f, "microcode"),
+            Layer::Base => write!(f, "base"),
+            Layer::Overlay => write!(f, "overlay"),
+            Layer::Generated => write!(f, "generated"),
+        }
+    }
+}
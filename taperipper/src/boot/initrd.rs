@@ -0,0 +1,226 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// This module serves an initramfs to the kernel using the `LoadFile2` protocol.
+//
+// Since v5.8 the Linux EFI stub will look for a `LoadFile2` protocol installed on a
+// handle carrying a vendor media device path with `LINUX_EFI_INITRD_MEDIA_GUID`, and
+// if it finds one, it asks it for the initrd rather than going hunting for `initrd=`
+// files on the ESP. That lets us hand it something we pulled off of tape directly.
+//
+// see: https://github.com/torvalds/linux/blob/master/drivers/firmware/efi/libstub/efi-stub-helper.c
+
+use core::{ffi::c_void, mem, ptr, slice};
+
+use tracing::{debug, error, trace, warn};
+use uefi::{
+    Guid, Handle, Identify, Status, boot, guid,
+    proto::device_path::{DevicePath, DeviceSubType, DeviceType},
+};
+
+pub const LINUX_EFI_INITRD_MEDIA_GUID: Guid = guid!("5568e427-68fc-4f3d-ac74-ca555231cc68");
+pub const LOAD_FILE2_PROTOCOL_GUID: Guid = guid!("4006c0c1-fcb3-403e-996d-4a6c8724e06d");
+
+// Something we can fill the kernels initrd buffer from
+pub trait InitrdSource: Send {
+    // The total size of the initrd in bytes, this must be known up-front
+    fn size(&self) -> usize;
+    // Fill `buffer` with the initrd, it is always exactly `size()` bytes long
+    fn read_into(&mut self, buffer: &mut [u8]) -> Result<(), uefi::Error>;
+}
+
+impl InitrdSource for Vec<u8> {
+    fn size(&self) -> usize {
+        self.len()
+    }
+
+    fn read_into(&mut self, buffer: &mut [u8]) -> Result<(), uefi::Error> {
+        buffer.copy_from_slice(self);
+        Ok(())
+    }
+}
+
+impl InitrdSource for Box<[u8]> {
+    fn size(&self) -> usize {
+        self.len()
+    }
+
+    fn read_into(&mut self, buffer: &mut [u8]) -> Result<(), uefi::Error> {
+        buffer.copy_from_slice(self);
+        Ok(())
+    }
+}
+
+#[repr(C, packed)]
+struct DevicePathNode {
+    major_type: DeviceType,
+    sub_type: DeviceSubType,
+    length: [u8; 2],
+}
+
+#[repr(C, packed)]
+struct InitrdDevicePath {
+    vendor: DevicePathNode,
+    vendor_guid: Guid,
+    end: DevicePathNode,
+}
+
+impl InitrdDevicePath {
+    const fn new() -> Self {
+        Self {
+            vendor: DevicePathNode {
+                major_type: DeviceType::MEDIA,
+                sub_type: DeviceSubType::MEDIA_VENDOR,
+                length: ((mem::size_of::<DevicePathNode>() + mem::size_of::<Guid>()) as u16)
+                    .to_le_bytes(),
+            },
+            vendor_guid: LINUX_EFI_INITRD_MEDIA_GUID,
+            end: DevicePathNode {
+                major_type: DeviceType::END,
+                sub_type: DeviceSubType::END_ENTIRE,
+                length: (mem::size_of::<DevicePathNode>() as u16).to_le_bytes(),
+            },
+        }
+    }
+}
+
+type LoadFileFn = unsafe extern "efiapi" fn(
+    this: *mut LoadFile2,
+    file_path: *const c_void,
+    boot_policy: u8,
+    buffer_size: *mut usize,
+    buffer: *mut c_void,
+) -> Status;
+
+// NOTE(aki): `load_file` *must* be the first member, the firmware only knows about
+// that, and we use the `this` pointer to get back at the rest of the structure.
+#[repr(C)]
+struct LoadFile2 {
+    load_file: LoadFileFn,
+    source: Box<dyn InitrdSource>,
+}
+
+unsafe extern "efiapi" fn load_initrd(
+    this: *mut LoadFile2,
+    _file_path: *const c_void,
+    boot_policy: u8,
+    buffer_size: *mut usize,
+    buffer: *mut c_void,
+) -> Status {
+    if this.is_null() || buffer_size.is_null() {
+        return Status::INVALID_PARAMETER;
+    }
+
+    // `LoadFile2` is never used as a boot option, the spec says it must be `FALSE`
+    if boot_policy != 0 {
+        return Status::UNSUPPORTED;
+    }
+
+    let proto = unsafe { &mut *this };
+    let size = proto.source.size();
+
+    // The kernel first asks how big of a buffer it needs
+    if buffer.is_null() || unsafe { *buffer_size } < size {
+        trace!("Kernel asked for initrd size, {} bytes", size);
+        unsafe { *buffer_size = size };
+        return Status::BUFFER_TOO_SMALL;
+    }
+
+    debug!(
+        "Loading initrd into {:#018x} ({} bytes)",
+        buffer as usize, size
+    );
+
+    let buffer = unsafe { slice::from_raw_parts_mut(buffer.cast::<u8>(), size) };
+    match proto.source.read_into(buffer) {
+        Ok(_) => {
+            unsafe { *buffer_size = size };
+            Status::SUCCESS
+        }
+        Err(err) => {
+            error!("Unable to load initrd: {:?}", err.status());
+            err.status()
+        }
+    }
+}
+
+// The installed initrd media handle, dropping it pulls the protocols back out
+pub struct InitrdMedia {
+    handle: Handle,
+    device_path: Box<InitrdDevicePath>,
+    load_file: Box<LoadFile2>,
+}
+
+impl InitrdMedia {
+    pub fn install(source: Box<dyn InitrdSource>) -> Result<Self, uefi::Error> {
+        let device_path = Box::new(InitrdDevicePath::new());
+        let mut load_file = Box::new(LoadFile2 {
+            load_file: load_initrd,
+            source,
+        });
+
+        debug!(
+            "Installing initrd media device ({} bytes)",
+            load_file.source.size()
+        );
+
+        let handle = unsafe {
+            boot::install_protocol_interface(
+                None,
+                &DevicePath::GUID,
+                ptr::from_ref(device_path.as_ref()).cast(),
+            )
+        }
+        .inspect_err(|err| error!("Unable to install initrd device path: {:?}", err.status()))?;
+
+        if let Err(err) = unsafe {
+            boot::install_protocol_interface(
+                Some(handle),
+                &LOAD_FILE2_PROTOCOL_GUID,
+                ptr::from_mut(load_file.as_mut()).cast(),
+            )
+        } {
+            error!("Unable to install initrd LoadFile2: {:?}", err.status());
+            let _ = unsafe {
+                boot::uninstall_protocol_interface(
+                    handle,
+                    &DevicePath::GUID,
+                    ptr::from_ref(device_path.as_ref()).cast(),
+                )
+            };
+            return Err(err);
+        }
+
+        trace!("Initrd media handle: {:?}", handle);
+
+        Ok(Self {
+            handle,
+            device_path,
+            load_file,
+        })
+    }
+}
+
+impl Drop for InitrdMedia {
+    fn drop(&mut self) {
+        debug!("Removing initrd media device");
+
+        if let Err(err) = unsafe {
+            boot::uninstall_protocol_interface(
+                self.handle,
+                &LOAD_FILE2_PROTOCOL_GUID,
+                ptr::from_mut(self.load_file.as_mut()).cast(),
+            )
+        } {
+            warn!("Unable to uninstall initrd LoadFile2: {:?}", err.status());
+        }
+
+        if let Err(err) = unsafe {
+            boot::uninstall_protocol_interface(
+                self.handle,
+                &DevicePath::GUID,
+                ptr::from_ref(self.device_path.as_ref()).cast(),
+            )
+        } {
+            warn!("Unable to uninstall initrd device path: {:?}", err.status());
+        }
+    }
+}
@@ -0,0 +1,644 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// This module boots a Linux kernel with the x86 64-bit boot protocol directly, for when the
+// kernel has no EFI stub to hand it off to.
+//
+// We pull the setup header out of the bzImage, build a zero page (`boot_params`) around it,
+// copy the protected-mode kernel into place, exit boot services, and jump to the 64-bit entry
+// point with `%rsi` pointing at the zero page.
+//
+// see: https://www.kernel.org/doc/html/latest/arch/x86/boot.html
+// and: https://github.com/torvalds/linux/blob/master/arch/x86/include/uapi/asm/bootparam.h
+
+use core::{arch::asm, mem, ptr, slice};
+
+use tracing::{debug, error, info, trace, warn};
+use uefi::{
+    Status,
+    boot::AllocateType,
+    mem::memory_map::{MemoryMap, MemoryMapMut, MemoryType},
+    proto::console::gop::PixelFormat,
+    table,
+};
+
+use crate::{
+    boot::initrd::InitrdSource,
+    display::framebuffer::Framebuffer,
+    platform::{self, uefi::memory::PAGE_SIZE},
+};
+
+const SETUP_HEADER_OFFSET: usize = 0x1F1;
+const SETUP_HEADER_END: usize = SETUP_HEADER_OFFSET + mem::size_of::<SetupHeader>();
+const BOOT_FLAG: u16 = 0xAA55;
+const HDRS_MAGIC: u32 = u32::from_le_bytes(*b"HdrS");
+// The first protocol version with `xloadflags`
+const MIN_PROTOCOL_VERSION: u16 = 0x020C;
+
+const LOADER_TYPE_UNDEFINED: u8 = 0xFF;
+const LOADFLAGS_LOADED_HIGH: u8 = 1 << 0;
+const XLF_KERNEL_64: u16 = 1 << 0;
+const XLF_CAN_BE_LOADED_ABOVE_4G: u16 = 1 << 1;
+
+const VIDEO_TYPE_EFI: u8 = 0x70;
+const VIDEO_CAPABILITY_64BIT_BASE: u32 = 1 << 1;
+
+const EFI64_LOADER_SIGNATURE: u32 = u32::from_le_bytes(*b"EL64");
+
+const E820_MAX_ENTRIES: usize = 128;
+const E820_TYPE_RAM: u32 = 1;
+const E820_TYPE_RESERVED: u32 = 2;
+const E820_TYPE_ACPI: u32 = 3;
+const E820_TYPE_NVS: u32 = 4;
+const E820_TYPE_UNUSABLE: u32 = 5;
+const E820_TYPE_PMEM: u32 = 7;
+// How many more entries than there are now the memory map might have by the time we exit
+// boot services
+const E820_EXT_SLACK: usize = 32;
+
+const SETUP_E820_EXT: u32 = 1;
+
+// The 64-bit entry point is always 0x200 past the start of the protected-mode kernel
+const ENTRY_64_OFFSET: u64 = 0x200;
+
+#[repr(C, packed)]
+#[derive(Clone, Copy, Default)]
+struct ScreenInfo {
+    orig_x: u8,
+    orig_y: u8,
+    ext_mem_k: u16,
+    orig_video_page: u16,
+    orig_video_mode: u8,
+    orig_video_cols: u8,
+    flags: u8,
+    _unused2: u8,
+    orig_video_ega_bx: u16,
+    _unused3: u16,
+    orig_video_lines: u8,
+    orig_video_is_vga: u8,
+    orig_video_points: u16,
+    lfb_width: u16,
+    lfb_height: u16,
+    lfb_depth: u16,
+    lfb_base: u32,
+    lfb_size: u32,
+    cl_magic: u16,
+    cl_offset: u16,
+    lfb_linelength: u16,
+    red_size: u8,
+    red_pos: u8,
+    green_size: u8,
+    green_pos: u8,
+    blue_size: u8,
+    blue_pos: u8,
+    rsvd_size: u8,
+    rsvd_pos: u8,
+    vesapm_seg: u16,
+    vesapm_off: u16,
+    pages: u16,
+    vesa_attributes: u16,
+    capabilities: u32,
+    ext_lfb_base: u32,
+    _reserved: [u8; 2],
+}
+
+#[repr(C, packed)]
+#[derive(Clone, Copy, Default)]
+struct SetupHeader {
+    setup_sects: u8,
+    root_flags: u16,
+    syssize: u32,
+    ram_size: u16,
+    vid_mode: u16,
+    root_dev: u16,
+    boot_flag: u16,
+    jump: u16,
+    header: u32,
+    version: u16,
+    realmode_swtch: u32,
+    start_sys_seg: u16,
+    kernel_version: u16,
+    type_of_loader: u8,
+    loadflags: u8,
+    setup_move_size: u16,
+    code32_start: u32,
+    ramdisk_image: u32,
+    ramdisk_size: u32,
+    bootsect_kludge: u32,
+    heap_end_ptr: u16,
+    ext_loader_ver: u8,
+    ext_loader_type: u8,
+    cmd_line_ptr: u32,
+    initrd_addr_max: u32,
+    kernel_alignment: u32,
+    relocatable_kernel: u8,
+    min_alignment: u8,
+    xloadflags: u16,
+    cmdline_size: u32,
+    hardware_subarch: u32,
+    hardware_subarch_data: u64,
+    payload_offset: u32,
+    payload_length: u32,
+    setup_data: u64,
+    pref_address: u64,
+    init_size: u32,
+    handover_offset: u32,
+    kernel_info_offset: u32,
+}
+
+#[repr(C, packed)]
+#[derive(Clone, Copy, Default)]
+struct EfiInfo {
+    efi_loader_signature: u32,
+    efi_systab: u32,
+    efi_memdesc_size: u32,
+    efi_memdesc_version: u32,
+    efi_memmap: u32,
+    efi_memmap_size: u32,
+    efi_systab_hi: u32,
+    efi_memmap_hi: u32,
+}
+
+#[repr(C, packed)]
+#[derive(Clone, Copy, Default)]
+struct E820Entry {
+    addr: u64,
+    size: u64,
+    kind: u32,
+}
+
+// A node in the `setup_data` list, the data follows straight after
+#[repr(C, packed)]
+#[derive(Clone, Copy, Default)]
+struct SetupData {
+    next: u64,
+    kind: u32,
+    len: u32,
+}
+
+// NOTE(aki): Only the bits of the zero page we actually fill in are broken out,
+// everything else is lumped into padding at the right offsets.
+#[repr(C, packed)]
+struct BootParams {
+    screen_info: ScreenInfo,                   // 0x000
+    _pad0: [u8; 0x30],                         // 0x040
+    acpi_rsdp_addr: u64,                       // 0x070
+    _pad1: [u8; 0x48],                         // 0x078
+    ext_ramdisk_image: u32,                    // 0x0C0
+    ext_ramdisk_size: u32,                     // 0x0C4
+    ext_cmd_line_ptr: u32,                     // 0x0C8
+    _pad2: [u8; 0xF4],                         // 0x0CC
+    efi_info: EfiInfo,                         // 0x1C0
+    _pad3: [u8; 0x08],                         // 0x1E0
+    e820_entries: u8,                          // 0x1E8
+    _pad4: [u8; 0x08],                         // 0x1E9
+    hdr: SetupHeader,                          // 0x1F1
+    _pad5: [u8; 0x64],                         // 0x26C
+    e820_table: [E820Entry; E820_MAX_ENTRIES], // 0x2D0
+    _pad6: [u8; 0x330],                        // 0xCD0
+}
+
+const _: () = assert!(mem::size_of::<ScreenInfo>() == 0x40);
+const _: () = assert!(mem::size_of::<SetupHeader>() == 0x7B);
+const _: () = assert!(mem::size_of::<EfiInfo>() == 0x20);
+const _: () = assert!(mem::size_of::<BootParams>() == PAGE_SIZE);
+
+pub struct BzImage<'a> {
+    image: &'a [u8],
+    hdr: SetupHeader,
+}
+
+impl<'a> BzImage<'a> {
+    pub fn parse(image: &'a [u8]) -> Option<Self> {
+        if image.len() < SETUP_HEADER_END {
+            return None;
+        }
+
+        // SAFETY: We checked the length above, and the header is packed so alignment is moot
+        let hdr = unsafe {
+            ptr::read_unaligned(image[SETUP_HEADER_OFFSET..].as_ptr().cast::<SetupHeader>())
+        };
+
+        if hdr.boot_flag != BOOT_FLAG || hdr.header != HDRS_MAGIC {
+            return None;
+        }
+
+        let bz = Self { image, hdr };
+        if bz.kernel_offset() >= image.len() {
+            return None;
+        }
+
+        Some(bz)
+    }
+
+    pub fn version(&self) -> u16 {
+        self.hdr.version
+    }
+
+    pub fn can_boot_64(&self) -> bool {
+        self.version() >= MIN_PROTOCOL_VERSION && (self.hdr.xloadflags & XLF_KERNEL_64) != 0
+    }
+
+    fn setup_sects(&self) -> usize {
+        // A zero here means the ancient default of 4
+        match self.hdr.setup_sects {
+            0 => 4,
+            sects => sects as usize,
+        }
+    }
+
+    // The protected-mode kernel lives right after the real-mode setup code
+    fn kernel_offset(&self) -> usize {
+        (self.setup_sects() + 1) * 512
+    }
+
+    pub fn kernel(&self) -> &'a [u8] {
+        &self.image[self.kernel_offset()..]
+    }
+
+    // The setup header as it is in the image, this runs past our struct on newer kernels
+    fn raw_header(&self) -> &'a [u8] {
+        let end = (0x202 + self.image[0x201] as usize).min(SETUP_HEADER_END);
+        &self.image[SETUP_HEADER_OFFSET..end]
+    }
+}
+
+fn e820_type(ty: MemoryType) -> u32 {
+    match ty {
+        MemoryType::CONVENTIONAL
+        | MemoryType::LOADER_CODE
+        | MemoryType::LOADER_DATA
+        | MemoryType::BOOT_SERVICES_CODE
+        | MemoryType::BOOT_SERVICES_DATA => E820_TYPE_RAM,
+        MemoryType::ACPI_RECLAIM => E820_TYPE_ACPI,
+        MemoryType::ACPI_NON_VOLATILE => E820_TYPE_NVS,
+        MemoryType::UNUSABLE => E820_TYPE_UNUSABLE,
+        MemoryType::PERSISTENT_MEMORY => E820_TYPE_PMEM,
+        _ => E820_TYPE_RESERVED,
+    }
+}
+
+// Room for the e820 entries that don't fit in the zero page, handed to the kernel as a
+// `SETUP_E820_EXT` node
+struct E820Ext {
+    header: &'static mut SetupData,
+    entries: &'static mut [E820Entry],
+}
+
+impl E820Ext {
+    // Set aside enough room for however big the memory map could get, if it could get bigger
+    // than the zero page has room for
+    // NOTE(aki): This has to happen before `ExitBootServices`, after that there's no allocating
+    fn allocate() -> Result<Option<Self>, uefi::Error> {
+        let capacity = platform::uefi::memory::memory_map()?.len() + E820_EXT_SLACK;
+        let extra = capacity.saturating_sub(E820_MAX_ENTRIES);
+        if extra == 0 {
+            return Ok(None);
+        }
+
+        let size = mem::size_of::<SetupData>() + extra * mem::size_of::<E820Entry>();
+        let ptr = platform::uefi::memory::allocate_pages(AllocateType::MaxAddress(u64::MAX), size)?;
+
+        debug!("Room for {} e820 entries past the zero page", extra);
+
+        // SAFETY: The pages are zeroed, which is valid for both, they're both packed so
+        // there's nothing to align, and we never free them
+        let header = unsafe { &mut *ptr.as_ptr().cast::<SetupData>() };
+        let entries = unsafe {
+            slice::from_raw_parts_mut(
+                ptr.as_ptr()
+                    .add(mem::size_of::<SetupData>())
+                    .cast::<E820Entry>(),
+                extra,
+            )
+        };
+
+        Ok(Some(Self { header, entries }))
+    }
+}
+
+// Build the e820 table from the UEFI memory map, anything that doesn't fit in the zero page
+// goes in `ext`
+// NOTE(aki): This runs after `ExitBootServices`, so it must not allocate or log
+fn fill_e820(params: &mut BootParams, mmap: &mut impl MemoryMapMut, ext: Option<&mut E820Ext>) {
+    // The UEFI memory map isn't guaranteed to be sorted, the kernel wants it to be, and
+    // entries can only be coalesced with their neighbours once it is
+    mmap.sort();
+
+    let (ext_header, ext_entries) = match ext {
+        Some(ext) => (Some(&mut *ext.header), &mut *ext.entries),
+        None => (None, Default::default()),
+    };
+    let mut slots = params.e820_table.iter_mut().chain(ext_entries.iter_mut());
+    let mut prev: Option<&mut E820Entry> = None;
+    let mut count = 0;
+
+    for desc in mmap.entries() {
+        let entry = E820Entry {
+            addr: desc.phys_start,
+            size: desc.page_count * PAGE_SIZE as u64,
+            kind: e820_type(desc.ty),
+        };
+
+        // Try to coalesce with the previous entry if they're contiguous and the same type
+        if let Some(prev) = prev.as_deref_mut()
+            && prev.kind == entry.kind
+            && prev.addr + prev.size == entry.addr
+        {
+            prev.size += entry.size;
+            continue;
+        }
+
+        // NOTE(aki): The slack `E820Ext` leaves should mean we never run out of room, but if
+        // we do, the most we can do is drop the highest entries
+        let Some(slot) = slots.next() else {
+            break;
+        };
+        *slot = entry;
+        prev = Some(slot);
+        count += 1;
+    }
+
+    params.e820_entries = count.min(E820_MAX_ENTRIES) as u8;
+
+    let extra = count.saturating_sub(E820_MAX_ENTRIES);
+    if let Some(header) = ext_header
+        && extra > 0
+    {
+        header.next = params.hdr.setup_data;
+        header.kind = SETUP_E820_EXT;
+        header.len = (extra * mem::size_of::<E820Entry>()) as u32;
+        params.hdr.setup_data = ptr::from_mut(header) as u64;
+    }
+}
+
+fn fill_screen_info(params: &mut BootParams, fb: &mut Framebuffer) {
+    if !fb.is_valid() {
+        return;
+    }
+
+    // NOTE(aki): With no framebuffer we can draw to directly there's nothing for the kernel to
+    // take over, leaving `orig_video_is_vga` unset means it won't go looking for one
+    if fb.pixel_format() == PixelFormat::BltOnly {
+        debug!("GOP is Blt only, not passing a framebuffer to the kernel");
+        return;
+    }
+
+    let base = fb.get_raw() as u64;
+    let info = &mut params.screen_info;
+
+    info.orig_video_is_vga = VIDEO_TYPE_EFI;
+    info.lfb_width = fb.width() as u16;
+    info.lfb_height = fb.height() as u16;
+    info.lfb_base = base as u32;
+    info.ext_lfb_base = (base >> 32) as u32;
+    info.lfb_size = fb.size() as u32;
+    info.capabilities = VIDEO_CAPABILITY_64BIT_BASE;
+
+    (
+        (info.red_size, info.red_pos),
+        (info.green_size, info.green_pos),
+        (info.blue_size, info.blue_pos),
+        (info.rsvd_size, info.rsvd_pos),
+    ) = match (fb.pixel_format(), fb.pixel_bitmask()) {
+        (PixelFormat::Bitmask, Some(mask)) => (
+            mask_bits(mask.red),
+            mask_bits(mask.green),
+            mask_bits(mask.blue),
+            mask_bits(mask.reserved),
+        ),
+        (PixelFormat::Bgr, _) => ((8, 16), (8, 8), (8, 0), (8, 24)),
+        _ => ((8, 0), (8, 8), (8, 16), (8, 24)),
+    };
+
+    // Same as the EFI stub, a bitmask pixel is as big as all of its channels put together
+    info.lfb_depth = (info.red_size + info.green_size + info.blue_size + info.rsvd_size) as u16;
+    info.lfb_linelength = (fb.stride() * info.lfb_depth as usize / 8) as u16;
+
+    debug!(
+        "screen_info: {}x{}x{} @ {:#018x}",
+        fb.width(),
+        fb.height(),
+        { info.lfb_depth },
+        base
+    );
+}
+
+// The size and position of the run of bits set in a GOP channel mask
+fn mask_bits(mask: u32) -> (u8, u8) {
+    if mask == 0 {
+        return (0, 0);
+    }
+
+    let pos = mask.trailing_zeros();
+    ((mask >> pos).trailing_ones() as u8, pos as u8)
+}
+
+fn load_kernel(bz: &BzImage<'_>) -> Result<u64, uefi::Error> {
+    let kernel = bz.kernel();
+    let align = (bz.hdr.kernel_alignment as usize).max(PAGE_SIZE);
+    let size = (bz.hdr.init_size as usize).max(kernel.len());
+    let pref_address = bz.hdr.pref_address;
+
+    let load_addr =
+        match platform::uefi::memory::allocate_pages(AllocateType::Address(pref_address), size) {
+            Ok(ptr) => ptr.as_ptr() as u64,
+            Err(_) if bz.hdr.relocatable_kernel != 0 => {
+                debug!(
+                    "Unable to load kernel at {:#018x}, relocating",
+                    pref_address
+                );
+                // Over-allocate so we can bump it up to the alignment the kernel wants
+                let ptr = platform::uefi::memory::allocate_pages(
+                    AllocateType::MaxAddress(u64::MAX),
+                    size + align,
+                )?;
+                (ptr.as_ptr() as u64).next_multiple_of(align as u64)
+            }
+            Err(err) => {
+                error!(
+                    "Kernel is not relocatable and {:#018x} is not available",
+                    pref_address
+                );
+                return Err(err);
+            }
+        };
+
+    debug!(
+        "Loading kernel at {:#018x} ({} bytes, {} reserved)",
+        load_addr,
+        kernel.len(),
+        size
+    );
+
+    unsafe {
+        ptr::copy_nonoverlapping(kernel.as_ptr(), load_addr as *mut u8, kernel.len());
+    }
+
+    Ok(load_addr)
+}
+
+fn load_cmdline(bz: &BzImage<'_>, cmdline: &str) -> Result<u64, uefi::Error> {
+    let mut cmdline = cmdline.as_bytes();
+    let max_len = bz.hdr.cmdline_size as usize;
+
+    if cmdline.len() > max_len {
+        warn!(
+            "Kernel command line is too long, truncating to {} bytes",
+            max_len
+        );
+        cmdline = &cmdline[..max_len];
+    }
+
+    // Keep it under 4G so it fits in `cmd_line_ptr` alone
+    let ptr = platform::uefi::memory::allocate_pages(
+        AllocateType::MaxAddress(u32::MAX as u64),
+        cmdline.len() + 1,
+    )?;
+
+    // The pages are zeroed, so it is already NUL terminated
+    unsafe { ptr::copy_nonoverlapping(cmdline.as_ptr(), ptr.as_ptr(), cmdline.len()) };
+
+    Ok(ptr.as_ptr() as u64)
+}
+
+fn load_initrd(
+    bz: &BzImage<'_>,
+    mut initrd: Box<dyn InitrdSource>,
+) -> Result<(u64, usize), uefi::Error> {
+    let size = initrd.size();
+    let max_addr = if (bz.hdr.xloadflags & XLF_CAN_BE_LOADED_ABOVE_4G) != 0 {
+        u64::MAX
+    } else {
+        bz.hdr.initrd_addr_max as u64
+    };
+
+    let ptr = platform::uefi::memory::allocate_pages(AllocateType::MaxAddress(max_addr), size)?;
+    let buffer = unsafe { slice::from_raw_parts_mut(ptr.as_ptr(), size) };
+
+    initrd.read_into(buffer)?;
+
+    debug!(
+        "Loaded initrd at {:#018x} ({} bytes)",
+        ptr.as_ptr() as usize,
+        size
+    );
+
+    Ok((ptr.as_ptr() as u64, size))
+}
+
+pub fn boot(
+    kernel: &[u8],
+    initrd: Option<Box<dyn InitrdSource>>,
+    cmdline: &str,
+    fb: &mut Framebuffer,
+) -> uefi::Error {
+    let Some(bz) = BzImage::parse(kernel) else {
+        error!("Kernel image is not a bzImage");
+        return uefi::Error::new(Status::LOAD_ERROR, ());
+    };
+
+    debug!(
+        "Linux boot protocol v{}.{:02}",
+        bz.version() >> 8,
+        bz.version() & 0xFF
+    );
+
+    if !bz.can_boot_64() {
+        error!("Kernel does not support the 64-bit boot protocol");
+        return uefi::Error::new(Status::UNSUPPORTED, ());
+    }
+
+    match boot_params(&bz, initrd, cmdline, fb) {
+        Ok((params, load_addr, ext)) => handoff(params, load_addr + ENTRY_64_OFFSET, ext),
+        Err(err) => err,
+    }
+}
+
+fn boot_params(
+    bz: &BzImage<'_>,
+    initrd: Option<Box<dyn InitrdSource>>,
+    cmdline: &str,
+    fb: &mut Framebuffer,
+) -> Result<(&'static mut BootParams, u64, Option<E820Ext>), uefi::Error> {
+    let params_ptr = platform::uefi::memory::allocate_pages(
+        AllocateType::MaxAddress(u64::MAX),
+        mem::size_of::<BootParams>(),
+    )?;
+    // SAFETY: The pages are zeroed, which is a valid `BootParams`, and we never free them
+    let params = unsafe { &mut *params_ptr.as_ptr().cast::<BootParams>() };
+
+    // Start off with the setup header straight out of the image
+    let raw_hdr = bz.raw_header();
+    unsafe {
+        ptr::copy_nonoverlapping(
+            raw_hdr.as_ptr(),
+            ptr::addr_of_mut!(params.hdr).cast::<u8>(),
+            raw_hdr.len(),
+        );
+    }
+
+    let load_addr = load_kernel(bz)?;
+    params.hdr.code32_start = load_addr as u32;
+    params.hdr.type_of_loader = LOADER_TYPE_UNDEFINED;
+    params.hdr.loadflags |= LOADFLAGS_LOADED_HIGH;
+
+    let cmdline_addr = load_cmdline(bz, cmdline)?;
+    params.hdr.cmd_line_ptr = cmdline_addr as u32;
+    params.ext_cmd_line_ptr = (cmdline_addr >> 32) as u32;
+    debug!("Kernel command line: {}", cmdline);
+
+    if let Some(initrd) = initrd {
+        let (initrd_addr, initrd_size) = load_initrd(bz, initrd)?;
+        params.hdr.ramdisk_image = initrd_addr as u32;
+        params.hdr.ramdisk_size = initrd_size as u32;
+        params.ext_ramdisk_image = (initrd_addr >> 32) as u32;
+        params.ext_ramdisk_size = (initrd_size as u64 >> 32) as u32;
+    }
+
+    fill_screen_info(params, fb);
+
+    if let Some((_, rsdp)) = platform::uefi::tables::get_acpi() {
+        params.acpi_rsdp_addr = rsdp as u64;
+    }
+
+    let systab = table::system_table_raw().unwrap().as_ptr() as u64;
+    params.efi_info.efi_loader_signature = EFI64_LOADER_SIGNATURE;
+    params.efi_info.efi_systab = systab as u32;
+    params.efi_info.efi_systab_hi = (systab >> 32) as u32;
+
+    let ext = E820Ext::allocate()?;
+
+    platform::uefi::memory::dump_memory_map();
+
+    Ok((params, load_addr, ext))
+}
+
+fn handoff(params: &'static mut BootParams, entry: u64, mut ext: Option<E820Ext>) -> ! {
+    info!("Jumping to Linux kernel at {:#018x}", entry);
+    trace!("boot_params at {:#018x}", ptr::from_ref(&*params) as usize);
+
+    // NOTE(aki): No more logging past this point, the consoles are all gone
+    let mut mmap = unsafe { platform::uefi::memory::exit_boot_services() };
+
+    fill_e820(params, &mut mmap, ext.as_mut());
+
+    let meta = mmap.meta();
+    let mmap_addr = mmap.buffer().as_ptr() as u64;
+    params.efi_info.efi_memdesc_size = meta.desc_size as u32;
+    params.efi_info.efi_memdesc_version = meta.desc_version;
+    params.efi_info.efi_memmap = mmap_addr as u32;
+    params.efi_info.efi_memmap_hi = (mmap_addr >> 32) as u32;
+    params.efi_info.efi_memmap_size = meta.map_size as u32;
+
+    // The kernel hangs onto the memory map, so we can't let it be freed
+    mem::forget(mmap);
+
+    unsafe {
+        asm!(
+            "cli",
+            "jmp {entry}",
+            entry = in(reg) entry,
+            in("rsi") ptr::from_mut(params),
+            options(noreturn)
+        );
+    }
+}
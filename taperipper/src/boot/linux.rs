@@ -0,0 +1,161 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// This module hands a Linux kernel off to the firmware by way of the kernel's EFI stub.
+//
+// The kernel image is loaded out of memory with `LoadImage`, the command line is stuffed
+// into the `LoadedImage` load options as UCS-2, and then the image is started. If all goes
+// well `StartImage` never comes back, so anything we want to say has to be said before that.
+
+use tracing::{debug, error, info, trace, warn};
+use uefi::{
+    CString16, Handle, Status,
+    boot::{self, LoadImageSource},
+    proto::loaded_image::LoadedImage,
+};
+
+use crate::boot::initrd::{InitrdMedia, InitrdSource};
+
+// Offsets into the bzImage/PE header we poke at for diagnostics
+const MZ_MAGIC: &[u8; 2] = b"MZ";
+const HDRS_MAGIC: &[u8; 4] = b"HdrS";
+const HDRS_OFFSET: usize = 0x202;
+const HDRS_VERSION_OFFSET: usize = 0x206;
+const PE_OFFSET_OFFSET: usize = 0x3C;
+const PE_MAGIC: &[u8; 4] = b"PE\0\0";
+
+// Check to see if the kernel was built with an EFI stub (`CONFIG_EFI_STUB`)
+pub fn has_efi_stub(kernel: &[u8]) -> bool {
+    if kernel.len() < PE_OFFSET_OFFSET + 4 || &kernel[..2] != MZ_MAGIC {
+        return false;
+    }
+
+    let pe_offset = u32::from_le_bytes(
+        kernel[PE_OFFSET_OFFSET..PE_OFFSET_OFFSET + 4]
+            .try_into()
+            .unwrap(),
+    ) as usize;
+
+    kernel
+        .get(pe_offset..pe_offset + 4)
+        .is_some_and(|magic| magic == PE_MAGIC)
+}
+
+pub struct EfiStubKernel {
+    handle: Handle,
+    // NOTE(aki): The firmware only holds a pointer to this, it *must* outlive `start`
+    cmdline: Option<CString16>,
+    started: bool,
+}
+
+impl EfiStubKernel {
+    pub fn load(kernel: &[u8]) -> Result<Self, uefi::Error> {
+        if kernel.len() < HDRS_VERSION_OFFSET + 2 || &kernel[..2] != MZ_MAGIC {
+            error!("Kernel image is not a PE image, refusing to load it");
+            return Err(uefi::Error::new(Status::LOAD_ERROR, ()));
+        }
+
+        if &kernel[HDRS_OFFSET..HDRS_OFFSET + 4] == HDRS_MAGIC {
+            let version =
+                u16::from_le_bytes([kernel[HDRS_VERSION_OFFSET], kernel[HDRS_VERSION_OFFSET + 1]]);
+            debug!(
+                "Linux boot protocol v{}.{:02}",
+                version >> 8,
+                version & 0xFF
+            );
+        } else {
+            warn!("Kernel image has no Linux setup header, it might not be a Linux kernel");
+        }
+
+        debug!(
+            "Loading kernel image from {:#018x} ({} bytes)",
+            kernel.as_ptr() as usize,
+            kernel.len()
+        );
+
+        let handle = boot::load_image(
+            boot::image_handle(),
+            LoadImageSource::FromBuffer {
+                buffer: kernel,
+                file_path: None,
+            },
+        )
+        .inspect_err(|err| error!("Firmware refused to load kernel image: {:?}", err.status()))?;
+
+        trace!("Kernel image handle: {:?}", handle);
+
+        Ok(Self {
+            handle,
+            cmdline: None,
+            started: false,
+        })
+    }
+
+    pub fn set_cmdline(&mut self, cmdline: &str) -> Result<(), uefi::Error> {
+        let cmdline = CString16::try_from(cmdline).map_err(|_| {
+            error!("Kernel command line is not representable as UCS-2");
+            uefi::Error::new(Status::INVALID_PARAMETER, ())
+        })?;
+
+        let mut loaded = boot::open_protocol_exclusive::<LoadedImage>(self.handle)?;
+
+        // SAFETY: The command line buffer is held onto by us until the image is started
+        unsafe {
+            loaded.set_load_options(cmdline.as_ptr().cast(), cmdline.num_bytes() as u32);
+        }
+
+        debug!("Kernel command line: {}", cmdline);
+
+        self.cmdline = Some(cmdline);
+        Ok(())
+    }
+
+    // Start the kernel, this only ever returns if the kernel fails to boot
+    pub fn start(mut self) -> uefi::Error {
+        info!("Starting Linux kernel");
+
+        // Once started, the firmware unloads the image itself when it exits
+        self.started = true;
+
+        let err = match boot::start_image(self.handle) {
+            // The EFI stub returning at all is a failure
+            Ok(_) => uefi::Error::new(Status::ABORTED, ()),
+            Err(err) => err,
+        };
+
+        error!("Kernel returned from EFI stub: {:?}", err.status());
+
+        err
+    }
+}
+
+impl Drop for EfiStubKernel {
+    fn drop(&mut self) {
+        if self.started {
+            return;
+        }
+
+        trace!("Unloading kernel image");
+        if let Err(err) = boot::unload_image(self.handle) {
+            warn!("Unable to unload kernel image: {:?}", err.status());
+        }
+    }
+}
+
+// Load and start a kernel in one go
+pub fn boot(kernel: &[u8], initrd: Option<Box<dyn InitrdSource>>, cmdline: &str) -> uefi::Error {
+    let mut kernel = match EfiStubKernel::load(kernel) {
+        Ok(kernel) => kernel,
+        Err(err) => return err,
+    };
+
+    if let Err(err) = kernel.set_cmdline(cmdline) {
+        return err;
+    }
+
+    // If the kernel fails to boot, this falls out of scope and the media device is removed
+    let _initrd = match initrd.map(InitrdMedia::install).transpose() {
+        Ok(initrd) => initrd,
+        Err(err) => return err,
+    };
+
+    kernel.start()
+}
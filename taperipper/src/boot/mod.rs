@@ -0,0 +1,332 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// This module is responsible for actually getting a kernel off the ground once
+// we have it in memory.
+
+use std::{
+    mem,
+    sync::{Arc, RwLock},
+};
+
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    boot::{
+        info::BootInfo,
+        initramfs::{Initramfs, Layer, Part},
+        initrd::InitrdSource,
+    },
+    display::framebuffer::Framebuffer,
+    platform,
+    tape::{
+        self,
+        device::{TapeDevice, scsi::ScsiTape},
+        format::ebcdic::CodePage,
+        image::{ESP_IMAGE_DIR, ImageTape},
+        loader::{self, Payloads},
+        readahead::{ReadAhead, ReadAheadConfig},
+        recovery::{RecoveringTape, RecoveryPolicy},
+        scsi::density::Density,
+        transport::Transport,
+        volume::ReelChanger,
+    },
+};
+
+pub mod cpio;
+pub mod info;
+pub mod initramfs;
+pub mod initrd;
+pub mod legacy;
+pub mod linux;
+pub mod signature;
+pub mod verify;
+
+// TODO(aki): These are stop-gaps until we can pull the payloads off of tape
+pub const ESP_KERNEL_PATH: &str = "EFI\\taperipper\\vmlinuz";
+pub const ESP_INITRD_PATH: &str = "EFI\\taperipper\\initrd.img";
+// Tape images we'll boot from if there is no drive, in `ESP_IMAGE_DIR`
+pub const ESP_IMAGE_NAMES: &[&str] = &["boot.tap", "boot.aws"];
+
+pub fn cmdline() -> String {
+    platform::uefi::variables::get_str("TAPERIPPER_CMDLINE").unwrap_or_default()
+}
+
+// How hard to try to get a block to read before giving up on it
+pub fn recovery_policy() -> RecoveryPolicy {
+    platform::uefi::variables::get_parsed("TAPERIPPER_READ_RECOVERY", RecoveryPolicy::parse)
+        .unwrap_or_default()
+}
+
+// How far ahead of the loader the drive is allowed to read
+pub fn read_ahead_config() -> ReadAheadConfig {
+    platform::uefi::variables::get_parsed("TAPERIPPER_READ_AHEAD", ReadAheadConfig::parse)
+        .unwrap_or_default()
+}
+
+// The density to tell drives the reel is, if they can't work it out for themselves
+pub fn forced_density() -> Option<Density> {
+    platform::uefi::variables::get_parsed("TAPERIPPER_DENSITY", Density::parse)
+}
+
+// The code page text files on IBM labelled tapes are in
+pub fn code_page() -> CodePage {
+    platform::uefi::variables::get_parsed("TAPERIPPER_CODEPAGE", CodePage::parse)
+        .unwrap_or_default()
+}
+
+// The volume serials of the reels in a multi-reel set, in the order they should be mounted
+pub fn volume_serials() -> Vec<String> {
+    platform::uefi::variables::get_list("TAPERIPPER_VOLUMES")
+}
+
+// Boot the kernel however it wants to be booted
+pub fn boot_kernel(
+    kernel: &[u8],
+    initrd: Option<Box<dyn InitrdSource>>,
+    cmdline: &str,
+    fb: &Arc<RwLock<Framebuffer>>,
+) -> uefi::Error {
+    if linux::has_efi_stub(kernel) {
+        linux::boot(kernel, initrd, cmdline)
+    } else {
+        info!("Kernel has no EFI stub, using the legacy boot protocol");
+        // Copy the framebuffer out so we don't hold the lock into the handoff
+        let mut fb = *fb.read().unwrap();
+        legacy::boot(kernel, initrd, cmdline, &mut fb)
+    }
+}
+
+// Boot the kernel, initrd, and any extra initrd parts sitting on the ESP
+//
+// NOTE(aki): None of it is signed, and the ESP can be written by anyone who gets to run
+// before us or from the OS. If we trust anyone at all, booting it would be a way around
+// them for anyone who can blank the reel, so it's refused outright.
+pub async fn boot_from_esp(fb: &Arc<RwLock<Framebuffer>>) -> uefi::Error {
+    if !signature::trusted_keys().is_empty() {
+        error!("Signatures are required, refusing to boot from the ESP");
+        let lines = [
+            "Nothing signed by a key we trust was found on tape".to_string(),
+            String::new(),
+            "Nothing on the ESP is signed, refusing to boot".to_string(),
+        ];
+        return verify::refuse(fb, "NO SIGNED TAPE FOUND", &lines).await;
+    }
+
+    let kernel = match platform::uefi::fs::read(ESP_KERNEL_PATH) {
+        Ok(kernel) => kernel,
+        Err(err) => return err,
+    };
+    let mut initramfs = Initramfs::default();
+    if let Ok(initrd) = platform::uefi::fs::read(ESP_INITRD_PATH) {
+        initramfs.push(Part::new(Layer::Base, ESP_INITRD_PATH, initrd));
+    }
+    initramfs.add_from_esp();
+    BootInfo::esp().add_to(&mut initramfs, None);
+    let cmdline = cmdline();
+
+    debug!("Kernel image is {} bytes", kernel.len());
+    let initrd = initramfs.build();
+    if initrd.is_none() {
+        warn!("No initrd found, booting without one");
+    }
+
+    boot_kernel(&kernel, initrd, &cmdline, fb)
+}
+
+// Pull everything we need off of a tape, starting from wherever it is positioned
+async fn load_from_device<D: TapeDevice + 'static>(
+    device: &mut ReadAhead<RecoveringTape<D>>,
+    reels: &mut ReelChanger,
+    info: &mut BootInfo,
+    code_page: CodePage,
+) -> Option<Payloads> {
+    match device.status().await {
+        Ok(status) if status.no_medium => {
+            info!(device = device.name(), "No reel mounted");
+            return None;
+        }
+        Ok(status) => debug!(
+            device = device.name(),
+            ready = status.ready,
+            write_protected = status.write_protected,
+            density = status.density,
+            block_len = status.block_len,
+            "Device status"
+        ),
+        Err(err) => warn!(
+            device = device.name(),
+            "Unable to get device status: {}", err
+        ),
+    }
+
+    let result = loader::load(device, reels, code_page).await;
+
+    let totals = device.inner().await.totals();
+    info.read(reels.reel(), totals);
+    let stats = device.stats();
+    debug!(
+        device = device.name(),
+        blocks = stats.blocks,
+        bytes = stats.bytes,
+        drive_stalls = stats.drive_stalls,
+        read_stalls = stats.read_stalls,
+        overlapped = stats.overlapped,
+        "Read-ahead stats"
+    );
+
+    if !totals.is_clean() {
+        warn!(
+            device = device.name(),
+            errors = totals.errors,
+            retries = totals.retries,
+            recovered = totals.recovered,
+            holes = totals.holes,
+            "Tape read with errors"
+        );
+    }
+
+    match result {
+        Ok(payloads) => Some(payloads),
+        Err(err) => {
+            error!(device = device.name(), "Unable to load from tape: {}", err);
+            None
+        }
+    }
+}
+
+async fn boot_payloads(
+    mut payloads: Payloads,
+    info: BootInfo,
+    fb: &Arc<RwLock<Framebuffer>>,
+) -> uefi::Error {
+    // NOTE(aki): Don't fall back to anything else if these fail, whatever is wrong with the
+    // tape we want someone to go and look at it
+    if let Err(mismatches) = verify::verify(&mut payloads) {
+        error!("Payloads failed integrity checks, refusing to boot");
+        return verify::refuse(fb, "INTEGRITY CHECK FAILED", &verify::describe(&mismatches)).await;
+    }
+
+    let signed_by = match signature::check(&payloads) {
+        Ok(key) => key,
+        Err(err) => {
+            error!(
+                "Payloads failed signature checks, refusing to boot: {}",
+                err
+            );
+            let lines = [
+                format!("The {err}"),
+                String::new(),
+                "The tape was not mastered by anyone we trust, refusing to boot".to_string(),
+            ];
+            return verify::refuse(fb, "SIGNATURE CHECK FAILED", &lines).await;
+        }
+    };
+
+    // NOTE(aki): Once the tape is signed, nothing that isn't gets a say in what we boot. The
+    // variable and the ESP can be written by anyone who gets to run before us, or from the OS,
+    // so the command-line override and the extra initrd parts are only for unsigned tapes
+    let overrides = cmdline();
+    let cmdline = if signed_by.is_some() {
+        if !overrides.is_empty() {
+            warn!("Tape is signed, ignoring the command-line in TAPERIPPER_CMDLINE");
+        }
+        payloads.cmdline.take().unwrap_or_default()
+    } else {
+        // A command-line set in the variable overrides the one on the tape
+        Some(overrides)
+            .filter(|cmdline| !cmdline.is_empty())
+            .or(payloads.cmdline.take())
+            .unwrap_or_default()
+    };
+
+    let mut initramfs = Initramfs::new(mem::take(&mut payloads.initrds));
+    if signed_by.is_some() {
+        debug!("Tape is signed, not picking up initrd parts off of the ESP");
+    } else {
+        initramfs.add_from_esp();
+    }
+    info.add_to(&mut initramfs, Some(&payloads));
+
+    let initrd = initramfs.build();
+    if initrd.is_none() {
+        warn!("No initrd found, booting without one");
+    }
+
+    boot_kernel(&payloads.kernel, initrd, &cmdline, fb)
+}
+
+// Open every tape drive we can find, leaving out any that won't talk to us
+pub async fn open_drives() -> Vec<ScsiTape<Box<dyn Transport>>> {
+    let mut drives = Vec::new();
+
+    for transport in tape::transport::find_drives() {
+        match ScsiTape::open(transport).await {
+            Ok(drive) => drives.push(drive),
+            Err(err) => warn!("Unable to open tape drive: {}", err),
+        }
+    }
+
+    drives
+}
+
+// Try the drives from `open_drives`, then any tape images on the ESP, then the ESP itself
+pub async fn boot_from_tape(
+    fb: &Arc<RwLock<Framebuffer>>,
+    drives: Vec<ScsiTape<Box<dyn Transport>>>,
+) -> uefi::Error {
+    let policy = recovery_policy();
+    debug!("Read error recovery policy: {}", policy);
+    let read_ahead = read_ahead_config();
+    debug!("Read-ahead: {}", read_ahead);
+
+    let density = forced_density();
+
+    let code_page = code_page();
+    if code_page != CodePage::default() {
+        debug!("Reading IBM text files as {}", code_page);
+    }
+
+    let serials = volume_serials();
+    if !serials.is_empty() {
+        debug!("Volume set: {}", serials.join(", "));
+    }
+
+    for mut drive in drives {
+        // Nothing to set the density of if the drive is empty
+        if let Some(density) = density
+            && drive.format().is_some()
+            && let Err(err) = drive.set_density(density).await
+        {
+            warn!(
+                device = drive.name(),
+                "Unable to set density to {}: {}", density, err
+            );
+        }
+
+        let mut info = BootInfo::drive(&drive);
+        let mut drive = ReadAhead::new(RecoveringTape::new(drive, policy), read_ahead);
+        let mut reels = ReelChanger::new(fb.clone(), serials.clone());
+
+        if let Some(payloads) = load_from_device(&mut drive, &mut reels, &mut info, code_page).await
+        {
+            return boot_payloads(payloads, info, fb).await;
+        }
+    }
+
+    for name in ESP_IMAGE_NAMES {
+        let path = format!("{ESP_IMAGE_DIR}\\{name}");
+        let Ok(image) = ImageTape::open(&path) else {
+            continue;
+        };
+        let mut info = BootInfo::image(&path);
+        let mut image = ReadAhead::new(RecoveringTape::new(image, policy), read_ahead);
+        let mut reels = ReelChanger::new(fb.clone(), serials.clone());
+
+        if let Some(payloads) = load_from_device(&mut image, &mut reels, &mut info, code_page).await
+        {
+            return boot_payloads(payloads, info, fb).await;
+        }
+    }
+
+    warn!("Nothing bootable found on tape, falling back to the ESP");
+    boot_from_esp(fb).await
+}
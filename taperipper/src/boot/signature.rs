@@ -0,0 +1,142 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// Making sure a tape was mastered by someone we trust before we boot it.
+//
+// Trusted keys are Ed25519 public keys, as comma separated hex, either baked in at build
+// time from `TAPERIPPER_TRUSTED_KEYS` in the environment, or enrolled in the
+// `TAPERIPPER_TRUSTED_KEYS` UEFI variable. If there are any at all, every tape has to carry
+// a signature by one of them, see `tape/format/signature.rs` for what is signed, and nothing
+// is booted off of the ESP.
+//
+// The variable has to be boot services only, e.g. `setvar -bs -nv` from the UEFI shell, one
+// that the OS can write to would let anything running on it enroll its own key.
+
+use core::fmt;
+
+use ed25519_dalek::VerifyingKey;
+use tracing::{debug, info, warn};
+
+use crate::{
+    platform,
+    tape::{
+        digest,
+        format::signature::{self, KEY_LEN, Signature, SignatureError},
+        loader::Payloads,
+    },
+};
+
+// The keys that were trusted when this was built
+const BUILTIN_KEYS: Option<&str> = option_env!("TAPERIPPER_TRUSTED_KEYS");
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrustError {
+    // We trust someone, but the tape isn't signed at all
+    Unsigned,
+    Malformed(SignatureError),
+    // Signed, but by someone we don't know
+    UntrustedKey([u8; KEY_LEN]),
+    // The signature doesn't check out against the payloads we loaded
+    BadSignature([u8; KEY_LEN]),
+}
+
+impl fmt::Display for TrustError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrustError::Unsigned => write!(f, "tape is not signed"),
+            TrustError::Malformed(err) => write!(f, "signature is malformed: {err}"),
+            TrustError::UntrustedKey(key) => {
+                write!(f, "tape is signed by untrusted key {}", digest::to_hex(key))
+            }
+            TrustError::BadSignature(key) => write!(
+                f,
+                "signature by {} does not match the payloads",
+                digest::to_hex(key)
+            ),
+        }
+    }
+}
+
+impl From<SignatureError> for TrustError {
+    fn from(value: SignatureError) -> Self {
+        TrustError::Malformed(value)
+    }
+}
+
+fn parse_keys(keys: &str, source: &str) -> Vec<[u8; KEY_LEN]> {
+    keys.split(',')
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+        .filter_map(|key| {
+            digest::parse_hex(key).or_else(|| {
+                warn!(
+                    "Trusted key '{}' from {} is not valid, ignoring it",
+                    key, source
+                );
+                None
+            })
+        })
+        .collect()
+}
+
+// Everyone whose signature we'll boot
+pub fn trusted_keys() -> Vec<[u8; KEY_LEN]> {
+    let mut keys = BUILTIN_KEYS
+        .map(|keys| parse_keys(keys, "the build"))
+        .unwrap_or_default();
+
+    if let Some(enrolled) = platform::uefi::variables::get_boot_only_str("TAPERIPPER_TRUSTED_KEYS")
+    {
+        keys.extend(parse_keys(&enrolled, "TAPERIPPER_TRUSTED_KEYS"));
+    }
+
+    keys
+}
+
+// Check the signature on the tape against the payloads we loaded
+//
+// Returns the key it was signed with, or nothing if we don't trust anyone and so didn't
+// check at all.
+pub fn check(payloads: &Payloads) -> Result<Option<[u8; KEY_LEN]>, TrustError> {
+    let keys = trusted_keys();
+    if keys.is_empty() {
+        if payloads.signature.is_some() {
+            warn!("Tape is signed, but there are no trusted keys to check it against");
+        } else {
+            debug!("No trusted keys, not checking signatures");
+        }
+        return Ok(None);
+    }
+
+    let record = payloads.signature.as_deref().ok_or(TrustError::Unsigned)?;
+    let signature = Signature::parse(record)?;
+    if !keys.contains(&signature.key) {
+        return Err(TrustError::UntrustedKey(signature.key));
+    }
+
+    let signed = signature::SIGNED_KINDS
+        .iter()
+        .filter_map(|&kind| {
+            payloads
+                .digest_of(kind)
+                .map(|digest| (kind, digest.len, digest.sha256))
+        })
+        .collect::<Vec<_>>();
+    let message = signature::signed_message(&signed);
+
+    // NOTE(aki): Strict verification, so a signature can't be mangled into a second valid one
+    VerifyingKey::from_bytes(&signature.key)
+        .and_then(|key| {
+            key.verify_strict(
+                &message,
+                &ed25519_dalek::Signature::from_bytes(&signature.signature),
+            )
+        })
+        .map_err(|_| TrustError::BadSignature(signature.key))?;
+
+    info!(
+        key = digest::to_hex(&signature.key),
+        payloads = signed.len(),
+        "Payloads are signed by a trusted key"
+    );
+
+    Ok(Some(signature.key))
+}
@@ -0,0 +1,191 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// Making sure what came off of the tape is what was put on it before we boot it.
+//
+// The loaders work out the digest of each payload as it streams in, and note down anything
+// on the tape that says what it should have been. On top of that the firmware can pin the
+// kernel and initrd with the `TAPERIPPER_{KERNEL,INITRD}_{SHA256,CRC32}` variables, which
+// are worth more than anything on the tape as they can't have been written by the same
+// thing that wrote the payloads.
+//
+// Like the trusted keys, they have to be boot services only, e.g. `setvar -bs -nv` from the
+// UEFI shell, or whatever we booted last time could pin its own replacement.
+
+use core::time::Duration;
+use std::sync::{Arc, RwLock};
+
+use maitake::time;
+use tracing::{error, info, warn};
+
+use crate::{
+    display::{framebuffer::Framebuffer, prompt},
+    platform,
+    tape::{
+        digest::{self, Digest, Expected},
+        format::manifest::PayloadKind,
+        loader::{PayloadDigest, Payloads},
+    },
+};
+
+// How long to leave the diagnostic up before giving up and letting the machine shut down
+pub const REFUSED_HOLD: Duration = Duration::from_mins(5);
+
+// A payload that didn't come out how something said it should have
+#[derive(Clone, Debug)]
+pub struct Mismatch {
+    pub kind: PayloadKind,
+    pub name: String,
+    pub expected: Expected,
+    pub actual: Digest,
+}
+
+// A hex digest out of a UEFI variable, if it's set, makes sense, and the OS can't write it
+fn hex_variable<const N: usize>(name: &str) -> Option<[u8; N]> {
+    platform::uefi::variables::get_boot_only_parsed(name, digest::parse_hex)
+}
+
+// What the firmware says a payload should come out as
+fn expected_from_variables(kind: PayloadKind) -> Option<Expected> {
+    let prefix = match kind {
+        PayloadKind::Kernel => "TAPERIPPER_KERNEL",
+        PayloadKind::Initrd => "TAPERIPPER_INITRD",
+        _ => return None,
+    };
+
+    let sha256 = hex_variable(&format!("{prefix}_SHA256"));
+    let crc32 = hex_variable(&format!("{prefix}_CRC32")).map(u32::from_be_bytes);
+
+    (sha256.is_some() || crc32.is_some()).then_some(Expected {
+        sha256,
+        crc32,
+        source: "UEFI variable",
+    })
+}
+
+// Check every payload we loaded against everything that says what it should be
+//
+// Returns every mismatch rather than stopping at the first, so the operator gets to see
+// the whole of what went wrong.
+pub fn verify(payloads: &mut Payloads) -> Result<(), Vec<Mismatch>> {
+    for kind in [PayloadKind::Kernel, PayloadKind::Initrd] {
+        let Some(expected) = expected_from_variables(kind) else {
+            continue;
+        };
+
+        let parts = payloads
+            .digests
+            .iter()
+            .filter(|payload| payload.kind == kind)
+            .count();
+
+        if parts == 1 {
+            let payload = payloads
+                .digests
+                .iter_mut()
+                .find(|payload| payload.kind == kind);
+            payload.unwrap().expected.push(expected);
+            continue;
+        }
+
+        // NOTE(aki): The variable is over the whole payload as it was on the tape, if it came
+        // off of it in more than one part (or not at all) we check all of it taken as one
+        let location = payloads
+            .digests
+            .iter()
+            .find(|payload| payload.kind == kind)
+            .map(|payload| payload.location)
+            .unwrap_or_default();
+        payloads.digests.push(PayloadDigest {
+            kind,
+            name: kind.to_string(),
+            location,
+            digest: payloads.digest_of(kind).unwrap_or(Digest::of(&[])),
+            expected: vec![expected],
+        });
+    }
+
+    let mut mismatches = Vec::new();
+
+    for payload in &payloads.digests {
+        info!(
+            name = payload.name.as_str(),
+            sha256 = payload.digest.sha256_hex(),
+            crc32 = format!("{:08x}", payload.digest.crc32),
+            len = payload.digest.len,
+            "Digest of {}",
+            payload.kind
+        );
+
+        if payload.expected.is_empty() {
+            warn!(
+                name = payload.name.as_str(),
+                "Nothing to check the {} against", payload.kind
+            );
+            continue;
+        }
+
+        for expected in &payload.expected {
+            if expected.matches(&payload.digest) {
+                info!(
+                    name = payload.name.as_str(),
+                    source = expected.source,
+                    "{} matches",
+                    payload.kind
+                );
+                continue;
+            }
+
+            error!(
+                name = payload.name.as_str(),
+                source = expected.source,
+                expected_sha256 = expected.sha256.map(|sha256| digest::to_hex(&sha256)),
+                expected_crc32 = expected.crc32.map(|crc32| format!("{crc32:08x}")),
+                "{} does not match",
+                payload.kind
+            );
+            mismatches.push(Mismatch {
+                kind: payload.kind,
+                name: payload.name.clone(),
+                expected: *expected,
+                actual: payload.digest,
+            });
+        }
+    }
+
+    if mismatches.is_empty() {
+        Ok(())
+    } else {
+        Err(mismatches)
+    }
+}
+
+// What went wrong, for putting up on the screen
+pub fn describe(mismatches: &[Mismatch]) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    for mismatch in mismatches {
+        lines.push(format!(
+            "The {} ({}) does not match the {}",
+            mismatch.kind, mismatch.name, mismatch.expected.source
+        ));
+        if let Some(sha256) = mismatch.expected.sha256 {
+            lines.push(format!("expected sha256 {}", digest::to_hex(&sha256)));
+            lines.push(format!("     got sha256 {}", mismatch.actual.sha256_hex()));
+        }
+        if let Some(crc32) = mismatch.expected.crc32 {
+            lines.push(format!("expected crc32 {:08x}", crc32));
+            lines.push(format!("     got crc32 {:08x}", mismatch.actual.crc32));
+        }
+        lines.push(String::new());
+    }
+    lines.push("The tape is damaged or has been tampered with, refusing to boot".to_string());
+
+    lines
+}
+
+// Put up what went wrong and hold it there, we're not booting any of this
+pub async fn refuse(fb: &Arc<RwLock<Framebuffer>>, title: &str, lines: &[String]) -> uefi::Error {
+    prompt::show(fb, title, lines);
+    time::sleep(REFUSED_HOLD).await;
+
+    uefi::Error::new(uefi::Status::SECURITY_VIOLATION, ())
+}
@@ -4,7 +4,7 @@ use core::{convert, fmt, ptr, slice};
 
 use uefi::{
     boot::ScopedProtocol,
-    proto::console::gop::{self, GraphicsOutput, PixelFormat},
+    proto::console::gop::{self, GraphicsOutput, PixelBitmask, PixelFormat},
 };
 
 use eg_bdf::BdfTextStyle;
@@ -32,6 +32,8 @@ pub struct Framebuffer {
     cursor_x: usize,
     cursor_y: usize,
     pix_format: PixelFormat,
+    // Where each channel is in a pixel, only for `PixelFormat::Bitmask`
+    pix_mask: Option<PixelBitmask>,
     fg_color: formatting::Color,
     bg_color: formatting::Color,
     style: formatting::Style,
@@ -73,6 +75,7 @@ impl Default for Framebuffer {
             cursor_x: 0,
             cursor_y: 0,
             pix_format: PixelFormat::Rgb,
+            pix_mask: None,
             fg_color: formatting::Color::Default,
             bg_color: formatting::Color::Black,
             style: formatting::Style::None,
@@ -103,6 +106,12 @@ impl Framebuffer {
         self.cursor_y = 0;
     }
 
+    // Move the text cursor to a character cell, anything written next starts from there
+    pub fn set_cursor(&mut self, col: usize, row: usize) {
+        self.cursor_x = col.min(self.width_chars().saturating_sub(1));
+        self.cursor_y = row.min(self.height_chars().saturating_sub(1));
+    }
+
     pub fn get_raw(&mut self) -> *mut u8 {
         self.raw_fb
     }
@@ -119,6 +128,18 @@ impl Framebuffer {
         self.y
     }
 
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    pub fn pixel_format(&self) -> PixelFormat {
+        self.pix_format
+    }
+
+    pub fn pixel_bitmask(&self) -> Option<PixelBitmask> {
+        self.pix_mask
+    }
+
     pub fn width_chars(&self) -> usize {
         self.x / Framebuffer::FONT.width()
     }
@@ -142,6 +163,7 @@ impl Framebuffer {
             cursor_x: 0,
             cursor_y: 0,
             pix_format: mode.pixel_format(),
+            pix_mask: mode.pixel_bitmask(),
             fg_color: formatting::Color::Default,
             bg_color: formatting::Color::Black,
             style: formatting::Style::None,
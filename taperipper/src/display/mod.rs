@@ -4,3 +4,4 @@ pub mod fmt;
 pub mod font;
 pub mod formatting;
 pub mod framebuffer;
+pub mod prompt;
@@ -0,0 +1,58 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// Full-screen prompts, for when we need someone to go and do something.
+//
+// There is no input to speak of this early, so a prompt is just a screen of text that stays
+// up until whatever we're waiting on happens, the log carries on from a clean screen after.
+
+use core::fmt::Write;
+use std::sync::{Arc, RwLock};
+
+use crate::{
+    display::{formatting::SetFormatting, framebuffer::Framebuffer},
+    tape::volume::Operator,
+};
+
+// Clear the screen and put `title` and `lines` up in the middle of it
+//
+// Does nothing if there is no framebuffer, the caller should have logged it anyway.
+pub fn show(fb: &Arc<RwLock<Framebuffer>>, title: &str, lines: &[String]) {
+    let mut fb = fb.write().unwrap();
+    if !fb.is_valid() {
+        return;
+    }
+
+    fb.clear_screen();
+
+    let width = fb.width_chars();
+    // A blank line between the title and the rest
+    let height = lines.len() + 2;
+    let mut row = fb.height_chars().saturating_sub(height) / 3;
+
+    fb.set_cursor(width.saturating_sub(title.len()) / 2, row);
+    let _ = fb.with_bold().write_str(title);
+    row += 2;
+
+    for line in lines {
+        fb.set_cursor(width.saturating_sub(line.len()) / 2, row);
+        let _ = fb.write_str(line);
+        row += 1;
+    }
+}
+
+// Take a prompt down, leaving the log to start again from the top
+pub fn dismiss(fb: &Arc<RwLock<Framebuffer>>) {
+    let mut fb = fb.write().unwrap();
+    if fb.is_valid() {
+        fb.clear_screen();
+    }
+}
+
+impl Operator for Arc<RwLock<Framebuffer>> {
+    fn show(&self, title: &str, lines: &[String]) {
+        show(self, title, lines);
+    }
+
+    fn dismiss(&self) {
+        dismiss(self);
+    }
+}
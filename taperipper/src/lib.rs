@@ -0,0 +1,80 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// The parts of taperipper that don't need the firmware, so they can be tested on the host.
+//
+// NOTE(aki): The bootloader doesn't link against this, `main.rs` has its own module tree and
+// these files are built into both. The tree here mirrors it, just without anything that has
+// to talk to the firmware, so paths inside of these files work the same either way.
+//
+// The tests are run with `cargo test --lib --target x86_64-unknown-linux-gnu`.
+
+pub mod boot {
+    pub mod initramfs {
+        mod layer;
+
+        pub use layer::Layer;
+    }
+}
+
+pub mod display {
+    pub mod fmt;
+}
+
+pub mod platform {
+    pub mod uefi {
+        pub mod scsi;
+        pub mod usb;
+    }
+}
+
+pub mod tape {
+    pub mod decompress;
+
+    pub mod device {
+        mod error;
+        mod io;
+
+        pub use error::DeviceError;
+        pub use io::{DeviceStatus, ReadOutcome, TapeDevice, TapePosition};
+    }
+
+    pub mod format {
+        pub mod ansi;
+        pub mod deblock;
+        pub mod ebcdic;
+        pub mod ibm;
+        pub mod manifest;
+        pub mod tar;
+    }
+
+    pub mod digest;
+
+    pub mod image {
+        pub mod aws;
+        mod record;
+        pub mod simh;
+
+        pub(crate) use record::{le32, medium_error};
+    }
+
+    pub mod recovery;
+    pub mod scsi;
+    pub mod stream;
+    #[cfg(test)]
+    mod testing;
+
+    pub mod transport {
+        mod error;
+        mod execute;
+
+        pub mod squishy {
+            mod framing;
+
+            pub use framing::{BulkPipe, Endpoint, PipeError, SquishyTransport};
+        }
+
+        pub use error::TransportError;
+        pub use execute::{Response, Transport, request_sense};
+    }
+
+    pub mod volume;
+}
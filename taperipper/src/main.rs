@@ -8,7 +8,6 @@
     allocator_api
 )]
 
-use maitake::time;
 use std::{
     panic,
     str::FromStr,
@@ -19,16 +18,18 @@ use tracing_core::LevelFilter;
 use tracing_subscriber::{Layer, filter::Targets, layer::SubscriberExt, util::SubscriberInitExt};
 use uefi::system;
 
+mod boot;
 #[cfg(feature = "stack-unwinding")]
 mod debug;
 mod display;
 mod log;
 mod platform;
 mod runtime;
+mod tape;
 
 #[cfg(feature = "stack-unwinding")]
 use crate::debug::info;
-use crate::display::framebuffer::Framebuffer;
+use crate::{display::framebuffer::Framebuffer, tape::device::TapeDevice};
 
 #[cfg(debug_assertions)]
 const DEFAULT_LOG_LEVEL: tracing::Level = tracing::Level::DEBUG;
@@ -141,23 +142,32 @@ fn main() {
 
     let mut executor = runtime::init();
 
-    runtime::spawn(async {
-        loop {
-            time::sleep(time::Duration::from_millis(700)).await;
-            debug!("Hello everynyan!");
+    let boot_fb = fb.clone();
+    runtime::spawn(async move {
+        // NOTE(aki): Opening a drive waits on it, so the tape part of the startup info has to
+        // wait for the executor
+        let drives = boot::open_drives().await;
+
+        for drive in &drives {
+            let inquiry = drive.inquiry().map(|inquiry| inquiry.to_string());
+            debug!(
+                "Tape Drive: {} ({})",
+                drive.name(),
+                inquiry.as_deref().unwrap_or("unknown")
+            );
+            match drive.format() {
+                Some(format) => debug!("Tape Format: {}", format),
+                None => debug!("Tape Format: no reel mounted"),
+            }
         }
-    });
-
-    runtime::spawn(async {
-        loop {
-            time::sleep(time::Duration::from_millis(750)).await;
-            debug!("Meow");
+        if let Some(density) = boot::forced_density() {
+            debug!("Tape Density: {} (forced)", density);
         }
-    });
 
-    runtime::spawn(async {
-        time::sleep(time::Duration::from_secs(60)).await;
-        panic!("AWAWAWAWAWAW");
+        let err = boot::boot_from_tape(&boot_fb, drives).await;
+        error!("Unable to boot kernel: {:?}", err.status());
+
+        platform::uefi::system::shutdown_now();
     });
 
     executor.run();
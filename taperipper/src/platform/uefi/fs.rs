@@ -0,0 +1,34 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use tracing::{debug, warn};
+use uefi::{CString16, Status, boot, fs};
+
+// Read a whole file off of the filesystem we were loaded from
+pub fn read(path: &str) -> Result<Vec<u8>, uefi::Error> {
+    let file_path =
+        CString16::try_from(path).map_err(|_| uefi::Error::new(Status::INVALID_PARAMETER, ()))?;
+
+    let fs = boot::get_image_file_system(boot::image_handle())?;
+    let mut fs = fs::FileSystem::new(fs);
+
+    debug!("Reading {} from the ESP", path);
+
+    fs.read(&*file_path).map_err(|err| {
+        warn!("Unable to read {}: {:?}", path, err);
+        uefi::Error::new(Status::NOT_FOUND, ())
+    })
+}
+
+// Check if there is anything at `path` on the filesystem we were loaded from
+pub fn exists(path: &str) -> bool {
+    let Ok(file_path) = CString16::try_from(path) else {
+        return false;
+    };
+
+    let Ok(fs) = boot::get_image_file_system(boot::image_handle()) else {
+        return false;
+    };
+    let mut fs = fs::FileSystem::new(fs);
+
+    fs.try_exists(&*file_path).unwrap_or(false)
+}
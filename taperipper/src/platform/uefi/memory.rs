@@ -0,0 +1,56 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use core::ptr::NonNull;
+
+use tracing::{debug, trace};
+use uefi::{
+    boot::{self, AllocateType},
+    mem::memory_map::{MemoryMap, MemoryMapOwned, MemoryType},
+};
+
+pub const PAGE_SIZE: usize = 4096;
+
+pub fn memory_map() -> Result<MemoryMapOwned, uefi::Error> {
+    boot::memory_map(MemoryType::LOADER_DATA)
+}
+
+pub fn dump_memory_map() {
+    let Ok(mmap) = memory_map() else {
+        return;
+    };
+
+    trace!("UEFI memory map ({} entries):", mmap.len());
+    for desc in mmap.entries() {
+        trace!(
+            " * {:#018x}-{:#018x} {:?}",
+            desc.phys_start,
+            desc.phys_start + (desc.page_count * PAGE_SIZE as u64),
+            desc.ty
+        );
+    }
+}
+
+// Allocate `size` bytes worth of zeroed pages as `LOADER_DATA`
+pub fn allocate_pages(alloc_type: AllocateType, size: usize) -> Result<NonNull<u8>, uefi::Error> {
+    let pages = size.div_ceil(PAGE_SIZE);
+    let ptr = boot::allocate_pages(alloc_type, MemoryType::LOADER_DATA, pages)?;
+
+    unsafe { ptr.write_bytes(0, pages * PAGE_SIZE) };
+
+    trace!(
+        "Allocated {} pages at {:#018x}",
+        pages,
+        ptr.as_ptr() as usize
+    );
+
+    Ok(ptr)
+}
+
+// Leave boot services behind, after this there is no more firmware to lean on,
+// nor any logging, as both of the consoles need boot services.
+//
+// SAFETY: Nothing that touches boot services can be called after this
+pub unsafe fn exit_boot_services() -> MemoryMapOwned {
+    debug!("Exiting boot services");
+    unsafe { boot::exit_boot_services(MemoryType::LOADER_DATA) }
+}
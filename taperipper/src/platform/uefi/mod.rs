@@ -1,13 +1,22 @@
 // SPDX-License-Identifier: BSD-3-Clause
 
 use std::os::uefi as uefi_std;
-use uefi::{Handle, boot, proto, table};
+use uefi::{
+    Handle,
+    boot::{self, OpenProtocolAttributes, OpenProtocolParams},
+    proto, table,
+};
 
+pub mod fs;
 pub mod image;
+pub mod memory;
 pub mod output;
+pub mod scsi;
 pub mod system;
 pub mod tables;
 pub mod time;
+#[cfg(feature = "squishy")]
+pub mod usb;
 pub mod variables;
 
 pub fn init_uefi() {
@@ -40,3 +49,32 @@ where
 {
     boot::get_handle_for_protocol::<P>().and_then(|hndl| boot::open_protocol_exclusive::<P>(hndl))
 }
+
+// Get every handle that has `P` installed on it
+pub fn get_proto_handles<P>() -> Result<Vec<Handle>, uefi::Error>
+where
+    P: proto::Protocol,
+{
+    boot::find_handles::<P>()
+}
+
+// Open `P` on a specific handle without disconnecting whatever driver is managing it
+//
+// NOTE(aki): This is for things like the SCSI pass-thru, where opening it exclusively
+// would tear down the SCSI bus driver and with it any disks on the same controller, which
+// might just be the ESP we were loaded from.
+pub fn get_proto_shared<P>(handle: Handle) -> Result<boot::ScopedProtocol<P>, uefi::Error>
+where
+    P: proto::Protocol,
+{
+    unsafe {
+        boot::open_protocol::<P>(
+            OpenProtocolParams {
+                handle,
+                agent: boot::image_handle(),
+                controller: None,
+            },
+            OpenProtocolAttributes::GetProtocol,
+        )
+    }
+}
@@ -0,0 +1,201 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// Bindings for `EFI_EXT_SCSI_PASS_THRU_PROTOCOL`.
+//
+// This is what firmware SCSI HBA drivers (virtio-scsi, LSI, mpt, etc) expose, and it lets
+// us throw raw CDBs at anything hanging off of them, which is exactly what we need for a
+// tape drive, as nothing in the firmware knows what to do with one.
+//
+// see: https://uefi.org/specs/UEFI/2.10/15_SCSI_Driver_Models_and_Bus_Support.html#extended-scsi-pass-thru-protocol
+
+use core::{ffi::c_void, ptr};
+
+use uefi::{Event, Status, proto::unsafe_protocol};
+
+pub const TARGET_MAX_BYTES: usize = 16;
+
+// Mode attributes
+pub const ATTRIBUTES_LOGICAL: u32 = 0x0002;
+pub const ATTRIBUTES_NONBLOCKING: u32 = 0x0004;
+
+// Request packet data directions
+pub const DATA_DIRECTION_READ: u8 = 0;
+pub const DATA_DIRECTION_WRITE: u8 = 1;
+
+// Host adapter status codes
+pub const HOST_ADAPTER_OK: u8 = 0x00;
+pub const HOST_ADAPTER_TIMEOUT_COMMAND: u8 = 0x09;
+pub const HOST_ADAPTER_TIMEOUT: u8 = 0x0B;
+pub const HOST_ADAPTER_MESSAGE_REJECT: u8 = 0x0D;
+pub const HOST_ADAPTER_BUS_RESET: u8 = 0x0E;
+pub const HOST_ADAPTER_PARITY_ERROR: u8 = 0x0F;
+pub const HOST_ADAPTER_REQUEST_SENSE_FAILED: u8 = 0x10;
+pub const HOST_ADAPTER_SELECTION_TIMEOUT: u8 = 0x11;
+pub const HOST_ADAPTER_DATA_OVERRUN_UNDERRUN: u8 = 0x12;
+pub const HOST_ADAPTER_BUS_FREE: u8 = 0x13;
+pub const HOST_ADAPTER_PHASE_ERROR: u8 = 0x14;
+
+// A target ID of all 0xFF's starts the target/LUN enumeration
+pub const TARGET_ENUMERATE: [u8; TARGET_MAX_BYTES] = [0xFF; TARGET_MAX_BYTES];
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ExtScsiPassThruMode {
+    pub adapter_id: u32,
+    pub attributes: u32,
+    pub io_align: u32,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct ScsiRequestPacket {
+    // In units of 100ns, zero means wait forever
+    pub timeout: u64,
+    pub in_data_buffer: *mut c_void,
+    pub out_data_buffer: *mut c_void,
+    pub sense_data: *mut c_void,
+    pub cdb: *mut c_void,
+    pub in_transfer_length: u32,
+    pub out_transfer_length: u32,
+    pub cdb_length: u8,
+    pub data_direction: u8,
+    pub host_adapter_status: u8,
+    pub target_status: u8,
+    pub sense_data_length: u8,
+}
+
+impl Default for ScsiRequestPacket {
+    fn default() -> Self {
+        Self {
+            timeout: 0,
+            in_data_buffer: ptr::null_mut(),
+            out_data_buffer: ptr::null_mut(),
+            sense_data: ptr::null_mut(),
+            cdb: ptr::null_mut(),
+            in_transfer_length: 0,
+            out_transfer_length: 0,
+            cdb_length: 0,
+            data_direction: DATA_DIRECTION_READ,
+            host_adapter_status: 0,
+            target_status: 0,
+            sense_data_length: 0,
+        }
+    }
+}
+
+#[repr(C)]
+#[unsafe_protocol("143b7632-b81b-4cb7-abd3-b625a5b9bffe")]
+pub struct ExtScsiPassThru {
+    mode: *const ExtScsiPassThruMode,
+    pass_thru: unsafe extern "efiapi" fn(
+        this: *mut ExtScsiPassThru,
+        target: *const u8,
+        lun: u64,
+        packet: *mut ScsiRequestPacket,
+        event: *mut c_void,
+    ) -> Status,
+    get_next_target_lun: unsafe extern "efiapi" fn(
+        this: *const ExtScsiPassThru,
+        target: *mut *mut u8,
+        lun: *mut u64,
+    ) -> Status,
+    build_device_path: unsafe extern "efiapi" fn(
+        this: *const ExtScsiPassThru,
+        target: *const u8,
+        lun: u64,
+        device_path: *mut *mut c_void,
+    ) -> Status,
+    get_target_lun: unsafe extern "efiapi" fn(
+        this: *const ExtScsiPassThru,
+        device_path: *const c_void,
+        target: *mut *mut u8,
+        lun: *mut u64,
+    ) -> Status,
+    reset_channel: unsafe extern "efiapi" fn(this: *mut ExtScsiPassThru) -> Status,
+    reset_target_lun: unsafe extern "efiapi" fn(
+        this: *mut ExtScsiPassThru,
+        target: *const u8,
+        lun: u64,
+    ) -> Status,
+    get_next_target:
+        unsafe extern "efiapi" fn(this: *const ExtScsiPassThru, target: *mut *mut u8) -> Status,
+}
+
+impl ExtScsiPassThru {
+    pub fn mode(&self) -> ExtScsiPassThruMode {
+        unsafe { *self.mode }
+    }
+
+    // The alignment any data buffers handed to the controller must have
+    pub fn io_align(&self) -> usize {
+        (self.mode().io_align as usize).max(1)
+    }
+
+    // Every target/LUN pair on this channel that the controller knows about
+    pub fn targets(&self) -> Vec<([u8; TARGET_MAX_BYTES], u64)> {
+        let mut targets = Vec::new();
+        let mut target = TARGET_ENUMERATE;
+        let mut lun = 0u64;
+
+        loop {
+            let mut target_ptr = target.as_mut_ptr();
+            let status = unsafe {
+                (self.get_next_target_lun)(ptr::from_ref(self), &raw mut target_ptr, &raw mut lun)
+            };
+
+            // NOT_FOUND is how the controller tells us we've seen them all
+            if status.is_error() {
+                break;
+            }
+
+            targets.push((target, lun));
+        }
+
+        targets
+    }
+
+    /// Send a request packet to the given target/LUN
+    ///
+    /// Without an `event` this blocks until the command is done. With one, and if the
+    /// controller has `ATTRIBUTES_NONBLOCKING`, it returns as soon as the command is queued
+    /// and `event` is signaled once it's done.
+    ///
+    /// # Safety
+    /// All of the buffers in `packet` must be valid for the lengths given and aligned to
+    /// `io_align()`. If the command was queued, `packet` and the buffers in it must stay put
+    /// until `event` is signaled.
+    pub unsafe fn pass_thru(
+        &mut self,
+        target: &[u8; TARGET_MAX_BYTES],
+        lun: u64,
+        packet: &mut ScsiRequestPacket,
+        event: Option<&Event>,
+    ) -> Status {
+        unsafe {
+            (self.pass_thru)(
+                ptr::from_mut(self),
+                target.as_ptr(),
+                lun,
+                ptr::from_mut(packet),
+                event.map_or(ptr::null_mut(), Event::as_ptr),
+            )
+        }
+    }
+}
+
+// Human-readable host adapter status
+pub fn describe_host_adapter_status(status: u8) -> &'static str {
+    match status {
+        HOST_ADAPTER_OK => "ok",
+        HOST_ADAPTER_TIMEOUT_COMMAND => "command timeout",
+        HOST_ADAPTER_TIMEOUT => "timeout",
+        HOST_ADAPTER_MESSAGE_REJECT => "message rejected",
+        HOST_ADAPTER_BUS_RESET => "bus reset",
+        HOST_ADAPTER_PARITY_ERROR => "parity error",
+        HOST_ADAPTER_REQUEST_SENSE_FAILED => "request sense failed",
+        HOST_ADAPTER_SELECTION_TIMEOUT => "selection timeout",
+        HOST_ADAPTER_DATA_OVERRUN_UNDERRUN => "data overrun/underrun",
+        HOST_ADAPTER_BUS_FREE => "unexpected bus free",
+        HOST_ADAPTER_PHASE_ERROR => "phase error",
+        _ => "other",
+    }
+}
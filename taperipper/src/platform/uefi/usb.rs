@@ -0,0 +1,247 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// Bindings for `EFI_USB_IO_PROTOCOL`.
+//
+// The USB bus driver installs one of these on a handle for every interface of every
+// device it finds, which lets us talk to devices the firmware has no class driver for.
+//
+// see: https://uefi.org/specs/UEFI/2.10/17_Protocols_USB_Support.html#efi-usb-io-protocol
+
+use core::{ffi::c_void, mem::MaybeUninit, ptr};
+
+use uefi::{Status, StatusExt, proto::unsafe_protocol};
+
+// Transfer result bits
+pub const USB_ERR_NOTEXECUTE: u32 = 0x0001;
+pub const USB_ERR_STALL: u32 = 0x0002;
+pub const USB_ERR_BUFFER: u32 = 0x0004;
+pub const USB_ERR_BABBLE: u32 = 0x0008;
+pub const USB_ERR_NAK: u32 = 0x0010;
+pub const USB_ERR_CRC: u32 = 0x0020;
+pub const USB_ERR_TIMEOUT: u32 = 0x0040;
+pub const USB_ERR_BITSTUFF: u32 = 0x0080;
+pub const USB_ERR_SYSTEM: u32 = 0x0100;
+
+// Standard requests
+pub const REQUEST_CLEAR_FEATURE: u8 = 0x01;
+pub const FEATURE_ENDPOINT_HALT: u16 = 0x0000;
+pub const REQUEST_TYPE_ENDPOINT_OUT: u8 = 0x02;
+
+pub const ENDPOINT_DIR_IN: u8 = 0x80;
+pub const ENDPOINT_TYPE_MASK: u8 = 0x03;
+pub const ENDPOINT_TYPE_BULK: u8 = 0x02;
+
+pub const CLASS_VENDOR_SPECIFIC: u8 = 0xFF;
+
+// `EFI_USB_DATA_DIRECTION`, only requests without a data stage are ever sent
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum DataDirection {
+    None = 2,
+}
+
+#[repr(C, packed)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DeviceRequest {
+    pub request_type: u8,
+    pub request: u8,
+    pub value: u16,
+    pub index: u16,
+    pub length: u16,
+}
+
+#[repr(C, packed)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DeviceDescriptor {
+    pub length: u8,
+    pub descriptor_type: u8,
+    pub bcd_usb: u16,
+    pub device_class: u8,
+    pub device_subclass: u8,
+    pub device_protocol: u8,
+    pub max_packet_size0: u8,
+    pub id_vendor: u16,
+    pub id_product: u16,
+    pub bcd_device: u16,
+    pub str_manufacturer: u8,
+    pub str_product: u8,
+    pub str_serial_number: u8,
+    pub num_configurations: u8,
+}
+
+#[repr(C, packed)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct InterfaceDescriptor {
+    pub length: u8,
+    pub descriptor_type: u8,
+    pub interface_number: u8,
+    pub alternate_setting: u8,
+    pub num_endpoints: u8,
+    pub interface_class: u8,
+    pub interface_subclass: u8,
+    pub interface_protocol: u8,
+    pub interface: u8,
+}
+
+#[repr(C, packed)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EndpointDescriptor {
+    pub length: u8,
+    pub descriptor_type: u8,
+    pub endpoint_address: u8,
+    pub attributes: u8,
+    pub max_packet_size: u16,
+    pub interval: u8,
+}
+
+impl EndpointDescriptor {
+    pub fn is_bulk(&self) -> bool {
+        (self.attributes & ENDPOINT_TYPE_MASK) == ENDPOINT_TYPE_BULK
+    }
+
+    pub fn is_in(&self) -> bool {
+        (self.endpoint_address & ENDPOINT_DIR_IN) != 0
+    }
+}
+
+#[repr(C)]
+#[unsafe_protocol("2b2f68d6-0cd2-44cf-8e8b-bba20b1b5b75")]
+pub struct UsbIo {
+    control_transfer: unsafe extern "efiapi" fn(
+        this: *mut UsbIo,
+        request: *mut DeviceRequest,
+        direction: DataDirection,
+        timeout: u32,
+        data: *mut c_void,
+        data_length: usize,
+        status: *mut u32,
+    ) -> Status,
+    bulk_transfer: unsafe extern "efiapi" fn(
+        this: *mut UsbIo,
+        endpoint: u8,
+        data: *mut c_void,
+        data_length: *mut usize,
+        timeout: usize,
+        status: *mut u32,
+    ) -> Status,
+    async_interrupt_transfer: *const c_void,
+    sync_interrupt_transfer: *const c_void,
+    isochronous_transfer: *const c_void,
+    async_isochronous_transfer: *const c_void,
+    get_device_descriptor:
+        unsafe extern "efiapi" fn(this: *mut UsbIo, desc: *mut DeviceDescriptor) -> Status,
+    get_config_descriptor: *const c_void,
+    get_interface_descriptor:
+        unsafe extern "efiapi" fn(this: *mut UsbIo, desc: *mut InterfaceDescriptor) -> Status,
+    get_endpoint_descriptor: unsafe extern "efiapi" fn(
+        this: *mut UsbIo,
+        index: u8,
+        desc: *mut EndpointDescriptor,
+    ) -> Status,
+    get_string_descriptor: *const c_void,
+    get_supported_languages: *const c_void,
+    port_reset: unsafe extern "efiapi" fn(this: *mut UsbIo) -> Status,
+}
+
+impl UsbIo {
+    pub fn device_descriptor(&mut self) -> Result<DeviceDescriptor, uefi::Error> {
+        let mut desc = MaybeUninit::<DeviceDescriptor>::zeroed();
+        unsafe { (self.get_device_descriptor)(ptr::from_mut(self), desc.as_mut_ptr()) }
+            .to_result_with_val(|| unsafe { desc.assume_init() })
+    }
+
+    pub fn interface_descriptor(&mut self) -> Result<InterfaceDescriptor, uefi::Error> {
+        let mut desc = MaybeUninit::<InterfaceDescriptor>::zeroed();
+        unsafe { (self.get_interface_descriptor)(ptr::from_mut(self), desc.as_mut_ptr()) }
+            .to_result_with_val(|| unsafe { desc.assume_init() })
+    }
+
+    // `index` is the index of the endpoint in the interface, not its address
+    pub fn endpoint_descriptor(&mut self, index: u8) -> Result<EndpointDescriptor, uefi::Error> {
+        let mut desc = MaybeUninit::<EndpointDescriptor>::zeroed();
+        unsafe { (self.get_endpoint_descriptor)(ptr::from_mut(self), index, desc.as_mut_ptr()) }
+            .to_result_with_val(|| unsafe { desc.assume_init() })
+    }
+
+    // Returns the number of bytes moved along with the USB transfer result bits
+    //
+    // NOTE(aki): The transfer result is returned even when the status is an error, as
+    // that's the only way to tell a stall apart from everything else.
+    pub fn bulk_transfer(
+        &mut self,
+        endpoint: u8,
+        data: *mut u8,
+        len: usize,
+        timeout_ms: usize,
+    ) -> (Status, usize, u32) {
+        let mut data_length = len;
+        let mut result = 0u32;
+
+        let status = unsafe {
+            (self.bulk_transfer)(
+                ptr::from_mut(self),
+                endpoint,
+                data.cast(),
+                &raw mut data_length,
+                timeout_ms,
+                &raw mut result,
+            )
+        };
+
+        (status, data_length, result)
+    }
+
+    // Send a request that has no data stage, returns the USB transfer result bits
+    pub fn control_transfer(
+        &mut self,
+        mut request: DeviceRequest,
+        timeout_ms: u32,
+    ) -> (Status, u32) {
+        let mut result = 0u32;
+
+        let status = unsafe {
+            (self.control_transfer)(
+                ptr::from_mut(self),
+                &raw mut request,
+                DataDirection::None,
+                timeout_ms,
+                ptr::null_mut(),
+                0,
+                &raw mut result,
+            )
+        };
+
+        (status, result)
+    }
+
+    // Clear a halt (stall) condition on the given endpoint
+    pub fn clear_halt(&mut self, endpoint: u8, timeout_ms: u32) -> (Status, u32) {
+        let request = DeviceRequest {
+            request_type: REQUEST_TYPE_ENDPOINT_OUT,
+            request: REQUEST_CLEAR_FEATURE,
+            value: FEATURE_ENDPOINT_HALT,
+            index: endpoint as u16,
+            length: 0,
+        };
+
+        self.control_transfer(request, timeout_ms)
+    }
+}
+
+// Human-readable USB transfer result
+//
+// NOTE(aki): More than one bit can be set, the lowest one is as good a reason as any
+pub fn describe_transfer_result(result: u32) -> &'static str {
+    match result.isolate_lowest_one() {
+        0 => "ok",
+        USB_ERR_NOTEXECUTE => "not executed",
+        USB_ERR_STALL => "stalled",
+        USB_ERR_BUFFER => "buffer error",
+        USB_ERR_BABBLE => "babble",
+        USB_ERR_NAK => "NAK",
+        USB_ERR_CRC => "CRC error",
+        USB_ERR_TIMEOUT => "timeout",
+        USB_ERR_BITSTUFF => "bit stuffing error",
+        USB_ERR_SYSTEM => "system error",
+        _ => "unknown error",
+    }
+}
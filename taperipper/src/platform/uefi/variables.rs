@@ -1,5 +1,6 @@
 // SPDX-License-Identifier: BSD-3-Clause
 
+use tracing::warn;
 use uefi::{
     CStr16, Guid, guid,
     runtime::{self, VariableAttributes, VariableVendor},
@@ -8,23 +9,85 @@ use uefi::{
 pub const TAPERIPPER_UEFI_NAMESPACE: Guid = guid!("70a40a42-5ee6-4620-ad7c-97567d038a20");
 pub const TAPERIPPER_UEFI_VENDOR: VariableVendor = VariableVendor(TAPERIPPER_UEFI_NAMESPACE);
 
-pub fn get(name: &str) -> Option<Box<[u8]>> {
+fn encode(name: &str) -> Vec<u16> {
     let mut enc = name.encode_utf16().collect::<Vec<_>>();
     enc.push(0x00);
+    enc
+}
 
+fn get_with_attributes(name: &str) -> Option<(Box<[u8]>, VariableAttributes)> {
+    let enc = encode(name);
     let var_name = CStr16::from_u16_until_nul(enc.as_slice()).ok()?;
 
-    if let Ok(var) = runtime::get_variable_boxed(var_name, &TAPERIPPER_UEFI_VENDOR) {
-        Some(var.0)
-    } else {
+    runtime::get_variable_boxed(var_name, &TAPERIPPER_UEFI_VENDOR).ok()
+}
+
+pub fn get(name: &str) -> Option<Box<[u8]>> {
+    get_with_attributes(name).map(|(data, _)| data)
+}
+
+// A variable as a string, less any trailing NULs, if it's set and valid UTF-8
+pub fn get_str(name: &str) -> Option<String> {
+    get(name).and_then(|var| to_str(name, &var))
+}
+
+// Like `get_str`, but only for variables that can't be written once the OS is up
+//
+// NOTE(aki): Anything with runtime access can be set from userspace on every OS worth
+// booting, so it is as good as missing for anything we have to be able to trust
+pub fn get_boot_only_str(name: &str) -> Option<String> {
+    let (data, attrs) = get_with_attributes(name)?;
+
+    if attrs.contains(VariableAttributes::RUNTIME_ACCESS) {
+        warn!(name, "Variable can be written from the OS, ignoring it");
+        return None;
+    }
+
+    to_str(name, &data)
+}
+
+// A variable run through `parse`, if it's set and makes sense
+pub fn get_parsed<T>(name: &str, parse: impl FnOnce(&str) -> Option<T>) -> Option<T> {
+    parse_str(name, &get_str(name)?, parse)
+}
+
+// Like `get_parsed`, but only for variables that can't be written once the OS is up
+pub fn get_boot_only_parsed<T>(name: &str, parse: impl FnOnce(&str) -> Option<T>) -> Option<T> {
+    parse_str(name, &get_boot_only_str(name)?, parse)
+}
+
+fn parse_str<T>(name: &str, value: &str, parse: impl FnOnce(&str) -> Option<T>) -> Option<T> {
+    parse(value).or_else(|| {
+        warn!(name, value, "Variable is not valid, ignoring it");
         None
+    })
+}
+
+// A variable that is a comma separated list, with empty entries left out
+pub fn get_list(name: &str) -> Vec<String> {
+    get_str(name)
+        .map(|list| {
+            list.split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn to_str(name: &str, data: &[u8]) -> Option<String> {
+    match str::from_utf8(data) {
+        Ok(value) => Some(value.trim_end_matches('\0').to_string()),
+        Err(_) => {
+            warn!(name, "Variable is not valid UTF-8, ignoring it");
+            None
+        }
     }
 }
 
 pub fn set(name: &str, data: &[u8]) {
-    let mut enc = name.encode_utf16().collect::<Vec<_>>();
-    enc.push(0x00);
-
+    let enc = encode(name);
     let var_name = CStr16::from_u16_until_nul(enc.as_slice()).unwrap();
 
     runtime::set_variable(
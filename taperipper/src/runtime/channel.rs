@@ -0,0 +1,98 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// A bare-bones channel for handing things between tasks.
+//
+// maitake-sync doesn't have a channel type, just the locks, `WaitQueue`, `WaitCell` and
+// `Semaphore` to build one out of, so this is a fixed size ring behind its `Mutex` with a
+// `WaitCell` for the receiving end to park on.
+//
+// There is only ever one sender and one receiver, and the sender never waits. The ring is
+// sized up front for everything that will ever be in it at once, which suits things that
+// hand a fixed set of buffers back and forth, and sending to a full one is a bug.
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use maitake_sync::{WaitCell, blocking::Mutex};
+
+struct Shared<T> {
+    // NOTE(aki): Never pushed past the capacity it was made with, so it never reallocates
+    ring: Mutex<VecDeque<T>>,
+    capacity: usize,
+    waiting: WaitCell,
+    closed: AtomicBool,
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+// A channel with room for `capacity` things at once
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        ring: Mutex::new(VecDeque::with_capacity(capacity)),
+        capacity,
+        waiting: WaitCell::new(),
+        closed: AtomicBool::new(false),
+    });
+
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+impl<T> Sender<T> {
+    pub fn send(&self, value: T) {
+        {
+            let mut ring = self.shared.ring.lock();
+            assert!(ring.len() < self.shared.capacity, "Sent to a full channel");
+            ring.push_back(value);
+        }
+
+        self.shared.waiting.wake();
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Release);
+        self.shared.waiting.close();
+    }
+}
+
+impl<T> Receiver<T> {
+    // Wait for the next value, returns `None` once the sender is gone and the ring is empty
+    pub async fn recv(&self) -> Option<T> {
+        // NOTE(aki): This registers with the cell before it looks in the ring, so a value sent
+        // in between still wakes us
+        match self.shared.waiting.wait_for_value(|| self.try_recv()).await {
+            Ok(value) => Some(value),
+            // Closed, but there may have been something sent just before it was
+            Err(_) => self.try_recv(),
+        }
+    }
+
+    pub fn try_recv(&self) -> Option<T> {
+        self.shared.ring.lock().pop_front()
+    }
+
+    // Whether the sender is gone, there may still be things left in the ring
+    pub fn is_closed(&self) -> bool {
+        self.shared.closed.load(Ordering::Acquire)
+    }
+
+    pub fn len(&self) -> usize {
+        self.shared.ring.lock().len()
+    }
+}
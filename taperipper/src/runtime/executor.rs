@@ -12,7 +12,7 @@ use rand_core::SeedableRng;
 use rand_xoshiro::Xoshiro256PlusPlus;
 use tracing::{debug, error, info, trace};
 
-use crate::runtime::{CORE_SCHED, RUNTIME, time};
+use crate::runtime::{CORE_SCHED, RUNTIME, io, time};
 
 pub struct CoreExecutor {
     sched: &'static StaticScheduler,
@@ -112,10 +112,11 @@ impl CoreExecutor {
     }
 
     fn tick(&mut self) -> bool {
-        // TODO(aki): Deal with per-core interrupts and IO bits
+        // TODO(aki): Deal with per-core interrupts
 
         let tck = self.sched.tick();
         time::timer().turn();
+        io::turn();
 
         if tck.has_remaining {
             return true;
@@ -0,0 +1,118 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// Futures for things that complete outside of the executor.
+//
+// UEFI doesn't give us interrupts, just events we can check on, so anything waiting on
+// one parks its waker here and the executor checks them all every time it ticks.
+
+use core::{
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+
+use maitake_sync::blocking::Mutex;
+use tracing::{trace, warn};
+use uefi::{Event, boot};
+
+// NOTE(aki): `Event` is just a pointer-sized handle, and it's only ever checked from
+// whichever core is running the executor, so passing them around is fine.
+struct PendingEvent {
+    event: Event,
+    waker: Waker,
+    // Checking an event un-signals it, so whoever checks it has to pass on that it fired
+    fired: Arc<AtomicBool>,
+}
+
+unsafe impl Send for PendingEvent {}
+
+static PENDING: Mutex<Vec<PendingEvent>> = Mutex::new(Vec::new());
+
+// Wake up everything waiting on an event that has been signaled
+//
+// Called from `CoreExecutor::tick`
+pub fn turn() {
+    let mut pending = PENDING.lock();
+    if pending.is_empty() {
+        return;
+    }
+
+    pending.retain(
+        |waiting| match boot::check_event(unsafe { waiting.event.unsafe_clone() }) {
+            Ok(false) => true,
+            Ok(true) => {
+                waiting.fired.store(true, Ordering::Release);
+                waiting.waker.wake_by_ref();
+                false
+            }
+            Err(err) => {
+                // Wake it anyway, the future will see the error when it checks again
+                warn!("Unable to check event: {:?}", err.status());
+                waiting.waker.wake_by_ref();
+                false
+            }
+        },
+    );
+}
+
+pub struct EventFuture {
+    event: Event,
+    fired: Arc<AtomicBool>,
+}
+
+unsafe impl Send for EventFuture {}
+
+impl EventFuture {
+    // Stop `turn` from checking on our behalf
+    fn forget(&self) {
+        PENDING
+            .lock()
+            .retain(|waiting| waiting.event.as_ptr() != self.event.as_ptr());
+    }
+}
+
+impl Future for EventFuture {
+    type Output = Result<(), uefi::Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.fired.load(Ordering::Acquire) {
+            return Poll::Ready(Ok(()));
+        }
+
+        // NOTE(aki): Only ever be waiting in the one place, or `turn` might eat the signal
+        // from under a check here
+        self.forget();
+
+        match boot::check_event(unsafe { self.event.unsafe_clone() }) {
+            Ok(true) => Poll::Ready(Ok(())),
+            Ok(false) => {
+                trace!("Waiting on event");
+                PENDING.lock().push(PendingEvent {
+                    event: unsafe { self.event.unsafe_clone() },
+                    waker: cx.waker().clone(),
+                    fired: self.fired.clone(),
+                });
+                Poll::Pending
+            }
+            Err(err) => Poll::Ready(Err(err)),
+        }
+    }
+}
+
+impl Drop for EventFuture {
+    fn drop(&mut self) {
+        self.forget();
+    }
+}
+
+// Wait for `event` to be signaled
+//
+// NOTE(aki): The event must not be a `NOTIFY_SIGNAL` event, `check_event` refuses those
+pub fn wait_for_event(event: Event) -> EventFuture {
+    EventFuture {
+        event,
+        fired: Arc::new(AtomicBool::new(false)),
+    }
+}
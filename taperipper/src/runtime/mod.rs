@@ -14,7 +14,9 @@ use maitake_sync::spin::InitOnce;
 
 use crate::platform::{local, smp};
 
+pub mod channel;
 pub mod executor;
+pub mod io;
 pub mod panic;
 pub mod time;
 
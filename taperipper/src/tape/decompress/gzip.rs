@@ -0,0 +1,170 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// gzip (RFC 1952), which is DEFLATE with a header and a CRC32 on the end.
+//
+// `miniz_oxide` does the actual inflating, we just deal with the wrapping around it.
+
+use miniz_oxide::{
+    DataFormat, MZError, MZFlush, MZStatus,
+    inflate::stream::{InflateState, inflate},
+};
+
+use super::{CHUNK_LEN, DecompressError, Format, Progress, grow};
+use crate::tape::digest::Crc32;
+
+pub const MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+const HEADER_LEN: usize = 10;
+const TRAILER_LEN: usize = 8;
+const METHOD_DEFLATE: u8 = 8;
+
+// Header flags
+const FHCRC: u8 = 0x02;
+const FEXTRA: u8 = 0x04;
+const FNAME: u8 = 0x08;
+const FCOMMENT: u8 = 0x10;
+const FRESERVED: u8 = 0xE0;
+
+const CORRUPT: DecompressError = DecompressError::Corrupt(Format::Gzip);
+
+enum State {
+    Header,
+    Body,
+    Trailer,
+}
+
+pub struct GzipDecoder {
+    state: State,
+    inflate: Box<InflateState>,
+    crc32: Crc32,
+    len: u64,
+}
+
+impl Default for GzipDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GzipDecoder {
+    pub fn new() -> Self {
+        Self {
+            state: State::Header,
+            inflate: InflateState::new_boxed(DataFormat::Raw),
+            crc32: Crc32::default(),
+            len: 0,
+        }
+    }
+
+    pub fn decode(
+        &mut self,
+        input: &[u8],
+        out: &mut Vec<u8>,
+        _last: bool,
+    ) -> Result<Progress, DecompressError> {
+        let mut consumed = 0;
+
+        loop {
+            let input = &input[consumed..];
+
+            match self.state {
+                State::Header => match header_len(input)? {
+                    Some(len) => {
+                        consumed += len;
+                        self.state = State::Body;
+                    }
+                    None => return Ok(Progress::more(consumed)),
+                },
+                State::Body => {
+                    let start = grow(out, CHUNK_LEN);
+                    // NOTE(aki): Never `MZFlush::Finish`, that wants all of the output to fit in
+                    // the one buffer if it's the first call
+                    let result =
+                        inflate(&mut self.inflate, input, &mut out[start..], MZFlush::None);
+                    out.truncate(start + result.bytes_written);
+
+                    self.crc32.update(&out[start..]);
+                    self.len += result.bytes_written as u64;
+                    consumed += result.bytes_consumed;
+
+                    match result.status {
+                        Ok(MZStatus::StreamEnd) => self.state = State::Trailer,
+                        Ok(_) => {}
+                        // NOTE(aki): Buf is just "give me more", if there is no more then the
+                        // stream is short, which the caller sorts out
+                        Err(MZError::Buf) => return Ok(Progress::more(consumed)),
+                        Err(_) => return Err(CORRUPT),
+                    }
+
+                    if result.bytes_consumed == 0 && result.bytes_written == 0 {
+                        return Ok(Progress::more(consumed));
+                    }
+                }
+                State::Trailer => {
+                    if input.len() < TRAILER_LEN {
+                        return Ok(Progress::more(consumed));
+                    }
+
+                    let crc32 = u32::from_le_bytes(input[0..4].try_into().unwrap());
+                    let isize = u32::from_le_bytes(input[4..8].try_into().unwrap());
+                    if crc32 != self.crc32.finish() {
+                        return Err(DecompressError::BadChecksum(Format::Gzip));
+                    }
+                    // NOTE(aki): The size is only kept mod 2^32
+                    if isize != self.len as u32 {
+                        return Err(DecompressError::SizeMismatch {
+                            format: Format::Gzip,
+                            expected: isize as u64,
+                            actual: self.len,
+                        });
+                    }
+
+                    return Ok(Progress::done(consumed + TRAILER_LEN));
+                }
+            }
+        }
+    }
+}
+
+// How long the header at the start of `data` is, if it's all there
+fn header_len(data: &[u8]) -> Result<Option<usize>, DecompressError> {
+    if data.len() < HEADER_LEN {
+        return Ok(None);
+    }
+    if !data.starts_with(&MAGIC) {
+        return Err(CORRUPT);
+    }
+    if data[2] != METHOD_DEFLATE || data[3] & FRESERVED != 0 {
+        return Err(DecompressError::Unsupported(Format::Gzip));
+    }
+
+    let flags = data[3];
+    let mut len = HEADER_LEN;
+
+    if flags & FEXTRA != 0 {
+        let Some(extra) = data.get(len..len + 2) else {
+            return Ok(None);
+        };
+        len += 2 + u16::from_le_bytes([extra[0], extra[1]]) as usize;
+    }
+
+    // The original file name and a comment, both NUL terminated
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            match data
+                .get(len..)
+                .and_then(|rest| rest.iter().position(|&b| b == 0))
+            {
+                Some(end) => len += end + 1,
+                None => return Ok(None),
+            }
+        }
+    }
+
+    // NOTE(aki): There is a CRC16 of the header, but no one ever sets it, and the CRC32 at the
+    // end covers anything that would matter
+    if flags & FHCRC != 0 {
+        len += 2;
+    }
+
+    Ok((data.len() >= len).then_some(len))
+}
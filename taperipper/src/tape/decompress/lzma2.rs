@@ -0,0 +1,648 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// LZMA2, the compression inside of xz, decoded a chunk at a time.
+//
+// LZMA2 cuts an LZMA stream up into chunks that say up-front how long they are, at most 64KiB
+// compressed and 2MiB uncompressed. Rather than keep the range decoder going across however
+// the input happens to be split up, we wait until a whole chunk is in and do it in one go.
+//
+// There is no separate dictionary, the output is the dictionary, as we keep all of it anyway.
+
+use super::{DecompressError, Format};
+
+const CORRUPT: DecompressError = DecompressError::Corrupt(Format::Xz);
+
+// Range decoder
+const PROB_BITS: u32 = 11;
+const PROB_INIT: u16 = 1 << (PROB_BITS - 1);
+const MOVE_BITS: u32 = 5;
+const TOP: u32 = 1 << 24;
+
+const STATES: usize = 12;
+// The first state that means the last thing decoded was a match rather than a literal
+const LIT_STATES: usize = 7;
+const POS_STATES_MAX: usize = 1 << 4;
+
+const LITERAL_CODER_LEN: usize = 0x300;
+// NOTE(aki): lc + lp can be at most 4 in LZMA2
+const LITERAL_CODERS_MAX: usize = 1 << 4;
+
+const MATCH_LEN_MIN: usize = 2;
+const LEN_LOW_BITS: u32 = 3;
+const LEN_MID_BITS: u32 = 3;
+const LEN_HIGH_BITS: u32 = 8;
+const LEN_LOW: usize = 1 << LEN_LOW_BITS;
+const LEN_MID: usize = 1 << LEN_MID_BITS;
+const LEN_HIGH: usize = 1 << LEN_HIGH_BITS;
+
+const DIST_STATES: usize = 4;
+const DIST_SLOT_BITS: u32 = 6;
+const DIST_SLOTS: usize = 1 << DIST_SLOT_BITS;
+const DIST_MODEL_START: u32 = 4;
+const DIST_MODEL_END: u32 = 14;
+const FULL_DISTANCES: usize = 1 << (DIST_MODEL_END / 2);
+const ALIGN_BITS: u32 = 4;
+const ALIGN_SIZE: usize = 1 << ALIGN_BITS;
+
+// Chunk control bytes
+const CTRL_END: u8 = 0x00;
+const CTRL_UNCOMPRESSED_RESET: u8 = 0x01;
+const CTRL_UNCOMPRESSED: u8 = 0x02;
+const CTRL_LZMA: u8 = 0x80;
+
+// The largest dictionary size the filter properties can ask for
+pub const DICT_SIZE_PROP_MAX: u8 = 40;
+
+struct RangeDecoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+    range: u32,
+    code: u32,
+    // NOTE(aki): Rather than check every byte we read, running off the end reads zeros and
+    // we check this once the chunk is done
+    overrun: bool,
+}
+
+impl<'a> RangeDecoder<'a> {
+    fn new(data: &'a [u8]) -> Result<Self, DecompressError> {
+        if data.len() < 5 || data[0] != 0 {
+            return Err(CORRUPT);
+        }
+
+        Ok(Self {
+            data,
+            pos: 5,
+            range: !0,
+            code: u32::from_be_bytes(data[1..5].try_into().unwrap()),
+            overrun: false,
+        })
+    }
+
+    // The encoder flushes everything it has at the end of a chunk, so once that's been read
+    // there should be nothing left over
+    fn is_finished(&mut self) -> bool {
+        self.normalize();
+        !self.overrun && self.pos == self.data.len() && self.code == 0
+    }
+
+    fn normalize(&mut self) {
+        if self.range < TOP {
+            let byte = match self.data.get(self.pos) {
+                Some(&byte) => byte,
+                None => {
+                    self.overrun = true;
+                    0
+                }
+            };
+            self.pos += 1;
+            self.range <<= 8;
+            self.code = (self.code << 8) | byte as u32;
+        }
+    }
+
+    fn bit(&mut self, prob: &mut u16) -> u32 {
+        self.normalize();
+
+        let bound = (self.range >> PROB_BITS) * *prob as u32;
+        if self.code < bound {
+            self.range = bound;
+            *prob += ((1 << PROB_BITS) - *prob) >> MOVE_BITS;
+            0
+        } else {
+            self.range -= bound;
+            self.code -= bound;
+            *prob -= *prob >> MOVE_BITS;
+            1
+        }
+    }
+
+    // `probs` is indexed from 1, the first entry is never used
+    fn bittree(&mut self, probs: &mut [u16], bits: u32) -> u32 {
+        let mut symbol = 1;
+        for _ in 0..bits {
+            symbol = (symbol << 1) | self.bit(&mut probs[symbol as usize]);
+        }
+
+        symbol - (1 << bits)
+    }
+
+    // Same as `bittree` but the bits come out least significant first
+    fn reverse_bittree(&mut self, probs: &mut [u16], bits: u32) -> u32 {
+        let mut symbol = 1;
+        let mut result = 0;
+        for idx in 0..bits {
+            let bit = self.bit(&mut probs[symbol as usize]);
+            symbol = (symbol << 1) | bit;
+            result |= bit << idx;
+        }
+
+        result
+    }
+
+    // Bits with a fixed probability of a half
+    fn direct(&mut self, bits: u32) -> u32 {
+        let mut result = 0;
+        for _ in 0..bits {
+            self.normalize();
+            self.range >>= 1;
+
+            let bit = if self.code >= self.range {
+                self.code -= self.range;
+                1
+            } else {
+                0
+            };
+            result = (result << 1) | bit;
+        }
+
+        result
+    }
+}
+
+struct LengthDecoder {
+    choice: u16,
+    choice2: u16,
+    low: [[u16; LEN_LOW]; POS_STATES_MAX],
+    mid: [[u16; LEN_MID]; POS_STATES_MAX],
+    high: [u16; LEN_HIGH],
+}
+
+impl LengthDecoder {
+    fn new() -> Self {
+        Self {
+            choice: PROB_INIT,
+            choice2: PROB_INIT,
+            low: [[PROB_INIT; LEN_LOW]; POS_STATES_MAX],
+            mid: [[PROB_INIT; LEN_MID]; POS_STATES_MAX],
+            high: [PROB_INIT; LEN_HIGH],
+        }
+    }
+
+    fn decode(&mut self, rc: &mut RangeDecoder, pos_state: usize) -> usize {
+        let len = if rc.bit(&mut self.choice) == 0 {
+            rc.bittree(&mut self.low[pos_state], LEN_LOW_BITS) as usize
+        } else if rc.bit(&mut self.choice2) == 0 {
+            LEN_LOW + rc.bittree(&mut self.mid[pos_state], LEN_MID_BITS) as usize
+        } else {
+            LEN_LOW + LEN_MID + rc.bittree(&mut self.high, LEN_HIGH_BITS) as usize
+        };
+
+        MATCH_LEN_MIN + len
+    }
+}
+
+// Everything the LZMA decoder learns as it goes, which gets thrown away on a state reset
+struct LzmaState {
+    state: usize,
+    // The last four match distances, less one
+    reps: [u32; 4],
+
+    is_match: [u16; STATES * POS_STATES_MAX],
+    is_rep: [u16; STATES],
+    is_rep0: [u16; STATES],
+    is_rep1: [u16; STATES],
+    is_rep2: [u16; STATES],
+    is_rep0_long: [u16; STATES * POS_STATES_MAX],
+
+    dist_slot: [[u16; DIST_SLOTS]; DIST_STATES],
+    // NOTE(aki): One longer than it needs to be, so it can be indexed from 1 like the others
+    dist_special: [u16; FULL_DISTANCES - DIST_MODEL_END as usize + 1],
+    dist_align: [u16; ALIGN_SIZE],
+
+    match_len: LengthDecoder,
+    rep_len: LengthDecoder,
+
+    literal: [u16; LITERAL_CODER_LEN * LITERAL_CODERS_MAX],
+}
+
+impl LzmaState {
+    fn new() -> Self {
+        Self {
+            state: 0,
+            reps: [0; 4],
+            is_match: [PROB_INIT; STATES * POS_STATES_MAX],
+            is_rep: [PROB_INIT; STATES],
+            is_rep0: [PROB_INIT; STATES],
+            is_rep1: [PROB_INIT; STATES],
+            is_rep2: [PROB_INIT; STATES],
+            is_rep0_long: [PROB_INIT; STATES * POS_STATES_MAX],
+            dist_slot: [[PROB_INIT; DIST_SLOTS]; DIST_STATES],
+            dist_special: [PROB_INIT; FULL_DISTANCES - DIST_MODEL_END as usize + 1],
+            dist_align: [PROB_INIT; ALIGN_SIZE],
+            match_len: LengthDecoder::new(),
+            rep_len: LengthDecoder::new(),
+            literal: [PROB_INIT; LITERAL_CODER_LEN * LITERAL_CODERS_MAX],
+        }
+    }
+
+    fn after_literal(&mut self) {
+        self.state = match self.state {
+            0..=3 => 0,
+            4..=9 => self.state - 3,
+            _ => self.state - 6,
+        };
+    }
+
+    fn after_match(&mut self) {
+        self.state = if self.state < LIT_STATES { 7 } else { 10 };
+    }
+
+    fn after_long_rep(&mut self) {
+        self.state = if self.state < LIT_STATES { 8 } else { 11 };
+    }
+
+    fn after_short_rep(&mut self) {
+        self.state = if self.state < LIT_STATES { 9 } else { 11 };
+    }
+
+    fn distance(&mut self, rc: &mut RangeDecoder, len: usize) -> u32 {
+        let dist_state = (len - MATCH_LEN_MIN).min(DIST_STATES - 1);
+        let slot = rc.bittree(&mut self.dist_slot[dist_state], DIST_SLOT_BITS);
+        if slot < DIST_MODEL_START {
+            return slot;
+        }
+
+        let direct = (slot >> 1) - 1;
+        let dist = (2 | (slot & 1)) << direct;
+
+        if slot < DIST_MODEL_END {
+            dist + rc.reverse_bittree(&mut self.dist_special[(dist - slot) as usize..], direct)
+        } else {
+            let high = rc.direct(direct - ALIGN_BITS) << ALIGN_BITS;
+            dist + high + rc.reverse_bittree(&mut self.dist_align, ALIGN_BITS)
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Props {
+    lc: u32,
+    lp: u32,
+    pb: u32,
+}
+
+impl Props {
+    fn parse(byte: u8) -> Result<Self, DecompressError> {
+        let mut props = byte as u32;
+        if props >= 9 * 5 * 5 {
+            return Err(CORRUPT);
+        }
+
+        let lc = props % 9;
+        props /= 9;
+        let lp = props % 5;
+        let pb = props / 5;
+
+        if lc + lp > 4 {
+            return Err(CORRUPT);
+        }
+
+        Ok(Self { lc, lp, pb })
+    }
+}
+
+pub struct Lzma2Decoder {
+    lzma: Box<LzmaState>,
+    props: Option<Props>,
+    // Where in the output the dictionary starts, matches can't reach back past it
+    dict_start: Option<usize>,
+}
+
+impl Default for Lzma2Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Lzma2Decoder {
+    pub fn new() -> Self {
+        Self {
+            lzma: Box::new(LzmaState::new()),
+            props: None,
+            dict_start: None,
+        }
+    }
+
+    // Decode the next chunk, if all of it is in `input`
+    //
+    // Returns how long the chunk was and whether it was the end marker.
+    pub fn decode_chunk(
+        &mut self,
+        input: &[u8],
+        out: &mut Vec<u8>,
+    ) -> Result<Option<(usize, bool)>, DecompressError> {
+        let Some(&ctrl) = input.first() else {
+            return Ok(None);
+        };
+
+        match ctrl {
+            CTRL_END => Ok(Some((1, true))),
+            CTRL_UNCOMPRESSED_RESET | CTRL_UNCOMPRESSED => {
+                let Some(header) = input.get(..3) else {
+                    return Ok(None);
+                };
+                let len = u16::from_be_bytes([header[1], header[2]]) as usize + 1;
+                let Some(data) = input.get(3..3 + len) else {
+                    return Ok(None);
+                };
+
+                if ctrl == CTRL_UNCOMPRESSED_RESET {
+                    self.dict_start = Some(out.len());
+                } else if self.dict_start.is_none() {
+                    return Err(CORRUPT);
+                }
+
+                out.extend_from_slice(data);
+                Ok(Some((3 + len, false)))
+            }
+            CTRL_LZMA.. => {
+                let reset = (ctrl >> 5) & 0x03;
+                let header_len = if reset >= 2 { 6 } else { 5 };
+                let Some(header) = input.get(..header_len) else {
+                    return Ok(None);
+                };
+
+                let unpacked = ((ctrl as usize & 0x1F) << 16)
+                    + u16::from_be_bytes([header[1], header[2]]) as usize
+                    + 1;
+                let packed = u16::from_be_bytes([header[3], header[4]]) as usize + 1;
+                let Some(data) = input.get(header_len..header_len + packed) else {
+                    return Ok(None);
+                };
+
+                // 3 resets everything, 2 gets new properties, 1 just the state, 0 nothing
+                if reset == 3 {
+                    self.dict_start = Some(out.len());
+                } else if self.dict_start.is_none() {
+                    return Err(CORRUPT);
+                }
+                if reset >= 2 {
+                    self.props = Some(Props::parse(header[5])?);
+                }
+                if reset >= 1 {
+                    *self.lzma = LzmaState::new();
+                }
+
+                self.decode_lzma(data, unpacked, out)?;
+                Ok(Some((header_len + packed, false)))
+            }
+            _ => Err(CORRUPT),
+        }
+    }
+
+    fn decode_lzma(
+        &mut self,
+        data: &[u8],
+        unpacked: usize,
+        out: &mut Vec<u8>,
+    ) -> Result<(), DecompressError> {
+        let Props { lc, lp, pb } = self.props.ok_or(CORRUPT)?;
+        let dict_start = self.dict_start.ok_or(CORRUPT)?;
+        let lzma = &mut *self.lzma;
+        let mut rc = RangeDecoder::new(data)?;
+
+        let end = out.len() + unpacked;
+        let pb_mask = (1 << pb) - 1;
+        let lp_mask = (1 << lp) - 1;
+        out.reserve(unpacked);
+
+        while out.len() < end {
+            let pos = out.len();
+            // NOTE(aki): Positions count from the dictionary reset, not the start of the output
+            let pos_state = (pos - dict_start) & pb_mask;
+
+            if rc.bit(&mut lzma.is_match[lzma.state * POS_STATES_MAX + pos_state]) == 0 {
+                let prev = if pos > dict_start { out[pos - 1] } else { 0 };
+                let coder = ((((pos - dict_start) & lp_mask) << lc) + (prev as usize >> (8 - lc)))
+                    * LITERAL_CODER_LEN;
+                let probs = &mut lzma.literal[coder..coder + LITERAL_CODER_LEN];
+
+                let symbol = if lzma.state < LIT_STATES {
+                    rc.bittree(probs, 8)
+                } else {
+                    // After a match the byte that followed the match last time is a good guess
+                    let rep0 = lzma.reps[0] as usize;
+                    if rep0 >= pos - dict_start {
+                        return Err(CORRUPT);
+                    }
+
+                    let mut match_byte = (out[pos - rep0 - 1] as u32) << 1;
+                    let mut offset = 0x100;
+                    let mut symbol = 1;
+                    while symbol < 0x100 {
+                        let match_bit = match_byte & offset;
+                        match_byte <<= 1;
+
+                        let bit = rc.bit(&mut probs[(offset + match_bit + symbol) as usize]);
+                        symbol = (symbol << 1) | bit;
+                        offset &= if bit == 0 { !match_bit } else { match_bit };
+                    }
+
+                    symbol - 0x100
+                };
+
+                out.push(symbol as u8);
+                lzma.after_literal();
+                continue;
+            }
+
+            let len = if rc.bit(&mut lzma.is_rep[lzma.state]) == 0 {
+                let len = lzma.match_len.decode(&mut rc, pos_state);
+                let dist = lzma.distance(&mut rc, len);
+                // NOTE(aki): LZMA has an end marker distance, but LZMA2 chunks can't use it
+                if dist == u32::MAX {
+                    return Err(CORRUPT);
+                }
+
+                lzma.after_match();
+                lzma.reps = [dist, lzma.reps[0], lzma.reps[1], lzma.reps[2]];
+                len
+            } else {
+                if rc.bit(&mut lzma.is_rep0[lzma.state]) == 0 {
+                    // A single byte from the last distance
+                    if rc.bit(&mut lzma.is_rep0_long[lzma.state * POS_STATES_MAX + pos_state]) == 0
+                    {
+                        lzma.after_short_rep();
+                        copy_match(out, dict_start, lzma.reps[0], 1, end)?;
+                        continue;
+                    }
+                } else {
+                    let dist = if rc.bit(&mut lzma.is_rep1[lzma.state]) == 0 {
+                        lzma.reps[1]
+                    } else if rc.bit(&mut lzma.is_rep2[lzma.state]) == 0 {
+                        let dist = lzma.reps[2];
+                        lzma.reps[2] = lzma.reps[1];
+                        dist
+                    } else {
+                        let dist = lzma.reps[3];
+                        lzma.reps[3] = lzma.reps[2];
+                        lzma.reps[2] = lzma.reps[1];
+                        dist
+                    };
+                    lzma.reps[1] = lzma.reps[0];
+                    lzma.reps[0] = dist;
+                }
+
+                lzma.after_long_rep();
+                lzma.rep_len.decode(&mut rc, pos_state)
+            };
+
+            copy_match(out, dict_start, lzma.reps[0], len, end)?;
+        }
+
+        if !rc.is_finished() {
+            return Err(CORRUPT);
+        }
+
+        Ok(())
+    }
+}
+
+// Copy `len` bytes from `dist` + 1 back onto the end of the output, they can overlap
+fn copy_match(
+    out: &mut Vec<u8>,
+    dict_start: usize,
+    dist: u32,
+    len: usize,
+    end: usize,
+) -> Result<(), DecompressError> {
+    let pos = out.len();
+    let dist = dist as usize + 1;
+    if dist > pos - dict_start || pos + len > end {
+        return Err(CORRUPT);
+    }
+
+    for idx in pos..pos + len {
+        out.push(out[idx - dist]);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tape::decompress::testdata;
+
+    const TEXT: &[u8] = include_bytes!("testdata/text.lzma2");
+
+    // Decode chunks until the end marker, returning how much of `data` they took up
+    fn decode(
+        decoder: &mut Lzma2Decoder,
+        data: &[u8],
+        out: &mut Vec<u8>,
+    ) -> Result<usize, DecompressError> {
+        let mut pos = 0;
+
+        loop {
+            match decoder.decode_chunk(&data[pos..], out)? {
+                Some((len, end)) => {
+                    pos += len;
+                    if end {
+                        return Ok(pos);
+                    }
+                }
+                None => return Err(DecompressError::Truncated(Format::Xz)),
+            }
+        }
+    }
+
+    #[test]
+    fn lzma() {
+        let mut out = Vec::new();
+        assert_eq!(
+            decode(&mut Lzma2Decoder::new(), TEXT, &mut out),
+            Ok(TEXT.len())
+        );
+        assert_eq!(out, testdata::text(2000));
+    }
+
+    #[test]
+    fn partial_chunk() {
+        // Nothing happens until all of the chunk is there
+        let (len, _) = Lzma2Decoder::new()
+            .decode_chunk(TEXT, &mut Vec::new())
+            .unwrap()
+            .unwrap();
+
+        for end in 0..len {
+            let mut out = Vec::new();
+            assert_eq!(
+                Lzma2Decoder::new().decode_chunk(&TEXT[..end], &mut out),
+                Ok(None)
+            );
+            assert!(out.is_empty());
+        }
+    }
+
+    #[test]
+    fn uncompressed() {
+        let mut data = vec![CTRL_UNCOMPRESSED_RESET, 0x00, 0x04];
+        data.extend_from_slice(b"hello");
+        data.extend_from_slice(&[CTRL_UNCOMPRESSED, 0x00, 0x05]);
+        data.extend_from_slice(b" world");
+        data.push(CTRL_END);
+
+        let mut out = b"before ".to_vec();
+        assert_eq!(
+            decode(&mut Lzma2Decoder::new(), &data, &mut out),
+            Ok(data.len())
+        );
+        assert_eq!(out, b"before hello world");
+    }
+
+    #[test]
+    fn no_dictionary_reset() {
+        // The first chunk has to reset the dictionary
+        let mut data = vec![CTRL_UNCOMPRESSED, 0x00, 0x04];
+        data.extend_from_slice(b"hello");
+        assert_eq!(
+            Lzma2Decoder::new().decode_chunk(&data, &mut Vec::new()),
+            Err(CORRUPT)
+        );
+
+        // Knock the first chunk of the real thing down from a full reset to new properties
+        let mut data = TEXT.to_vec();
+        assert_eq!(data[0] & 0xE0, 0xE0);
+        data[0] &= !0x20;
+        assert_eq!(
+            Lzma2Decoder::new().decode_chunk(&data, &mut Vec::new()),
+            Err(CORRUPT)
+        );
+    }
+
+    #[test]
+    fn bad_chunks() {
+        for ctrl in 0x03..CTRL_LZMA {
+            assert_eq!(
+                Lzma2Decoder::new().decode_chunk(&[ctrl; 8], &mut Vec::new()),
+                Err(CORRUPT)
+            );
+        }
+
+        // lc + lp over 4, and properties past the end of the table
+        let mut data = TEXT.to_vec();
+        for props in [4 + 9, 9 * 5 * 5] {
+            data[5] = props;
+            assert_eq!(
+                Lzma2Decoder::new().decode_chunk(&data, &mut Vec::new()),
+                Err(CORRUPT)
+            );
+        }
+    }
+
+    #[test]
+    fn corrupt() {
+        // Whatever the damage, it's either caught or turns into the wrong output, never a panic
+        let text = testdata::text(2000);
+
+        for pos in 0..TEXT.len() {
+            let mut data = TEXT.to_vec();
+            data[pos] ^= 0x55;
+
+            let mut out = Vec::new();
+            if decode(&mut Lzma2Decoder::new(), &data, &mut out).is_ok() {
+                assert_ne!(out, text, "corrupting byte {pos} went unnoticed");
+            }
+        }
+    }
+}
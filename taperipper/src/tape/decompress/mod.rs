@@ -0,0 +1,304 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// Decompressing payloads as they come off of the tape.
+//
+// Tape is small, so initrds and sometimes the kernel go on it compressed. Rather than read
+// the whole compressed thing into memory and then decompress it into a second buffer, the
+// `Unpacker` sits between the tape and the payload and decompresses it as it's read, so all
+// that is ever held of the compressed data is whatever the decoder needs to make progress.
+//
+// gzip, zstd, and xz are picked out by their magic, anything else is passed through as is.
+// One stream ending and another starting straight after it, like a compressed initrd with an
+// uncompressed microcode archive tacked on the end, is fine, each is detected on its own.
+
+use core::fmt;
+
+use tracing::debug;
+
+pub mod gzip;
+pub mod lzma2;
+pub mod xz;
+pub mod zstd;
+
+#[cfg(test)]
+mod testdata;
+
+// Enough to tell all of the formats apart
+const MAGIC_LEN: usize = 6;
+
+// How much room to make at the end of the output for each go at decoding
+const CHUNK_LEN: usize = 64 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Gzip,
+    Zstd,
+    Xz,
+}
+
+impl Format {
+    // Work out what `data` is compressed with from the start of it, if it's compressed at all
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        if data.starts_with(&gzip::MAGIC) {
+            Some(Format::Gzip)
+        } else if data.starts_with(&zstd::MAGIC) || zstd::is_skippable(data) {
+            Some(Format::Zstd)
+        } else if data.starts_with(&xz::MAGIC) {
+            Some(Format::Xz)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Format::Gzip => write!(f, "gzip"),
+            Format::Zstd => write!(f, "zstd"),
+            Format::Xz => write!(f, "xz"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecompressError {
+    // Uses something we don't know how to decode, like an xz filter other than LZMA2
+    Unsupported(Format),
+    // The compressed data doesn't make sense
+    Corrupt(Format),
+    // The payload ended before the compressed stream did
+    Truncated(Format),
+    // What came out doesn't match the check in the stream
+    BadChecksum(Format),
+    // What came out isn't as big as the stream said it would be
+    SizeMismatch {
+        format: Format,
+        expected: u64,
+        actual: u64,
+    },
+}
+
+impl fmt::Display for DecompressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecompressError::Unsupported(format) => {
+                write!(f, "{format} stream uses unsupported features")
+            }
+            DecompressError::Corrupt(format) => write!(f, "{format} stream is corrupt"),
+            DecompressError::Truncated(format) => write!(f, "{format} stream is truncated"),
+            DecompressError::BadChecksum(format) => {
+                write!(f, "{format} stream failed its integrity check")
+            }
+            DecompressError::SizeMismatch {
+                format,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{format} stream should have been {expected} bytes, but was {actual} bytes"
+            ),
+        }
+    }
+}
+
+// How far a decoder got with what it was given
+pub struct Progress {
+    // How much of the input it is done with
+    pub consumed: usize,
+    // The stream has ended, anything past `consumed` is not part of it
+    pub done: bool,
+}
+
+impl Progress {
+    fn more(consumed: usize) -> Self {
+        Self {
+            consumed,
+            done: false,
+        }
+    }
+
+    fn done(consumed: usize) -> Self {
+        Self {
+            consumed,
+            done: true,
+        }
+    }
+}
+
+// Any of the decoders we have
+//
+// Each takes as much of the input as it can use and appends what it decompressed onto the
+// output, the output is also what they refer back to for earlier data where they need to.
+enum Decoder {
+    Gzip(Box<gzip::GzipDecoder>),
+    Zstd(Box<zstd::ZstdDecoder>),
+    Xz(Box<xz::XzDecoder>),
+}
+
+impl Decoder {
+    fn new(format: Format) -> Self {
+        match format {
+            Format::Gzip => Decoder::Gzip(Box::default()),
+            Format::Zstd => Decoder::Zstd(Box::default()),
+            Format::Xz => Decoder::Xz(Box::default()),
+        }
+    }
+
+    fn format(&self) -> Format {
+        match self {
+            Decoder::Gzip(_) => Format::Gzip,
+            Decoder::Zstd(_) => Format::Zstd,
+            Decoder::Xz(_) => Format::Xz,
+        }
+    }
+
+    // `last` is set once there is nothing more to come after `input`
+    fn decode(
+        &mut self,
+        input: &[u8],
+        out: &mut Vec<u8>,
+        last: bool,
+    ) -> Result<Progress, DecompressError> {
+        match self {
+            Decoder::Gzip(decoder) => decoder.decode(input, out, last),
+            Decoder::Zstd(decoder) => decoder.decode(input, out, last),
+            Decoder::Xz(decoder) => decoder.decode(input, out, last),
+        }
+    }
+}
+
+enum State {
+    // Waiting on enough of the start of a stream to tell what it is
+    Detect,
+    // Not compressed, straight through
+    Raw,
+    Stream(Decoder),
+}
+
+// Takes a payload a piece at a time and gives back all of it decompressed
+pub struct Unpacker {
+    state: State,
+    // Input the decoder couldn't do anything with yet
+    pending: Vec<u8>,
+    out: Vec<u8>,
+    // How big we think the payload will be, if it's not compressed
+    size_hint: usize,
+    // The format of the first stream in the payload, if there was one
+    format: Option<Format>,
+    compressed: u64,
+}
+
+impl Unpacker {
+    // An `Unpacker` that decompresses anything it recognises
+    //
+    // What comes out goes on the end of `out`, so parts of a payload can be put back together
+    // without copying them. `size_hint` is how much is going to be pushed in, which is
+    // reserved up-front if it turns out to not be compressed.
+    pub fn new(out: Vec<u8>, size_hint: usize) -> Self {
+        Self {
+            state: State::Detect,
+            pending: Vec::new(),
+            out,
+            size_hint,
+            format: None,
+            compressed: 0,
+        }
+    }
+
+    // An `Unpacker` that passes everything through untouched
+    pub fn raw(mut out: Vec<u8>, size_hint: usize) -> Self {
+        out.reserve_exact(size_hint);
+
+        Self {
+            state: State::Raw,
+            ..Self::new(out, size_hint)
+        }
+    }
+
+    // What the payload was compressed with, if anything
+    pub fn format(&self) -> Option<Format> {
+        self.format
+    }
+
+    // How much has been pushed in so far
+    pub fn compressed_len(&self) -> u64 {
+        self.compressed
+    }
+
+    pub fn push(&mut self, data: &[u8]) -> Result<(), DecompressError> {
+        self.compressed += data.len() as u64;
+
+        // NOTE(aki): No point copying it through `pending` if we're not going to decode it
+        if matches!(self.state, State::Raw) {
+            self.out.extend_from_slice(data);
+            return Ok(());
+        }
+
+        self.pending.extend_from_slice(data);
+        self.run(false)
+    }
+
+    // There is nothing more to come, hand over what came out
+    pub fn finish(mut self) -> Result<Vec<u8>, DecompressError> {
+        self.run(true)?;
+        Ok(self.out)
+    }
+
+    fn run(&mut self, last: bool) -> Result<(), DecompressError> {
+        loop {
+            match &mut self.state {
+                State::Detect => {
+                    if self.pending.is_empty() || (self.pending.len() < MAGIC_LEN && !last) {
+                        return Ok(());
+                    }
+
+                    match Format::detect(&self.pending) {
+                        Some(format) => {
+                            debug!(
+                                format = %format,
+                                offset = self.compressed - self.pending.len() as u64,
+                                "Found compressed stream"
+                            );
+                            self.format.get_or_insert(format);
+                            self.state = State::Stream(Decoder::new(format));
+                        }
+                        None => {
+                            if self.format.is_none() {
+                                self.out.reserve_exact(self.size_hint);
+                            }
+                            self.state = State::Raw;
+                        }
+                    }
+                }
+                State::Raw => {
+                    self.out.extend_from_slice(&self.pending);
+                    self.pending = Vec::new();
+                    return Ok(());
+                }
+                State::Stream(decoder) => {
+                    let progress = decoder.decode(&self.pending, &mut self.out, last)?;
+                    self.pending.drain(..progress.consumed);
+
+                    if progress.done {
+                        self.state = State::Detect;
+                        continue;
+                    }
+                    if last {
+                        return Err(DecompressError::Truncated(decoder.format()));
+                    }
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+// Make room for `len` more bytes at the end of `out` for a decoder to write into
+//
+// Returns where the new room starts, `out` should be truncated back down to what was actually
+// written once the decoder is done with it.
+fn grow(out: &mut Vec<u8>, len: usize) -> usize {
+    let start = out.len();
+    out.resize(start + len, 0);
+    start
+}
@@ -0,0 +1,36 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// What the vectors in `testdata/` decompress to.
+//
+// They're all straight out of `xz` 5.8, from the output of `text(2000)` unless noted:
+//   check_{none,crc32,crc64,sha256}.xz  xz --check={none,crc32,crc64,sha256}
+//   multi_block.xz                      xz --check=crc64 --block-size=32KiB
+//   props.xz                            xz --check=crc32 --lzma2=preset=6,lc=0,lp=2,pb=0
+//   uncompressed.xz                     xz --check=crc32, of `noise(61440)` then `text(200)`
+//   text.lzma2                          xz --format=raw --lzma2=preset=6
+//
+// The noise doesn't compress, so the first LZMA2 chunk of `uncompressed.xz` is stored as is,
+// and the text after it is in an LZMA chunk that resets the state and properties but not the
+// dictionary.
+
+// Numbered lines of the same sentence, which compress down to next to nothing
+pub fn text(lines: usize) -> Vec<u8> {
+    (0..lines)
+        .flat_map(|line| {
+            format!("{line:05} the quick brown fox jumps over the lazy dog\n").into_bytes()
+        })
+        .collect()
+}
+
+// xorshift32, which doesn't compress at all
+pub fn noise(len: usize) -> Vec<u8> {
+    let mut state = 0x2545_F491u32;
+
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
@@ -0,0 +1,601 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// The xz container, which is LZMA2 blocks with a header, an index, and a footer around them.
+//
+// We only handle what `xz` itself writes for things like initrds, a single LZMA2 filter per
+// block, with no BCJ or delta filters in front of it. Each block's check is verified as it
+// ends, and the index is checked against the blocks we actually saw.
+
+use sha2::{Digest as _, Sha256};
+use tracing::debug;
+
+use super::{DecompressError, Format, Progress, lzma2::Lzma2Decoder};
+use crate::tape::digest::Crc32;
+
+pub const MAGIC: [u8; 6] = [0xFD, b'7', b'z', b'X', b'Z', 0x00];
+const FOOTER_MAGIC: [u8; 2] = *b"YZ";
+
+const STREAM_HEADER_LEN: usize = 12;
+const STREAM_FOOTER_LEN: usize = 12;
+
+const FILTER_LZMA2: u64 = 0x21;
+
+// Block header flags
+const BLOCK_FILTERS: u8 = 0x03;
+const BLOCK_RESERVED: u8 = 0x3C;
+const BLOCK_COMPRESSED_SIZE: u8 = 0x40;
+const BLOCK_UNCOMPRESSED_SIZE: u8 = 0x80;
+
+const CORRUPT: DecompressError = DecompressError::Corrupt(Format::Xz);
+
+// CRC-64/XZ, which is the ECMA-182 polynomial bit reflected
+const CRC64_POLY: u64 = 0xC96C_5795_D787_0F42;
+const CRC64_TABLE: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut idx = 0;
+
+    while idx < 256 {
+        let mut crc = idx as u64;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ CRC64_POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[idx] = crc;
+        idx += 1;
+    }
+
+    table
+};
+
+#[derive(Clone, Copy, Debug)]
+struct Crc64(u64);
+
+impl Crc64 {
+    fn new() -> Self {
+        Self(!0)
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.0 = CRC64_TABLE[((self.0 ^ byte as u64) & 0xFF) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        !self.0
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::default();
+    crc.update(data);
+    crc.finish()
+}
+
+// The check at the end of each block, set for the whole stream
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Check {
+    None,
+    Crc32,
+    Crc64,
+    Sha256,
+    // Something newer, which we can skip over but not check
+    Unknown(u8),
+}
+
+impl Check {
+    fn from_flags(flags: u8) -> Self {
+        match flags & 0x0F {
+            0x00 => Check::None,
+            0x01 => Check::Crc32,
+            0x04 => Check::Crc64,
+            0x0A => Check::Sha256,
+            check => Check::Unknown(check),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Check::None => 0,
+            Check::Crc32 => 4,
+            Check::Crc64 => 8,
+            Check::Sha256 => 32,
+            // NOTE(aki): The size is fixed by which group of three the id is in
+            Check::Unknown(check) => [0, 4, 8, 16, 32, 64][(*check as usize).div_ceil(3)],
+        }
+    }
+
+    // Check the block that was decompressed into `data`
+    fn verify(&self, data: &[u8], expected: &[u8]) -> bool {
+        match self {
+            Check::None | Check::Unknown(_) => true,
+            Check::Crc32 => crc32(data).to_le_bytes() == expected,
+            Check::Crc64 => {
+                let mut crc = Crc64::new();
+                crc.update(data);
+                crc.finish().to_le_bytes() == expected
+            }
+            Check::Sha256 => Sha256::digest(data).as_slice() == expected,
+        }
+    }
+}
+
+// Read a variable length integer, 7 bits a byte, least significant first
+//
+// Returns nothing if `data` ends before it does.
+fn read_vli(data: &[u8], pos: &mut usize) -> Result<Option<u64>, DecompressError> {
+    let mut value = 0u64;
+
+    for idx in 0..9 {
+        let Some(&byte) = data.get(*pos) else {
+            return Ok(None);
+        };
+        *pos += 1;
+
+        value |= ((byte & 0x7F) as u64) << (idx * 7);
+        if byte & 0x80 == 0 {
+            // NOTE(aki): Has to be the shortest way of writing it
+            if byte == 0 && idx != 0 {
+                return Err(CORRUPT);
+            }
+            return Ok(Some(value));
+        }
+    }
+
+    Err(CORRUPT)
+}
+
+// Padding up to the next multiple of four
+fn padding(len: u64) -> usize {
+    ((4 - len % 4) % 4) as usize
+}
+
+#[derive(Clone, Copy)]
+struct Block {
+    // Where in the output it starts
+    start: usize,
+    header_len: usize,
+    compressed: u64,
+    // What the header says the sizes will be, if it says
+    expected_compressed: Option<u64>,
+    expected_uncompressed: Option<u64>,
+}
+
+enum State {
+    StreamHeader,
+    BlockHeader,
+    Block(Block),
+    BlockEnd(Block),
+    Index,
+    Footer { index_len: u64 },
+}
+
+// Decodes a single stream, streams can be concatenated but the next one gets a new decoder
+pub struct XzDecoder {
+    state: State,
+    flags: [u8; 2],
+    check: Check,
+    lzma2: Lzma2Decoder,
+    // The unpadded and uncompressed size of every block, to check the index against
+    blocks: Vec<(u64, u64)>,
+}
+
+impl Default for XzDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl XzDecoder {
+    pub fn new() -> Self {
+        Self {
+            state: State::StreamHeader,
+            flags: [0; 2],
+            check: Check::None,
+            lzma2: Lzma2Decoder::new(),
+            blocks: Vec::new(),
+        }
+    }
+
+    pub fn decode(
+        &mut self,
+        input: &[u8],
+        out: &mut Vec<u8>,
+        _last: bool,
+    ) -> Result<Progress, DecompressError> {
+        let mut consumed = 0;
+
+        loop {
+            let input = &input[consumed..];
+
+            match &mut self.state {
+                State::StreamHeader => {
+                    let Some(header) = input.get(..STREAM_HEADER_LEN) else {
+                        return Ok(Progress::more(consumed));
+                    };
+                    if !header.starts_with(&MAGIC) {
+                        return Err(CORRUPT);
+                    }
+
+                    let flags = &header[6..8];
+                    if flags[0] != 0 || flags[1] & 0xF0 != 0 {
+                        return Err(DecompressError::Unsupported(Format::Xz));
+                    }
+                    if crc32(flags).to_le_bytes() != header[8..12] {
+                        return Err(CORRUPT);
+                    }
+
+                    self.flags = [flags[0], flags[1]];
+                    self.check = Check::from_flags(flags[1]);
+                    if let Check::Unknown(check) = self.check {
+                        debug!(check, "Can't verify xz blocks with an unknown check type");
+                    }
+
+                    consumed += STREAM_HEADER_LEN;
+                    self.state = State::BlockHeader;
+                }
+                State::BlockHeader => {
+                    let Some(&size) = input.first() else {
+                        return Ok(Progress::more(consumed));
+                    };
+                    // A zero where the block header size would be is the start of the index
+                    if size == 0 {
+                        self.state = State::Index;
+                        continue;
+                    }
+
+                    let header_len = (size as usize + 1) * 4;
+                    let Some(header) = input.get(..header_len) else {
+                        return Ok(Progress::more(consumed));
+                    };
+
+                    let block = self.parse_block_header(header, out.len())?;
+                    if let Some(len) = block.expected_uncompressed {
+                        out.reserve(len as usize);
+                    }
+
+                    consumed += header_len;
+                    self.lzma2 = Lzma2Decoder::new();
+                    self.state = State::Block(block);
+                }
+                State::Block(block) => match self.lzma2.decode_chunk(input, out)? {
+                    Some((len, end)) => {
+                        consumed += len;
+                        block.compressed += len as u64;
+
+                        if block
+                            .expected_compressed
+                            .is_some_and(|expected| block.compressed > expected)
+                        {
+                            return Err(CORRUPT);
+                        }
+                        if end {
+                            self.state = State::BlockEnd(*block);
+                        }
+                    }
+                    None => return Ok(Progress::more(consumed)),
+                },
+                State::BlockEnd(block) => {
+                    let unpadded = block.header_len as u64 + block.compressed;
+                    let padding = padding(unpadded);
+                    let check_len = self.check.len();
+
+                    let Some(end) = input.get(..padding + check_len) else {
+                        return Ok(Progress::more(consumed));
+                    };
+                    if end[..padding].iter().any(|&byte| byte != 0) {
+                        return Err(CORRUPT);
+                    }
+
+                    let uncompressed = (out.len() - block.start) as u64;
+                    if let Some(expected) = block.expected_compressed
+                        && expected != block.compressed
+                    {
+                        return Err(CORRUPT);
+                    }
+                    if let Some(expected) = block.expected_uncompressed
+                        && expected != uncompressed
+                    {
+                        return Err(DecompressError::SizeMismatch {
+                            format: Format::Xz,
+                            expected,
+                            actual: uncompressed,
+                        });
+                    }
+                    if !self.check.verify(&out[block.start..], &end[padding..]) {
+                        return Err(DecompressError::BadChecksum(Format::Xz));
+                    }
+
+                    self.blocks
+                        .push((unpadded + check_len as u64, uncompressed));
+                    consumed += padding + check_len;
+                    self.state = State::BlockHeader;
+                }
+                State::Index => match self.index_len(input)? {
+                    Some(index_len) => {
+                        consumed += index_len;
+                        self.state = State::Footer {
+                            index_len: index_len as u64,
+                        };
+                    }
+                    None => return Ok(Progress::more(consumed)),
+                },
+                State::Footer { index_len } => {
+                    let Some(footer) = input.get(..STREAM_FOOTER_LEN) else {
+                        return Ok(Progress::more(consumed));
+                    };
+
+                    let backward_size =
+                        (u32::from_le_bytes(footer[4..8].try_into().unwrap()) as u64 + 1) * 4;
+                    if crc32(&footer[4..10]).to_le_bytes() != footer[0..4]
+                        || footer[10..12] != FOOTER_MAGIC
+                        || footer[8..10] != self.flags
+                        || backward_size != *index_len
+                    {
+                        return Err(CORRUPT);
+                    }
+
+                    return Ok(Progress::done(consumed + STREAM_FOOTER_LEN));
+                }
+            }
+        }
+    }
+
+    fn parse_block_header(&self, header: &[u8], start: usize) -> Result<Block, DecompressError> {
+        let (header, crc) = header.split_at(header.len() - 4);
+        if crc32(header).to_le_bytes() != crc {
+            return Err(CORRUPT);
+        }
+
+        let flags = header[1];
+        if flags & BLOCK_RESERVED != 0 {
+            return Err(DecompressError::Unsupported(Format::Xz));
+        }
+
+        let mut pos = 2;
+        let expected_compressed = if flags & BLOCK_COMPRESSED_SIZE != 0 {
+            Some(read_vli(header, &mut pos)?.ok_or(CORRUPT)?)
+        } else {
+            None
+        };
+        let expected_uncompressed = if flags & BLOCK_UNCOMPRESSED_SIZE != 0 {
+            Some(read_vli(header, &mut pos)?.ok_or(CORRUPT)?)
+        } else {
+            None
+        };
+
+        // NOTE(aki): Anything other than plain LZMA2 means BCJ or delta filters in front of it,
+        // which `xz` only uses if you ask it to
+        let filters = (flags & BLOCK_FILTERS) + 1;
+        let id = read_vli(header, &mut pos)?.ok_or(CORRUPT)?;
+        if filters != 1 || id != FILTER_LZMA2 {
+            debug!(filters, id, "Unsupported xz filter chain");
+            return Err(DecompressError::Unsupported(Format::Xz));
+        }
+
+        let props_len = read_vli(header, &mut pos)?.ok_or(CORRUPT)?;
+        if props_len != 1 {
+            return Err(CORRUPT);
+        }
+        // The dictionary size, we don't need it as the output is the dictionary
+        match header.get(pos) {
+            Some(&dict_size) if dict_size <= super::lzma2::DICT_SIZE_PROP_MAX => pos += 1,
+            _ => return Err(CORRUPT),
+        }
+
+        if header[pos..].iter().any(|&byte| byte != 0) {
+            return Err(CORRUPT);
+        }
+
+        Ok(Block {
+            start,
+            header_len: header.len() + 4,
+            compressed: 0,
+            expected_compressed,
+            expected_uncompressed,
+        })
+    }
+
+    // Check the index against the blocks we saw, returns how long it is if it's all there
+    fn index_len(&self, data: &[u8]) -> Result<Option<usize>, DecompressError> {
+        // Skip the indicator byte
+        let mut pos = 1;
+
+        let Some(count) = read_vli(data, &mut pos)? else {
+            return Ok(None);
+        };
+        if count != self.blocks.len() as u64 {
+            return Err(CORRUPT);
+        }
+
+        for &(unpadded, uncompressed) in &self.blocks {
+            for expected in [unpadded, uncompressed] {
+                match read_vli(data, &mut pos)? {
+                    Some(value) if value == expected => {}
+                    Some(_) => return Err(CORRUPT),
+                    None => return Ok(None),
+                }
+            }
+        }
+
+        let len = pos + padding(pos as u64);
+        let Some(crc) = data.get(len..len + 4) else {
+            return Ok(None);
+        };
+        if data[pos..len].iter().any(|&byte| byte != 0) || crc32(&data[..len]).to_le_bytes() != crc
+        {
+            return Err(CORRUPT);
+        }
+
+        Ok(Some(len + 4))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tape::decompress::{Unpacker, testdata};
+
+    const CHECK_NONE: &[u8] = include_bytes!("testdata/check_none.xz");
+    const CHECK_CRC32: &[u8] = include_bytes!("testdata/check_crc32.xz");
+    const CHECK_CRC64: &[u8] = include_bytes!("testdata/check_crc64.xz");
+    const CHECK_SHA256: &[u8] = include_bytes!("testdata/check_sha256.xz");
+    const MULTI_BLOCK: &[u8] = include_bytes!("testdata/multi_block.xz");
+    const PROPS: &[u8] = include_bytes!("testdata/props.xz");
+    const UNCOMPRESSED: &[u8] = include_bytes!("testdata/uncompressed.xz");
+
+    fn decode(decoder: &mut XzDecoder, data: &[u8]) -> Result<Vec<u8>, DecompressError> {
+        let mut out = Vec::new();
+        let progress = decoder.decode(data, &mut out, true)?;
+        if !progress.done {
+            return Err(DecompressError::Truncated(Format::Xz));
+        }
+
+        assert_eq!(progress.consumed, data.len());
+        Ok(out)
+    }
+
+    // Where the check of the last block starts
+    fn last_check(data: &[u8], check_len: usize) -> usize {
+        let footer = &data[data.len() - STREAM_FOOTER_LEN..];
+        let index_len = (u32::from_le_bytes(footer[4..8].try_into().unwrap()) as usize + 1) * 4;
+        data.len() - STREAM_FOOTER_LEN - index_len - check_len
+    }
+
+    #[test]
+    fn crc64() {
+        let mut crc = Crc64::new();
+        crc.update(b"123456789");
+        assert_eq!(crc.finish(), 0x995D_C9BB_DF19_39FA);
+    }
+
+    #[test]
+    fn checks() {
+        let text = testdata::text(2000);
+
+        for (data, check) in [
+            (CHECK_NONE, Check::None),
+            (CHECK_CRC32, Check::Crc32),
+            (CHECK_CRC64, Check::Crc64),
+            (CHECK_SHA256, Check::Sha256),
+        ] {
+            let mut decoder = XzDecoder::new();
+            assert_eq!(decode(&mut decoder, data).unwrap(), text);
+            assert_eq!(decoder.check, check);
+            assert_eq!(decoder.blocks.len(), 1);
+        }
+    }
+
+    #[test]
+    fn multi_block() {
+        let mut decoder = XzDecoder::new();
+        assert_eq!(
+            decode(&mut decoder, MULTI_BLOCK).unwrap(),
+            testdata::text(2000)
+        );
+
+        let sizes: Vec<_> = decoder
+            .blocks
+            .iter()
+            .map(|&(_, uncompressed)| uncompressed)
+            .collect();
+        assert_eq!(sizes, [32768, 32768, 32768, 1696]);
+    }
+
+    #[test]
+    fn props() {
+        assert_eq!(
+            decode(&mut XzDecoder::new(), PROPS).unwrap(),
+            testdata::text(2000)
+        );
+    }
+
+    #[test]
+    fn uncompressed_chunk() {
+        let mut expected = testdata::noise(61440);
+        expected.extend(testdata::text(200));
+
+        assert_eq!(
+            decode(&mut XzDecoder::new(), UNCOMPRESSED).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn pieces() {
+        // However it's split up on the way in, the same thing comes out
+        let mut expected = testdata::text(2000);
+        expected.extend(testdata::noise(61440));
+        expected.extend(testdata::text(200));
+
+        for len in [1, 7, 512, 4096] {
+            let mut unpacker = Unpacker::new(Vec::new(), 0);
+            for piece in MULTI_BLOCK.chunks(len).chain(UNCOMPRESSED.chunks(len)) {
+                unpacker.push(piece).unwrap();
+            }
+
+            assert_eq!(unpacker.format(), Some(Format::Xz));
+            assert_eq!(unpacker.finish().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn bad_check() {
+        for (data, check_len) in [(CHECK_CRC32, 4), (CHECK_CRC64, 8), (CHECK_SHA256, 32)] {
+            let mut data = data.to_vec();
+            let pos = last_check(&data, check_len);
+            data[pos] ^= 0x01;
+
+            assert_eq!(
+                decode(&mut XzDecoder::new(), &data),
+                Err(DecompressError::BadChecksum(Format::Xz))
+            );
+        }
+    }
+
+    #[test]
+    fn unsupported() {
+        // A check type from the future, with its header CRC fixed up
+        let mut data = CHECK_NONE.to_vec();
+        data[7] = 0x10;
+        let crc = crc32(&data[6..8]);
+        data[8..12].copy_from_slice(&crc.to_le_bytes());
+
+        assert_eq!(
+            decode(&mut XzDecoder::new(), &data),
+            Err(DecompressError::Unsupported(Format::Xz))
+        );
+    }
+
+    #[test]
+    fn corrupt() {
+        // Between the header CRCs, the check, and the index, there isn't a byte that can be
+        // changed without it being noticed
+        for pos in 0..CHECK_CRC32.len() {
+            let mut data = CHECK_CRC32.to_vec();
+            data[pos] ^= 0x01;
+
+            assert!(
+                decode(&mut XzDecoder::new(), &data).is_err(),
+                "corrupting byte {pos} went unnoticed"
+            );
+        }
+    }
+
+    #[test]
+    fn truncated() {
+        for len in MAGIC.len()..CHECK_CRC64.len() {
+            let mut unpacker = Unpacker::new(Vec::new(), 0);
+            unpacker.push(&CHECK_CRC64[..len]).unwrap();
+
+            assert_eq!(
+                unpacker.finish(),
+                Err(DecompressError::Truncated(Format::Xz))
+            );
+        }
+    }
+}
@@ -0,0 +1,153 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// Zstandard (RFC 8878), by way of `ruzstd`.
+//
+// The decoder only gets anywhere once it has a whole block, which can be up to 128KiB, so
+// that's about as much of the compressed data as ends up held at once.
+
+use ruzstd::decoding::{FrameDecoder, errors::FrameDecoderError};
+use tracing::{debug, warn};
+
+use super::{CHUNK_LEN, DecompressError, Format, Progress, grow};
+
+pub const MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+
+// Skippable frames have a magic of 0x184D2A5?, then a length, and then that many bytes
+const SKIPPABLE_MAGIC: u32 = 0x184D_2A50;
+const SKIPPABLE_HEADER_LEN: usize = 8;
+
+// The longest a frame header can be
+const FRAME_HEADER_MAX: usize = 18;
+// The checksum after the last block, if there is one
+const CHECKSUM_LEN: usize = 4;
+
+const CORRUPT: DecompressError = DecompressError::Corrupt(Format::Zstd);
+
+pub fn is_skippable(data: &[u8]) -> bool {
+    data.get(..4)
+        .map(|magic| u32::from_le_bytes(magic.try_into().unwrap()) & !0xF == SKIPPABLE_MAGIC)
+        .unwrap_or(false)
+}
+
+// Decodes a single frame, the next one gets a new decoder
+pub struct ZstdDecoder {
+    decoder: FrameDecoder,
+    started: bool,
+    // How much of a skippable frame there is left to skip
+    skip: usize,
+}
+
+impl Default for ZstdDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ZstdDecoder {
+    pub fn new() -> Self {
+        Self {
+            decoder: FrameDecoder::new(),
+            started: false,
+            skip: 0,
+        }
+    }
+
+    pub fn decode(
+        &mut self,
+        input: &[u8],
+        out: &mut Vec<u8>,
+        last: bool,
+    ) -> Result<Progress, DecompressError> {
+        if !self.started {
+            // NOTE(aki): The header has to be there whole, the decoder can't pick up half way
+            if input.len() < FRAME_HEADER_MAX && !last {
+                return Ok(Progress::more(0));
+            }
+
+            if is_skippable(input) {
+                return self.skip(input);
+            }
+        }
+        if self.skip != 0 {
+            return self.skip(input);
+        }
+
+        let mut consumed = 0;
+
+        loop {
+            if self.started && self.decoder.is_finished() && self.decoder.can_collect() == 0 {
+                self.check()?;
+                return Ok(Progress::done(consumed));
+            }
+
+            let input = &input[consumed..];
+            // NOTE(aki): With fewer than the checksum's worth it says it took the checksum even
+            // though it didn't, so hold off until it's all there
+            if input.len() < CHECKSUM_LEN && !last {
+                return Ok(Progress::more(consumed));
+            }
+
+            let start = grow(out, CHUNK_LEN);
+            let result = self.decoder.decode_from_to(input, &mut out[start..]);
+            let (read, written) = match result {
+                Ok(progress) => progress,
+                Err(err) => {
+                    out.truncate(start);
+                    warn!("Failed to decode zstd frame: {}", err);
+                    return Err(match err {
+                        FrameDecoderError::ReadFrameHeaderError(_)
+                        | FrameDecoderError::FrameHeaderError(_) => {
+                            DecompressError::Unsupported(Format::Zstd)
+                        }
+                        _ => CORRUPT,
+                    });
+                }
+            };
+            out.truncate(start + written);
+
+            if !self.started {
+                self.started = true;
+                if let content_size @ 1.. = self.decoder.content_size() {
+                    out.reserve(content_size as usize);
+                }
+            }
+
+            let read = read.min(input.len());
+            consumed += read;
+            if read == 0 && written == 0 {
+                return Ok(Progress::more(consumed));
+            }
+        }
+    }
+
+    fn skip(&mut self, input: &[u8]) -> Result<Progress, DecompressError> {
+        if !self.started {
+            if input.len() < SKIPPABLE_HEADER_LEN {
+                return Err(DecompressError::Truncated(Format::Zstd));
+            }
+
+            self.started = true;
+            let len = u32::from_le_bytes(input[4..8].try_into().unwrap()) as usize;
+            debug!(len, "Skipping zstd skippable frame");
+            self.skip = SKIPPABLE_HEADER_LEN + len;
+        }
+
+        let len = self.skip.min(input.len());
+        self.skip -= len;
+        Ok(Progress {
+            consumed: len,
+            done: self.skip == 0,
+        })
+    }
+
+    fn check(&self) -> Result<(), DecompressError> {
+        match (
+            self.decoder.get_checksum_from_data(),
+            self.decoder.get_calculated_checksum(),
+        ) {
+            (Some(expected), Some(actual)) if expected != actual => {
+                Err(DecompressError::BadChecksum(Format::Zstd))
+            }
+            _ => Ok(()),
+        }
+    }
+}
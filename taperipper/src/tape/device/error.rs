@@ -0,0 +1,47 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// What can go wrong with a tape device, be it a real drive or an image.
+
+use core::fmt;
+
+use crate::tape::{scsi::sense::TapeError, transport::TransportError};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceError {
+    Transport(TransportError),
+    Tape(TapeError),
+    // The block on tape is larger than the buffer we gave it, the tape is past it now
+    BlockTooLarge { len: usize },
+    // Spaced or read past the last thing recorded on the tape
+    EndOfData,
+    // The drive never became ready
+    NotReady,
+    // The tape image is damaged or not what we expected
+    Image(&'static str),
+}
+
+impl fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceError::Transport(err) => write!(f, "{err}"),
+            DeviceError::Tape(err) => write!(f, "{err}"),
+            DeviceError::BlockTooLarge { len } => {
+                write!(f, "block too large for buffer ({len} bytes)")
+            }
+            DeviceError::EndOfData => write!(f, "end of data"),
+            DeviceError::NotReady => write!(f, "drive never became ready"),
+            DeviceError::Image(what) => write!(f, "bad tape image: {what}"),
+        }
+    }
+}
+
+impl From<TransportError> for DeviceError {
+    fn from(err: TransportError) -> Self {
+        DeviceError::Transport(err)
+    }
+}
+
+impl From<TapeError> for DeviceError {
+    fn from(err: TapeError) -> Self {
+        DeviceError::Tape(err)
+    }
+}
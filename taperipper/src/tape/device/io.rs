@@ -0,0 +1,87 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// The `TapeDevice` trait, and what comes back from one.
+
+use core::time::Duration;
+
+use crate::tape::device::DeviceError;
+
+// What a single read got us
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReadOutcome {
+    // A block of this many bytes is now in the buffer
+    Block(usize),
+    // We read over a filemark, the tape is now at the start of the next file
+    Filemark,
+    // There is nothing more recorded on the tape
+    EndOfData,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TapePosition {
+    // The tape file we're in, `None` if we've lost track
+    pub file: Option<u32>,
+    // The logical block number from the beginning of the partition, if known
+    pub block: Option<u64>,
+    pub beginning_of_partition: bool,
+    pub end_of_partition: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DeviceStatus {
+    pub ready: bool,
+    pub no_medium: bool,
+    pub write_protected: bool,
+    pub density: Option<u8>,
+    // Zero means variable block mode
+    pub block_len: u32,
+}
+
+// Something that can be read like a tape
+//
+// NOTE(aki): Only SCSI pass-thru controllers that can run commands in the background actually
+// give up the executor while the I/O is in flight. Squishy can't (`UsbIo` has no async bulk
+// transfers) and neither can images on the ESP, so with those every call blocks until it's
+// done, and the only yielding is while waiting on the drive to become ready.
+pub trait TapeDevice: Send {
+    // Something to tell the user what the device is
+    fn name(&self) -> &str;
+
+    // Read the next block into `buffer`, which should be at least as large as the largest
+    // block expected on the tape
+    fn read_block(
+        &mut self,
+        buffer: &mut [u8],
+    ) -> impl Future<Output = Result<ReadOutcome, DeviceError>> + Send;
+
+    // Space over `count` filemarks, negative counts space backwards
+    //
+    // Spacing forwards leaves the tape at the start of the next file, backwards leaves it
+    // just before the filemark, on the BOT side.
+    fn space_filemarks(
+        &mut self,
+        count: i32,
+    ) -> impl Future<Output = Result<(), DeviceError>> + Send;
+
+    // Space over `count` blocks, negative counts space backwards
+    //
+    // Like a drive, running into a filemark stops it just past the filemark.
+    fn space_blocks(&mut self, count: i32) -> impl Future<Output = Result<(), DeviceError>> + Send;
+
+    fn rewind(&mut self) -> impl Future<Output = Result<(), DeviceError>> + Send;
+
+    // Run the reel to the end and back to even out the tension on it, leaving it at BOT
+    fn retension(&mut self) -> impl Future<Output = Result<(), DeviceError>> + Send;
+
+    // Take the reel off-line so it can be dismounted
+    fn unload(&mut self) -> impl Future<Output = Result<(), DeviceError>> + Send;
+
+    // Wait up to `timeout` for a reel to be mounted and the drive to become ready, leaving it
+    // at BOT
+    //
+    // Nothing here checks that it's the reel we wanted, that's up to whatever is on it.
+    fn load(&mut self, timeout: Duration) -> impl Future<Output = Result<(), DeviceError>> + Send;
+
+    fn position(&mut self) -> impl Future<Output = Result<TapePosition, DeviceError>> + Send;
+
+    fn status(&mut self) -> impl Future<Output = Result<DeviceStatus, DeviceError>> + Send;
+}
@@ -0,0 +1,13 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// The tape device abstraction everything past this point is written against.
+//
+// Whether the reel is on a real drive at the end of a transport or is an image sitting on
+// the ESP, the boot pipeline only ever sees a `TapeDevice`. Operations are async, but how much
+// that buys depends on what's underneath, see `TapeDevice`.
+
+mod error;
+mod io;
+pub mod scsi;
+
+pub use error::DeviceError;
+pub use io::{DeviceStatus, ReadOutcome, TapeDevice, TapePosition};
@@ -0,0 +1,625 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// A `TapeDevice` for a real SSC drive at the end of a `Transport`.
+//
+// Commands go out through `Transport::submit` where the transport can do that, and we wait on
+// the event it hands back so other tasks get to run while the drive is busy. Where it can't,
+// `execute` blocks until the command is done, so anything that can take a while (rewinds,
+// loads) is issued with IMMED set either way, and we then poll the drive with TEST UNIT READY,
+// sleeping on the executor timer in between.
+
+use core::{fmt, time::Duration};
+
+use maitake::time;
+use tracing::{debug, info, trace, warn};
+
+use crate::{
+    runtime::io,
+    tape::{
+        device::{DeviceError, DeviceStatus, ReadOutcome, TapeDevice, TapePosition},
+        scsi::{
+            Command, commands,
+            data::{
+                BlockLimits, DensityDescriptor, DensitySupport, InquiryData, ModeParameters,
+                Position,
+            },
+            density::Density,
+            sense::{TapeCondition, TapeError},
+        },
+        transport::{Response, Transport, TransportError},
+    },
+};
+
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(500);
+const READY_TIMEOUT: Duration = Duration::from_mins(10);
+// How many times to retry a command the drive told us to try again later
+const MAX_RETRIES: usize = 5;
+const RETRY_DELAY: Duration = Duration::from_millis(250);
+const MODE_SENSE_LEN: u8 = 0xFF;
+
+// How the reel in the drive is recorded, as far as the drive can tell us
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TapeFormat {
+    // `None` if the drive didn't give us a block descriptor
+    pub density: Option<Density>,
+    // Zero means variable block mode
+    pub block_len: u32,
+    pub write_protected: bool,
+    // What the drive can do, empty if it doesn't support REPORT DENSITY SUPPORT
+    pub supported: Vec<DensityDescriptor>,
+}
+
+impl TapeFormat {
+    fn new(params: &ModeParameters, supported: Vec<DensityDescriptor>) -> Self {
+        Self {
+            density: params.density().map(Density),
+            block_len: params.block_len().unwrap_or(0),
+            write_protected: params.write_protected,
+            supported,
+        }
+    }
+
+    // Whether the drive says it can read `density`, if it told us what it supports
+    pub fn supports(&self, density: Density) -> Option<bool> {
+        (!self.supported.is_empty()).then(|| {
+            self.supported
+                .iter()
+                .any(|desc| desc.primary_code == density.0 || desc.secondary_code == density.0)
+        })
+    }
+}
+
+impl fmt::Display for TapeFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.density {
+            Some(density) => write!(f, "density {density}")?,
+            None => write!(f, "unknown density")?,
+        }
+
+        match self.block_len {
+            0 => write!(f, ", variable blocks")?,
+            len => write!(f, ", {len} byte blocks")?,
+        }
+
+        if self.write_protected {
+            write!(f, ", write protected")?;
+        }
+
+        if !self.supported.is_empty() {
+            write!(f, ", drive supports")?;
+            for (idx, desc) in self.supported.iter().enumerate() {
+                let sep = if idx == 0 { " " } else { ", " };
+                write!(f, "{sep}{:#04x}", desc.primary_code)?;
+                if !desc.name().is_empty() {
+                    write!(f, " ({})", desc.name())?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub struct ScsiTape<T: Transport> {
+    transport: T,
+    name: String,
+    inquiry: Option<InquiryData>,
+    limits: Option<BlockLimits>,
+    format: Option<TapeFormat>,
+    // A density we were told to use, which has to be set again for every reel
+    forced_density: Option<Density>,
+    // Zero for variable block mode
+    block_len: u32,
+    // The tape file we're in, `None` if we've lost track of it
+    file: Option<u32>,
+}
+
+impl<T: Transport> ScsiTape<T> {
+    // Wait for the drive to become ready and figure out how it's set up, if there's a reel in it
+    pub async fn open(transport: T) -> Result<Self, DeviceError> {
+        let mut tape = Self {
+            name: transport.name().to_string(),
+            transport,
+            inquiry: None,
+            limits: None,
+            format: None,
+            forced_density: None,
+            block_len: 0,
+            file: None,
+        };
+
+        tape.inquiry = tape.transport.inquiry().ok();
+        if let Some(inquiry) = &tape.inquiry {
+            debug!(device = tape.name.as_str(), "{}", inquiry);
+        }
+
+        // NOTE(aki): An empty drive is still a drive, it's up to whoever uses it to notice
+        // there's no reel in it and move on
+        match tape.wait_ready(READY_TIMEOUT).await {
+            Ok(()) => {}
+            Err(DeviceError::Tape(TapeError::NotReady {
+                no_medium: true, ..
+            })) => {
+                debug!(device = tape.name.as_str(), "Drive is empty");
+                return Ok(tape);
+            }
+            Err(err) => return Err(err),
+        }
+
+        let mut limits = [0u8; commands::BLOCK_LIMITS_LEN];
+        let (len, _) = tape
+            .command(&commands::read_block_limits(), &mut limits)
+            .await?;
+        tape.limits = BlockLimits::parse(&limits[..len]).ok();
+        if let Some(limits) = &tape.limits {
+            debug!(
+                device = tape.name.as_str(),
+                min_len = limits.min_len,
+                max_len = limits.max_len,
+                granularity = limits.granularity,
+                fixed_only = limits.is_fixed(),
+                "Block limits"
+            );
+        }
+
+        tape.detect_format().await?;
+
+        debug!(
+            device = tape.name.as_str(),
+            block_len = tape.block_len,
+            "Drive ready"
+        );
+
+        if let Ok(pos) = tape.position().await {
+            if pos.beginning_of_partition {
+                tape.file = Some(0);
+            }
+        }
+
+        Ok(tape)
+    }
+
+    pub fn inquiry(&self) -> Option<&InquiryData> {
+        self.inquiry.as_ref()
+    }
+
+    pub fn format(&self) -> Option<&TapeFormat> {
+        self.format.as_ref()
+    }
+
+    // Send a single command, waiting on it in the background if the transport can
+    async fn execute(
+        &mut self,
+        command: &Command,
+        data: &mut [u8],
+    ) -> Result<Response, DeviceError> {
+        let Some(event) = self.transport.submit(command, data)? else {
+            return Ok(self.transport.execute(command, data)?);
+        };
+
+        io::wait_for_event(event)
+            .await
+            .map_err(TransportError::from)?;
+        Ok(self.transport.complete(command, data)?)
+    }
+
+    // Run a command, retrying it if the drive asks us to, returns how many bytes were
+    // transferred and any condition the drive reported
+    pub(crate) async fn command(
+        &mut self,
+        command: &Command,
+        data: &mut [u8],
+    ) -> Result<(usize, Option<TapeCondition>), DeviceError> {
+        let mut attempt = 0;
+
+        loop {
+            let resp = self.execute(command, data).await?;
+
+            match resp.check(command) {
+                Ok(condition) => return Ok((resp.transferred, condition)),
+                Err(err) if err.is_transient() && attempt < MAX_RETRIES => {
+                    attempt += 1;
+                    trace!(
+                        device = self.name.as_str(),
+                        command = command.name(),
+                        attempt,
+                        "Retrying: {}",
+                        err
+                    );
+
+                    // A unit attention means the drive may have been reset or the reel changed
+                    if let TapeError::UnitAttention { .. } = err {
+                        self.file = None;
+                    }
+
+                    time::sleep(RETRY_DELAY * attempt as u32).await;
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    // Poll TEST UNIT READY until the drive is ready or we give up
+    //
+    // Only a drive that says it's on its way to being ready is waited on, with no reel in it
+    // there is nothing to wait for, so that comes straight back as an error.
+    pub async fn wait_ready(&mut self, timeout: Duration) -> Result<(), DeviceError> {
+        let cmd = commands::test_unit_ready();
+        let attempts = (timeout.as_millis() / READY_POLL_INTERVAL.as_millis()).max(1);
+
+        for _ in 0..attempts {
+            let resp = self.execute(&cmd, &mut []).await?;
+
+            match resp.check(&cmd) {
+                Ok(_) => return Ok(()),
+                Err(TapeError::UnitAttention { .. }) => {
+                    self.file = None;
+                }
+                Err(err) if err.is_transient() => {}
+                Err(err) => return Err(err.into()),
+            }
+
+            time::sleep(READY_POLL_INTERVAL).await;
+        }
+
+        warn!(device = self.name.as_str(), "Drive never became ready");
+        Err(DeviceError::NotReady)
+    }
+
+    // Poll TEST UNIT READY until someone mounts a reel
+    //
+    // Unlike `wait_ready` this sits through the drive saying it needs someone to come and do
+    // something, as that's exactly what we're waiting on.
+    async fn wait_mounted(&mut self, timeout: Duration) -> Result<(), DeviceError> {
+        let cmd = commands::test_unit_ready();
+        let attempts = (timeout.as_millis() / READY_POLL_INTERVAL.as_millis()).max(1);
+
+        for _ in 0..attempts {
+            let resp = self.execute(&cmd, &mut []).await?;
+
+            match resp.check(&cmd) {
+                Ok(_) => return Ok(()),
+                Err(TapeError::UnitAttention { .. }) => {
+                    self.file = None;
+                }
+                Err(TapeError::NotReady { .. }) => {}
+                Err(err) if err.is_transient() => {}
+                Err(err) => return Err(err.into()),
+            }
+
+            time::sleep(READY_POLL_INTERVAL).await;
+        }
+
+        warn!(device = self.name.as_str(), "No reel was mounted");
+        Err(DeviceError::NotReady)
+    }
+
+    pub async fn mode_sense(&mut self) -> Result<Option<ModeParameters>, DeviceError> {
+        let cmd = commands::mode_sense6(false, 0x00, MODE_SENSE_LEN);
+        let mut data = [0u8; MODE_SENSE_LEN as usize];
+
+        let (len, _) = self.command(&cmd, &mut data).await?;
+        Ok(ModeParameters::parse(&data[..len]).ok())
+    }
+
+    // Find out what density the reel is and what densities the drive can do
+    pub async fn detect_format(&mut self) -> Result<&TapeFormat, DeviceError> {
+        let params = self.mode_sense().await?.unwrap_or_default();
+
+        // What the drive can do doesn't change, so we only need to ask the once
+        let supported = match &self.format {
+            Some(format) => format.supported.clone(),
+            None => self.density_support().await?,
+        };
+
+        let format = TapeFormat::new(&params, supported);
+        self.block_len = format.block_len;
+
+        info!(
+            device = self.name.as_str(),
+            density = ?format.density.map(|density| density.0),
+            block_len = format.block_len,
+            write_protected = format.write_protected,
+            "Tape format: {}",
+            format
+        );
+        if let Some(density) = format.density
+            && density.0 != 0
+            && density.description().is_none()
+        {
+            warn!(
+                device = self.name.as_str(),
+                "Unknown density code {}", density
+            );
+        }
+
+        Ok(self.format.insert(format))
+    }
+
+    async fn density_support(&mut self) -> Result<Vec<DensityDescriptor>, DeviceError> {
+        let cmd = commands::report_density_support(false, commands::DENSITY_SUPPORT_LEN);
+        let mut data = vec![0u8; commands::DENSITY_SUPPORT_LEN as usize];
+
+        // NOTE(aki): REPORT DENSITY SUPPORT came along with SSC, plenty of older drives
+        // don't know it
+        match self.command(&cmd, &mut data).await {
+            Ok((len, _)) => Ok(DensitySupport::parse(&data[..len])
+                .map(|support| support.descriptors)
+                .unwrap_or_default()),
+            Err(DeviceError::Tape(TapeError::IllegalRequest(_))) => Ok(Vec::new()),
+            Err(err) => Err(err),
+        }
+    }
+
+    // Tell the drive what density the reel is, for drives that can't work it out
+    //
+    // NOTE(aki): Most drives figure out the density on their own when reading, and only pay
+    // attention to this when writing from BOT, but some multi-density drives need telling.
+    pub async fn set_density(&mut self, density: Density) -> Result<(), DeviceError> {
+        if let Some(false) = self
+            .format
+            .as_ref()
+            .and_then(|format| format.supports(density))
+        {
+            warn!(
+                device = self.name.as_str(),
+                "Drive doesn't list density {} as supported, trying anyway", density
+            );
+        }
+
+        // Only touch the block descriptor, the pages don't all go back in how they came out
+        let mut params = self.mode_sense().await?.unwrap_or_default();
+        params.pages.clear();
+        params.medium_type = 0;
+
+        let desc = params.block_descriptor.get_or_insert_default();
+        desc.density = density.0;
+        desc.blocks = 0;
+        desc.block_len = self.block_len;
+
+        let mut data = params.to_bytes();
+        let cmd = commands::mode_select6(true, data.len() as u8);
+        self.command(&cmd, &mut data).await?;
+
+        info!(
+            device = self.name.as_str(),
+            "Forced tape density to {}", density
+        );
+        self.forced_density = Some(density);
+        self.detect_format().await?;
+
+        Ok(())
+    }
+
+    fn max_transfer(&self, buffer_len: usize) -> u32 {
+        let max = self
+            .limits
+            .map(|limits| limits.max_len)
+            .filter(|&max| max != 0)
+            .unwrap_or(commands::READ6_MAX_LEN);
+
+        (buffer_len as u32).min(max).min(commands::READ6_MAX_LEN)
+    }
+}
+
+impl<T: Transport> TapeDevice for ScsiTape<T> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn read_block(&mut self, buffer: &mut [u8]) -> Result<ReadOutcome, DeviceError> {
+        let (cmd, requested) = if self.block_len == 0 {
+            let max = self.max_transfer(buffer.len());
+            (commands::read_variable(max, true), max as i64)
+        } else {
+            if buffer.len() < self.block_len as usize {
+                // NOTE(aki): Space over it rather than leave it where it is, so we end up past
+                // it like with any other block that doesn't fit
+                let cmd = commands::space(commands::SpaceCode::Blocks, 1);
+                return match self.command(&cmd, &mut []).await? {
+                    (_, Some(TapeCondition::Filemark { .. })) => {
+                        self.file = self.file.map(|file| file + 1);
+                        Ok(ReadOutcome::Filemark)
+                    }
+                    (_, Some(TapeCondition::EndOfData)) => Ok(ReadOutcome::EndOfData),
+                    _ => Err(DeviceError::BlockTooLarge {
+                        len: self.block_len as usize,
+                    }),
+                };
+            }
+            // NOTE(aki): Some drives reject SILI in fixed block mode
+            (
+                commands::read_fixed(1, self.block_len, false),
+                self.block_len as i64,
+            )
+        };
+
+        let (len, condition) = self.command(&cmd, buffer).await?;
+
+        match condition {
+            None | Some(TapeCondition::Recovered) | Some(TapeCondition::EndOfMedium { .. }) => {
+                Ok(ReadOutcome::Block(len))
+            }
+            Some(TapeCondition::Filemark { .. }) => {
+                self.file = self.file.map(|file| file + 1);
+                Ok(ReadOutcome::Filemark)
+            }
+            Some(TapeCondition::EndOfData) => Ok(ReadOutcome::EndOfData),
+            // NOTE(aki): In fixed block mode the residue is in blocks, not bytes, so all it
+            // tells us is that the block wasn't `block_len`. If it was short the transfer
+            // count is how long it was, if it was long we only know it didn't fit.
+            Some(TapeCondition::IncorrectLength { .. }) if self.block_len != 0 => {
+                if len < self.block_len as usize {
+                    Ok(ReadOutcome::Block(len))
+                } else {
+                    Err(DeviceError::BlockTooLarge {
+                        len: self.block_len as usize,
+                    })
+                }
+            }
+            // A short block, the residue is how much less than asked for we got
+            Some(TapeCondition::IncorrectLength { residue }) if residue >= 0 => {
+                Ok(ReadOutcome::Block((requested - residue) as usize))
+            }
+            Some(TapeCondition::IncorrectLength { residue }) => Err(DeviceError::BlockTooLarge {
+                len: (requested - residue) as usize,
+            }),
+            Some(TapeCondition::BeginningOfMedium) => Ok(ReadOutcome::Block(len)),
+        }
+    }
+
+    async fn space_filemarks(&mut self, count: i32) -> Result<(), DeviceError> {
+        let cmd = commands::space(commands::SpaceCode::Filemarks, count);
+
+        match self.command(&cmd, &mut []).await {
+            Ok((_, None)) => {
+                self.file = self.file.map(|file| file.saturating_add_signed(count));
+                Ok(())
+            }
+            Ok((_, Some(TapeCondition::BeginningOfMedium))) => {
+                self.file = Some(0);
+                Ok(())
+            }
+            Ok((_, Some(TapeCondition::EndOfData))) => {
+                self.file = None;
+                Err(DeviceError::EndOfData)
+            }
+            Ok((_, Some(condition))) => {
+                trace!(device = self.name.as_str(), "SPACE: {}", condition);
+                self.file = self.file.map(|file| file.saturating_add_signed(count));
+                Ok(())
+            }
+            Err(err) => {
+                self.file = None;
+                Err(err)
+            }
+        }
+    }
+
+    async fn space_blocks(&mut self, count: i32) -> Result<(), DeviceError> {
+        let cmd = commands::space(commands::SpaceCode::Blocks, count);
+
+        match self.command(&cmd, &mut []).await {
+            Ok((_, Some(TapeCondition::Filemark { .. }))) => {
+                // We ran into a filemark before we ran out of blocks, and are now past it
+                self.file = self
+                    .file
+                    .map(|file| file.saturating_add_signed(count.signum()));
+                Ok(())
+            }
+            Ok((_, Some(TapeCondition::BeginningOfMedium))) => {
+                self.file = Some(0);
+                Ok(())
+            }
+            Ok((_, Some(TapeCondition::EndOfData))) => {
+                self.file = None;
+                Err(DeviceError::EndOfData)
+            }
+            Ok(_) => Ok(()),
+            Err(err) => {
+                self.file = None;
+                Err(err)
+            }
+        }
+    }
+
+    async fn rewind(&mut self) -> Result<(), DeviceError> {
+        debug!(device = self.name.as_str(), "Rewinding");
+
+        self.command(&commands::rewind(true), &mut []).await?;
+        self.wait_ready(READY_TIMEOUT).await?;
+        self.file = Some(0);
+
+        Ok(())
+    }
+
+    async fn retension(&mut self) -> Result<(), DeviceError> {
+        debug!(device = self.name.as_str(), "Retensioning");
+
+        self.command(&commands::load(true, true), &mut []).await?;
+        self.wait_ready(READY_TIMEOUT).await?;
+        self.file = Some(0);
+
+        Ok(())
+    }
+
+    async fn unload(&mut self) -> Result<(), DeviceError> {
+        debug!(device = self.name.as_str(), "Unloading");
+
+        // NOTE(aki): Not IMMED, there's nothing to wait on afterwards, the drive just goes
+        // not ready until someone mounts another reel
+        self.command(&commands::unload(false), &mut []).await?;
+        self.file = None;
+
+        Ok(())
+    }
+
+    async fn load(&mut self, timeout: Duration) -> Result<(), DeviceError> {
+        self.wait_mounted(timeout).await?;
+
+        // Some drives come ready as soon as the reel is threaded, make sure it's at BOT
+        self.command(&commands::load(true, false), &mut []).await?;
+        self.wait_ready(READY_TIMEOUT).await?;
+        self.file = Some(0);
+
+        debug!(device = self.name.as_str(), "Reel loaded");
+
+        // The new reel may well not be recorded the same as the last one
+        match self.forced_density {
+            Some(density) => self.set_density(density).await,
+            None => self.detect_format().await.map(|_| ()),
+        }
+    }
+
+    async fn position(&mut self) -> Result<TapePosition, DeviceError> {
+        let mut data = [0u8; commands::READ_POSITION_SHORT_LEN];
+        let (len, _) = self.command(&commands::read_position(), &mut data).await?;
+
+        let pos = Position::parse(&data[..len]).map_err(TapeError::from)?;
+        trace!(
+            device = self.name.as_str(),
+            partition = pos.partition,
+            first_block = ?pos.first_block,
+            last_block = ?pos.last_block,
+            blocks_in_buffer = ?pos.blocks_in_buffer,
+            bytes_in_buffer = ?pos.bytes_in_buffer,
+            "Position"
+        );
+        if pos.position_error {
+            warn!(
+                device = self.name.as_str(),
+                "Drive isn't sure where it is on the tape"
+            );
+        }
+
+        Ok(TapePosition {
+            file: self.file,
+            block: pos.first_block.map(u64::from),
+            beginning_of_partition: pos.beginning_of_partition,
+            end_of_partition: pos.end_of_partition,
+        })
+    }
+
+    async fn status(&mut self) -> Result<DeviceStatus, DeviceError> {
+        let cmd = commands::test_unit_ready();
+        let resp = self.execute(&cmd, &mut []).await?;
+
+        let mut status = DeviceStatus::default();
+        match resp.check(&cmd) {
+            Ok(_) => status.ready = true,
+            Err(TapeError::NotReady { no_medium, .. }) => {
+                status.no_medium = no_medium;
+                return Ok(status);
+            }
+            Err(err) if err.is_transient() => return Ok(status),
+            Err(err) => return Err(err.into()),
+        }
+
+        if let Some(params) = self.mode_sense().await? {
+            status.write_protected = params.write_protected;
+            status.density = params.density();
+            status.block_len = params.block_len().unwrap_or(self.block_len);
+        }
+
+        Ok(status)
+    }
+}
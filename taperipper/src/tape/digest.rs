@@ -0,0 +1,295 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// Checksumming payloads as they come off of the tape.
+//
+// A flipped bit on tape can give you a kernel that boots fine but is quietly wrong, so every
+// payload gets a SHA-256 and a CRC32 worked out a chunk at a time as it's read, rather than
+// going back over it once it's all in memory. The CRC32 is only there as a quick check for
+// people comparing against what the mastering tools printed out, the SHA-256 is the one that
+// counts.
+
+use core::fmt;
+
+use sha2::{Digest as _, Sha256};
+
+pub const SHA256_LEN: usize = 32;
+
+// File names of a `sha256sum` style list of digests on a tape
+pub const SUMS_NAMES: &[&str] = &["SHA256SUMS", "sha256sums"];
+
+// CRC-32/ISO-HDLC, the one zlib and everyone else uses
+const CRC32_POLY: u32 = 0xEDB8_8320;
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut idx = 0;
+
+    while idx < 256 {
+        let mut crc = idx as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ CRC32_POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[idx] = crc;
+        idx += 1;
+    }
+
+    table
+};
+
+#[derive(Clone, Copy, Debug)]
+pub struct Crc32(u32);
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self(!0)
+    }
+}
+
+impl Crc32 {
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.0 = CRC32_TABLE[((self.0 ^ byte as u32) & 0xFF) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.0
+    }
+}
+
+// What a payload came out as
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Digest {
+    pub sha256: [u8; SHA256_LEN],
+    pub crc32: u32,
+    pub len: u64,
+}
+
+impl Digest {
+    // Work out the digest of something already in memory
+    pub fn of(data: &[u8]) -> Self {
+        let mut hasher = Hasher::new();
+        hasher.update(data);
+        hasher.finish()
+    }
+
+    pub fn sha256_hex(&self) -> String {
+        to_hex(&self.sha256)
+    }
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "sha256:{} crc32:{:08x} ({} bytes)",
+            self.sha256_hex(),
+            self.crc32,
+            self.len
+        )
+    }
+}
+
+// What something says a payload should come out as, and where it said so
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Expected {
+    pub sha256: Option<[u8; SHA256_LEN]>,
+    pub crc32: Option<u32>,
+    pub source: &'static str,
+}
+
+impl Expected {
+    pub fn sha256(sha256: [u8; SHA256_LEN], source: &'static str) -> Self {
+        Self {
+            sha256: Some(sha256),
+            crc32: None,
+            source,
+        }
+    }
+
+    pub fn matches(&self, digest: &Digest) -> bool {
+        self.sha256.is_none_or(|sha256| sha256 == digest.sha256)
+            && self.crc32.is_none_or(|crc32| crc32 == digest.crc32)
+    }
+}
+
+// Works out a `Digest` a chunk at a time
+#[derive(Clone)]
+pub struct Hasher {
+    sha256: Sha256,
+    crc32: Crc32,
+    len: u64,
+}
+
+impl Default for Hasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hasher {
+    pub fn new() -> Self {
+        Self {
+            sha256: Sha256::new(),
+            crc32: Crc32::default(),
+            len: 0,
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.sha256.update(data);
+        self.crc32.update(data);
+        self.len += data.len() as u64;
+    }
+
+    pub fn finish(self) -> Digest {
+        Digest {
+            sha256: self.sha256.finalize().into(),
+            crc32: self.crc32.finish(),
+            len: self.len,
+        }
+    }
+}
+
+pub fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+// Parse a hex string of exactly `N` bytes, either case
+pub fn parse_hex<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    let hex = hex.trim();
+    // NOTE(aki): `from_str_radix` takes a leading '+', so check the digits ourselves
+    if hex.len() != N * 2 || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let mut data = [0u8; N];
+    for (byte, pair) in data.iter_mut().zip(hex.as_bytes().as_chunks::<2>().0) {
+        *byte = u8::from_str_radix(str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+
+    Some(data)
+}
+
+// Parse the output of `sha256sum`, a digest and a file name per line
+//
+// Only the last component of the path is kept, as that is all we match payloads on. Lines
+// that don't make sense are skipped.
+pub fn parse_sums(data: &[u8]) -> Vec<(String, [u8; SHA256_LEN])> {
+    String::from_utf8_lossy(data)
+        .lines()
+        .filter_map(|line| {
+            let (digest, name) = line.trim().split_once(char::is_whitespace)?;
+            let digest = parse_hex(digest)?;
+            // A leading '*' means it was hashed in binary mode, which is all we care about
+            let name = name.trim_start().trim_start_matches('*');
+            let name = name.rsplit('/').next().unwrap_or(name);
+
+            (!name.is_empty()).then(|| (name.to_string(), digest))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ABC_SHA256: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+    const EMPTY_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    #[test]
+    fn check_values() {
+        let mut crc32 = Crc32::default();
+        crc32.update(b"123456789");
+        assert_eq!(crc32.finish(), 0xCBF4_3926);
+        assert_eq!(Crc32::default().finish(), 0);
+
+        let digest = Digest::of(b"abc");
+        assert_eq!(digest.sha256_hex(), ABC_SHA256);
+        assert_eq!(digest.crc32, 0x3524_41C2);
+        assert_eq!(digest.len, 3);
+
+        assert_eq!(Digest::of(b"").sha256_hex(), EMPTY_SHA256);
+    }
+
+    #[test]
+    fn chunked() {
+        let data: Vec<u8> = (0..10_000u32)
+            .map(|idx| (idx * 7 + idx / 251) as u8)
+            .collect();
+        let whole = Digest::of(&data);
+
+        for chunk_len in [1, 3, 64, 1000, 4096] {
+            let mut hasher = Hasher::new();
+            for chunk in data.chunks(chunk_len) {
+                hasher.update(chunk);
+            }
+            assert_eq!(hasher.finish(), whole, "{chunk_len}");
+        }
+    }
+
+    #[test]
+    fn hex() {
+        let sha256 = parse_hex::<SHA256_LEN>(ABC_SHA256).unwrap();
+        assert_eq!(to_hex(&sha256), ABC_SHA256);
+        assert_eq!(
+            parse_hex::<SHA256_LEN>(&ABC_SHA256.to_uppercase()),
+            Some(sha256)
+        );
+        assert_eq!(parse_hex::<2>(" beef\n"), Some([0xBE, 0xEF]));
+
+        assert_eq!(parse_hex::<2>("bee"), None);
+        assert_eq!(parse_hex::<2>("beefed"), None);
+        assert_eq!(parse_hex::<2>("beeg"), None);
+        assert_eq!(parse_hex::<2>("+bef"), None);
+        assert_eq!(parse_hex::<2>("béf"), None);
+    }
+
+    #[test]
+    fn sums() {
+        // `sha256sum -b boot/vmlinuz initrd.img`, and then some
+        let sums = format!(
+            "{ABC_SHA256} *boot/vmlinuz\n\
+             \n\
+             {EMPTY_SHA256} *initrd.img\r\n\
+             {ABC_SHA256}  cmdline\n\
+             not-a-digest  README\n\
+             {}  short\n\
+             {EMPTY_SHA256}\n\
+             {EMPTY_SHA256}  dir/\n",
+            &ABC_SHA256[..62],
+        );
+
+        let abc = parse_hex(ABC_SHA256).unwrap();
+        let empty = parse_hex(EMPTY_SHA256).unwrap();
+        assert_eq!(
+            parse_sums(sums.as_bytes()),
+            [
+                ("vmlinuz".to_string(), abc),
+                ("initrd.img".to_string(), empty),
+                ("cmdline".to_string(), abc),
+            ]
+        );
+    }
+
+    #[test]
+    fn expected() {
+        let digest = Digest::of(b"abc");
+        let sha256 = parse_hex(ABC_SHA256).unwrap();
+
+        assert!(Expected::sha256(sha256, "test").matches(&digest));
+        assert!(!Expected::sha256([0; SHA256_LEN], "test").matches(&digest));
+
+        let crc32 = Expected {
+            sha256: None,
+            crc32: Some(0x3524_41C2),
+            source: "test",
+        };
+        assert!(crc32.matches(&digest));
+        assert!(!crc32.matches(&Digest::of(b"abd")));
+    }
+}
@@ -0,0 +1,483 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// ANSI X3.27 standard tape labels.
+//
+// A labelled volume starts with a VOL1 label, and every file on it is wrapped in a header
+// label group (HDR1, HDR2, ...) and a trailer label group (EOF1, EOF2, ...), each group
+// being a run of 80 byte records ended by a filemark:
+//
+//   VOL1 HDR1 HDR2 * data * EOF1 EOF2 * HDR1 HDR2 * data * EOF1 EOF2 * *
+//
+// Two filemarks in a row after a trailer group is the end of the volume. If a file doesn't
+// fit on the reel the trailer is EOV1/EOV2 instead and the file carries on on the next one.
+//
+// IBM standard labels are laid out the same way, just in EBCDIC and with a few fields moved
+// around, so they're parsed into the same types here, with the differences in `ibm`. Walking
+// the volume itself is up to `labelled`.
+//
+// see: https://www.ecma-international.org/wp-content/uploads/ECMA-13_4th_edition_december_1985.pdf
+
+use core::fmt;
+
+use tracing::trace;
+
+use crate::tape::{device::DeviceError, format::ibm};
+
+pub const LABEL_LEN: usize = 80;
+pub const FILE_IDENTIFIER_LEN: usize = 17;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LabelError {
+    Device(DeviceError),
+    // The tape doesn't start with a VOL1 label
+    NotLabelled,
+    // A label record was not where one was expected, or was the wrong size
+    Unexpected(&'static str),
+    Field(&'static str),
+    // The block count in the trailer doesn't match what we read
+    BlockCount { expected: u64, actual: u64 },
+}
+
+impl fmt::Display for LabelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LabelError::Device(err) => write!(f, "{err}"),
+            LabelError::NotLabelled => write!(f, "tape is not labelled"),
+            LabelError::Unexpected(what) => write!(f, "unexpected label: {what}"),
+            LabelError::Field(what) => write!(f, "bad label field: {what}"),
+            LabelError::BlockCount { expected, actual } => write!(
+                f,
+                "block count mismatch, trailer says {expected} but read {actual}"
+            ),
+        }
+    }
+}
+
+impl From<DeviceError> for LabelError {
+    fn from(err: DeviceError) -> Self {
+        LabelError::Device(err)
+    }
+}
+
+// Which flavour of labels a volume has
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LabelStandard {
+    #[default]
+    Ansi,
+    // IBM standard labels, in EBCDIC
+    Ibm,
+}
+
+// A date in a label, stored as " yyddd" with the first character picking the century
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LabelDate {
+    pub year: u16,
+    pub day: u16,
+}
+
+impl LabelDate {
+    pub(crate) fn parse(field: &[u8]) -> Option<Self> {
+        let century = match field[0] {
+            b' ' => 1900,
+            digit @ b'0'..=b'9' => 2000 + (digit - b'0') as u16 * 100,
+            _ => return None,
+        };
+
+        let year = parse_number(&field[1..3])? as u16;
+        let day = parse_number(&field[3..6])? as u16;
+
+        // Day zero means no date at all
+        (day != 0).then_some(Self {
+            year: century + year,
+            day,
+        })
+    }
+}
+
+impl fmt::Display for LabelDate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}-{:03}", self.year, self.day)
+    }
+}
+
+// Which group a file label came from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LabelGroup {
+    Header,
+    EndOfFile,
+    EndOfVolume,
+}
+
+impl LabelGroup {
+    pub(crate) fn from_id(id: &[u8]) -> Option<Self> {
+        match id {
+            b"HDR" => Some(LabelGroup::Header),
+            b"EOF" => Some(LabelGroup::EndOfFile),
+            b"EOV" => Some(LabelGroup::EndOfVolume),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordFormat {
+    Fixed,
+    // Variable length, with a 4 digit ASCII length prefix on ANSI tapes, or a binary
+    // record descriptor word on IBM ones
+    Variable,
+    // Spanned records, which can cross block boundaries
+    Spanned,
+    Undefined,
+}
+
+impl RecordFormat {
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            b'F' => Some(RecordFormat::Fixed),
+            b'D' => Some(RecordFormat::Variable),
+            b'S' => Some(RecordFormat::Spanned),
+            b'U' => Some(RecordFormat::Undefined),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VolumeLabel {
+    pub serial: String,
+    pub accessibility: u8,
+    pub owner: String,
+    // Which version of the standard the volume was written to
+    pub version: u8,
+}
+
+impl VolumeLabel {
+    pub fn parse(label: &[u8]) -> Result<Self, LabelError> {
+        if label.len() != LABEL_LEN || &label[0..4] != b"VOL1" {
+            return Err(LabelError::NotLabelled);
+        }
+
+        Ok(Self {
+            serial: field_str(&label[4..10]),
+            accessibility: label[10],
+            owner: field_str(&label[37..51]),
+            version: label[79],
+        })
+    }
+}
+
+// HDR1, EOF1, and EOV1 all share the same layout
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileLabel1 {
+    pub group: LabelGroup,
+    pub file_identifier: String,
+    pub file_set_identifier: String,
+    pub section: u32,
+    pub sequence: u32,
+    pub created: Option<LabelDate>,
+    // Always zero in a header, the number of blocks in the file in a trailer
+    pub block_count: u64,
+    pub system: String,
+}
+
+impl FileLabel1 {
+    pub fn parse(label: &[u8]) -> Result<Self, LabelError> {
+        if label.len() != LABEL_LEN || label[3] != b'1' {
+            return Err(LabelError::Unexpected("not a file label 1"));
+        }
+
+        let group = LabelGroup::from_id(&label[0..3])
+            .ok_or(LabelError::Unexpected("not a file label 1"))?;
+
+        Ok(Self {
+            group,
+            file_identifier: field_str(&label[4..21]),
+            file_set_identifier: field_str(&label[21..27]),
+            section: parse_number(&label[27..31]).unwrap_or(1) as u32,
+            sequence: parse_number(&label[31..35]).ok_or(LabelError::Field("sequence"))? as u32,
+            created: LabelDate::parse(&label[41..47]),
+            block_count: parse_number(&label[54..60]).unwrap_or(0),
+            system: field_str(&label[60..73]),
+        })
+    }
+}
+
+// HDR2, EOF2, and EOV2
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileLabel2 {
+    pub group: LabelGroup,
+    pub record_format: Option<RecordFormat>,
+    pub block_len: u32,
+    pub record_len: u32,
+    // Bytes at the start of each block before the first record
+    pub buffer_offset: u32,
+    // More than one record per block
+    pub blocked: bool,
+}
+
+impl FileLabel2 {
+    pub fn parse(label: &[u8]) -> Result<Self, LabelError> {
+        if label.len() != LABEL_LEN || label[3] != b'2' {
+            return Err(LabelError::Unexpected("not a file label 2"));
+        }
+
+        let group = LabelGroup::from_id(&label[0..3])
+            .ok_or(LabelError::Unexpected("not a file label 2"))?;
+
+        let record_format = RecordFormat::from_byte(label[4]);
+        let block_len =
+            parse_number(&label[5..10]).ok_or(LabelError::Field("block length"))? as u32;
+        let record_len = parse_number(&label[10..15]).unwrap_or(0) as u32;
+
+        Ok(Self {
+            group,
+            record_format,
+            block_len,
+            record_len,
+            buffer_offset: parse_number(&label[50..52]).unwrap_or(0) as u32,
+            // ANSI has no flag for this, it's blocked if more than one record fits
+            blocked: record_len != 0 && block_len > record_len,
+        })
+    }
+}
+
+// Everything we know about a file from one of its label groups
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileLabels {
+    pub label1: FileLabel1,
+    pub label2: Option<FileLabel2>,
+}
+
+impl FileLabels {
+    pub fn file_identifier(&self) -> &str {
+        &self.label1.file_identifier
+    }
+
+    pub fn sequence(&self) -> u32 {
+        self.label1.sequence
+    }
+
+    // Whether the file carries on on another volume
+    pub fn continues(&self) -> bool {
+        self.label1.group == LabelGroup::EndOfVolume
+    }
+
+    // Compare against a file identifier the way the user would expect, ignoring the padding
+    pub fn matches(&self, identifier: &str) -> bool {
+        let identifier = identifier.trim();
+        identifier.len() <= FILE_IDENTIFIER_LEN
+            && self.file_identifier().eq_ignore_ascii_case(identifier)
+    }
+
+    // Build from the records of a label group, ignoring anything past the second label
+    pub fn from_group(group: &[Vec<u8>], standard: LabelStandard) -> Result<Self, LabelError> {
+        let Some(first) = group.first() else {
+            return Err(LabelError::Unexpected("empty label group"));
+        };
+
+        let label1 = match standard {
+            LabelStandard::Ansi => FileLabel1::parse(first)?,
+            LabelStandard::Ibm => ibm::parse_label1(first)?,
+        };
+        let label2 = group
+            .get(1)
+            .filter(|label| label.len() == LABEL_LEN && label[3] == b'2')
+            .map(|label| match standard {
+                LabelStandard::Ansi => FileLabel2::parse(label),
+                LabelStandard::Ibm => ibm::parse_label2(label),
+            })
+            .transpose()?;
+
+        for label in group.iter().skip(2) {
+            trace!(
+                label = &*String::from_utf8_lossy(&label[..4.min(label.len())]),
+                "Ignoring label"
+            );
+        }
+
+        Ok(Self { label1, label2 })
+    }
+}
+
+// Label fields are space padded
+pub(crate) fn field_str(field: &[u8]) -> String {
+    String::from_utf8_lossy(field).trim_end().to_string()
+}
+
+// Numeric fields are zero-padded ASCII decimal, or all spaces if they're not used
+pub(crate) fn parse_number(field: &[u8]) -> Option<u64> {
+    let field = str::from_utf8(field).ok()?.trim();
+    if field.is_empty() {
+        return None;
+    }
+    field.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An 80 byte label of spaces, with `fields` put in at their offsets
+    fn label(fields: &[(usize, &str)]) -> Vec<u8> {
+        let mut label = vec![b' '; LABEL_LEN];
+        for (offset, field) in fields {
+            label[*offset..*offset + field.len()].copy_from_slice(field.as_bytes());
+        }
+        label
+    }
+
+    #[test]
+    fn volume() {
+        let vol1 = label(&[(0, "VOL1"), (4, "TAPE01"), (37, "aki"), (79, "4")]);
+
+        assert_eq!(
+            VolumeLabel::parse(&vol1),
+            Ok(VolumeLabel {
+                serial: "TAPE01".to_string(),
+                accessibility: b' ',
+                owner: "aki".to_string(),
+                version: b'4',
+            })
+        );
+
+        assert_eq!(
+            VolumeLabel::parse(&label(&[(0, "HDR1")])),
+            Err(LabelError::NotLabelled)
+        );
+        assert_eq!(
+            VolumeLabel::parse(&vol1[..79]),
+            Err(LabelError::NotLabelled)
+        );
+    }
+
+    #[test]
+    fn file_label1() {
+        let hdr1 = label(&[
+            (0, "HDR1"),
+            (4, "VMLINUZ"),
+            (21, "TAPE01"),
+            (27, "0001"),
+            (31, "0002"),
+            (35, "0001"),
+            (39, "00"),
+            (41, "024045"),
+            (47, " 99365"),
+            (54, "000000"),
+            (60, "TAPERIPPER"),
+        ]);
+
+        let label1 = FileLabel1::parse(&hdr1).unwrap();
+        assert_eq!(label1.group, LabelGroup::Header);
+        assert_eq!(label1.file_identifier, "VMLINUZ");
+        assert_eq!(label1.file_set_identifier, "TAPE01");
+        assert_eq!(label1.section, 1);
+        assert_eq!(label1.sequence, 2);
+        assert_eq!(
+            label1.created,
+            Some(LabelDate {
+                year: 2024,
+                day: 45
+            })
+        );
+        assert_eq!(label1.block_count, 0);
+        assert_eq!(label1.system, "TAPERIPPER");
+    }
+
+    #[test]
+    fn file_label1_trailer() {
+        let eof1 = label(&[(0, "EOF1"), (4, "VMLINUZ"), (31, "0001"), (54, "001234")]);
+
+        let label1 = FileLabel1::parse(&eof1).unwrap();
+        assert_eq!(label1.group, LabelGroup::EndOfFile);
+        assert_eq!(label1.block_count, 1234);
+        // Blank fields are the defaults, not errors
+        assert_eq!(label1.section, 1);
+        assert_eq!(label1.created, None);
+
+        let eov1 = label(&[(0, "EOV1"), (31, "0001")]);
+        assert_eq!(
+            FileLabel1::parse(&eov1).unwrap().group,
+            LabelGroup::EndOfVolume
+        );
+    }
+
+    #[test]
+    fn file_label1_bad() {
+        assert_eq!(
+            FileLabel1::parse(&label(&[(0, "HDR2")])),
+            Err(LabelError::Unexpected("not a file label 1"))
+        );
+        assert_eq!(
+            FileLabel1::parse(&label(&[(0, "UHL1")])),
+            Err(LabelError::Unexpected("not a file label 1"))
+        );
+        assert_eq!(
+            FileLabel1::parse(&label(&[(0, "HDR1"), (31, "00x1")])),
+            Err(LabelError::Field("sequence"))
+        );
+    }
+
+    #[test]
+    fn file_label2() {
+        let hdr2 = label(&[
+            (0, "HDR2"),
+            (4, "F"),
+            (5, "08000"),
+            (10, "00080"),
+            (50, "00"),
+        ]);
+
+        let label2 = FileLabel2::parse(&hdr2).unwrap();
+        assert_eq!(label2.group, LabelGroup::Header);
+        assert_eq!(label2.record_format, Some(RecordFormat::Fixed));
+        assert_eq!(label2.block_len, 8000);
+        assert_eq!(label2.record_len, 80);
+        assert_eq!(label2.buffer_offset, 0);
+        assert!(label2.blocked);
+
+        let hdr2 = label(&[
+            (0, "HDR2"),
+            (4, "D"),
+            (5, "02048"),
+            (10, "02048"),
+            (50, "04"),
+        ]);
+        let label2 = FileLabel2::parse(&hdr2).unwrap();
+        assert_eq!(label2.record_format, Some(RecordFormat::Variable));
+        assert_eq!(label2.buffer_offset, 4);
+        assert!(!label2.blocked);
+
+        assert_eq!(
+            FileLabel2::parse(&label(&[(0, "HDR2"), (4, "F")])),
+            Err(LabelError::Field("block length"))
+        );
+    }
+
+    #[test]
+    fn label_group() {
+        let group = [
+            label(&[(0, "HDR1"), (4, "INITRD.IMG"), (31, "0002")]),
+            label(&[(0, "HDR2"), (4, "U"), (5, "32768")]),
+            label(&[(0, "HDR3")]),
+        ];
+
+        let labels = FileLabels::from_group(&group, LabelStandard::Ansi).unwrap();
+        assert_eq!(labels.file_identifier(), "INITRD.IMG");
+        assert_eq!(labels.sequence(), 2);
+        assert!(!labels.continues());
+        assert!(labels.matches(" initrd.img "));
+        assert!(!labels.matches("initrd"));
+        assert_eq!(
+            labels.label2.map(|label2| label2.record_format),
+            Some(Some(RecordFormat::Undefined))
+        );
+
+        // Just the HDR1 is enough
+        let labels = FileLabels::from_group(&group[..1], LabelStandard::Ansi).unwrap();
+        assert_eq!(labels.label2, None);
+
+        assert_eq!(
+            FileLabels::from_group(&[], LabelStandard::Ansi),
+            Err(LabelError::Unexpected("empty label group"))
+        );
+    }
+}
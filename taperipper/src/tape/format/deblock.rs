@@ -0,0 +1,471 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// Splitting tape blocks back up into the logical records they were built from.
+//
+// Fixed records are just chopped off of the block `record_len` at a time. Variable records
+// on IBM tapes have a 4 byte block descriptor word at the start of the block, and then a 4
+// byte record descriptor word ahead of each record, both starting with a big-endian length
+// that includes the descriptor itself. ANSI variable records instead have a 4 digit ASCII
+// length and no block descriptor, with the end of the block padded out with '^'.
+//
+// see: https://www.ibm.com/docs/en/zos/latest?topic=formats-variable-length-record-format
+
+use core::fmt;
+
+use crate::tape::format::ansi::{FileLabels, LabelStandard, RecordFormat};
+
+const DESCRIPTOR_LEN: usize = 4;
+// What ANSI pads the end of a block with
+const ANSI_PAD: u8 = b'^';
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeblockError {
+    // We can't pull records out of this format
+    Unsupported,
+    BlockDescriptor,
+    RecordDescriptor { offset: usize },
+    // The block isn't a whole number of fixed records
+    ShortRecord { len: usize },
+}
+
+impl fmt::Display for DeblockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeblockError::Unsupported => write!(f, "unsupported record format"),
+            DeblockError::BlockDescriptor => write!(f, "bad block descriptor word"),
+            DeblockError::RecordDescriptor { offset } => {
+                write!(f, "bad record descriptor at offset {offset}")
+            }
+            DeblockError::ShortRecord { len } => write!(f, "short fixed record ({len} bytes)"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layout {
+    // F and FB, `record_len` bytes at a time
+    Fixed { record_len: usize },
+    // V and VB, with binary descriptor words
+    Variable,
+    // ANSI D, with ASCII lengths
+    VariableAscii,
+    // U, each block is one record
+    Undefined,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Deblocker {
+    layout: Layout,
+    // Bytes to skip at the start of every block
+    offset: usize,
+}
+
+impl Deblocker {
+    pub fn new(layout: Layout) -> Result<Self, DeblockError> {
+        if let Layout::Fixed { record_len: 0 } = layout {
+            return Err(DeblockError::Unsupported);
+        }
+
+        Ok(Self { layout, offset: 0 })
+    }
+
+    // Figure out how to deblock a file from its HDR2
+    pub fn for_file(labels: &FileLabels, standard: LabelStandard) -> Result<Self, DeblockError> {
+        let Some(label2) = &labels.label2 else {
+            // No HDR2, nothing to tell us how it's blocked
+            return Self::new(Layout::Undefined);
+        };
+
+        let layout = match (label2.record_format, standard) {
+            (Some(RecordFormat::Fixed), _) => Layout::Fixed {
+                record_len: if label2.record_len != 0 {
+                    label2.record_len
+                } else {
+                    label2.block_len
+                } as usize,
+            },
+            (Some(RecordFormat::Variable), LabelStandard::Ibm) => Layout::Variable,
+            (Some(RecordFormat::Variable), LabelStandard::Ansi) => Layout::VariableAscii,
+            (Some(RecordFormat::Undefined), _) => Layout::Undefined,
+            // TODO(aki): Spanned records need reassembling across blocks
+            (Some(RecordFormat::Spanned), _) | (None, _) => return Err(DeblockError::Unsupported),
+        };
+
+        Ok(Self {
+            layout,
+            offset: label2.buffer_offset as usize,
+        })
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    // Iterate over the records in a block
+    pub fn records<'b>(&self, block: &'b [u8]) -> Records<'b> {
+        let block = block.get(self.offset..).unwrap_or_default();
+
+        // Variable blocks start with a descriptor giving how much of the block is used
+        let (data, pending) = match self.layout {
+            Layout::Variable => match block_descriptor(block) {
+                Some(len) => (&block[DESCRIPTOR_LEN..len], None),
+                None => (block, Some(DeblockError::BlockDescriptor)),
+            },
+            _ => (block, None),
+        };
+
+        Records {
+            layout: self.layout,
+            data,
+            pos: 0,
+            pending,
+            done: false,
+        }
+    }
+}
+
+pub struct Records<'b> {
+    layout: Layout,
+    data: &'b [u8],
+    pos: usize,
+    // The whole block is bad, which gets reported before anything else
+    pending: Option<DeblockError>,
+    // Once something goes wrong there's no telling where the next record starts
+    done: bool,
+}
+
+impl<'b> Records<'b> {
+    fn fail(&mut self, err: DeblockError) -> Option<Result<&'b [u8], DeblockError>> {
+        self.done = true;
+        Some(Err(err))
+    }
+}
+
+impl<'b> Iterator for Records<'b> {
+    type Item = Result<&'b [u8], DeblockError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        if let Some(err) = self.pending.take() {
+            return self.fail(err);
+        }
+
+        let rest = &self.data[self.pos..];
+        if rest.is_empty() {
+            return None;
+        }
+
+        match self.layout {
+            Layout::Fixed { record_len } => {
+                if rest.len() < record_len {
+                    // ANSI pads short blocks out, anything else is a broken block
+                    if rest.iter().all(|&b| b == ANSI_PAD) {
+                        return None;
+                    }
+                    return self.fail(DeblockError::ShortRecord { len: rest.len() });
+                }
+
+                self.pos += record_len;
+                Some(Ok(&rest[..record_len]))
+            }
+            Layout::Variable => {
+                let offset = self.pos;
+                let Some(len) = rest
+                    .get(..DESCRIPTOR_LEN)
+                    .map(|rdw| u16::from_be_bytes([rdw[0], rdw[1]]) as usize)
+                    .filter(|&len| len >= DESCRIPTOR_LEN && len <= rest.len())
+                else {
+                    return self.fail(DeblockError::RecordDescriptor { offset });
+                };
+
+                self.pos += len;
+                Some(Ok(&rest[DESCRIPTOR_LEN..len]))
+            }
+            Layout::VariableAscii => {
+                if rest[0] == ANSI_PAD {
+                    return None;
+                }
+
+                let offset = self.pos;
+                let Some(len) = rest
+                    .get(..DESCRIPTOR_LEN)
+                    .and_then(|len| str::from_utf8(len).ok())
+                    .and_then(|len| len.parse::<usize>().ok())
+                    .filter(|&len| len >= DESCRIPTOR_LEN && len <= rest.len())
+                else {
+                    return self.fail(DeblockError::RecordDescriptor { offset });
+                };
+
+                self.pos += len;
+                Some(Ok(&rest[DESCRIPTOR_LEN..len]))
+            }
+            Layout::Undefined => {
+                self.pos = self.data.len();
+                Some(Ok(rest))
+            }
+        }
+    }
+}
+
+// The used length of a variable block from its descriptor word, if it makes sense
+fn block_descriptor(block: &[u8]) -> Option<usize> {
+    let bdw = block.get(..DESCRIPTOR_LEN)?;
+
+    // The top bit set means an extended 31-bit length for blocks over 32760 bytes
+    let len = if bdw[0] & 0x80 != 0 {
+        (u32::from_be_bytes([bdw[0] & 0x7F, bdw[1], bdw[2], bdw[3]])) as usize
+    } else {
+        u16::from_be_bytes([bdw[0], bdw[1]]) as usize
+    };
+
+    (len >= DESCRIPTOR_LEN && len <= block.len()).then_some(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tape::format::ansi::{FileLabel1, FileLabel2, LabelGroup};
+
+    fn records(deblocker: &Deblocker, block: &[u8]) -> Vec<Result<Vec<u8>, DeblockError>> {
+        deblocker
+            .records(block)
+            .map(|record| record.map(<[u8]>::to_vec))
+            .collect()
+    }
+
+    // A V/VB block, with its descriptor word and one for each record
+    fn variable_block(records: &[&[u8]]) -> Vec<u8> {
+        let len = DESCRIPTOR_LEN + records.iter().map(|rec| rec.len() + 4).sum::<usize>();
+
+        let mut block = Vec::new();
+        block.extend_from_slice(&(len as u16).to_be_bytes());
+        block.extend_from_slice(&[0, 0]);
+        for record in records {
+            block.extend_from_slice(&(record.len() as u16 + 4).to_be_bytes());
+            block.extend_from_slice(&[0, 0]);
+            block.extend_from_slice(record);
+        }
+        block
+    }
+
+    fn labels(
+        record_format: Option<RecordFormat>,
+        block_len: u32,
+        record_len: u32,
+        buffer_offset: u32,
+    ) -> FileLabels {
+        FileLabels {
+            label1: FileLabel1 {
+                group: LabelGroup::Header,
+                file_identifier: "FILE".to_string(),
+                file_set_identifier: String::new(),
+                section: 1,
+                sequence: 1,
+                created: None,
+                block_count: 0,
+                system: String::new(),
+            },
+            label2: Some(FileLabel2 {
+                group: LabelGroup::Header,
+                record_format,
+                block_len,
+                record_len,
+                buffer_offset,
+                blocked: true,
+            }),
+        }
+    }
+
+    #[test]
+    fn fixed() {
+        let deblocker = Deblocker::new(Layout::Fixed { record_len: 4 }).unwrap();
+
+        assert_eq!(
+            records(&deblocker, b"AAAABBBBCCCC"),
+            [
+                Ok(b"AAAA".to_vec()),
+                Ok(b"BBBB".to_vec()),
+                Ok(b"CCCC".to_vec())
+            ]
+        );
+        // ANSI pads out the last block of a file
+        assert_eq!(records(&deblocker, b"AAAA^^"), [Ok(b"AAAA".to_vec())]);
+        assert_eq!(
+            records(&deblocker, b"AAAABB"),
+            [
+                Ok(b"AAAA".to_vec()),
+                Err(DeblockError::ShortRecord { len: 2 })
+            ]
+        );
+
+        assert_eq!(
+            Deblocker::new(Layout::Fixed { record_len: 0 }),
+            Err(DeblockError::Unsupported)
+        );
+    }
+
+    #[test]
+    fn variable() {
+        let deblocker = Deblocker::new(Layout::Variable).unwrap();
+
+        let block = variable_block(&[b"first", b"", b"third record"]);
+        assert_eq!(
+            records(&deblocker, &block),
+            [
+                Ok(b"first".to_vec()),
+                Ok(Vec::new()),
+                Ok(b"third record".to_vec())
+            ]
+        );
+
+        // Anything past what the block descriptor says is used is ignored
+        let mut padded = block.clone();
+        padded.extend_from_slice(&[0xFF; 16]);
+        assert_eq!(records(&deblocker, &padded).len(), 3);
+    }
+
+    #[test]
+    fn variable_extended_block_descriptor() {
+        let deblocker = Deblocker::new(Layout::Variable).unwrap();
+
+        let mut block = variable_block(&[b"record"]);
+        let len = block.len() as u32 | 0x8000_0000;
+        block[..4].copy_from_slice(&len.to_be_bytes());
+
+        assert_eq!(records(&deblocker, &block), [Ok(b"record".to_vec())]);
+    }
+
+    #[test]
+    fn bad_block_descriptor() {
+        let deblocker = Deblocker::new(Layout::Variable).unwrap();
+
+        // Longer than the block, shorter than itself, and not even there
+        let mut block = variable_block(&[b"record"]);
+        block[..2].copy_from_slice(&0x0100u16.to_be_bytes());
+        assert_eq!(
+            records(&deblocker, &block),
+            [Err(DeblockError::BlockDescriptor)]
+        );
+
+        block[..2].copy_from_slice(&2u16.to_be_bytes());
+        assert_eq!(
+            records(&deblocker, &block),
+            [Err(DeblockError::BlockDescriptor)]
+        );
+
+        assert_eq!(
+            records(&deblocker, &[0x00, 0x04]),
+            [Err(DeblockError::BlockDescriptor)]
+        );
+    }
+
+    #[test]
+    fn bad_record_descriptor() {
+        let deblocker = Deblocker::new(Layout::Variable).unwrap();
+
+        // The second record says it runs past the end of the block, and nothing after it
+        // can be trusted
+        let mut block = variable_block(&[b"good", b"bad", b"never"]);
+        block[12..14].copy_from_slice(&0x0040u16.to_be_bytes());
+        assert_eq!(
+            records(&deblocker, &block),
+            [
+                Ok(b"good".to_vec()),
+                Err(DeblockError::RecordDescriptor { offset: 8 })
+            ]
+        );
+
+        // A record shorter than its own descriptor
+        let mut block = variable_block(&[b"bad"]);
+        block[4..6].copy_from_slice(&3u16.to_be_bytes());
+        assert_eq!(
+            records(&deblocker, &block),
+            [Err(DeblockError::RecordDescriptor { offset: 0 })]
+        );
+    }
+
+    #[test]
+    fn variable_ascii() {
+        let deblocker = Deblocker::new(Layout::VariableAscii).unwrap();
+
+        assert_eq!(
+            records(&deblocker, b"0009first00040014second rec^^^^"),
+            [
+                Ok(b"first".to_vec()),
+                Ok(Vec::new()),
+                Ok(b"second rec".to_vec())
+            ]
+        );
+
+        assert_eq!(
+            records(&deblocker, b"0006ab00x9cdefg"),
+            [
+                Ok(b"ab".to_vec()),
+                Err(DeblockError::RecordDescriptor { offset: 6 })
+            ]
+        );
+    }
+
+    #[test]
+    fn undefined() {
+        let deblocker = Deblocker::new(Layout::Undefined).unwrap();
+
+        assert_eq!(
+            records(&deblocker, b"the whole block"),
+            [Ok(b"the whole block".to_vec())]
+        );
+        assert!(records(&deblocker, b"").is_empty());
+    }
+
+    #[test]
+    fn for_file() {
+        let fb = labels(Some(RecordFormat::Fixed), 800, 80, 0);
+        assert_eq!(
+            Deblocker::for_file(&fb, LabelStandard::Ibm).map(|deblocker| deblocker.layout()),
+            Ok(Layout::Fixed { record_len: 80 })
+        );
+
+        // F without a record length is one record per block
+        let f = labels(Some(RecordFormat::Fixed), 800, 0, 0);
+        assert_eq!(
+            Deblocker::for_file(&f, LabelStandard::Ansi).map(|deblocker| deblocker.layout()),
+            Ok(Layout::Fixed { record_len: 800 })
+        );
+
+        let v = labels(Some(RecordFormat::Variable), 32760, 32756, 0);
+        assert_eq!(
+            Deblocker::for_file(&v, LabelStandard::Ibm).map(|deblocker| deblocker.layout()),
+            Ok(Layout::Variable)
+        );
+        assert_eq!(
+            Deblocker::for_file(&v, LabelStandard::Ansi).map(|deblocker| deblocker.layout()),
+            Ok(Layout::VariableAscii)
+        );
+
+        let vs = labels(Some(RecordFormat::Spanned), 32760, 0, 0);
+        assert_eq!(
+            Deblocker::for_file(&vs, LabelStandard::Ibm),
+            Err(DeblockError::Unsupported)
+        );
+
+        let mut none = labels(None, 0, 0, 0);
+        none.label2 = None;
+        assert_eq!(
+            Deblocker::for_file(&none, LabelStandard::Ansi).map(|deblocker| deblocker.layout()),
+            Ok(Layout::Undefined)
+        );
+    }
+
+    #[test]
+    fn buffer_offset() {
+        let labels = labels(Some(RecordFormat::Fixed), 12, 4, 4);
+        let deblocker = Deblocker::for_file(&labels, LabelStandard::Ansi).unwrap();
+
+        assert_eq!(
+            records(&deblocker, b"XXXXAAAABBBB"),
+            [Ok(b"AAAA".to_vec()), Ok(b"BBBB".to_vec())]
+        );
+    }
+}
@@ -0,0 +1,172 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// EBCDIC to ASCII (well, Latin-1) translation.
+//
+// Both of the code pages we care about cover all of Latin-1, so translating is just a
+// table lookup. 1047 is 037 with a handful of characters moved around so that the
+// brackets and caret land where C programmers expect them.
+//
+// Labels only ever use the characters the two have in common, so they're always read as 037,
+// which code page text payloads are in is up to whoever boots the tape.
+//
+// see: https://www.ibm.com/docs/en/zos/latest?topic=sets-coded-character-set-identifiers
+
+use core::fmt;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CodePage {
+    // US/Canada, what most labels and datasets are in
+    #[default]
+    Cp037,
+    // Latin-1 "open systems", what z/OS UNIX uses
+    Cp1047,
+}
+
+impl CodePage {
+    // Parse a code page someone gave us, by its number with or without a leading "CP"
+    pub fn parse(page: &str) -> Option<Self> {
+        let page = page.trim().to_ascii_lowercase();
+
+        match page.strip_prefix("cp").unwrap_or(&page) {
+            "037" | "37" => Some(CodePage::Cp037),
+            "1047" => Some(CodePage::Cp1047),
+            _ => None,
+        }
+    }
+
+    // Translate a single EBCDIC byte into its Latin-1 equivalent
+    #[inline]
+    pub fn to_latin1(self, byte: u8) -> u8 {
+        match self {
+            CodePage::Cp037 => CP037_TO_LATIN1[byte as usize],
+            CodePage::Cp1047 => CP1047_DIFFERENCES
+                .iter()
+                .find(|(ebcdic, _)| *ebcdic == byte)
+                .map_or(CP037_TO_LATIN1[byte as usize], |(_, latin1)| *latin1),
+        }
+    }
+
+    // Translate a run of EBCDIC bytes into Latin-1 bytes
+    pub fn translate(self, data: &[u8]) -> Vec<u8> {
+        data.iter().map(|&byte| self.to_latin1(byte)).collect()
+    }
+
+    pub fn translate_in_place(self, data: &mut [u8]) {
+        for byte in data {
+            *byte = self.to_latin1(*byte);
+        }
+    }
+
+    // Decode EBCDIC text into a string
+    pub fn decode(self, data: &[u8]) -> String {
+        data.iter()
+            .map(|&byte| char::from(self.to_latin1(byte)))
+            .collect()
+    }
+}
+
+impl fmt::Display for CodePage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodePage::Cp037 => write!(f, "CP037"),
+            CodePage::Cp1047 => write!(f, "CP1047"),
+        }
+    }
+}
+
+// Latin-1 for each EBCDIC code point in code page 037
+#[rustfmt::skip]
+static CP037_TO_LATIN1: [u8; 256] = [
+    0x00, 0x01, 0x02, 0x03, 0x9C, 0x09, 0x86, 0x7F, 0x97, 0x8D, 0x8E, 0x0B, 0x0C, 0x0D, 0x0E, 0x0F,
+    0x10, 0x11, 0x12, 0x13, 0x9D, 0x85, 0x08, 0x87, 0x18, 0x19, 0x92, 0x8F, 0x1C, 0x1D, 0x1E, 0x1F,
+    0x80, 0x81, 0x82, 0x83, 0x84, 0x0A, 0x17, 0x1B, 0x88, 0x89, 0x8A, 0x8B, 0x8C, 0x05, 0x06, 0x07,
+    0x90, 0x91, 0x16, 0x93, 0x94, 0x95, 0x96, 0x04, 0x98, 0x99, 0x9A, 0x9B, 0x14, 0x15, 0x9E, 0x1A,
+    0x20, 0xA0, 0xE2, 0xE4, 0xE0, 0xE1, 0xE3, 0xE5, 0xE7, 0xF1, 0xA2, 0x2E, 0x3C, 0x28, 0x2B, 0x7C,
+    0x26, 0xE9, 0xEA, 0xEB, 0xE8, 0xED, 0xEE, 0xEF, 0xEC, 0xDF, 0x21, 0x24, 0x2A, 0x29, 0x3B, 0xAC,
+    0x2D, 0x2F, 0xC2, 0xC4, 0xC0, 0xC1, 0xC3, 0xC5, 0xC7, 0xD1, 0xA6, 0x2C, 0x25, 0x5F, 0x3E, 0x3F,
+    0xF8, 0xC9, 0xCA, 0xCB, 0xC8, 0xCD, 0xCE, 0xCF, 0xCC, 0x60, 0x3A, 0x23, 0x40, 0x27, 0x3D, 0x22,
+    0xD8, 0x61, 0x62, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69, 0xAB, 0xBB, 0xF0, 0xFD, 0xFE, 0xB1,
+    0xB0, 0x6A, 0x6B, 0x6C, 0x6D, 0x6E, 0x6F, 0x70, 0x71, 0x72, 0xAA, 0xBA, 0xE6, 0xB8, 0xC6, 0xA4,
+    0xB5, 0x7E, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7A, 0xA1, 0xBF, 0xD0, 0xDD, 0xDE, 0xAE,
+    0x5E, 0xA3, 0xA5, 0xB7, 0xA9, 0xA7, 0xB6, 0xBC, 0xBD, 0xBE, 0x5B, 0x5D, 0xAF, 0xA8, 0xB4, 0xD7,
+    0x7B, 0x41, 0x42, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49, 0xAD, 0xF4, 0xF6, 0xF2, 0xF3, 0xF5,
+    0x7D, 0x4A, 0x4B, 0x4C, 0x4D, 0x4E, 0x4F, 0x50, 0x51, 0x52, 0xB9, 0xFB, 0xFC, 0xF9, 0xFA, 0xFF,
+    0x5C, 0xF7, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5A, 0xB2, 0xD4, 0xD6, 0xD2, 0xD3, 0xD5,
+    0x30, 0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0xB3, 0xDB, 0xDC, 0xD9, 0xDA, 0x9F,
+];
+
+// Where 1047 differs from 037, EBCDIC to Latin-1
+const CP1047_DIFFERENCES: [(u8, u8); 6] = [
+    (0x5F, b'^'),
+    (0xAD, b'['),
+    (0xB0, 0xAC), // ¬
+    (0xBA, 0xDD), // Ý
+    (0xBB, 0xA8), // ¨
+    (0xBD, b']'),
+];
+
+// EBCDIC "VOL1", to tell an IBM labelled tape from an ANSI one
+pub const VOL1: [u8; 4] = [0xE5, 0xD6, 0xD3, 0xF1];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        // Both code pages cover all of Latin-1, so every byte has to come out exactly once
+        for page in [CodePage::Cp037, CodePage::Cp1047] {
+            let mut seen = [false; 256];
+            for byte in 0..=255 {
+                seen[page.to_latin1(byte) as usize] = true;
+            }
+            assert!(seen.iter().all(|&seen| seen), "{page:?}");
+        }
+    }
+
+    #[test]
+    fn cp037() {
+        let text = [
+            0xC8, 0x85, 0x93, 0x93, 0x96, 0x6B, 0x40, 0xE6, 0x96, 0x99, 0x93, 0x84, 0x5A, 0x40,
+            0xF0, 0xF1, 0xF2, 0xF3, 0xF4, 0xF5, 0xF6, 0xF7, 0xF8, 0xF9,
+        ];
+        assert_eq!(CodePage::Cp037.decode(&text), "Hello, World! 0123456789");
+        assert_eq!(CodePage::Cp037.translate(&VOL1), b"VOL1");
+        assert_eq!(
+            CodePage::Cp037.translate(&[0xC8, 0xC4, 0xD9, 0xF1]),
+            b"HDR1"
+        );
+
+        // The brackets, caret, and not sign that 1047 moves around
+        assert_eq!(
+            CodePage::Cp037.decode(&[0xBA, 0xBB, 0xB0, 0x4F, 0x5A, 0x5F]),
+            "[]^|!¬"
+        );
+    }
+
+    #[test]
+    fn cp1047() {
+        assert_eq!(
+            CodePage::Cp1047.decode(&[0xAD, 0xBD, 0x5F, 0x4F, 0x5A, 0xB0]),
+            "[]^|!¬"
+        );
+        // Everything else is the same as 037
+        assert_eq!(CodePage::Cp1047.translate(&VOL1), b"VOL1");
+        assert_eq!(CodePage::Cp1047.to_latin1(0x25), b'\n');
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(CodePage::parse("037"), Some(CodePage::Cp037));
+        assert_eq!(CodePage::parse("CP37"), Some(CodePage::Cp037));
+        assert_eq!(CodePage::parse(" cp1047 "), Some(CodePage::Cp1047));
+        assert_eq!(CodePage::parse("1140"), None);
+        assert_eq!(CodePage::parse(""), None);
+    }
+
+    #[test]
+    fn in_place() {
+        let mut data = VOL1;
+        CodePage::Cp037.translate_in_place(&mut data);
+        assert_eq!(&data, b"VOL1");
+    }
+}
@@ -0,0 +1,237 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// IBM standard tape labels.
+//
+// These are close enough to the ANSI ones that we translate them out of EBCDIC and parse
+// them into the same types, the only real differences being where the owner lives in the
+// VOL1, the high-order block count in the EOF1, and the HDR2 being laid out for
+// RECFM/LRECL/BLKSIZE rather than the ANSI record formats.
+//
+// Everything in here works on labels that have already been translated to ASCII.
+//
+// see: https://www.ibm.com/docs/en/zos/latest?topic=labels-standard-label-format
+
+use crate::tape::format::ansi::{
+    FileLabel1, FileLabel2, LABEL_LEN, LabelError, LabelGroup, RecordFormat, VolumeLabel,
+    field_str, parse_number,
+};
+
+pub fn parse_volume(label: &[u8]) -> Result<VolumeLabel, LabelError> {
+    if label.len() != LABEL_LEN || &label[0..4] != b"VOL1" {
+        return Err(LabelError::NotLabelled);
+    }
+
+    Ok(VolumeLabel {
+        serial: field_str(&label[4..10]),
+        // The volume security byte, '0' for none
+        accessibility: label[10],
+        owner: field_str(&label[41..51]),
+        version: label[79],
+    })
+}
+
+// HDR1, EOF1, and EOV1
+pub fn parse_label1(label: &[u8]) -> Result<FileLabel1, LabelError> {
+    let mut label1 = FileLabel1::parse(label)?;
+
+    // Trailers keep the high-order digits of the block count off at the end
+    if let Some(high) = parse_number(&label[76..80]) {
+        label1.block_count += high * 1_000_000;
+    }
+
+    Ok(label1)
+}
+
+// HDR2, EOF2, and EOV2
+pub fn parse_label2(label: &[u8]) -> Result<FileLabel2, LabelError> {
+    if label.len() != LABEL_LEN || label[3] != b'2' {
+        return Err(LabelError::Unexpected("not a file label 2"));
+    }
+
+    let group =
+        LabelGroup::from_id(&label[0..3]).ok_or(LabelError::Unexpected("not a file label 2"))?;
+
+    // 'B'locked, 'S'panned, or 'R' for both
+    let attribute = label[38];
+    let blocked = matches!(attribute, b'B' | b'R');
+    let spanned = matches!(attribute, b'S' | b'R');
+
+    let record_format = match label[4] {
+        b'F' => Some(RecordFormat::Fixed),
+        b'V' if spanned => Some(RecordFormat::Spanned),
+        b'V' => Some(RecordFormat::Variable),
+        b'U' => Some(RecordFormat::Undefined),
+        _ => None,
+    };
+
+    let block_len = parse_number(&label[5..10]).ok_or(LabelError::Field("block length"))?;
+    // Blocks over 32760 bytes don't fit in BLKSIZE, so they go at the end of the label
+    let large_block_len = parse_number(&label[70..80]).unwrap_or(0);
+
+    Ok(FileLabel2 {
+        group,
+        record_format,
+        block_len: block_len.max(large_block_len) as u32,
+        record_len: parse_number(&label[10..15]).unwrap_or(0) as u32,
+        buffer_offset: 0,
+        blocked,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tape::format::{
+        ansi::{FileLabels, LabelStandard},
+        ebcdic::CodePage,
+    };
+
+    // An IBM label as it comes off of the tape, in EBCDIC, then translated back the way the
+    // navigator does it
+    fn label(fields: &[(usize, &str)]) -> Vec<u8> {
+        let mut label = [b' '; LABEL_LEN];
+        for (offset, field) in fields {
+            label[*offset..*offset + field.len()].copy_from_slice(field.as_bytes());
+        }
+
+        let ebcdic: Vec<_> = label
+            .iter()
+            .map(|&ascii| {
+                (0..=255)
+                    .find(|&byte| CodePage::Cp037.to_latin1(byte) == ascii)
+                    .unwrap()
+            })
+            .collect();
+
+        CodePage::Cp037.translate(&ebcdic)
+    }
+
+    #[test]
+    fn volume() {
+        let vol1 = label(&[(0, "VOL1"), (4, "000123"), (10, "0"), (41, "SYSPROG")]);
+
+        let volume = parse_volume(&vol1).unwrap();
+        assert_eq!(volume.serial, "000123");
+        assert_eq!(volume.accessibility, b'0');
+        assert_eq!(volume.owner, "SYSPROG");
+
+        assert_eq!(
+            parse_volume(&label(&[(0, "VOL2")])),
+            Err(LabelError::NotLabelled)
+        );
+    }
+
+    #[test]
+    fn label1() {
+        let hdr1 = label(&[
+            (0, "HDR1"),
+            (4, "SYS1.LINUX.KERNEL"),
+            (21, "000123"),
+            (27, "0001"),
+            (31, "0001"),
+            (41, "024001"),
+            (54, "000000"),
+            (60, "IBM OS/VS 370"),
+        ]);
+
+        let label1 = parse_label1(&hdr1).unwrap();
+        assert_eq!(label1.group, LabelGroup::Header);
+        assert_eq!(label1.file_identifier, "SYS1.LINUX.KERNEL");
+        assert_eq!(label1.sequence, 1);
+        assert_eq!(label1.created.map(|date| date.year), Some(2024));
+        assert_eq!(label1.system, "IBM OS/VS 370");
+        assert_eq!(label1.block_count, 0);
+    }
+
+    #[test]
+    fn label1_large_block_count() {
+        // 12,345,678 blocks, split into the low and high order digits
+        let eof1 = label(&[(0, "EOF1"), (31, "0001"), (54, "345678"), (76, "0012")]);
+
+        let label1 = parse_label1(&eof1).unwrap();
+        assert_eq!(label1.group, LabelGroup::EndOfFile);
+        assert_eq!(label1.block_count, 12_345_678);
+    }
+
+    #[test]
+    fn label2() {
+        // FB, 80 byte records in 27920 byte blocks
+        let hdr2 = label(&[
+            (0, "HDR2"),
+            (4, "F"),
+            (5, "27920"),
+            (10, "00080"),
+            (38, "B"),
+        ]);
+
+        let label2 = parse_label2(&hdr2).unwrap();
+        assert_eq!(label2.record_format, Some(RecordFormat::Fixed));
+        assert_eq!(label2.block_len, 27920);
+        assert_eq!(label2.record_len, 80);
+        assert!(label2.blocked);
+
+        // VB
+        let hdr2 = label(&[
+            (0, "HDR2"),
+            (4, "V"),
+            (5, "32760"),
+            (10, "32756"),
+            (38, "B"),
+        ]);
+        let label2 = parse_label2(&hdr2).unwrap();
+        assert_eq!(label2.record_format, Some(RecordFormat::Variable));
+        assert!(label2.blocked);
+
+        // VBS
+        let hdr2 = label(&[
+            (0, "EOF2"),
+            (4, "V"),
+            (5, "32760"),
+            (10, "00000"),
+            (38, "R"),
+        ]);
+        let label2 = parse_label2(&hdr2).unwrap();
+        assert_eq!(label2.group, LabelGroup::EndOfFile);
+        assert_eq!(label2.record_format, Some(RecordFormat::Spanned));
+        assert!(label2.blocked);
+
+        // U, with the real block size out at the end of the label
+        let hdr2 = label(&[(0, "HDR2"), (4, "U"), (5, "00000"), (70, "0000262144")]);
+        let label2 = parse_label2(&hdr2).unwrap();
+        assert_eq!(label2.record_format, Some(RecordFormat::Undefined));
+        assert_eq!(label2.block_len, 262_144);
+        assert!(!label2.blocked);
+    }
+
+    #[test]
+    fn label2_bad() {
+        assert_eq!(
+            parse_label2(&label(&[(0, "HDR1")])),
+            Err(LabelError::Unexpected("not a file label 2"))
+        );
+        assert_eq!(
+            parse_label2(&label(&[(0, "HDR2"), (4, "F"), (5, "ABCDE")])),
+            Err(LabelError::Field("block length"))
+        );
+    }
+
+    #[test]
+    fn label_group() {
+        let group = [
+            label(&[(0, "HDR1"), (4, "INITRD"), (31, "0002")]),
+            label(&[
+                (0, "HDR2"),
+                (4, "V"),
+                (5, "32760"),
+                (10, "32756"),
+                (38, "B"),
+            ]),
+        ];
+
+        let labels = FileLabels::from_group(&group, LabelStandard::Ibm).unwrap();
+        assert_eq!(labels.file_identifier(), "INITRD");
+        assert_eq!(
+            labels.label2.map(|label2| label2.record_format),
+            Some(Some(RecordFormat::Variable))
+        );
+    }
+}
@@ -0,0 +1,382 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// Walking the files on a labelled volume.
+//
+// This is the part of ANSI and IBM standard labels that needs a tape, reading the label
+// groups around each file and keeping track of where on the volume we are, the labels
+// themselves are parsed in `ansi` and `ibm`.
+
+use tracing::{debug, warn};
+
+use crate::tape::{
+    device::{DeviceError, ReadOutcome, TapeDevice},
+    format::{
+        ansi::{FileLabels, LABEL_LEN, LabelError, LabelGroup, LabelStandard, VolumeLabel},
+        ebcdic, ibm,
+    },
+    volume::ReelChanger,
+};
+
+// Large enough to read any label record, and not much else
+const LABEL_BUFFER_LEN: usize = 256;
+// Labels after the first couple in a group are optional, but a group this long is garbage
+const MAX_GROUP_LEN: usize = 16;
+
+// Walks the files on a labelled volume
+//
+// The navigator only ever moves forward, once it has handed out a file header the tape is
+// at the start of the data, which the caller can read themselves through `device()` and
+// then hand back with `close_file`, or skip with `skip_file`.
+pub struct LabelledTape<'d, D: TapeDevice> {
+    device: &'d mut D,
+    volume: VolumeLabel,
+    standard: LabelStandard,
+    // A header group we've read but not handed out yet
+    pending: Option<FileLabels>,
+    // The header of the file we're in the middle of
+    current: Option<FileLabels>,
+    // The header of the file that ran off the end of the volume, if one did
+    continued: Option<FileLabels>,
+    end_of_volume: bool,
+}
+
+impl<'d, D: TapeDevice> LabelledTape<'d, D> {
+    // Rewind and read the volume label, leaving the tape at the data of the first file
+    pub async fn open(device: &'d mut D) -> Result<Self, LabelError> {
+        device.rewind().await?;
+        let (volume, standard, pending) = read_volume(device).await?;
+
+        Ok(Self {
+            end_of_volume: pending.is_none(),
+            device,
+            volume,
+            standard,
+            pending,
+            current: None,
+            continued: None,
+        })
+    }
+
+    pub fn volume(&self) -> &VolumeLabel {
+        &self.volume
+    }
+
+    pub fn standard(&self) -> LabelStandard {
+        self.standard
+    }
+
+    pub fn device(&mut self) -> &mut D {
+        self.device
+    }
+
+    // Read a label group, in ASCII no matter what the tape is in
+    async fn read_labels(&mut self) -> Result<Vec<Vec<u8>>, LabelError> {
+        let group = read_group(self.device).await?;
+
+        Ok(match self.standard {
+            LabelStandard::Ansi => group,
+            LabelStandard::Ibm => translate_group(group),
+        })
+    }
+
+    // Move on to the next file, leaving the tape at the start of its data
+    //
+    // If the current file was never closed, it's skipped.
+    pub async fn next_file(&mut self) -> Result<Option<&FileLabels>, LabelError> {
+        if self.current.is_some() {
+            self.skip_file().await?;
+        }
+
+        let labels = match self.pending.take() {
+            Some(labels) => labels,
+            None if self.end_of_volume => return Ok(None),
+            None => {
+                let group = self.read_labels().await?;
+                // An empty group is the second filemark at the end of the volume
+                if group.is_empty() {
+                    debug!(device = self.device.name(), "End of labelled volume");
+                    self.end_of_volume = true;
+                    return Ok(None);
+                }
+
+                FileLabels::from_group(&group, self.standard)?
+            }
+        };
+
+        if labels.label1.group != LabelGroup::Header {
+            return Err(LabelError::Unexpected("expected a header label"));
+        }
+
+        debug!(
+            device = self.device.name(),
+            file = labels.file_identifier(),
+            sequence = labels.sequence(),
+            created = labels.label1.created.map(tracing::field::display),
+            system = labels.label1.system.as_str(),
+            block_len = labels.label2.as_ref().map(|label| label.block_len),
+            blocked = labels.label2.as_ref().map(|label| label.blocked),
+            "Labelled file"
+        );
+
+        self.current = Some(labels);
+        Ok(self.current.as_ref())
+    }
+
+    // Read the trailer group after the data of the current file
+    async fn read_trailer(&mut self) -> Result<FileLabels, LabelError> {
+        let Some(header) = self.current.take() else {
+            return Err(LabelError::Unexpected("no file open"));
+        };
+
+        let group = self.read_labels().await?;
+        let trailer = FileLabels::from_group(&group, self.standard)?;
+
+        if trailer.label1.group == LabelGroup::Header {
+            return Err(LabelError::Unexpected("expected a trailer label"));
+        }
+
+        if trailer.label1.file_identifier != header.label1.file_identifier {
+            warn!(
+                header = header.file_identifier(),
+                trailer = trailer.file_identifier(),
+                "Trailer label is for a different file"
+            );
+        }
+
+        if trailer.continues() {
+            // NOTE(aki): The rest of the file is on the next reel, nothing more on this one
+            self.end_of_volume = true;
+            self.continued = Some(header);
+        }
+
+        Ok(trailer)
+    }
+
+    // Skip the data of the current file without reading it
+    pub async fn skip_file(&mut self) -> Result<FileLabels, LabelError> {
+        self.device.space_filemarks(1).await?;
+        self.read_trailer().await
+    }
+
+    // Finish off the current file once its data has been read up to the filemark, checking
+    // we got as many blocks as the trailer says there should be
+    pub async fn close_file(&mut self, blocks_read: u64) -> Result<FileLabels, LabelError> {
+        let trailer = self.read_trailer().await?;
+
+        // NOTE(aki): ANSI only has six digits for the count, so it wraps on really big files
+        let expected = trailer.label1.block_count;
+        if expected != blocks_read && expected != blocks_read % 1_000_000 {
+            warn!(
+                device = self.device.name(),
+                file = trailer.file_identifier(),
+                expected,
+                actual = blocks_read,
+                "Block count mismatch"
+            );
+            return Err(LabelError::BlockCount {
+                expected,
+                actual: blocks_read,
+            });
+        }
+
+        Ok(trailer)
+    }
+
+    // Whether the last file closed carries on on another volume
+    pub fn continues(&self) -> bool {
+        self.continued.is_some()
+    }
+
+    // Have the next reel of the set mounted and carry on with the file that ran off the end
+    // of this one, leaving the tape at the start of its next section
+    //
+    // The operator gets asked again for as long as they keep mounting the wrong reel.
+    pub async fn next_volume(
+        &mut self,
+        reels: &mut ReelChanger,
+    ) -> Result<&FileLabels, LabelError> {
+        let Some(header) = self.continued.take() else {
+            return Err(LabelError::Unexpected(
+                "no file continues on another volume",
+            ));
+        };
+
+        let mut problem = None;
+        loop {
+            reels.mount_next(self.device, problem.as_deref()).await?;
+
+            match self.check_volume(&header, reels.next_serial()).await? {
+                Ok((volume, standard, labels)) => {
+                    reels.mounted(Some(&volume.serial));
+                    debug!(
+                        device = self.device.name(),
+                        serial = volume.serial.as_str(),
+                        file = labels.file_identifier(),
+                        section = labels.label1.section,
+                        "Continuing file"
+                    );
+
+                    self.volume = volume;
+                    self.standard = standard;
+                    self.pending = None;
+                    self.end_of_volume = false;
+                    self.current = Some(labels);
+
+                    return Ok(self.current.as_ref().unwrap());
+                }
+                Err(reason) => {
+                    warn!(device = self.device.name(), "Wrong reel: {}", reason);
+                    problem = Some(reason);
+                }
+            }
+        }
+    }
+
+    // Read the labels off of a freshly mounted reel and make sure it carries on from `header`
+    //
+    // The inner result is why it's the wrong reel, to tell the operator, anything else going
+    // wrong is an error.
+    async fn check_volume(
+        &mut self,
+        header: &FileLabels,
+        expected_serial: Option<&str>,
+    ) -> Result<Result<(VolumeLabel, LabelStandard, FileLabels), String>, LabelError> {
+        let (volume, standard, labels) = match read_volume(self.device).await {
+            Ok(volume) => volume,
+            Err(LabelError::NotLabelled) => {
+                return Ok(Err("The reel that was mounted is not labelled".to_string()));
+            }
+            Err(err) => return Err(err),
+        };
+        let serial = volume.serial.as_str();
+
+        if let Some(expected) = expected_serial
+            && serial != expected
+        {
+            return Ok(Err(format!(
+                "Volume {serial} was mounted, expected volume {expected}"
+            )));
+        }
+
+        if serial == self.volume.serial {
+            return Ok(Err(format!(
+                "Volume {serial} is the reel that was just read"
+            )));
+        }
+
+        let Some(labels) = labels.filter(|labels| labels.label1.group == LabelGroup::Header) else {
+            return Ok(Err(format!("Volume {serial} has no files on it")));
+        };
+
+        let (want, got) = (&header.label1, &labels.label1);
+        if got.file_set_identifier != want.file_set_identifier {
+            return Ok(Err(format!(
+                "Volume {serial} is from file set {}, expected {}",
+                got.file_set_identifier, want.file_set_identifier
+            )));
+        }
+
+        // NOTE(aki): The section number counts up by one for every volume a file is on
+        if got.file_identifier != want.file_identifier || got.section != want.section + 1 {
+            return Ok(Err(format!(
+                "Volume {serial} starts with section {} of {}, expected section {} of {}",
+                got.section,
+                got.file_identifier,
+                want.section + 1,
+                want.file_identifier
+            )));
+        }
+
+        Ok(Ok((volume, standard, labels)))
+    }
+}
+
+// NOTE(aki): Labels are only ever in the characters 037 and 1047 agree on
+fn translate_group(mut group: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+    for label in &mut group {
+        ebcdic::CodePage::Cp037.translate_in_place(label);
+    }
+    group
+}
+
+// Read the volume label group at BOT, returning the volume, which standard its labels are
+// in, and the header group of the first file if there is one
+async fn read_volume<D: TapeDevice>(
+    device: &mut D,
+) -> Result<(VolumeLabel, LabelStandard, Option<FileLabels>), LabelError> {
+    // Anything other than a label where the VOL1 should be means this isn't labelled
+    let group = match read_group(device).await {
+        Err(LabelError::Unexpected(_)) => return Err(LabelError::NotLabelled),
+        group => group?,
+    };
+    let Some(first) = group.first() else {
+        return Err(LabelError::NotLabelled);
+    };
+
+    let (standard, group) = if first.starts_with(&ebcdic::VOL1) {
+        (LabelStandard::Ibm, translate_group(group))
+    } else {
+        (LabelStandard::Ansi, group)
+    };
+
+    let volume = match standard {
+        LabelStandard::Ansi => VolumeLabel::parse(&group[0])?,
+        LabelStandard::Ibm => ibm::parse_volume(&group[0])?,
+    };
+    debug!(
+        device = device.name(),
+        serial = volume.serial.as_str(),
+        owner = volume.owner.as_str(),
+        accessibility = %char::from(volume.accessibility),
+        version = %char::from(volume.version),
+        standard = ?standard,
+        "Labelled volume"
+    );
+
+    // Skip over any user volume labels to get to the first header group
+    let headers: Vec<_> = group
+        .into_iter()
+        .skip(1)
+        .skip_while(|label| !label.starts_with(b"HDR"))
+        .collect();
+
+    let pending = if headers.is_empty() {
+        None
+    } else {
+        Some(FileLabels::from_group(&headers, standard)?)
+    };
+
+    Ok((volume, standard, pending))
+}
+
+// Read a run of label records up to the next filemark
+async fn read_group<D: TapeDevice>(device: &mut D) -> Result<Vec<Vec<u8>>, LabelError> {
+    let mut buffer = [0u8; LABEL_BUFFER_LEN];
+    let mut group = Vec::new();
+
+    loop {
+        match device.read_block(&mut buffer).await {
+            Ok(ReadOutcome::Block(len)) => {
+                if len != LABEL_LEN {
+                    return Err(LabelError::Unexpected("label is not 80 bytes"));
+                }
+                if group.len() == MAX_GROUP_LEN {
+                    return Err(LabelError::Unexpected("label group too long"));
+                }
+                group.push(buffer[..len].to_vec());
+            }
+            Ok(ReadOutcome::Filemark) => return Ok(group),
+            Ok(ReadOutcome::EndOfData) => {
+                // Blank tape where a label should be, treat it like the end of the volume
+                return if group.is_empty() {
+                    Ok(group)
+                } else {
+                    Err(LabelError::Unexpected("end of data in label group"))
+                };
+            }
+            Err(DeviceError::BlockTooLarge { .. }) => {
+                return Err(LabelError::Unexpected("label is not 80 bytes"));
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
@@ -0,0 +1,419 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// The taperipper boot tape format.
+//
+// Rather than go looking for things in an archive, a boot tape can say up-front what is on
+// it. Tape file 0 is a single record holding the manifest, and every payload after it gets
+// a tape file to itself, so the loader can space straight to each one by filemark count.
+//
+// The manifest is all little-endian:
+//
+//   0x00  [u8; 8]   magic, "TRIPBOOT"
+//   0x08  u16       version
+//   0x0A  u16       number of entries
+//   0x0C  u32       reserved, zero
+//   0x10  entries, 64 bytes each:
+//     0x00  u8        kind
+//     0x01  u8        for an initrd, which layer of the initramfs it is, zero otherwise
+//     0x02  [u8; 2]   reserved, zero
+//     0x04  u32       tape file the payload is in
+//     0x08  u64       payload size in bytes
+//     0x10  u32       block size it was written with, zero if variable
+//     0x14  [u8; 12]  reserved, zero
+//     0x20  [u8; 32]  SHA-256 of the payload
+
+use core::fmt;
+
+use crate::boot::initramfs::Layer;
+
+pub const MAGIC: [u8; 8] = *b"TRIPBOOT";
+pub const VERSION: u16 = 1;
+
+pub const HEADER_LEN: usize = 16;
+pub const ENTRY_LEN: usize = 64;
+pub const DIGEST_LEN: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PayloadKind {
+    Kernel,
+    Initrd,
+    Cmdline,
+    Config,
+    // A detached signature over the rest, see `signature.rs`
+    Signature,
+}
+
+impl PayloadKind {
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0x01 => Some(PayloadKind::Kernel),
+            0x02 => Some(PayloadKind::Initrd),
+            0x03 => Some(PayloadKind::Cmdline),
+            0x04 => Some(PayloadKind::Config),
+            0x05 => Some(PayloadKind::Signature),
+            _ => None,
+        }
+    }
+
+    pub fn as_byte(&self) -> u8 {
+        match self {
+            PayloadKind::Kernel => 0x01,
+            PayloadKind::Initrd => 0x02,
+            PayloadKind::Cmdline => 0x03,
+            PayloadKind::Config => 0x04,
+            PayloadKind::Signature => 0x05,
+        }
+    }
+}
+
+// NOTE(aki): The base initrd is zero so manifests from before there were layers mean the same
+fn layer_from_byte(byte: u8) -> Option<Layer> {
+    match byte {
+        0x00 => Some(Layer::Base),
+        0x01 => Some(Layer::Microcode),
+        0x02 => Some(Layer::Overlay),
+        _ => None,
+    }
+}
+
+impl fmt::Display for PayloadKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PayloadKind::Kernel => write!(f, "kernel"),
+            PayloadKind::Initrd => write!(f, "initrd"),
+            PayloadKind::Cmdline => write!(f, "command-line"),
+            PayloadKind::Config => write!(f, "config"),
+            PayloadKind::Signature => write!(f, "signature"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ManifestError {
+    // Not a manifest at all
+    BadMagic,
+    UnsupportedVersion(u16),
+    // The record is shorter than the entries it says it has
+    Truncated,
+    UnknownKind(u8),
+    UnknownLayer(u8),
+    NoKernel,
+    // More than one of something there should only be one of
+    Duplicate(PayloadKind),
+    // Payloads must come after the manifest, in order, one per tape file
+    BadFile {
+        index: usize,
+        file: u32,
+    },
+    // The payload on tape isn't the size the manifest says it is
+    SizeMismatch {
+        kind: PayloadKind,
+        expected: u64,
+        actual: u64,
+    },
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ManifestError::BadMagic => write!(f, "not a boot tape manifest"),
+            ManifestError::UnsupportedVersion(ver) => {
+                write!(f, "unsupported manifest version {ver}")
+            }
+            ManifestError::Truncated => write!(f, "manifest is truncated"),
+            ManifestError::UnknownKind(kind) => write!(f, "unknown payload kind {kind:#04x}"),
+            ManifestError::UnknownLayer(layer) => write!(f, "unknown initrd layer {layer:#04x}"),
+            ManifestError::NoKernel => write!(f, "manifest has no kernel"),
+            ManifestError::Duplicate(kind) => write!(f, "manifest has more than one {kind}"),
+            ManifestError::BadFile { index, file } => {
+                write!(f, "entry {index} has a bad tape file number ({file})")
+            }
+            ManifestError::SizeMismatch {
+                kind,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{kind} is {actual} bytes, manifest says it should be {expected}"
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub kind: PayloadKind,
+    // Which layer of the initramfs an initrd goes in, `None` for anything else
+    pub layer: Option<Layer>,
+    pub file: u32,
+    pub size: u64,
+    // Zero if the payload was written in variable blocks
+    pub block_len: u32,
+    pub sha256: [u8; DIGEST_LEN],
+}
+
+impl ManifestEntry {
+    fn parse(entry: &[u8]) -> Result<Self, ManifestError> {
+        let kind = PayloadKind::from_byte(entry[0]).ok_or(ManifestError::UnknownKind(entry[0]))?;
+        let layer = match kind {
+            PayloadKind::Initrd => {
+                Some(layer_from_byte(entry[1]).ok_or(ManifestError::UnknownLayer(entry[1]))?)
+            }
+            _ => None,
+        };
+
+        let mut sha256 = [0u8; DIGEST_LEN];
+        sha256.copy_from_slice(&entry[0x20..0x20 + DIGEST_LEN]);
+
+        Ok(Self {
+            kind,
+            layer,
+            file: u32::from_le_bytes(entry[0x04..0x08].try_into().unwrap()),
+            size: u64::from_le_bytes(entry[0x08..0x10].try_into().unwrap()),
+            block_len: u32::from_le_bytes(entry[0x10..0x14].try_into().unwrap()),
+            sha256,
+        })
+    }
+
+    pub fn digest_hex(&self) -> String {
+        self.sha256
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Manifest {
+    pub version: u16,
+    pub entries: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    // Whether a record looks like a manifest, without checking anything else
+    pub fn is_manifest(record: &[u8]) -> bool {
+        record.starts_with(&MAGIC)
+    }
+
+    // Parse and validate a manifest record
+    pub fn parse(record: &[u8]) -> Result<Self, ManifestError> {
+        if !Self::is_manifest(record) {
+            return Err(ManifestError::BadMagic);
+        }
+        if record.len() < HEADER_LEN {
+            return Err(ManifestError::Truncated);
+        }
+
+        let version = u16::from_le_bytes([record[0x08], record[0x09]]);
+        if version != VERSION {
+            return Err(ManifestError::UnsupportedVersion(version));
+        }
+
+        let count = u16::from_le_bytes([record[0x0A], record[0x0B]]) as usize;
+        let entries = record[HEADER_LEN..]
+            .get(..count * ENTRY_LEN)
+            .ok_or(ManifestError::Truncated)?
+            .as_chunks::<ENTRY_LEN>()
+            .0
+            .iter()
+            .map(|entry| ManifestEntry::parse(entry))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let manifest = Self { version, entries };
+        manifest.validate()?;
+
+        Ok(manifest)
+    }
+
+    fn validate(&self) -> Result<(), ManifestError> {
+        let count = |kind| self.entries.iter().filter(|e| e.kind == kind).count();
+
+        if count(PayloadKind::Kernel) == 0 {
+            return Err(ManifestError::NoKernel);
+        }
+
+        for kind in [
+            PayloadKind::Kernel,
+            PayloadKind::Cmdline,
+            PayloadKind::Config,
+            PayloadKind::Signature,
+        ] {
+            if count(kind) > 1 {
+                return Err(ManifestError::Duplicate(kind));
+            }
+        }
+
+        // File 0 is the manifest, and we only ever space forwards
+        let mut last = 0;
+        for (index, entry) in self.entries.iter().enumerate() {
+            if entry.file <= last {
+                return Err(ManifestError::BadFile {
+                    index,
+                    file: entry.file,
+                });
+            }
+            last = entry.file;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // File 0 of `xtask mktape -k vmlinuz -i initrd.img --microcode ucode.cpio
+    // -c console=ttyS0 -b 512`, with "kernel image\n" 100 times over as the kernel
+    const MKTAPE: &[u8] = include_bytes!("testdata/manifest.bin");
+
+    fn entry(kind: u8, layer: u8, file: u32) -> [u8; ENTRY_LEN] {
+        let mut entry = [0u8; ENTRY_LEN];
+        entry[0] = kind;
+        entry[1] = layer;
+        entry[0x04..0x08].copy_from_slice(&file.to_le_bytes());
+        entry[0x08..0x10].copy_from_slice(&(file as u64 * 100).to_le_bytes());
+        entry
+    }
+
+    fn record(entries: &[[u8; ENTRY_LEN]]) -> Vec<u8> {
+        let mut record = MAGIC.to_vec();
+        record.extend_from_slice(&VERSION.to_le_bytes());
+        record.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        record.extend_from_slice(&[0u8; 4]);
+        record.extend(entries.iter().flatten());
+        record
+    }
+
+    #[test]
+    fn mktape() {
+        let manifest = Manifest::parse(MKTAPE).unwrap();
+        assert_eq!(manifest.version, 1);
+
+        let entries: Vec<_> = manifest
+            .entries
+            .iter()
+            .map(|entry| {
+                (
+                    entry.kind,
+                    entry.layer,
+                    entry.file,
+                    entry.size,
+                    entry.block_len,
+                )
+            })
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                (PayloadKind::Kernel, None, 1, 1300, 512),
+                (PayloadKind::Initrd, Some(Layer::Microcode), 2, 9, 512),
+                (PayloadKind::Initrd, Some(Layer::Base), 3, 7, 512),
+                (PayloadKind::Cmdline, None, 4, 14, 512),
+            ]
+        );
+        assert_eq!(
+            manifest.entries[0].digest_hex(),
+            "7c70d162aa759c1cdd0682c740c94e911f45d89a9f12172685b73a56d056f485"
+        );
+
+        // It's just the one record, anything after the entries is ignored
+        let mut padded = MKTAPE.to_vec();
+        padded.resize(512, 0);
+        assert_eq!(Manifest::parse(&padded), Ok(manifest));
+    }
+
+    #[test]
+    fn header() {
+        assert!(Manifest::is_manifest(MKTAPE));
+        assert!(!Manifest::is_manifest(b"VOL1"));
+
+        let mut bad = MKTAPE.to_vec();
+        bad[0] = b'X';
+        assert_eq!(Manifest::parse(&bad), Err(ManifestError::BadMagic));
+
+        let mut bad = MKTAPE.to_vec();
+        bad[0x08] = 2;
+        assert_eq!(
+            Manifest::parse(&bad),
+            Err(ManifestError::UnsupportedVersion(2))
+        );
+
+        assert_eq!(
+            Manifest::parse(&MKTAPE[..HEADER_LEN - 1]),
+            Err(ManifestError::Truncated)
+        );
+        assert_eq!(
+            Manifest::parse(&MKTAPE[..MKTAPE.len() - 1]),
+            Err(ManifestError::Truncated)
+        );
+        assert_eq!(
+            Manifest::parse(&MKTAPE[..HEADER_LEN + ENTRY_LEN]),
+            Err(ManifestError::Truncated)
+        );
+    }
+
+    #[test]
+    fn entries() {
+        assert_eq!(
+            Manifest::parse(&record(&[entry(0x01, 0, 1), entry(0x06, 0, 2)])),
+            Err(ManifestError::UnknownKind(0x06))
+        );
+        assert_eq!(
+            Manifest::parse(&record(&[entry(0x01, 0, 1), entry(0x02, 0x03, 2)])),
+            Err(ManifestError::UnknownLayer(0x03))
+        );
+        // Only an initrd has a layer
+        let manifest = Manifest::parse(&record(&[entry(0x01, 0x03, 1)])).unwrap();
+        assert_eq!(manifest.entries[0].layer, None);
+
+        assert_eq!(Manifest::parse(&record(&[])), Err(ManifestError::NoKernel));
+        assert_eq!(
+            Manifest::parse(&record(&[entry(0x02, 0, 1)])),
+            Err(ManifestError::NoKernel)
+        );
+        assert_eq!(
+            Manifest::parse(&record(&[entry(0x01, 0, 1), entry(0x01, 0, 2)])),
+            Err(ManifestError::Duplicate(PayloadKind::Kernel))
+        );
+        assert_eq!(
+            Manifest::parse(&record(&[
+                entry(0x01, 0, 1),
+                entry(0x05, 0, 2),
+                entry(0x05, 0, 3)
+            ])),
+            Err(ManifestError::Duplicate(PayloadKind::Signature))
+        );
+        // But as many initrds as you like
+        let manifest = Manifest::parse(&record(&[
+            entry(0x01, 0, 1),
+            entry(0x02, 0, 2),
+            entry(0x02, 0, 3),
+        ]))
+        .unwrap();
+        assert_eq!(manifest.entries.len(), 3);
+    }
+
+    #[test]
+    fn files() {
+        // Gaps are fine, the loader spaces over them
+        assert!(Manifest::parse(&record(&[entry(0x01, 0, 2), entry(0x02, 0, 5)])).is_ok());
+
+        // File 0 is the manifest
+        assert_eq!(
+            Manifest::parse(&record(&[entry(0x01, 0, 0)])),
+            Err(ManifestError::BadFile { index: 0, file: 0 })
+        );
+        assert_eq!(
+            Manifest::parse(&record(&[entry(0x01, 0, 1), entry(0x02, 0, 1)])),
+            Err(ManifestError::BadFile { index: 1, file: 1 })
+        );
+        assert_eq!(
+            Manifest::parse(&record(&[
+                entry(0x01, 0, 1),
+                entry(0x02, 0, 3),
+                entry(0x03, 0, 2)
+            ])),
+            Err(ManifestError::BadFile { index: 2, file: 2 })
+        );
+    }
+}
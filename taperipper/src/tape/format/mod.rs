@@ -0,0 +1,14 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// The things people actually write onto tapes.
+//
+// Everything in here works off of a `FileStream` or raw records, so it doesn't care if the
+// tape is a real drive or an image.
+
+pub mod ansi;
+pub mod deblock;
+pub mod ebcdic;
+pub mod ibm;
+pub mod labelled;
+pub mod manifest;
+pub mod signature;
+pub mod tar;
@@ -0,0 +1,123 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// Detached signatures over the payloads on a boot tape.
+//
+// Digests only tell you the tape reads back the way it was written, not who wrote it, so a
+// tape can carry an Ed25519 signature as well. It sits in a tape file of its own, a single
+// record of:
+//
+//   0x00  [u8; 8]   magic, "TRIPSIGN"
+//   0x08  u16       version
+//   0x0A  [u8; 6]   reserved, zero
+//   0x10  [u8; 32]  Ed25519 public key of the signer
+//   0x30  [u8; 64]  Ed25519 signature
+//
+// What is signed isn't the payloads themselves, but a statement of their digests, as we
+// already work those out while they stream in and don't want to go over them all again:
+//
+//   0x00  [u8; 8]   magic, "TRIPPAYL"
+//   0x08  u16       version
+//   0x0A  u16       number of payloads
+//   0x0C  u32       reserved, zero
+//   0x10  payloads, 48 bytes each, the kernel, then the initrd, then the command-line:
+//     0x00  u8        kind, as in the manifest
+//     0x01  [u8; 7]   reserved, zero
+//     0x08  u64       payload size in bytes
+//     0x10  [u8; 32]  SHA-256 of the payload
+//
+// Everything is little-endian. An initrd that came in parts is signed as one, as it is
+// booted as one. Compressed payloads are signed as they are on the tape, not decompressed.
+
+use core::fmt;
+
+use crate::tape::format::manifest::{DIGEST_LEN, PayloadKind};
+
+pub const MAGIC: [u8; 8] = *b"TRIPSIGN";
+pub const SIGNED_MAGIC: [u8; 8] = *b"TRIPPAYL";
+pub const VERSION: u16 = 1;
+
+pub const RECORD_LEN: usize = 112;
+pub const KEY_LEN: usize = 32;
+pub const SIGNATURE_LEN: usize = 64;
+
+pub const SIGNED_HEADER_LEN: usize = 16;
+pub const SIGNED_ENTRY_LEN: usize = 48;
+
+// The kinds of payload that are signed, in the order they are signed in
+pub const SIGNED_KINDS: &[PayloadKind] = &[
+    PayloadKind::Kernel,
+    PayloadKind::Initrd,
+    PayloadKind::Cmdline,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SignatureError {
+    // Not a signature at all
+    BadMagic,
+    UnsupportedVersion(u16),
+    Truncated,
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureError::BadMagic => write!(f, "not a payload signature"),
+            SignatureError::UnsupportedVersion(ver) => {
+                write!(f, "unsupported signature version {ver}")
+            }
+            SignatureError::Truncated => write!(f, "signature is truncated"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Signature {
+    pub key: [u8; KEY_LEN],
+    pub signature: [u8; SIGNATURE_LEN],
+}
+
+impl Signature {
+    pub fn is_signature(record: &[u8]) -> bool {
+        record.starts_with(&MAGIC)
+    }
+
+    pub fn parse(record: &[u8]) -> Result<Self, SignatureError> {
+        if !Self::is_signature(record) {
+            return Err(SignatureError::BadMagic);
+        }
+        if record.len() < RECORD_LEN {
+            return Err(SignatureError::Truncated);
+        }
+
+        let version = u16::from_le_bytes([record[0x08], record[0x09]]);
+        if version != VERSION {
+            return Err(SignatureError::UnsupportedVersion(version));
+        }
+
+        Ok(Self {
+            key: record[0x10..0x30].try_into().unwrap(),
+            signature: record[0x30..0x70].try_into().unwrap(),
+        })
+    }
+}
+
+// Build the statement of `payloads` that the signature is over
+//
+// `payloads` should be in `SIGNED_KINDS` order, with anything that isn't on the tape left out.
+pub fn signed_message(payloads: &[(PayloadKind, u64, [u8; DIGEST_LEN])]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(SIGNED_HEADER_LEN + payloads.len() * SIGNED_ENTRY_LEN);
+
+    message.extend_from_slice(&SIGNED_MAGIC);
+    message.extend_from_slice(&VERSION.to_le_bytes());
+    message.extend_from_slice(&(payloads.len() as u16).to_le_bytes());
+    message.extend_from_slice(&[0u8; 4]);
+
+    for (kind, size, sha256) in payloads {
+        let mut entry = [0u8; SIGNED_ENTRY_LEN];
+        entry[0] = kind.as_byte();
+        entry[0x08..0x10].copy_from_slice(&size.to_le_bytes());
+        entry[0x10..0x30].copy_from_slice(sha256);
+        message.extend_from_slice(&entry);
+    }
+
+    message
+}
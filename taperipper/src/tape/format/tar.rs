@@ -0,0 +1,775 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// Streaming POSIX tar reader.
+//
+// A tar archive is a series of 512 byte headers each followed by the file body padded out
+// to a multiple of 512 bytes, ending with (at least) two blocks of zeros. On tape that gets
+// chopped into records, 20 blocks (10240 bytes) each by default, but we read it back as a
+// byte stream so the blocking doesn't matter.
+//
+// Plain ustar can only store 255 byte paths, so both pax and GNU tar put longer names (and
+// sizes too large for the octal field) in an extra entry ahead of the real one, which we
+// fold into the header of the entry it describes.
+//
+// see: https://pubs.opengroup.org/onlinepubs/9799919799/utilities/pax.html
+// see: https://www.gnu.org/software/tar/manual/html_node/Standard.html
+
+use core::fmt;
+
+use tracing::{debug, trace, warn};
+
+use crate::tape::{
+    device::{DeviceError, TapeDevice},
+    stream::FileStream,
+};
+
+pub const BLOCK_LEN: usize = 512;
+// What tar uses when it isn't told otherwise, 20 blocks per record
+pub const DEFAULT_RECORD_LEN: usize = BLOCK_LEN * 20;
+
+// NOTE(aki): Long names and pax headers are read into memory, don't let a bad archive
+// make us allocate something silly
+const MAX_EXTENDED_LEN: u64 = 64 * 1024;
+// The same goes for the small files we read whole, like digests and signatures
+const MAX_READ_TO_VEC_LEN: u64 = 1024 * 1024;
+
+const USTAR_MAGIC: &[u8] = b"ustar\0";
+const GNU_MAGIC: &[u8] = b"ustar ";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    HardLink,
+    Symlink,
+    CharDevice,
+    BlockDevice,
+    Directory,
+    Fifo,
+    // pax per-file extended header
+    PaxHeader,
+    // pax global extended header
+    PaxGlobal,
+    // GNU long name for the next entry
+    GnuLongName,
+    // GNU long link target for the next entry
+    GnuLongLink,
+    Other(u8),
+}
+
+impl EntryKind {
+    fn from_flag(flag: u8) -> Self {
+        match flag {
+            // '7' is a contiguous file, which nothing actually does anything special with
+            b'0' | b'\0' | b'7' => EntryKind::File,
+            b'1' => EntryKind::HardLink,
+            b'2' => EntryKind::Symlink,
+            b'3' => EntryKind::CharDevice,
+            b'4' => EntryKind::BlockDevice,
+            b'5' => EntryKind::Directory,
+            b'6' => EntryKind::Fifo,
+            b'x' => EntryKind::PaxHeader,
+            b'g' => EntryKind::PaxGlobal,
+            b'L' => EntryKind::GnuLongName,
+            b'K' => EntryKind::GnuLongLink,
+            flag => EntryKind::Other(flag),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TarError {
+    Device(DeviceError),
+    // The tape file ended in the middle of the archive
+    Truncated,
+    Checksum { offset: u64 },
+    Header(&'static str),
+    // An entry too big to read into memory whole
+    TooLarge { size: u64 },
+}
+
+impl fmt::Display for TarError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TarError::Device(err) => write!(f, "{err}"),
+            TarError::Truncated => write!(f, "archive is truncated"),
+            TarError::Checksum { offset } => {
+                write!(f, "header checksum mismatch at offset {offset}")
+            }
+            TarError::Header(what) => write!(f, "bad header: {what}"),
+            TarError::TooLarge { size } => {
+                write!(f, "entry of {size} bytes is too large to read into memory")
+            }
+        }
+    }
+}
+
+impl From<DeviceError> for TarError {
+    fn from(err: DeviceError) -> Self {
+        TarError::Device(err)
+    }
+}
+
+// A single raw 512 byte header block
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub name: String,
+    pub mode: u32,
+    pub size: u64,
+    pub mtime: u64,
+    pub kind: EntryKind,
+    pub link: String,
+}
+
+impl Header {
+    // Parse a header block, returns `None` for an all zero block
+    pub fn parse(block: &[u8; BLOCK_LEN], offset: u64) -> Result<Option<Self>, TarError> {
+        if block.iter().all(|&b| b == 0) {
+            return Ok(None);
+        }
+
+        let expected = parse_number(&block[148..156]).ok_or(TarError::Header("checksum"))?;
+        if !checksum_matches(block, expected) {
+            return Err(TarError::Checksum { offset });
+        }
+
+        let magic = &block[257..263];
+        let mut name = field_str(&block[0..100]);
+
+        // Only POSIX ustar has the prefix, GNU tar uses that space for other things
+        if magic == USTAR_MAGIC {
+            let prefix = field_str(&block[345..500]);
+            if !prefix.is_empty() {
+                name = format!("{prefix}/{name}");
+            }
+        } else if magic != GNU_MAGIC {
+            trace!(offset, "Pre-POSIX tar header");
+        }
+
+        Ok(Some(Self {
+            name,
+            mode: parse_number(&block[100..108]).ok_or(TarError::Header("mode"))? as u32,
+            size: parse_number(&block[124..136]).ok_or(TarError::Header("size"))?,
+            mtime: parse_number(&block[136..148]).unwrap_or(0),
+            kind: EntryKind::from_flag(block[156]),
+            link: field_str(&block[157..257]),
+        }))
+    }
+}
+
+// An entry in the archive, with any long names already applied
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub path: String,
+    pub link: Option<String>,
+    pub kind: EntryKind,
+    pub size: u64,
+    pub mode: u32,
+    pub mtime: u64,
+    // Where the header of this entry starts in the archive
+    pub offset: u64,
+}
+
+impl Entry {
+    // The last component of the path
+    pub fn file_name(&self) -> &str {
+        let path = self.path.trim_end_matches('/');
+        path.rsplit_once('/').map_or(path, |(_, name)| name)
+    }
+}
+
+// Things an extended header can override in the entries after it
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct Overrides {
+    path: Option<String>,
+    link: Option<String>,
+    size: Option<u64>,
+    mtime: Option<u64>,
+}
+
+impl Overrides {
+    // Apply pax "<len> <key>=<value>\n" records
+    fn apply_pax(&mut self, data: &[u8]) -> Result<(), TarError> {
+        let mut rest = data;
+
+        while !rest.is_empty() {
+            let space = rest
+                .iter()
+                .position(|&b| b == b' ')
+                .ok_or(TarError::Header("pax record length"))?;
+            let len: usize = str::from_utf8(&rest[..space])
+                .ok()
+                .and_then(|len| len.parse().ok())
+                .filter(|&len| len > space && len <= rest.len())
+                .ok_or(TarError::Header("pax record length"))?;
+
+            let record = &rest[space + 1..len];
+            let record = record.strip_suffix(b"\n").unwrap_or(record);
+            rest = &rest[len..];
+
+            let Some(eq) = record.iter().position(|&b| b == b'=') else {
+                return Err(TarError::Header("pax record"));
+            };
+            let key = String::from_utf8_lossy(&record[..eq]);
+            let value = String::from_utf8_lossy(&record[eq + 1..]).into_owned();
+
+            match &*key {
+                "path" => self.path = Some(value),
+                "linkpath" => self.link = Some(value),
+                "size" => {
+                    self.size = Some(value.parse().map_err(|_| TarError::Header("pax size"))?)
+                }
+                // Fractional timestamps, we only care about the seconds
+                "mtime" => self.mtime = value.split('.').next().and_then(|secs| secs.parse().ok()),
+                _ => trace!(key = &*key, "Ignoring pax record"),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn or(self, global: &Overrides) -> Self {
+        Self {
+            path: self.path.or_else(|| global.path.clone()),
+            link: self.link.or_else(|| global.link.clone()),
+            size: self.size.or(global.size),
+            mtime: self.mtime.or(global.mtime),
+        }
+    }
+}
+
+pub struct TarReader<'d, D: TapeDevice> {
+    stream: FileStream<'d, D>,
+    // How far into the archive we are
+    offset: u64,
+    // Body bytes of the current entry left to read, and the padding after them
+    remaining: u64,
+    padding: u64,
+    global: Overrides,
+    finished: bool,
+}
+
+impl<'d, D: TapeDevice> TarReader<'d, D> {
+    pub fn new(stream: FileStream<'d, D>) -> Self {
+        Self {
+            stream,
+            offset: 0,
+            remaining: 0,
+            padding: 0,
+            global: Overrides::default(),
+            finished: false,
+        }
+    }
+
+    pub fn stream(&self) -> &FileStream<'d, D> {
+        &self.stream
+    }
+
+    async fn skip(&mut self, count: u64) -> Result<(), TarError> {
+        if self.stream.skip(count).await? != count {
+            return Err(TarError::Truncated);
+        }
+        self.offset += count;
+        Ok(())
+    }
+
+    // Read the next header block, returns `false` if the tape file ends before it
+    async fn read_block(&mut self, block: &mut [u8; BLOCK_LEN]) -> Result<bool, TarError> {
+        // Running out part way through one is another matter
+        let len = self.stream.read(block).await?;
+        if len == 0 {
+            return Ok(false);
+        }
+        if !self.stream.read_exact(&mut block[len..]).await? {
+            return Err(TarError::Truncated);
+        }
+
+        self.offset += BLOCK_LEN as u64;
+        Ok(true)
+    }
+
+    // Read the body of an extension entry into memory
+    async fn read_extended(&mut self, size: u64) -> Result<Vec<u8>, TarError> {
+        if size > MAX_EXTENDED_LEN {
+            return Err(TarError::Header("extended header too large"));
+        }
+
+        let mut data = vec![0u8; size as usize];
+        if !self.stream.read_exact(&mut data).await? {
+            return Err(TarError::Truncated);
+        }
+        self.offset += size;
+        self.skip(padding(size)).await?;
+
+        Ok(data)
+    }
+
+    // Move on to the next entry, skipping whatever is left of the current one
+    pub async fn next_entry(&mut self) -> Result<Option<Entry>, TarError> {
+        if self.finished {
+            return Ok(None);
+        }
+
+        self.skip(self.remaining + self.padding).await?;
+        self.remaining = 0;
+        self.padding = 0;
+
+        let mut local = Overrides::default();
+        let mut block = [0u8; BLOCK_LEN];
+
+        loop {
+            let offset = self.offset;
+
+            if !self.read_block(&mut block).await? {
+                // NOTE(aki): Plenty of writers forget the trailer, so just running out of tape
+                // file on a header boundary is fine
+                warn!(offset, "Tar archive has no end-of-archive marker");
+                self.finished = true;
+                return Ok(None);
+            }
+
+            let Some(header) = Header::parse(&block, offset)? else {
+                debug!(offset, "End of tar archive");
+                self.finished = true;
+                return Ok(None);
+            };
+
+            match header.kind {
+                EntryKind::PaxHeader => {
+                    let data = self.read_extended(header.size).await?;
+                    local.apply_pax(&data)?;
+                }
+                EntryKind::PaxGlobal => {
+                    let data = self.read_extended(header.size).await?;
+                    self.global.apply_pax(&data)?;
+                }
+                EntryKind::GnuLongName => {
+                    let data = self.read_extended(header.size).await?;
+                    local.path = Some(field_str(&data));
+                }
+                EntryKind::GnuLongLink => {
+                    let data = self.read_extended(header.size).await?;
+                    local.link = Some(field_str(&data));
+                }
+                _ => {
+                    let overrides = local.or(&self.global);
+                    let size = overrides.size.unwrap_or(header.size);
+                    let link = overrides.link.unwrap_or(header.link);
+
+                    // Only files actually have a body, whatever the size field says
+                    self.remaining = match header.kind {
+                        EntryKind::File | EntryKind::Other(_) => size,
+                        _ => 0,
+                    };
+                    self.padding = padding(self.remaining);
+
+                    let entry = Entry {
+                        path: overrides.path.unwrap_or(header.name),
+                        link: (!link.is_empty()).then_some(link),
+                        kind: header.kind,
+                        size,
+                        mode: header.mode,
+                        mtime: overrides.mtime.unwrap_or(header.mtime),
+                        offset,
+                    };
+
+                    trace!(
+                        path = entry.path.as_str(),
+                        link = entry.link.as_deref(),
+                        size = entry.size,
+                        kind = ?entry.kind,
+                        mode = %format_args!("{:04o}", entry.mode),
+                        mtime = entry.mtime,
+                        offset = entry.offset,
+                        "Tar entry"
+                    );
+
+                    return Ok(Some(entry));
+                }
+            }
+        }
+    }
+
+    // Read some of the body of the current entry, returns 0 at the end of it
+    pub async fn read(&mut self, buffer: &mut [u8]) -> Result<usize, TarError> {
+        if self.remaining == 0 {
+            return Ok(0);
+        }
+
+        let want = buffer
+            .len()
+            .min(self.remaining.min(usize::MAX as u64) as usize);
+        let len = self.stream.read(&mut buffer[..want]).await?;
+        if len == 0 {
+            return Err(TarError::Truncated);
+        }
+
+        self.remaining -= len as u64;
+        self.offset += len as u64;
+
+        Ok(len)
+    }
+
+    // Read the whole body of the current entry into memory
+    pub async fn read_to_vec(&mut self) -> Result<Vec<u8>, TarError> {
+        if self.remaining > MAX_READ_TO_VEC_LEN {
+            return Err(TarError::TooLarge {
+                size: self.remaining,
+            });
+        }
+
+        let mut data = vec![0u8; self.remaining as usize];
+        let mut filled = 0;
+
+        while filled < data.len() {
+            filled += self.read(&mut data[filled..]).await?;
+        }
+
+        Ok(data)
+    }
+
+    // Read the rest of the tape file after the archive, so the tape is left at the next one
+    pub async fn finish(&mut self) -> Result<(), TarError> {
+        self.finished = true;
+        self.remaining = 0;
+        self.padding = 0;
+        self.stream.finish().await?;
+        Ok(())
+    }
+}
+
+// How much padding follows a body of `size` bytes
+fn padding(size: u64) -> u64 {
+    (BLOCK_LEN as u64 - size % BLOCK_LEN as u64) % BLOCK_LEN as u64
+}
+
+// A NUL terminated string field
+fn field_str(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+// Numeric fields are octal, or big-endian base-256 with the top bit set for large values
+fn parse_number(field: &[u8]) -> Option<u64> {
+    if field.first().is_some_and(|&b| b & 0x80 != 0) {
+        // NOTE(aki): Negative base-256 values are a thing but not for anything we read
+        return field[1..]
+            .iter()
+            .try_fold((field[0] & 0x7F) as u64, |acc, &b| {
+                acc.checked_mul(256).map(|acc| acc + b as u64)
+            });
+    }
+
+    let digits = field
+        .iter()
+        .copied()
+        .skip_while(|&b| b == b' ')
+        .take_while(|&b| b != b' ' && b != 0);
+
+    let mut value: u64 = 0;
+    let mut any = false;
+    for digit in digits {
+        if !(b'0'..=b'7').contains(&digit) {
+            return None;
+        }
+        value = value.checked_mul(8)? + (digit - b'0') as u64;
+        any = true;
+    }
+
+    // An empty field is a zero, some writers leave fields they don't care about blank
+    Some(if any { value } else { 0 })
+}
+
+// The checksum is the sum of the header with the checksum field as spaces, some old
+// writers summed signed bytes so we accept either
+fn checksum_matches(block: &[u8; BLOCK_LEN], expected: u64) -> bool {
+    let (unsigned, signed) = block
+        .iter()
+        .enumerate()
+        .map(|(idx, &b)| if (148..156).contains(&idx) { b' ' } else { b })
+        .fold((0u64, 0i64), |(unsigned, signed), b| {
+            (unsigned + b as u64, signed + (b as i8) as i64)
+        });
+
+    unsigned == expected || signed == expected as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tape::{
+        device::ReadOutcome,
+        testing::{Item, MockTape, block_on},
+    };
+
+    // All but `pax_size.tar` are GNU tar 1.34, with `--mtime=@1700000000 --owner=0 --group=0
+    // --numeric-owner --sort=name` and
+    //   ustar.tar       --format=ustar, a path that only fits with the prefix and a symlink
+    //   pax.tar         --format=pax, a path too long for ustar at all
+    //   gnu.tar         --format=gnu, the same path and a symlink with a long target
+    //   pax_global.tar  --format=pax --pax-option=comment=<70000 x's>, just the first 1024
+    //                   bytes of it
+    //   pax_size.tar    Python 3.11 tarfile's pax header for a 9 GiB file, without the file
+    const USTAR: &[u8] = include_bytes!("testdata/ustar.tar");
+    const PAX: &[u8] = include_bytes!("testdata/pax.tar");
+    const GNU: &[u8] = include_bytes!("testdata/gnu.tar");
+    const PAX_GLOBAL: &[u8] = include_bytes!("testdata/pax_global.tar");
+    const PAX_SIZE: &[u8] = include_bytes!("testdata/pax_size.tar");
+
+    const MTIME: u64 = 1_700_000_000;
+
+    // An archive on tape in `record_len` records, each archive its own tape file
+    fn tape(archives: &[&[u8]], record_len: usize) -> MockTape {
+        let mut items = Vec::new();
+        for archive in archives {
+            items.extend(
+                archive
+                    .chunks(record_len)
+                    .map(|record| Item::Block(record.to_vec())),
+            );
+            items.push(Item::Filemark);
+        }
+
+        MockTape::new(items)
+    }
+
+    // Everything in the archive and what is in it, up to the first error
+    fn read_all(archive: &[u8], record_len: usize) -> (Vec<(Entry, Vec<u8>)>, Option<TarError>) {
+        let mut device = tape(&[archive], record_len);
+        let mut reader = TarReader::new(FileStream::with_record_len(&mut device, record_len));
+        let mut entries = Vec::new();
+
+        block_on(async {
+            loop {
+                let entry = match reader.next_entry().await {
+                    Ok(Some(entry)) => entry,
+                    Ok(None) => return (entries, None),
+                    Err(err) => return (entries, Some(err)),
+                };
+                match reader.read_to_vec().await {
+                    Ok(data) => entries.push((entry, data)),
+                    Err(err) => return (entries, Some(err)),
+                }
+            }
+        })
+    }
+
+    fn long_path() -> String {
+        format!(
+            "boot/{}/{}/{}/initrd.img",
+            "l".repeat(120),
+            "m".repeat(120),
+            "n".repeat(40)
+        )
+    }
+
+    #[test]
+    fn ustar() {
+        let vmlinuz = format!("boot/{}/vmlinuz", "d".repeat(90));
+
+        // It makes no difference how the archive was blocked on tape
+        for record_len in [BLOCK_LEN, 1000, DEFAULT_RECORD_LEN] {
+            let (entries, err) = read_all(USTAR, record_len);
+            assert_eq!(err, None);
+
+            let summary: Vec<_> = entries
+                .iter()
+                .map(|(entry, data)| {
+                    (
+                        entry.path.as_str(),
+                        entry.link.as_deref(),
+                        entry.kind,
+                        entry.size,
+                        data.as_slice(),
+                    )
+                })
+                .collect();
+            assert_eq!(
+                summary,
+                [
+                    (
+                        vmlinuz.as_str(),
+                        None,
+                        EntryKind::File,
+                        7,
+                        b"kernel\n".as_slice()
+                    ),
+                    (
+                        "boot/cmdline",
+                        None,
+                        EntryKind::File,
+                        14,
+                        b"console=ttyS0\n"
+                    ),
+                    (
+                        "boot/current",
+                        Some(&vmlinuz[5..]),
+                        EntryKind::Symlink,
+                        0,
+                        b""
+                    ),
+                ]
+            );
+
+            assert_eq!(entries[0].0.file_name(), "vmlinuz");
+            assert_eq!(entries[0].0.mode, 0o644);
+            assert_eq!(entries[0].0.mtime, MTIME);
+            assert_eq!(
+                entries
+                    .iter()
+                    .map(|(entry, _)| entry.offset)
+                    .collect::<Vec<_>>(),
+                [0, 1024, 2048]
+            );
+        }
+    }
+
+    #[test]
+    fn pax() {
+        let (entries, err) = read_all(PAX, DEFAULT_RECORD_LEN);
+        assert_eq!(err, None);
+        assert_eq!(entries.len(), 2);
+
+        // The atime and ctime records are ignored, and the mtime is left alone
+        assert_eq!(entries[0].0.path, "boot/cmdline");
+        assert_eq!(entries[0].0.mtime, MTIME);
+        assert_eq!(entries[0].0.offset, 1024);
+        assert_eq!(entries[0].1, b"console=ttyS0\n");
+
+        assert_eq!(entries[1].0.path, long_path());
+        assert_eq!(entries[1].0.file_name(), "initrd.img");
+        assert_eq!(entries[1].1, b"initrd\n");
+    }
+
+    #[test]
+    fn pax_size() {
+        let mut device = tape(&[PAX_SIZE], DEFAULT_RECORD_LEN);
+        let mut reader = TarReader::new(FileStream::new(&mut device));
+
+        block_on(async {
+            let entry = reader.next_entry().await.unwrap().unwrap();
+            assert_eq!(entry.path, "boot/huge.img");
+            assert_eq!(entry.size, 9 * 1024 * 1024 * 1024);
+
+            assert_eq!(
+                reader.read_to_vec().await,
+                Err(TarError::TooLarge { size: entry.size })
+            );
+            assert_eq!(reader.read(&mut [0u8; 512]).await, Err(TarError::Truncated));
+        });
+    }
+
+    #[test]
+    fn gnu() {
+        let (entries, err) = read_all(GNU, DEFAULT_RECORD_LEN);
+        assert_eq!(err, None);
+        assert_eq!(entries.len(), 2);
+
+        assert_eq!(entries[0].0.path, long_path());
+        assert_eq!(entries[0].0.kind, EntryKind::File);
+        assert_eq!(entries[0].1, b"initrd\n");
+
+        assert_eq!(entries[1].0.path, "boot/longlink");
+        assert_eq!(entries[1].0.kind, EntryKind::Symlink);
+        assert_eq!(entries[1].0.link, Some("t".repeat(110)));
+    }
+
+    #[test]
+    fn pax_global() {
+        let (entries, err) = read_all(PAX_GLOBAL, DEFAULT_RECORD_LEN);
+        assert!(entries.is_empty());
+        assert_eq!(err, Some(TarError::Header("extended header too large")));
+    }
+
+    #[test]
+    fn checksum() {
+        let mut bad = USTAR.to_vec();
+        bad[1024] ^= 0x01;
+        let (entries, err) = read_all(&bad, DEFAULT_RECORD_LEN);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(err, Some(TarError::Checksum { offset: 1024 }));
+
+        let mut bad = USTAR.to_vec();
+        bad[148..156].copy_from_slice(b"0000000\0");
+        assert_eq!(
+            read_all(&bad, DEFAULT_RECORD_LEN).1,
+            Some(TarError::Checksum { offset: 0 })
+        );
+        bad[148..156].copy_from_slice(b"0000009\0");
+        assert_eq!(
+            read_all(&bad, DEFAULT_RECORD_LEN).1,
+            Some(TarError::Header("checksum"))
+        );
+    }
+
+    #[test]
+    fn truncated() {
+        // Part way through a body
+        let (entries, err) = read_all(&USTAR[..BLOCK_LEN + 3], BLOCK_LEN);
+        assert!(entries.is_empty());
+        assert_eq!(err, Some(TarError::Truncated));
+
+        // Part way through the padding after it
+        let (entries, err) = read_all(&USTAR[..BLOCK_LEN + 100], BLOCK_LEN);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(err, Some(TarError::Truncated));
+
+        // Part way through a header
+        let (entries, err) = read_all(&USTAR[..1024 + 100], BLOCK_LEN);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(err, Some(TarError::Truncated));
+
+        // Part way through a long name
+        let (entries, err) = read_all(&GNU[..BLOCK_LEN + 100], BLOCK_LEN);
+        assert!(entries.is_empty());
+        assert_eq!(err, Some(TarError::Truncated));
+
+        // But no end-of-archive marker at all is fine
+        let (entries, err) = read_all(&USTAR[..2560], BLOCK_LEN);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(err, None);
+    }
+
+    #[test]
+    fn blocking() {
+        // tar pads the archive out to a whole record, and the tape has to be left at the
+        // start of the next file once we're done with it
+        let mut device = tape(&[USTAR, GNU], DEFAULT_RECORD_LEN);
+
+        block_on(async {
+            let mut reader = TarReader::new(FileStream::new(&mut device));
+            while reader.next_entry().await.unwrap().is_some() {}
+            reader.finish().await.unwrap();
+            assert_eq!(reader.stream().records(), 1);
+            assert_eq!(reader.stream().first_record_len(), Some(DEFAULT_RECORD_LEN));
+
+            let mut reader = TarReader::new(FileStream::new(&mut device));
+            let entry = reader.next_entry().await.unwrap().unwrap();
+            assert_eq!(entry.path, long_path());
+            // Stopping part way through the archive
+            reader.finish().await.unwrap();
+        });
+
+        let mut buffer = [0u8; DEFAULT_RECORD_LEN];
+        assert_eq!(
+            block_on(device.read_block(&mut buffer)),
+            Ok(ReadOutcome::EndOfData)
+        );
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(parse_number(b"0000644\0"), Some(0o644));
+        assert_eq!(parse_number(b"  644 \0"), Some(0o644));
+        assert_eq!(parse_number(b"\0\0\0\0"), Some(0));
+        assert_eq!(parse_number(b"        "), Some(0));
+        assert_eq!(parse_number(b"0008\0"), None);
+        assert_eq!(
+            parse_number(&[0x80, 0, 0, 0, 0, 0, 0, 0x02, 0x40, 0, 0, 0]),
+            Some(9 * 1024 * 1024 * 1024)
+        );
+        assert_eq!(parse_number(&[0x80, 0x01, 0, 0, 0, 0, 0, 0, 0, 0]), None);
+
+        assert_eq!(padding(0), 0);
+        assert_eq!(padding(1), 511);
+        assert_eq!(padding(512), 0);
+    }
+}
@@ -0,0 +1,457 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// AWSTAPE (`.aws`) tape images.
+//
+// Every chunk of data is preceded by a 6-byte header, the little-endian length of this
+// chunk, the length of the previous chunk (so it can be walked backwards), and a pair of
+// flag bytes. A single tape block may be split over multiple chunks, the first one is
+// flagged as the start of the record and the last one as the end of it. A tape mark is a
+// header with no data and the tape mark flag set.
+//
+// see: https://www.hercules-390.eu/hercules/tapeconv.html
+
+use core::time::Duration;
+
+use tracing::{debug, warn};
+
+use crate::tape::{
+    device::{DeviceError, DeviceStatus, ReadOutcome, TapeDevice, TapePosition},
+    scsi::commands::SpaceCode,
+};
+
+const HEADER_LEN: usize = 6;
+
+const FLAG_NEW_RECORD: u8 = 0x80;
+const FLAG_TAPE_MARK: u8 = 0x40;
+const FLAG_END_RECORD: u8 = 0x20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Header {
+    len: usize,
+    prev_len: usize,
+    flags: u8,
+}
+
+impl Header {
+    fn parse(data: &[u8]) -> Self {
+        Self {
+            len: u16::from_le_bytes([data[0], data[1]]) as usize,
+            prev_len: u16::from_le_bytes([data[2], data[3]]) as usize,
+            flags: data[4],
+        }
+    }
+
+    fn is_tape_mark(&self) -> bool {
+        (self.flags & FLAG_TAPE_MARK) != 0
+    }
+
+    fn is_record_start(&self) -> bool {
+        (self.flags & FLAG_NEW_RECORD) != 0
+    }
+
+    fn is_record_end(&self) -> bool {
+        (self.flags & FLAG_END_RECORD) != 0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Record {
+    TapeMark,
+    EndOfMedium,
+    Data,
+}
+
+pub struct AwsTape {
+    name: String,
+    data: Vec<u8>,
+    offset: usize,
+    // Length of the chunk just behind `offset`, needed to find its header going backwards
+    prev_len: usize,
+    file: u32,
+    block: u64,
+}
+
+impl AwsTape {
+    pub fn from_bytes(name: &str, data: Vec<u8>) -> Self {
+        Self {
+            name: name.to_string(),
+            data,
+            offset: 0,
+            prev_len: 0,
+            file: 0,
+            block: 0,
+        }
+    }
+
+    fn at_end(&self) -> bool {
+        self.offset + HEADER_LEN > self.data.len()
+    }
+
+    fn header(&self, offset: usize) -> Header {
+        Header::parse(&self.data[offset..offset + HEADER_LEN])
+    }
+
+    // Pull the next record off of the image, reassembling it into `buffer` if we were
+    // given one, returns the full length of the record even if it didn't fit
+    fn next_record(
+        &mut self,
+        mut buffer: Option<&mut [u8]>,
+    ) -> Result<(Record, usize), DeviceError> {
+        if self.at_end() {
+            return Ok((Record::EndOfMedium, 0));
+        }
+
+        let first = self.header(self.offset);
+        if first.is_tape_mark() {
+            self.offset += HEADER_LEN;
+            self.prev_len = 0;
+            return Ok((Record::TapeMark, 0));
+        }
+
+        if !first.is_record_start() {
+            warn!(
+                offset = self.offset,
+                "Record in AWS image is missing its start flag"
+            );
+        }
+
+        let (record_offset, record_prev_len) = (self.offset, self.prev_len);
+        let mut total = 0;
+
+        loop {
+            let header = (!self.at_end()).then(|| self.header(self.offset));
+
+            // NOTE(aki): Leave the image where the record started, like a drive would
+            let Some(header) =
+                header.filter(|h| self.offset + HEADER_LEN + h.len <= self.data.len())
+            else {
+                warn!(offset = record_offset, "Truncated record in AWS image");
+                self.offset = record_offset;
+                self.prev_len = record_prev_len;
+                return Err(DeviceError::Image("truncated record"));
+            };
+
+            let start = self.offset + HEADER_LEN;
+            let end = start + header.len;
+
+            if let Some(buffer) = buffer.as_deref_mut() {
+                let room = buffer.len().saturating_sub(total);
+                let copy = header.len.min(room);
+                buffer[total..total + copy].copy_from_slice(&self.data[start..start + copy]);
+            }
+
+            total += header.len;
+            self.offset = end;
+            self.prev_len = header.len;
+
+            if header.is_record_end() {
+                return Ok((Record::Data, total));
+            }
+        }
+    }
+
+    // Move back over the previous record, returns `None` at BOT
+    fn prev_record(&mut self) -> Result<Option<Record>, DeviceError> {
+        loop {
+            if self.offset == 0 {
+                return Ok(None);
+            }
+
+            let Some(header_offset) = self.offset.checked_sub(HEADER_LEN + self.prev_len) else {
+                return Err(DeviceError::Image("bad previous chunk length"));
+            };
+
+            let header = self.header(header_offset);
+            self.offset = header_offset;
+            self.prev_len = header.prev_len;
+
+            if header.is_tape_mark() {
+                return Ok(Some(Record::TapeMark));
+            }
+
+            if header.is_record_start() {
+                return Ok(Some(Record::Data));
+            }
+        }
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<ReadOutcome, DeviceError> {
+        match self.next_record(Some(buffer))? {
+            (Record::TapeMark, _) => {
+                self.file += 1;
+                self.block += 1;
+                Ok(ReadOutcome::Filemark)
+            }
+            (Record::EndOfMedium, _) => Ok(ReadOutcome::EndOfData),
+            (Record::Data, len) => {
+                self.block += 1;
+
+                if len > buffer.len() {
+                    return Err(DeviceError::BlockTooLarge { len });
+                }
+
+                Ok(ReadOutcome::Block(len))
+            }
+        }
+    }
+
+    // Space over `count` filemarks or blocks, negative counts space backwards
+    fn space(&mut self, count: i32, code: SpaceCode) -> Result<(), DeviceError> {
+        let mut remaining = count.unsigned_abs();
+
+        if count >= 0 {
+            while remaining != 0 {
+                match self.next_record(None)? {
+                    (Record::TapeMark, _) => {
+                        self.file += 1;
+                        self.block += 1;
+                        // Spacing blocks stops once it has gone over a filemark
+                        if code == SpaceCode::Blocks {
+                            return Ok(());
+                        }
+                        remaining -= 1;
+                    }
+                    (Record::EndOfMedium, _) => return Err(DeviceError::EndOfData),
+                    (Record::Data, _) => {
+                        self.block += 1;
+                        if code == SpaceCode::Blocks {
+                            remaining -= 1;
+                        }
+                    }
+                }
+            }
+        } else {
+            while remaining != 0 {
+                match self.prev_record()? {
+                    Some(Record::TapeMark) => {
+                        self.file = self.file.saturating_sub(1);
+                        self.block = self.block.saturating_sub(1);
+                        if code == SpaceCode::Blocks {
+                            return Ok(());
+                        }
+                        remaining -= 1;
+                    }
+                    Some(_) => {
+                        self.block = self.block.saturating_sub(1);
+                        if code == SpaceCode::Blocks {
+                            remaining -= 1;
+                        }
+                    }
+                    // Hitting BOT isn't an error, the drive just stops there
+                    None => {
+                        self.file = 0;
+                        self.block = 0;
+                        return Ok(());
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl TapeDevice for AwsTape {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn read_block(&mut self, buffer: &mut [u8]) -> Result<ReadOutcome, DeviceError> {
+        self.read(buffer)
+    }
+
+    async fn space_filemarks(&mut self, count: i32) -> Result<(), DeviceError> {
+        self.space(count, SpaceCode::Filemarks)
+    }
+
+    async fn space_blocks(&mut self, count: i32) -> Result<(), DeviceError> {
+        self.space(count, SpaceCode::Blocks)
+    }
+
+    async fn rewind(&mut self) -> Result<(), DeviceError> {
+        debug!(device = self.name.as_str(), "Rewinding");
+        self.offset = 0;
+        self.prev_len = 0;
+        self.file = 0;
+        self.block = 0;
+        Ok(())
+    }
+
+    // Nothing to tension on an image, but it leaves us at BOT all the same
+    async fn retension(&mut self) -> Result<(), DeviceError> {
+        self.rewind().await
+    }
+
+    async fn unload(&mut self) -> Result<(), DeviceError> {
+        self.rewind().await
+    }
+
+    // An image is only ever the one reel
+    async fn load(&mut self, _timeout: Duration) -> Result<(), DeviceError> {
+        Err(DeviceError::Image("no other reels to load"))
+    }
+
+    async fn position(&mut self) -> Result<TapePosition, DeviceError> {
+        Ok(TapePosition {
+            file: Some(self.file),
+            block: Some(self.block),
+            beginning_of_partition: self.offset == 0,
+            end_of_partition: self.at_end(),
+        })
+    }
+
+    async fn status(&mut self) -> Result<DeviceStatus, DeviceError> {
+        Ok(DeviceStatus {
+            ready: true,
+            no_medium: false,
+            // Images are only ever read
+            write_protected: true,
+            density: None,
+            block_len: 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `xtask mktape -f aws -k vmlinuz -i initrd.img -c console=ttyS0 -b 512`, with
+    // "kernel image\n" 100 times over as the kernel
+    const MKTAPE: &[u8] = include_bytes!("testdata/boot.aws");
+
+    // Put an image together out of (flags, data) chunks
+    fn tape(chunks: &[(u8, &[u8])]) -> AwsTape {
+        let mut data = Vec::new();
+        let mut prev_len = 0u16;
+
+        for &(flags, chunk) in chunks {
+            data.extend_from_slice(&(chunk.len() as u16).to_le_bytes());
+            data.extend_from_slice(&prev_len.to_le_bytes());
+            data.extend_from_slice(&[flags, 0]);
+            data.extend_from_slice(chunk);
+            prev_len = chunk.len() as u16;
+        }
+
+        AwsTape::from_bytes("test.aws", data)
+    }
+
+    fn read(tape: &mut AwsTape) -> Result<Vec<u8>, DeviceError> {
+        let mut buffer = [0u8; 64];
+        match tape.read(&mut buffer)? {
+            ReadOutcome::Block(len) => Ok(buffer[..len].to_vec()),
+            outcome => panic!("expected a block, got {outcome:?}"),
+        }
+    }
+
+    #[test]
+    fn mktape() {
+        let mut tape = AwsTape::from_bytes("boot.aws", MKTAPE.to_vec());
+        let mut buffer = [0u8; 512];
+
+        assert_eq!(tape.read(&mut buffer), Ok(ReadOutcome::Block(208)));
+        assert!(buffer.starts_with(b"TRIPBOOT"));
+        assert_eq!(tape.read(&mut buffer), Ok(ReadOutcome::Filemark));
+        assert_eq!((tape.file, tape.block), (1, 2));
+
+        assert_eq!(tape.read(&mut buffer), Ok(ReadOutcome::Block(512)));
+        assert!(buffer.starts_with(b"kernel image\n"));
+        assert_eq!(tape.read(&mut buffer), Ok(ReadOutcome::Block(512)));
+        assert_eq!(tape.read(&mut buffer), Ok(ReadOutcome::Block(512)));
+        assert_eq!(tape.read(&mut buffer), Ok(ReadOutcome::Filemark));
+
+        assert_eq!(tape.read(&mut buffer), Ok(ReadOutcome::Block(512)));
+        assert!(buffer.starts_with(b"initrd\n\0"));
+        assert_eq!(tape.read(&mut buffer), Ok(ReadOutcome::Filemark));
+        assert_eq!(tape.read(&mut buffer), Ok(ReadOutcome::Block(512)));
+        assert!(buffer.starts_with(b"console=ttyS0\n\0"));
+
+        // mktape ends the tape with a second tapemark and nothing after it
+        assert_eq!(tape.read(&mut buffer), Ok(ReadOutcome::Filemark));
+        assert_eq!(tape.read(&mut buffer), Ok(ReadOutcome::Filemark));
+        assert_eq!(tape.read(&mut buffer), Ok(ReadOutcome::EndOfData));
+        assert_eq!(tape.read(&mut buffer), Ok(ReadOutcome::EndOfData));
+        assert_eq!(
+            tape.space(1, SpaceCode::Filemarks),
+            Err(DeviceError::EndOfData)
+        );
+        assert_eq!(
+            tape.space(1, SpaceCode::Blocks),
+            Err(DeviceError::EndOfData)
+        );
+
+        // And it can all be walked back to the start
+        tape.space(-10, SpaceCode::Filemarks).unwrap();
+        assert_eq!((tape.offset, tape.file, tape.block), (0, 0, 0));
+        assert_eq!(tape.read(&mut buffer), Ok(ReadOutcome::Block(208)));
+    }
+
+    #[test]
+    fn segments() {
+        let mut tape = tape(&[
+            (FLAG_NEW_RECORD, b"abc"),
+            (0, b"def"),
+            (FLAG_END_RECORD, b"gh"),
+            (FLAG_NEW_RECORD | FLAG_END_RECORD, b"ij"),
+            (FLAG_TAPE_MARK, b""),
+            (FLAG_NEW_RECORD, b"kl"),
+            (FLAG_END_RECORD, b"mno"),
+        ]);
+        let mut buffer = [0u8; 4];
+
+        assert_eq!(read(&mut tape).unwrap(), b"abcdefgh");
+        assert_eq!(read(&mut tape).unwrap(), b"ij");
+        assert_eq!(tape.read(&mut buffer), Ok(ReadOutcome::Filemark));
+
+        // Too large for the buffer, the tape still moves past it
+        assert_eq!(
+            tape.read(&mut buffer),
+            Err(DeviceError::BlockTooLarge { len: 5 })
+        );
+        assert_eq!(buffer, *b"klmn");
+        assert_eq!(tape.read(&mut buffer), Ok(ReadOutcome::EndOfData));
+        assert_eq!(tape.block, 4);
+
+        // Backwards over a record split over chunks gets to the start of it
+        tape.space(-1, SpaceCode::Blocks).unwrap();
+        assert_eq!((tape.file, tape.block), (1, 3));
+        assert_eq!(read(&mut tape).unwrap(), b"klmno");
+
+        tape.space(-1, SpaceCode::Blocks).unwrap();
+        tape.space(-1, SpaceCode::Blocks).unwrap();
+        assert_eq!((tape.file, tape.block), (0, 2));
+        tape.space(-1, SpaceCode::Blocks).unwrap();
+        assert_eq!(read(&mut tape).unwrap(), b"ij");
+
+        tape.space(-2, SpaceCode::Blocks).unwrap();
+        assert_eq!((tape.offset, tape.block), (0, 0));
+        assert_eq!(read(&mut tape).unwrap(), b"abcdefgh");
+
+        // Forwards, spacing over blocks stops once it goes over a filemark
+        tape.space(10, SpaceCode::Blocks).unwrap();
+        assert_eq!((tape.file, tape.block), (1, 3));
+        assert_eq!(read(&mut tape).unwrap(), b"klmno");
+    }
+
+    #[test]
+    fn truncated() {
+        let mut tape = tape(&[
+            (FLAG_NEW_RECORD | FLAG_END_RECORD, b"ok"),
+            (FLAG_NEW_RECORD, b"abc"),
+            (FLAG_END_RECORD, b"def"),
+        ]);
+        tape.data.truncate(tape.data.len() - 1);
+
+        assert_eq!(read(&mut tape).unwrap(), b"ok");
+        let offset = tape.offset;
+
+        // The image is left at the start of the record, like a drive would
+        assert_eq!(read(&mut tape), Err(DeviceError::Image("truncated record")));
+        assert_eq!(tape.offset, offset);
+        assert_eq!(
+            tape.space(1, SpaceCode::Blocks),
+            Err(DeviceError::Image("truncated record"))
+        );
+        tape.space(-1, SpaceCode::Blocks).unwrap();
+        assert_eq!(read(&mut tape).unwrap(), b"ok");
+    }
+}
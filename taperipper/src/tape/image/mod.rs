@@ -0,0 +1,125 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// Tape images, for when there is no reel handy.
+//
+// These get read off of the ESP whole and then pretend to be a drive, filemarks, short
+// records, bad blocks and all, so nothing past the `TapeDevice` can tell the difference.
+
+use core::time::Duration;
+
+use tracing::debug;
+
+use crate::{
+    platform,
+    tape::device::{DeviceError, DeviceStatus, ReadOutcome, TapeDevice, TapePosition},
+};
+
+pub mod aws;
+mod record;
+pub mod simh;
+
+pub(crate) use record::{le32, medium_error};
+
+// Where we look for a tape image on the ESP if there is no drive
+pub const ESP_IMAGE_DIR: &str = "EFI\\taperipper";
+
+// Read the raw image data off of the ESP
+fn load(path: &str) -> Result<Vec<u8>, uefi::Error> {
+    let data = platform::uefi::fs::read(path)?;
+    debug!("Loaded tape image {} ({} bytes)", path, data.len());
+    Ok(data)
+}
+
+// Any of the image formats we know how to read
+pub enum ImageTape {
+    Simh(simh::SimhTape),
+    Aws(aws::AwsTape),
+}
+
+impl ImageTape {
+    // Open an image, picking the format based on the file extension
+    pub fn open(path: &str) -> Result<Self, uefi::Error> {
+        let ext = path
+            .rsplit_once('.')
+            .map(|(_, ext)| ext.to_ascii_lowercase());
+
+        let tape = match ext.as_deref() {
+            Some("aws") => ImageTape::Aws(aws::AwsTape::from_bytes(path, load(path)?)),
+            Some("tap") => ImageTape::Simh(simh::SimhTape::from_bytes(path, load(path)?)),
+            _ => return Err(uefi::Error::new(uefi::Status::UNSUPPORTED, ())),
+        };
+
+        Ok(tape)
+    }
+}
+
+impl TapeDevice for ImageTape {
+    fn name(&self) -> &str {
+        match self {
+            ImageTape::Simh(tape) => tape.name(),
+            ImageTape::Aws(tape) => tape.name(),
+        }
+    }
+
+    async fn read_block(&mut self, buffer: &mut [u8]) -> Result<ReadOutcome, DeviceError> {
+        match self {
+            ImageTape::Simh(tape) => tape.read_block(buffer).await,
+            ImageTape::Aws(tape) => tape.read_block(buffer).await,
+        }
+    }
+
+    async fn space_filemarks(&mut self, count: i32) -> Result<(), DeviceError> {
+        match self {
+            ImageTape::Simh(tape) => tape.space_filemarks(count).await,
+            ImageTape::Aws(tape) => tape.space_filemarks(count).await,
+        }
+    }
+
+    async fn space_blocks(&mut self, count: i32) -> Result<(), DeviceError> {
+        match self {
+            ImageTape::Simh(tape) => tape.space_blocks(count).await,
+            ImageTape::Aws(tape) => tape.space_blocks(count).await,
+        }
+    }
+
+    async fn rewind(&mut self) -> Result<(), DeviceError> {
+        match self {
+            ImageTape::Simh(tape) => tape.rewind().await,
+            ImageTape::Aws(tape) => tape.rewind().await,
+        }
+    }
+
+    async fn retension(&mut self) -> Result<(), DeviceError> {
+        match self {
+            ImageTape::Simh(tape) => tape.retension().await,
+            ImageTape::Aws(tape) => tape.retension().await,
+        }
+    }
+
+    async fn unload(&mut self) -> Result<(), DeviceError> {
+        match self {
+            ImageTape::Simh(tape) => tape.unload().await,
+            ImageTape::Aws(tape) => tape.unload().await,
+        }
+    }
+
+    async fn load(&mut self, timeout: Duration) -> Result<(), DeviceError> {
+        match self {
+            ImageTape::Simh(tape) => tape.load(timeout).await,
+            ImageTape::Aws(tape) => tape.load(timeout).await,
+        }
+    }
+
+    async fn position(&mut self) -> Result<TapePosition, DeviceError> {
+        match self {
+            ImageTape::Simh(tape) => tape.position().await,
+            ImageTape::Aws(tape) => tape.position().await,
+        }
+    }
+
+    async fn status(&mut self) -> Result<DeviceStatus, DeviceError> {
+        match self {
+            ImageTape::Simh(tape) => tape.status().await,
+            ImageTape::Aws(tape) => tape.status().await,
+        }
+    }
+}
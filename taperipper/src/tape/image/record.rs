@@ -0,0 +1,22 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// What the image formats have in common.
+
+use crate::tape::{
+    device::DeviceError,
+    scsi::sense::{SenseData, SenseKey, TapeError},
+};
+
+// What a drive would say if it couldn't read a block
+pub(crate) fn medium_error() -> DeviceError {
+    // 11/00: UNRECOVERED READ ERROR
+    DeviceError::Tape(TapeError::Medium(SenseData::synthetic(
+        SenseKey::MediumError,
+        0x11,
+        0x00,
+    )))
+}
+
+#[inline]
+pub(crate) fn le32(data: &[u8]) -> u32 {
+    u32::from_le_bytes([data[0], data[1], data[2], data[3]])
+}
@@ -0,0 +1,540 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// SIMH `.tap` tape images.
+//
+// Every record is a 32-bit little-endian length, the data padded out to an even length,
+// and then the length again, so the tape can be read in either direction. The top four
+// bits of the length word are the record class, with a couple of special values for tape
+// marks, erase gaps, and the end of the medium.
+//
+// see: https://simh.trailing-edge.com/docs/simh_magtape.pdf
+
+use core::time::Duration;
+
+use tracing::{debug, trace, warn};
+
+use crate::tape::{
+    device::{DeviceError, DeviceStatus, ReadOutcome, TapeDevice, TapePosition},
+    image::{self, le32},
+    scsi::commands::SpaceCode,
+};
+
+const MARKER_LEN: usize = 4;
+
+const TAPE_MARK: u32 = 0x0000_0000;
+const END_OF_MEDIUM: u32 = 0xFFFF_FFFF;
+const ERASE_GAP: u32 = 0xFFFF_FFFE;
+// A half-gap is only two bytes long, the other half is the start of whatever follows
+const HALF_GAP: u32 = 0xFFFE_FFFF;
+
+const LENGTH_MASK: u32 = 0x0FFF_FFFF;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Class {
+    Good,
+    // The record was read with an error when the image was made
+    Bad,
+    // Private and reserved classes, with data we don't understand
+    Private,
+    // Private and reserved markers, no data at all
+    Marker,
+}
+
+impl Class {
+    fn from_marker(marker: u32) -> Self {
+        match marker >> 28 {
+            0x0 => Class::Good,
+            0x8 => Class::Bad,
+            0x7 | 0xF => Class::Marker,
+            _ => Class::Private,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Record {
+    TapeMark,
+    EndOfMedium,
+    Data {
+        class: Class,
+        // Where the data starts in the image
+        start: usize,
+        len: usize,
+    },
+}
+
+pub struct SimhTape {
+    name: String,
+    data: Vec<u8>,
+    offset: usize,
+    file: u32,
+    block: u64,
+}
+
+impl SimhTape {
+    pub fn from_bytes(name: &str, data: Vec<u8>) -> Self {
+        Self {
+            name: name.to_string(),
+            data,
+            offset: 0,
+            file: 0,
+            block: 0,
+        }
+    }
+
+    fn at_end(&self) -> bool {
+        self.offset + MARKER_LEN > self.data.len()
+    }
+
+    // Pull the next record off of the image, moving past it
+    fn next_record(&mut self) -> Result<Record, DeviceError> {
+        loop {
+            // Running off the end of the image is as good as blank tape
+            if self.at_end() {
+                return Ok(Record::EndOfMedium);
+            }
+
+            let marker = le32(&self.data[self.offset..]);
+
+            match marker {
+                TAPE_MARK => {
+                    self.offset += MARKER_LEN;
+                    return Ok(Record::TapeMark);
+                }
+                // NOTE(aki): We don't move past the end of medium, just like a drive at EOD
+                END_OF_MEDIUM => return Ok(Record::EndOfMedium),
+                ERASE_GAP => {
+                    self.offset += MARKER_LEN;
+                    continue;
+                }
+                HALF_GAP => {
+                    self.offset += MARKER_LEN / 2;
+                    continue;
+                }
+                _ => {}
+            }
+
+            let class = Class::from_marker(marker);
+            if class == Class::Marker {
+                trace!(marker = marker, "Skipping private marker");
+                self.offset += MARKER_LEN;
+                continue;
+            }
+
+            let len = (marker & LENGTH_MASK) as usize;
+            let start = self.offset + MARKER_LEN;
+            // Records are padded out to an even length
+            let trailer = start + len + (len & 1);
+
+            if trailer + MARKER_LEN > self.data.len() {
+                warn!(offset = self.offset, len, "Truncated record in SIMH image");
+                return Err(DeviceError::Image("truncated record"));
+            }
+
+            if le32(&self.data[trailer..]) != marker {
+                warn!(
+                    offset = self.offset,
+                    len, "Record length mismatch in SIMH image"
+                );
+                return Err(DeviceError::Image("record length mismatch"));
+            }
+
+            self.offset = trailer + MARKER_LEN;
+            return Ok(Record::Data { class, start, len });
+        }
+    }
+
+    // Move back over the previous record, returns `None` at BOT
+    fn prev_record(&mut self) -> Result<Option<Record>, DeviceError> {
+        loop {
+            if self.offset < MARKER_LEN {
+                self.offset = 0;
+                return Ok(None);
+            }
+
+            let marker = le32(&self.data[self.offset - MARKER_LEN..]);
+
+            match marker {
+                TAPE_MARK => {
+                    self.offset -= MARKER_LEN;
+                    return Ok(Some(Record::TapeMark));
+                }
+                ERASE_GAP => {
+                    self.offset -= MARKER_LEN;
+                    continue;
+                }
+                _ => {}
+            }
+
+            let class = Class::from_marker(marker);
+            if class == Class::Marker {
+                self.offset -= MARKER_LEN;
+                continue;
+            }
+
+            let len = (marker & LENGTH_MASK) as usize;
+            let Some(header) = self.offset.checked_sub(MARKER_LEN * 2 + len + (len & 1)) else {
+                return Err(DeviceError::Image("truncated record"));
+            };
+
+            if le32(&self.data[header..]) != marker {
+                return Err(DeviceError::Image("record length mismatch"));
+            }
+
+            self.offset = header;
+            return Ok(Some(Record::Data {
+                class,
+                start: header + MARKER_LEN,
+                len,
+            }));
+        }
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<ReadOutcome, DeviceError> {
+        loop {
+            match self.next_record()? {
+                Record::TapeMark => {
+                    self.file += 1;
+                    self.block += 1;
+                    return Ok(ReadOutcome::Filemark);
+                }
+                Record::EndOfMedium => return Ok(ReadOutcome::EndOfData),
+                Record::Data {
+                    class: Class::Private | Class::Marker,
+                    ..
+                } => continue,
+                Record::Data {
+                    class: Class::Bad,
+                    len,
+                    ..
+                } => {
+                    self.block += 1;
+                    warn!(
+                        device = self.name.as_str(),
+                        file = self.file,
+                        block = self.block - 1,
+                        len,
+                        "Error flagged record"
+                    );
+                    return Err(image::medium_error());
+                }
+                Record::Data {
+                    class: Class::Good,
+                    start,
+                    len,
+                } => {
+                    self.block += 1;
+
+                    let copy = len.min(buffer.len());
+                    buffer[..copy].copy_from_slice(&self.data[start..start + copy]);
+
+                    if copy < len {
+                        return Err(DeviceError::BlockTooLarge { len });
+                    }
+
+                    return Ok(ReadOutcome::Block(len));
+                }
+            }
+        }
+    }
+
+    // Space over `count` filemarks or blocks, negative counts space backwards
+    fn space(&mut self, count: i32, code: SpaceCode) -> Result<(), DeviceError> {
+        let mut remaining = count.unsigned_abs();
+
+        if count >= 0 {
+            while remaining != 0 {
+                match self.next_record()? {
+                    Record::TapeMark => {
+                        self.file += 1;
+                        self.block += 1;
+                        // Spacing blocks stops once it has gone over a filemark
+                        if code == SpaceCode::Blocks {
+                            return Ok(());
+                        }
+                        remaining -= 1;
+                    }
+                    Record::EndOfMedium => return Err(DeviceError::EndOfData),
+                    Record::Data { class, .. } => {
+                        self.block += 1;
+                        if code == SpaceCode::Blocks && matches!(class, Class::Good | Class::Bad) {
+                            remaining -= 1;
+                        }
+                    }
+                }
+            }
+        } else {
+            while remaining != 0 {
+                match self.prev_record()? {
+                    Some(Record::TapeMark) => {
+                        self.file = self.file.saturating_sub(1);
+                        self.block = self.block.saturating_sub(1);
+                        if code == SpaceCode::Blocks {
+                            return Ok(());
+                        }
+                        remaining -= 1;
+                    }
+                    Some(Record::Data { class, .. }) => {
+                        self.block = self.block.saturating_sub(1);
+                        if code == SpaceCode::Blocks && matches!(class, Class::Good | Class::Bad) {
+                            remaining -= 1;
+                        }
+                    }
+                    Some(Record::EndOfMedium) => {}
+                    // Hitting BOT isn't an error, the drive just stops there
+                    None => {
+                        self.file = 0;
+                        self.block = 0;
+                        return Ok(());
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl TapeDevice for SimhTape {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn read_block(&mut self, buffer: &mut [u8]) -> Result<ReadOutcome, DeviceError> {
+        self.read(buffer)
+    }
+
+    async fn space_filemarks(&mut self, count: i32) -> Result<(), DeviceError> {
+        self.space(count, SpaceCode::Filemarks)
+    }
+
+    async fn space_blocks(&mut self, count: i32) -> Result<(), DeviceError> {
+        self.space(count, SpaceCode::Blocks)
+    }
+
+    async fn rewind(&mut self) -> Result<(), DeviceError> {
+        debug!(device = self.name.as_str(), "Rewinding");
+        self.offset = 0;
+        self.file = 0;
+        self.block = 0;
+        Ok(())
+    }
+
+    // Nothing to tension on an image, but it leaves us at BOT all the same
+    async fn retension(&mut self) -> Result<(), DeviceError> {
+        self.rewind().await
+    }
+
+    async fn unload(&mut self) -> Result<(), DeviceError> {
+        self.rewind().await
+    }
+
+    // An image is only ever the one reel
+    async fn load(&mut self, _timeout: Duration) -> Result<(), DeviceError> {
+        Err(DeviceError::Image("no other reels to load"))
+    }
+
+    async fn position(&mut self) -> Result<TapePosition, DeviceError> {
+        Ok(TapePosition {
+            file: Some(self.file),
+            block: Some(self.block),
+            beginning_of_partition: self.offset == 0,
+            end_of_partition: self.at_end(),
+        })
+    }
+
+    async fn status(&mut self) -> Result<DeviceStatus, DeviceError> {
+        Ok(DeviceStatus {
+            ready: true,
+            no_medium: false,
+            // Images are only ever read
+            write_protected: true,
+            density: None,
+            block_len: 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `xtask mktape -k vmlinuz -i initrd.img -c console=ttyS0 -b 512 --bad-record 2:0`, with
+    // "kernel image\n" 100 times over as the kernel
+    const MKTAPE: &[u8] = include_bytes!("testdata/boot.tap");
+
+    fn record(marker: u32, data: &[u8]) -> Vec<u8> {
+        let mut record = marker.to_le_bytes().to_vec();
+        record.extend_from_slice(data);
+        if data.len() & 1 != 0 {
+            record.push(0);
+        }
+        record.extend_from_slice(&marker.to_le_bytes());
+        record
+    }
+
+    fn tape(records: &[Vec<u8>]) -> SimhTape {
+        SimhTape::from_bytes("test.tap", records.concat())
+    }
+
+    fn read(tape: &mut SimhTape) -> Result<Vec<u8>, DeviceError> {
+        let mut buffer = [0u8; 1024];
+        match tape.read(&mut buffer)? {
+            ReadOutcome::Block(len) => Ok(buffer[..len].to_vec()),
+            outcome => panic!("expected a block, got {outcome:?}"),
+        }
+    }
+
+    #[test]
+    fn mktape() {
+        let mut tape = SimhTape::from_bytes("boot.tap", MKTAPE.to_vec());
+        let mut buffer = [0u8; 512];
+
+        assert_eq!(tape.read(&mut buffer), Ok(ReadOutcome::Block(208)));
+        assert!(buffer.starts_with(b"TRIPBOOT"));
+        assert_eq!(tape.read(&mut buffer), Ok(ReadOutcome::Filemark));
+        assert_eq!((tape.file, tape.block), (1, 2));
+
+        assert_eq!(tape.read(&mut buffer), Ok(ReadOutcome::Block(512)));
+        assert!(buffer.starts_with(b"kernel image\n"));
+        assert_eq!(tape.read(&mut buffer), Ok(ReadOutcome::Block(512)));
+        // The last block is padded out with zeros
+        assert_eq!(tape.read(&mut buffer), Ok(ReadOutcome::Block(512)));
+        assert!(buffer[1300 - 1024..].iter().all(|&byte| byte == 0));
+        assert_eq!(tape.read(&mut buffer), Ok(ReadOutcome::Filemark));
+
+        // The initrd was flagged as bad, but the tape still moves on past it
+        assert_eq!(tape.read(&mut buffer), Err(image::medium_error()));
+        assert_eq!((tape.file, tape.block), (2, 7));
+        assert_eq!(tape.read(&mut buffer), Ok(ReadOutcome::Filemark));
+
+        assert_eq!(tape.read(&mut buffer), Ok(ReadOutcome::Block(512)));
+        assert!(buffer.starts_with(b"console=ttyS0\n\0"));
+
+        // mktape ends the tape with a second tapemark and nothing after it
+        assert_eq!(tape.read(&mut buffer), Ok(ReadOutcome::Filemark));
+        assert_eq!(tape.read(&mut buffer), Ok(ReadOutcome::Filemark));
+        assert_eq!(tape.read(&mut buffer), Ok(ReadOutcome::EndOfData));
+        assert_eq!(tape.read(&mut buffer), Ok(ReadOutcome::EndOfData));
+        assert!(tape.at_end());
+    }
+
+    #[test]
+    fn end_of_medium() {
+        let mut tape = tape(&[
+            record(4, b"data"),
+            TAPE_MARK.to_le_bytes().to_vec(),
+            TAPE_MARK.to_le_bytes().to_vec(),
+            END_OF_MEDIUM.to_le_bytes().to_vec(),
+            record(4, b"junk"),
+        ]);
+        let mut buffer = [0u8; 16];
+
+        assert_eq!(tape.read(&mut buffer), Ok(ReadOutcome::Block(4)));
+        assert_eq!(tape.read(&mut buffer), Ok(ReadOutcome::Filemark));
+        assert_eq!(tape.read(&mut buffer), Ok(ReadOutcome::Filemark));
+        // Nothing past the end of medium marker gets read, no matter how many times we ask
+        assert_eq!(tape.read(&mut buffer), Ok(ReadOutcome::EndOfData));
+        assert_eq!(tape.read(&mut buffer), Ok(ReadOutcome::EndOfData));
+        assert_eq!(
+            tape.space(1, SpaceCode::Filemarks),
+            Err(DeviceError::EndOfData)
+        );
+        assert_eq!(
+            tape.space(1, SpaceCode::Blocks),
+            Err(DeviceError::EndOfData)
+        );
+    }
+
+    #[test]
+    fn records() {
+        let mut tape = tape(&[
+            record(3, b"odd"),
+            ERASE_GAP.to_le_bytes().to_vec(),
+            record(2, b"ev"),
+            // A private marker, and a record in a private class
+            0x7000_0001u32.to_le_bytes().to_vec(),
+            record(0x1000_0005, b"abcde"),
+            record(0x8000_0001, b"x"),
+            record(5, b"fives"),
+        ]);
+
+        // Odd length records are padded out to an even length
+        assert_eq!(read(&mut tape).unwrap(), b"odd");
+        assert_eq!(read(&mut tape).unwrap(), b"ev");
+        assert_eq!(read(&mut tape), Err(image::medium_error()));
+        assert_eq!(read(&mut tape).unwrap(), b"fives");
+        assert_eq!(tape.block, 4);
+
+        // And can be read backwards
+        tape.space(-4, SpaceCode::Blocks).unwrap();
+        assert_eq!(tape.offset, 0);
+        assert_eq!(tape.block, 0);
+        assert_eq!(read(&mut tape).unwrap(), b"odd");
+
+        // Too large for the buffer, the tape still moves past it
+        let mut buffer = [0u8; 1];
+        assert_eq!(
+            tape.read(&mut buffer),
+            Err(DeviceError::BlockTooLarge { len: 2 })
+        );
+        assert_eq!(buffer, *b"e");
+        assert_eq!(read(&mut tape), Err(image::medium_error()));
+    }
+
+    #[test]
+    fn damaged() {
+        let mut truncated = tape(&[record(4, b"data")]);
+        truncated.data.truncate(8);
+        assert_eq!(
+            read(&mut truncated),
+            Err(DeviceError::Image("truncated record"))
+        );
+
+        let mut mismatch = tape(&[record(4, b"data")]);
+        mismatch.data[8] = 5;
+        assert_eq!(
+            read(&mut mismatch),
+            Err(DeviceError::Image("record length mismatch"))
+        );
+    }
+
+    #[test]
+    fn space() {
+        let mut tape = SimhTape::from_bytes("boot.tap", MKTAPE.to_vec());
+        let mut buffer = [0u8; 512];
+
+        tape.space(1, SpaceCode::Filemarks).unwrap();
+        assert_eq!((tape.file, tape.block), (1, 2));
+
+        // Back over the block we just read
+        assert_eq!(tape.read(&mut buffer), Ok(ReadOutcome::Block(512)));
+        assert_eq!(tape.read(&mut buffer), Ok(ReadOutcome::Block(512)));
+        let second = buffer;
+        tape.space(-1, SpaceCode::Blocks).unwrap();
+        assert_eq!((tape.file, tape.block), (1, 3));
+        assert_eq!(tape.read(&mut buffer), Ok(ReadOutcome::Block(512)));
+        assert_eq!(buffer, second);
+
+        // Spacing over blocks stops once it goes over a filemark, either way
+        tape.space(10, SpaceCode::Blocks).unwrap();
+        assert_eq!((tape.file, tape.block), (2, 6));
+        tape.space(-1, SpaceCode::Blocks).unwrap();
+        assert_eq!((tape.file, tape.block), (1, 5));
+        assert_eq!(tape.read(&mut buffer), Ok(ReadOutcome::Filemark));
+
+        // Backwards over a filemark leaves us on the BOT side of it
+        tape.space(1, SpaceCode::Filemarks).unwrap();
+        tape.space(-1, SpaceCode::Filemarks).unwrap();
+        assert_eq!((tape.file, tape.block), (2, 7));
+        assert_eq!(tape.read(&mut buffer), Ok(ReadOutcome::Filemark));
+
+        // And running into BOT just stops there
+        tape.space(-10, SpaceCode::Filemarks).unwrap();
+        assert_eq!((tape.offset, tape.file, tape.block), (0, 0, 0));
+
+        assert_eq!(
+            tape.space(10, SpaceCode::Filemarks),
+            Err(DeviceError::EndOfData)
+        );
+    }
+}
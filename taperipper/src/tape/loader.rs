@@ -0,0 +1,750 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// Finding the things we need to boot on a reel.
+
+use core::fmt;
+
+use tracing::{debug, info, warn};
+
+use crate::{
+    boot::initramfs::{Layer, Part},
+    tape::{
+        decompress::{DecompressError, Unpacker},
+        device::{DeviceError, ReadOutcome, TapeDevice},
+        digest::{self, Digest, Expected, Hasher, SHA256_LEN},
+        format::{
+            ansi::{LabelError, LabelStandard},
+            deblock::{DeblockError, Deblocker, Layout},
+            ebcdic::CodePage,
+            labelled::LabelledTape,
+            manifest::{Manifest, ManifestError, PayloadKind},
+            tar::{self, EntryKind, TarError, TarReader},
+        },
+        stream::{self, FileStream},
+        volume::ReelChanger,
+    },
+};
+
+// File names we look for in a tar archive, matched against the last path component, or
+// file identifiers on a labelled tape
+pub const KERNEL_NAMES: &[&str] = &["vmlinuz", "bzImage"];
+pub const INITRD_NAMES: &[&str] = &["initrd.img", "initramfs.img"];
+pub const MICROCODE_NAMES: &[&str] = &["microcode.cpio", "ucode.cpio"];
+pub const OVERLAY_NAMES: &[&str] = &["overlay.cpio"];
+pub const CMDLINE_NAMES: &[&str] = &["cmdline", "cmdline.txt"];
+pub const SIGNATURE_NAMES: &[&str] = &["boot.sig"];
+
+// Everything we pulled off of the tape to boot with
+#[derive(Default)]
+pub struct Payloads {
+    pub kernel: Vec<u8>,
+    // Every part of the initrd, in the order they were on the tape
+    pub initrds: Vec<Part>,
+    pub cmdline: Option<String>,
+    pub config: Option<Vec<u8>>,
+    // The signature record over the rest, if the tape was signed
+    pub signature: Option<Vec<u8>>,
+    // The manifest, if it was a taperipper boot tape
+    pub manifest: Option<Manifest>,
+    // Every payload we loaded, worked out as it came off of the tape
+    pub digests: Vec<PayloadDigest>,
+    // The digest of the whole initrd as it was on the tape, if it came in parts
+    pub initrd_digest: Option<Digest>,
+    // The serial of the volume we started loading from, if the tape was labelled
+    pub volume: Option<String>,
+    initrd_hasher: Hasher,
+}
+
+// Where on the tape a payload started
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Location {
+    // Which reel of the set, starting from one
+    pub reel: u32,
+    // The tape file it was in, or its file sequence number on a labelled tape, if it has one
+    pub file: Option<u32>,
+}
+
+impl Default for Location {
+    fn default() -> Self {
+        Self {
+            reel: 1,
+            file: None,
+        }
+    }
+}
+
+// What a payload came out as, and what anything on the tape says it should have been
+//
+// Digests are always of the payload as it was on the tape, before it was decompressed, as
+// that's what the mastering tools see.
+#[derive(Clone, Debug)]
+pub struct PayloadDigest {
+    pub kind: PayloadKind,
+    // The file name or identifier it had on the tape
+    pub name: String,
+    pub location: Location,
+    pub digest: Digest,
+    pub expected: Vec<Expected>,
+}
+
+impl Payloads {
+    fn add_digest(
+        &mut self,
+        kind: PayloadKind,
+        name: &str,
+        location: Location,
+        digest: Digest,
+    ) -> &mut PayloadDigest {
+        self.digests.push(PayloadDigest {
+            kind,
+            name: name.to_string(),
+            location,
+            digest,
+            expected: Vec::new(),
+        });
+        self.digests.last_mut().unwrap()
+    }
+
+    // The digest of everything of `kind` that we loaded, taken as one
+    pub fn digest_of(&self, kind: PayloadKind) -> Option<Digest> {
+        let mut parts = self.digests.iter().filter(|payload| payload.kind == kind);
+
+        match (parts.next(), parts.next()) {
+            (None, _) => None,
+            (Some(payload), None) => Some(payload.digest),
+            // NOTE(aki): Only initrds come in parts
+            _ => self.initrd_digest,
+        }
+    }
+
+    // Keep track of all of the initrd as it comes off the tape, along with each part of it
+    //
+    // NOTE(aki): The initrd is signed and pinned as a whole, as if the parts were one file
+    fn hash_initrd(&mut self, data: &[u8]) {
+        self.initrd_hasher.update(data);
+    }
+
+    fn add_initrd(&mut self, layer: Layer, name: &str, data: Vec<u8>) {
+        self.initrds.push(Part::new(layer, name, data));
+        if self.initrds.len() > 1 {
+            self.initrd_digest = Some(self.initrd_hasher.clone().finish());
+        }
+    }
+
+    // Match the digests in a `SHA256SUMS` file up with the payloads by name
+    fn apply_sums(&mut self, sums: &[(String, [u8; SHA256_LEN])]) {
+        for payload in &mut self.digests {
+            // NOTE(aki): Labelled tapes shout their file identifiers
+            if let Some((_, sha256)) = sums
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(&payload.name))
+            {
+                payload
+                    .expected
+                    .push(Expected::sha256(*sha256, "SHA256SUMS"));
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoadError {
+    Device(DeviceError),
+    Tar(TarError),
+    Label(LabelError),
+    Deblock(DeblockError),
+    Manifest(ManifestError),
+    Decompress(DecompressError),
+    // We read everything and never found a kernel
+    NoKernel,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Device(err) => write!(f, "{err}"),
+            LoadError::Tar(err) => write!(f, "{err}"),
+            LoadError::Label(err) => write!(f, "{err}"),
+            LoadError::Deblock(err) => write!(f, "{err}"),
+            LoadError::Manifest(err) => write!(f, "{err}"),
+            LoadError::Decompress(err) => write!(f, "{err}"),
+            LoadError::NoKernel => write!(f, "no kernel found on tape"),
+        }
+    }
+}
+
+impl From<DeviceError> for LoadError {
+    fn from(err: DeviceError) -> Self {
+        LoadError::Device(err)
+    }
+}
+
+impl From<TarError> for LoadError {
+    fn from(err: TarError) -> Self {
+        LoadError::Tar(err)
+    }
+}
+
+impl From<LabelError> for LoadError {
+    fn from(err: LabelError) -> Self {
+        LoadError::Label(err)
+    }
+}
+
+impl From<DeblockError> for LoadError {
+    fn from(err: DeblockError) -> Self {
+        LoadError::Deblock(err)
+    }
+}
+
+impl From<ManifestError> for LoadError {
+    fn from(err: ManifestError) -> Self {
+        LoadError::Manifest(err)
+    }
+}
+
+impl From<DecompressError> for LoadError {
+    fn from(err: DecompressError) -> Self {
+        LoadError::Decompress(err)
+    }
+}
+
+// Turn the contents of a command-line file into something we can hand the kernel
+pub(crate) fn parse_cmdline(data: &[u8]) -> String {
+    String::from_utf8_lossy(data)
+        .trim_end_matches('\0')
+        .trim()
+        .to_string()
+}
+
+// Takes a payload a chunk at a time as it comes off of the tape, working out its digest and
+// decompressing it on the way into memory if it's the kernel or initrd
+struct Intake {
+    kind: PayloadKind,
+    hasher: Hasher,
+    unpacker: Unpacker,
+}
+
+impl Intake {
+    // `size` is how big it is on the tape, if we know
+    fn new(kind: PayloadKind, size: u64) -> Self {
+        let unpacker = match kind {
+            PayloadKind::Kernel | PayloadKind::Initrd => Unpacker::new(Vec::new(), size as usize),
+            _ => Unpacker::raw(Vec::new(), size as usize),
+        };
+
+        Self {
+            kind,
+            hasher: Hasher::new(),
+            unpacker,
+        }
+    }
+
+    fn push(&mut self, data: &[u8]) -> Result<(), LoadError> {
+        self.hasher.update(data);
+        Ok(self.unpacker.push(data)?)
+    }
+
+    fn finish(self) -> Result<(Vec<u8>, Digest), LoadError> {
+        let format = self.unpacker.format();
+        let compressed = self.unpacker.compressed_len();
+        let data = self.unpacker.finish()?;
+
+        if let Some(format) = format {
+            info!(
+                format = %format,
+                compressed,
+                decompressed = data.len(),
+                "Decompressed {}",
+                self.kind
+            );
+        }
+
+        Ok((data, self.hasher.finish()))
+    }
+}
+
+// Which layer of the initramfs a file is, going by its name, if it's part of the initrd at all
+fn initrd_layer(matches: impl Fn(&[&str]) -> bool) -> Option<Layer> {
+    if matches(MICROCODE_NAMES) {
+        Some(Layer::Microcode)
+    } else if matches(INITRD_NAMES) {
+        Some(Layer::Base)
+    } else if matches(OVERLAY_NAMES) {
+        Some(Layer::Overlay)
+    } else {
+        None
+    }
+}
+
+// Read the body of the current tar entry, working out its digest as it goes, and that of the
+// initrd as a whole if it's part of one
+async fn read_entry<D: TapeDevice>(
+    archive: &mut TarReader<'_, D>,
+    kind: PayloadKind,
+    size: u64,
+    payloads: &mut Payloads,
+) -> Result<(Vec<u8>, Digest), LoadError> {
+    let mut intake = Intake::new(kind, size);
+    let mut chunk = vec![0u8; stream::DEFAULT_RECORD_LEN];
+
+    loop {
+        match archive.read(&mut chunk).await? {
+            0 => break,
+            len => {
+                intake.push(&chunk[..len])?;
+                if kind == PayloadKind::Initrd {
+                    payloads.hash_initrd(&chunk[..len]);
+                }
+            }
+        }
+    }
+
+    intake.finish()
+}
+
+// Read a tar archive in the current tape file, picking out the kernel, initrd, and
+// command-line, along with a `SHA256SUMS` and signature to check them against if there are any
+//
+// A multi-volume archive is read straight on from one reel to the next.
+pub async fn load_tar<D: TapeDevice>(
+    device: &mut D,
+    reels: &mut ReelChanger,
+) -> Result<Payloads, LoadError> {
+    let name = device.name().to_string();
+    // NOTE(aki): The whole archive is the one tape file
+    let position = device.position().await.ok();
+    let file = position.and_then(|pos| pos.file);
+    debug!(
+        device = name.as_str(),
+        file = ?file,
+        block = ?position.and_then(|pos| pos.block),
+        "Reading tar archive"
+    );
+    let mut archive = TarReader::new(FileStream::new(device).spanning(reels));
+    let mut payloads = Payloads::default();
+    let mut sums = Vec::new();
+    let mut found_kernel = false;
+
+    while let Some(entry) = archive.next_entry().await? {
+        if entry.kind != EntryKind::File {
+            continue;
+        }
+
+        let file_name = entry.file_name();
+        let location = Location {
+            reel: archive.stream().reel(),
+            file,
+        };
+
+        if KERNEL_NAMES.contains(&file_name) {
+            info!(
+                device = name.as_str(),
+                path = entry.path.as_str(),
+                size = entry.size,
+                "Found kernel"
+            );
+            let (data, digest) =
+                read_entry(&mut archive, PayloadKind::Kernel, entry.size, &mut payloads).await?;
+            payloads.add_digest(PayloadKind::Kernel, file_name, location, digest);
+            payloads.kernel = data;
+            found_kernel = true;
+        } else if let Some(layer) = initrd_layer(|names| names.contains(&file_name)) {
+            info!(
+                device = name.as_str(),
+                path = entry.path.as_str(),
+                size = entry.size,
+                layer = %layer,
+                "Found initrd"
+            );
+            let (data, digest) =
+                read_entry(&mut archive, PayloadKind::Initrd, entry.size, &mut payloads).await?;
+            payloads.add_digest(PayloadKind::Initrd, file_name, location, digest);
+            payloads.add_initrd(layer, file_name, data);
+        } else if CMDLINE_NAMES.contains(&file_name) {
+            info!(
+                device = name.as_str(),
+                path = entry.path.as_str(),
+                size = entry.size,
+                "Found command-line"
+            );
+            let (data, digest) = read_entry(
+                &mut archive,
+                PayloadKind::Cmdline,
+                entry.size,
+                &mut payloads,
+            )
+            .await?;
+            payloads.add_digest(PayloadKind::Cmdline, file_name, location, digest);
+            payloads.cmdline = Some(parse_cmdline(&data));
+        } else if digest::SUMS_NAMES.contains(&file_name) {
+            debug!(path = entry.path.as_str(), "Found digests");
+            sums.extend(digest::parse_sums(&archive.read_to_vec().await?));
+        } else if SIGNATURE_NAMES.contains(&file_name) {
+            debug!(path = entry.path.as_str(), "Found signature");
+            payloads.signature = Some(archive.read_to_vec().await?);
+        } else {
+            debug!(path = entry.path.as_str(), size = entry.size, "Skipping");
+        }
+    }
+
+    let stream = archive.stream();
+    if let Some(record_len) = stream.first_record_len() {
+        if record_len % tar::BLOCK_LEN != 0 {
+            warn!(
+                record_len,
+                "Tape records are not a whole number of tar blocks"
+            );
+        }
+        debug!(
+            device = name.as_str(),
+            records = stream.records(),
+            bytes = stream.bytes(),
+            blocking_factor = record_len / tar::BLOCK_LEN,
+            "Read tar archive"
+        );
+    }
+
+    // Leave the tape at the start of the next file
+    archive.finish().await?;
+
+    if !found_kernel {
+        return Err(LoadError::NoKernel);
+    }
+
+    payloads.apply_sums(&sums);
+    Ok(payloads)
+}
+
+// Walk the files on a labelled tape, picking out the kernel, initrd, and command-line by
+// their file identifiers, along with a `SHA256SUMS` and signature to check them against if
+// there are any
+//
+// Files that end in an EOV trailer are picked up again from the next reel of the set. Text
+// files on IBM labelled tapes are read as `code_page`.
+pub async fn load_labelled<D: TapeDevice>(
+    tape: &mut LabelledTape<'_, D>,
+    reels: &mut ReelChanger,
+    code_page: CodePage,
+) -> Result<Payloads, LoadError> {
+    let mut payloads = Payloads::default();
+    let mut sums = Vec::new();
+    let mut found_kernel = false;
+    let standard = tape.standard();
+    payloads.volume = Some(tape.volume().serial.clone());
+
+    while let Some(labels) = tape.next_file().await? {
+        let matches = |names: &[&str]| names.iter().any(|name| labels.matches(name));
+
+        let layer = initrd_layer(matches);
+        let (is_kernel, is_initrd, is_cmdline, is_sums, is_signature) = (
+            matches(KERNEL_NAMES),
+            layer.is_some(),
+            matches(CMDLINE_NAMES),
+            matches(digest::SUMS_NAMES),
+            matches(SIGNATURE_NAMES),
+        );
+        let is_text = is_cmdline || is_sums;
+
+        if !(is_kernel || is_initrd || is_text || is_signature) {
+            debug!(file = labels.file_identifier(), "Skipping");
+            continue;
+        }
+
+        let file = labels.file_identifier().to_string();
+        let location = Location {
+            reel: reels.reel(),
+            file: Some(labels.sequence()),
+        };
+        let deblocker = Deblocker::for_file(labels, standard);
+
+        let deblocker = if is_text {
+            Some(deblocker.unwrap_or(Deblocker::new(Layout::Undefined)?))
+        } else {
+            // Binary files only need the descriptors stripping out, fixed records are
+            // already exactly the data
+            match deblocker {
+                Ok(deblocker) => Some(deblocker).filter(|deblocker| {
+                    matches!(deblocker.layout(), Layout::Variable | Layout::VariableAscii)
+                }),
+                Err(err) => {
+                    warn!(file = file.as_str(), "Reading file as raw blocks: {}", err);
+                    None
+                }
+            }
+        };
+
+        let kind = if is_kernel {
+            PayloadKind::Kernel
+        } else if is_initrd {
+            PayloadKind::Initrd
+        } else if is_signature {
+            PayloadKind::Signature
+        } else {
+            PayloadKind::Cmdline
+        };
+
+        let mut intake = Intake::new(kind, 0);
+        let mut lines = Vec::new();
+        let mut blocks = 0;
+
+        loop {
+            let section = read_records(tape.device(), deblocker, |record| {
+                if is_text {
+                    // Text files are a line per record, and on IBM tapes they're in EBCDIC
+                    let line = match standard {
+                        LabelStandard::Ansi => String::from_utf8_lossy(record).into_owned(),
+                        LabelStandard::Ibm => code_page.decode(record),
+                    };
+                    lines.push(line.trim_end().to_string());
+                    if is_sums {
+                        return Ok(());
+                    }
+                }
+
+                if is_initrd {
+                    payloads.hash_initrd(record);
+                }
+                intake.push(record)
+            })
+            .await?;
+            blocks += section;
+
+            // Make sure we got the whole thing before we use any of it, the block count in
+            // the trailer only covers what is on this volume
+            tape.close_file(section).await?;
+
+            if !tape.continues() {
+                break;
+            }
+            tape.next_volume(reels).await?;
+        }
+
+        if is_sums {
+            debug!(file = file.as_str(), blocks, "Found digests");
+            sums.extend(digest::parse_sums(lines.join("\n").as_bytes()));
+            continue;
+        }
+
+        let (mut data, digest) = intake.finish()?;
+
+        if is_signature {
+            debug!(file = file.as_str(), blocks, "Found signature");
+            payloads.signature = Some(data);
+            continue;
+        }
+
+        // NOTE(aki): The digest is of the records as they were on the tape, padding, EBCDIC
+        // and all, it's only what the kernel gets that is put back together out of the lines
+        if is_cmdline {
+            data = lines.join(" ").into_bytes();
+        }
+
+        info!(
+            volume = tape.volume().serial.as_str(),
+            file = file.as_str(),
+            size = data.len(),
+            blocks,
+            "Found {}",
+            kind
+        );
+        payloads.add_digest(kind, &file, location, digest);
+
+        match kind {
+            PayloadKind::Kernel => {
+                payloads.kernel = data;
+                found_kernel = true;
+            }
+            PayloadKind::Initrd => payloads.add_initrd(layer.unwrap(), &file, data),
+            _ => payloads.cmdline = Some(parse_cmdline(&data)),
+        }
+    }
+
+    if !found_kernel {
+        return Err(LoadError::NoKernel);
+    }
+
+    payloads.apply_sums(&sums);
+    Ok(payloads)
+}
+
+// Read a tape file up to the filemark a block at a time, handing each record in it to
+// `record`, returns how many blocks were read
+async fn read_records<D: TapeDevice>(
+    device: &mut D,
+    deblocker: Option<Deblocker>,
+    mut record: impl FnMut(&[u8]) -> Result<(), LoadError>,
+) -> Result<u64, LoadError> {
+    let mut block = vec![0u8; stream::DEFAULT_RECORD_LEN];
+    let mut blocks = 0;
+
+    loop {
+        let len = match device.read_block(&mut block).await? {
+            ReadOutcome::Block(len) => len,
+            ReadOutcome::Filemark | ReadOutcome::EndOfData => return Ok(blocks),
+        };
+        blocks += 1;
+
+        match deblocker {
+            Some(deblocker) => {
+                for data in deblocker.records(&block[..len]) {
+                    record(data?)?;
+                }
+            }
+            None => record(&block[..len])?,
+        }
+    }
+}
+
+// Load the payloads from a taperipper boot tape, with the tape just past the manifest
+//
+// A payload that runs off the end of the reel carries on from the start of the next one, the
+// tape file numbers in the manifest count on from there as if it were all the one reel.
+pub async fn load_manifest<D: TapeDevice>(
+    device: &mut D,
+    manifest: Manifest,
+    reels: &mut ReelChanger,
+) -> Result<Payloads, LoadError> {
+    info!(
+        device = device.name(),
+        version = manifest.version,
+        entries = manifest.entries.len(),
+        "Found boot tape manifest"
+    );
+
+    // Get past the filemark at the end of the manifest
+    device.space_filemarks(1).await?;
+    let mut file = 1;
+    let mut payloads = Payloads::default();
+
+    for entry in &manifest.entries {
+        if entry.file > file {
+            device.space_filemarks((entry.file - file) as i32).await?;
+        }
+
+        let record_len = match entry.block_len {
+            0 => stream::DEFAULT_RECORD_LEN,
+            len => len as usize,
+        };
+
+        let location = Location {
+            reel: reels.reel(),
+            file: Some(entry.file),
+        };
+        let mut intake = Intake::new(entry.kind, entry.size);
+        let mut stream = FileStream::with_record_len(device, record_len).spanning(reels);
+        let mut chunk = vec![0u8; record_len];
+        let mut remaining = entry.size;
+
+        // NOTE(aki): Only ask for as much as the manifest says is there, so a payload that
+        // happens to end past the early warning doesn't send us looking for another reel
+        while remaining > 0 {
+            let want = remaining.min(chunk.len() as u64) as usize;
+            match stream.read(&mut chunk[..want]).await? {
+                0 => break,
+                len => {
+                    intake.push(&chunk[..len])?;
+                    if entry.kind == PayloadKind::Initrd {
+                        payloads.hash_initrd(&chunk[..len]);
+                    }
+                    remaining -= len as u64;
+                }
+            }
+        }
+        stream.finish().await?;
+
+        if let Some(len) = stream.first_record_len()
+            && entry.block_len != 0
+            && len != entry.block_len as usize
+        {
+            warn!(
+                kind = %entry.kind,
+                expected = entry.block_len,
+                actual = len,
+                "Payload block size doesn't match the manifest"
+            );
+        }
+
+        // We read up to the filemark, so we're at the start of the next file
+        file = entry.file + 1;
+
+        // Fixed blocks get padded out at the end, anything more than that is wrong
+        let actual = stream.bytes();
+        if remaining > 0 || actual - entry.size >= record_len as u64 {
+            return Err(ManifestError::SizeMismatch {
+                kind: entry.kind,
+                expected: entry.size,
+                actual,
+            }
+            .into());
+        }
+
+        info!(
+            file = entry.file,
+            size = entry.size,
+            sha256 = entry.digest_hex().as_str(),
+            "Loaded {}",
+            entry.kind
+        );
+        let (data, digest) = intake.finish()?;
+        payloads
+            .add_digest(entry.kind, &entry.kind.to_string(), location, digest)
+            .expected
+            .push(Expected::sha256(entry.sha256, "manifest"));
+
+        match entry.kind {
+            PayloadKind::Kernel => payloads.kernel = data,
+            PayloadKind::Initrd => payloads.add_initrd(
+                entry.layer.unwrap_or(Layer::Base),
+                &format!("tape file {}", entry.file),
+                data,
+            ),
+            PayloadKind::Cmdline => payloads.cmdline = Some(parse_cmdline(&data)),
+            PayloadKind::Config => payloads.config = Some(data),
+            PayloadKind::Signature => payloads.signature = Some(data),
+        }
+    }
+
+    payloads.manifest = Some(manifest);
+    Ok(payloads)
+}
+
+// Read the first record on the tape and see if it's a boot tape manifest
+async fn read_manifest<D: TapeDevice>(device: &mut D) -> Result<Option<Manifest>, LoadError> {
+    device.rewind().await?;
+
+    let mut record = vec![0u8; stream::DEFAULT_RECORD_LEN];
+    let len = match device.read_block(&mut record).await {
+        Ok(ReadOutcome::Block(len)) => len,
+        // Whatever it is, it's not a manifest
+        Ok(_) | Err(DeviceError::BlockTooLarge { .. }) => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+
+    if !Manifest::is_manifest(&record[..len]) {
+        return Ok(None);
+    }
+
+    Ok(Some(Manifest::parse(&record[..len])?))
+}
+
+// Figure out how the tape is laid out and load from it, asking for more reels from `reels`
+// if it spans more than one
+pub async fn load<D: TapeDevice>(
+    device: &mut D,
+    reels: &mut ReelChanger,
+    code_page: CodePage,
+) -> Result<Payloads, LoadError> {
+    if let Some(manifest) = read_manifest(device).await? {
+        return load_manifest(device, manifest, reels).await;
+    }
+
+    match LabelledTape::open(device).await {
+        Ok(mut tape) => return load_labelled(&mut tape, reels, code_page).await,
+        Err(LabelError::NotLabelled) => {}
+        Err(err) => return Err(err.into()),
+    }
+
+    debug!(device = device.name(), "Tape is not labelled, trying tar");
+    device.rewind().await?;
+    load_tar(device, reels).await
+}
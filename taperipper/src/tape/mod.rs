@@ -0,0 +1,17 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// This module is where everything to do with actually getting bits off of tape lives.
+
+pub mod decompress;
+pub mod device;
+pub mod digest;
+pub mod format;
+pub mod image;
+pub mod loader;
+pub mod readahead;
+pub mod recovery;
+pub mod scsi;
+pub mod stream;
+#[cfg(test)]
+mod testing;
+pub mod transport;
+pub mod volume;
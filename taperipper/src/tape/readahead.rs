@@ -0,0 +1,453 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// Keeping the drive streaming.
+//
+// A streaming drive that runs out of places to put data has to stop, back up, and get up to
+// speed again before it can carry on, and doing that over and over ("shoe-shining") is slow
+// and hard on the tape. `ReadAhead` sits in front of another `TapeDevice` and, as soon as
+// something reads a block, hands the device off to a task that reads the rest of the tape file
+// into a ring of buffers while whatever is consuming the blocks gets on with it.
+//
+// The ring never goes past the end of a tape file, and anything that moves the tape puts it
+// back to where the consumer thinks it is first.
+//
+// NOTE(aki): There is only the one executor, so the drive and the consumer only overlap when
+// the device gives it up while a read is in flight, which is only SCSI pass-thru on controllers
+// that can do non-blocking I/O (see `TapeDevice`). Anything else just takes turns with the
+// consumer, `ReadAheadStats::overlapped` says which one it was.
+
+use core::{fmt, time::Duration};
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+
+use maitake::{future, task::JoinHandle};
+use tracing::{debug, trace};
+
+use crate::{
+    runtime::{
+        self,
+        channel::{self, Receiver, Sender},
+    },
+    tape::{
+        device::{DeviceError, DeviceStatus, ReadOutcome, TapeDevice, TapePosition},
+        stream,
+    },
+};
+
+pub const DEFAULT_BUFFERS: usize = 8;
+pub const DEFAULT_BUFFER_LEN: usize = stream::DEFAULT_RECORD_LEN;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReadAheadConfig {
+    // How many blocks the drive can get ahead of us by, zero turns read-ahead off
+    pub buffers: usize,
+    // The largest block we can read ahead, the buffers are never any smaller than the one
+    // they are being read for though
+    pub buffer_len: usize,
+}
+
+impl Default for ReadAheadConfig {
+    fn default() -> Self {
+        Self {
+            buffers: DEFAULT_BUFFERS,
+            buffer_len: DEFAULT_BUFFER_LEN,
+        }
+    }
+}
+
+impl ReadAheadConfig {
+    // Parse a config from a comma separated list of `buffers=<n>` and `size=<bytes>`, anything
+    // not given is left at the default
+    pub fn parse(config: &str) -> Option<Self> {
+        let mut parsed = Self::default();
+
+        for option in config
+            .split(',')
+            .map(str::trim)
+            .filter(|opt| !opt.is_empty())
+        {
+            match option.split_once('=')? {
+                ("buffers", count) => parsed.buffers = count.trim().parse().ok()?,
+                ("size", len) => {
+                    parsed.buffer_len = len.trim().parse().ok().filter(|&len| len != 0)?
+                }
+                _ => return None,
+            }
+        }
+
+        Some(parsed)
+    }
+}
+
+impl fmt::Display for ReadAheadConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "buffers={},size={}", self.buffers, self.buffer_len)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ReadAheadStats {
+    // Blocks the drive read for us, and how many bytes were in them
+    pub blocks: u64,
+    pub bytes: u64,
+    // Times the drive had to wait for a free buffer, each one is most likely a reposition
+    pub drive_stalls: u64,
+    // Times a read had to wait for the drive
+    pub read_stalls: u64,
+    // The most blocks that were ever waiting to be read at once
+    pub high_water: usize,
+    // Blocks the consumer got while the drive was busy reading the next one, if this stays at
+    // zero then the two are just taking turns
+    pub overlapped: u64,
+}
+
+impl ReadAheadStats {
+    fn add(&mut self, other: &Self) {
+        self.blocks += other.blocks;
+        self.bytes += other.bytes;
+        self.drive_stalls += other.drive_stalls;
+        self.read_stalls += other.read_stalls;
+        self.high_water = self.high_water.max(other.high_water);
+        self.overlapped += other.overlapped;
+    }
+}
+
+// A block the drive has read, on its way to the consumer
+struct Filled {
+    buffer: Vec<u8>,
+    result: Result<ReadOutcome, DeviceError>,
+}
+
+// What the read-ahead task got up to
+struct TaskStats {
+    blocks: u64,
+    bytes: u64,
+    drive_stalls: u64,
+}
+
+// A running read-ahead task, and the two halves of the ring it reads into
+struct Streaming<D> {
+    task: JoinHandle<(D, TaskStats)>,
+    filled: Receiver<Filled>,
+    free: Sender<Vec<u8>>,
+    // How long each buffer in the ring is
+    buffer_len: usize,
+    // Set by the task while it's waiting on the drive
+    reading: Arc<AtomicBool>,
+    read_stalls: u64,
+    high_water: usize,
+    overlapped: u64,
+}
+
+// Whatever the drive read that the consumer never got to
+#[derive(Default)]
+struct Unread {
+    blocks: u32,
+    filemark: bool,
+    error: Option<DeviceError>,
+}
+
+pub struct ReadAhead<D: TapeDevice + 'static> {
+    name: String,
+    config: ReadAheadConfig,
+    // `None` while the read-ahead task has it
+    device: Option<D>,
+    streaming: Option<Streaming<D>>,
+    stats: ReadAheadStats,
+}
+
+impl<D: TapeDevice + 'static> ReadAhead<D> {
+    pub fn new(device: D, config: ReadAheadConfig) -> Self {
+        Self {
+            name: device.name().to_string(),
+            config,
+            device: Some(device),
+            streaming: None,
+            stats: ReadAheadStats::default(),
+        }
+    }
+
+    // Everything read ahead so far, not counting the file we're in
+    pub fn stats(&self) -> ReadAheadStats {
+        self.stats
+    }
+
+    // Get at the device underneath, stopping the read-ahead first
+    //
+    // NOTE(aki): This doesn't put the tape back, so it may be further along than we've read
+    pub async fn inner(&mut self) -> &mut D {
+        self.stop().await;
+        self.device.as_mut().unwrap()
+    }
+
+    // Hand the device off to a task that reads the rest of the file into the ring, with
+    // buffers at least `min_len` long
+    //
+    // NOTE(aki): The drive would read a block into the consumer's buffer if it fit, so a
+    // buffer in the ring must not turn it away for being too short
+    fn start(&mut self, min_len: usize) {
+        let mut device = self.device.take().unwrap();
+        // NOTE(aki): Every buffer is in one half of the ring or the other, or being read into
+        // or out of, so neither half can ever have more than all of them in it
+        let (filled_tx, filled) = channel::channel(self.config.buffers);
+        let (free, free_rx) = channel::channel(self.config.buffers);
+        let buffer_len = self.config.buffer_len.max(min_len);
+        let reading = Arc::new(AtomicBool::new(false));
+        let task_reading = reading.clone();
+
+        for _ in 0..self.config.buffers {
+            free.send(vec![0u8; buffer_len]);
+        }
+
+        trace!(
+            device = self.name.as_str(),
+            buffers = self.config.buffers,
+            buffer_len,
+            "Starting read-ahead"
+        );
+
+        let task = runtime::spawn(async move {
+            let mut stats = TaskStats {
+                blocks: 0,
+                bytes: 0,
+                drive_stalls: 0,
+            };
+
+            // Dropping the free half of the ring is how we get told to stop, even if there
+            // are still buffers in it
+            while !free_rx.is_closed() {
+                let mut buffer = match free_rx.try_recv() {
+                    Some(buffer) => buffer,
+                    // Every buffer is full, the drive is going to have to stop
+                    None => match free_rx.recv().await {
+                        Some(buffer) => {
+                            stats.drive_stalls += 1;
+                            buffer
+                        }
+                        None => break,
+                    },
+                };
+
+                task_reading.store(true, Ordering::Release);
+                let result = device.read_block(&mut buffer).await;
+                task_reading.store(false, Ordering::Release);
+                let more = match result {
+                    Ok(ReadOutcome::Block(len)) => {
+                        stats.blocks += 1;
+                        stats.bytes += len as u64;
+                        true
+                    }
+                    _ => false,
+                };
+
+                filled_tx.send(Filled { buffer, result });
+                if !more {
+                    break;
+                }
+
+                // Let the consumer at it, if the device never gave up the executor while it
+                // was reading then this is the only chance it gets before the ring is full
+                future::yield_now().await;
+            }
+
+            (device, stats)
+        });
+
+        self.streaming = Some(Streaming {
+            task,
+            filled,
+            free,
+            buffer_len,
+            reading,
+            read_stalls: 0,
+            high_water: 0,
+            overlapped: 0,
+        });
+    }
+
+    // Stop the read-ahead task and get the device back, along with whatever it read that
+    // nobody asked for yet
+    async fn stop(&mut self) -> Unread {
+        let Some(streaming) = self.streaming.take() else {
+            return Unread::default();
+        };
+
+        drop(streaming.free);
+        let Ok((device, task_stats)) = streaming.task.await else {
+            panic!("Read-ahead task went away with the tape device");
+        };
+        self.device = Some(device);
+
+        let mut unread = Unread::default();
+        while let Some(filled) = streaming.filled.try_recv() {
+            match filled.result {
+                Ok(ReadOutcome::Block(_)) => unread.blocks += 1,
+                Ok(ReadOutcome::Filemark) => unread.filemark = true,
+                Ok(ReadOutcome::EndOfData) => {}
+                Err(err) => unread.error = Some(err),
+            }
+        }
+
+        let stats = ReadAheadStats {
+            blocks: task_stats.blocks,
+            bytes: task_stats.bytes,
+            drive_stalls: task_stats.drive_stalls,
+            read_stalls: streaming.read_stalls,
+            high_water: streaming.high_water,
+            overlapped: streaming.overlapped,
+        };
+        debug!(
+            device = self.name.as_str(),
+            blocks = stats.blocks,
+            bytes = stats.bytes,
+            drive_stalls = stats.drive_stalls,
+            read_stalls = stats.read_stalls,
+            high_water = stats.high_water,
+            overlapped = stats.overlapped,
+            unread = unread.blocks,
+            "Read-ahead finished"
+        );
+        self.stats.add(&stats);
+
+        unread
+    }
+
+    // Stop reading ahead and put the tape back to where the consumer thinks it is
+    async fn sync(&mut self) -> Result<&mut D, DeviceError> {
+        let unread = self.stop().await;
+        let device = self.device.as_mut().unwrap();
+
+        // NOTE(aki): If the read-ahead ran into something we have no idea where the tape is,
+        // so the best we can do is report it now rather than lose it
+        if let Some(err) = unread.error {
+            return Err(err);
+        }
+
+        if unread.filemark {
+            device.space_filemarks(-1).await?;
+        }
+        if unread.blocks != 0 {
+            device.space_blocks(-(unread.blocks as i32)).await?;
+        }
+
+        Ok(device)
+    }
+}
+
+impl<D: TapeDevice + 'static> TapeDevice for ReadAhead<D> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn read_block(&mut self, buffer: &mut [u8]) -> Result<ReadOutcome, DeviceError> {
+        if self.config.buffers == 0 {
+            return self.device.as_mut().unwrap().read_block(buffer).await;
+        }
+
+        // Someone wants bigger blocks than the ring was made for, start it over with ones
+        // that are big enough
+        if let Some(streaming) = &self.streaming
+            && streaming.buffer_len < buffer.len()
+        {
+            self.sync().await?;
+        }
+
+        if self.streaming.is_none() {
+            self.start(buffer.len());
+        }
+        let streaming = self.streaming.as_mut().unwrap();
+
+        streaming.high_water = streaming.high_water.max(streaming.filled.len());
+        let filled = match streaming.filled.try_recv() {
+            Some(filled) => {
+                if streaming.reading.load(Ordering::Acquire) {
+                    streaming.overlapped += 1;
+                }
+                filled
+            }
+            None => {
+                streaming.read_stalls += 1;
+                // NOTE(aki): The task only ever stops after sending us how the file ended,
+                // or when we tell it to
+                streaming
+                    .filled
+                    .recv()
+                    .await
+                    .expect("Read-ahead task stopped early")
+            }
+        };
+
+        let result = match filled.result {
+            // Same as the drive would do, the tape has moved past it either way
+            Ok(ReadOutcome::Block(len)) if len > buffer.len() => {
+                Err(DeviceError::BlockTooLarge { len })
+            }
+            Ok(ReadOutcome::Block(len)) => {
+                buffer[..len].copy_from_slice(&filled.buffer[..len]);
+                Ok(ReadOutcome::Block(len))
+            }
+            result => result,
+        };
+
+        if matches!(filled.result, Ok(ReadOutcome::Block(_))) {
+            streaming.free.send(filled.buffer);
+        } else {
+            // That was the end of the file, so the task is done
+            self.stop().await;
+        }
+
+        result
+    }
+
+    async fn space_filemarks(&mut self, count: i32) -> Result<(), DeviceError> {
+        if count <= 0 {
+            return self.sync().await?.space_filemarks(count).await;
+        }
+
+        // Spacing forwards, it doesn't matter how far into the file the drive got unless it
+        // already went over the filemark
+        let unread = self.stop().await;
+        if let Some(err) = unread.error {
+            return Err(err);
+        }
+
+        let count = count - unread.filemark as i32;
+        match count {
+            0 => Ok(()),
+            count => self.device.as_mut().unwrap().space_filemarks(count).await,
+        }
+    }
+
+    async fn space_blocks(&mut self, count: i32) -> Result<(), DeviceError> {
+        self.sync().await?.space_blocks(count).await
+    }
+
+    async fn rewind(&mut self) -> Result<(), DeviceError> {
+        self.stop().await;
+        self.device.as_mut().unwrap().rewind().await
+    }
+
+    async fn retension(&mut self) -> Result<(), DeviceError> {
+        self.stop().await;
+        self.device.as_mut().unwrap().retension().await
+    }
+
+    async fn unload(&mut self) -> Result<(), DeviceError> {
+        self.stop().await;
+        self.device.as_mut().unwrap().unload().await
+    }
+
+    async fn load(&mut self, timeout: Duration) -> Result<(), DeviceError> {
+        self.stop().await;
+        self.device.as_mut().unwrap().load(timeout).await
+    }
+
+    async fn position(&mut self) -> Result<TapePosition, DeviceError> {
+        self.sync().await?.position().await
+    }
+
+    async fn status(&mut self) -> Result<DeviceStatus, DeviceError> {
+        self.sync().await?.status().await
+    }
+}
@@ -0,0 +1,583 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// Getting through read errors.
+//
+// Old reels throw soft errors all the time, and more often than not the block reads fine on
+// the next pass over the heads. `RecoveringTape` sits in front of another `TapeDevice`, and
+// when a read comes back with a MEDIUM ERROR it backspaces over the block and tries it again,
+// optionally retensioning the reel if that doesn't help. If the block still won't read, the
+// policy decides whether that's the end of it or whether we leave a hole and carry on.
+
+use core::{fmt, time::Duration};
+
+use tracing::{debug, error, info, warn};
+
+use crate::tape::{
+    device::{DeviceError, DeviceStatus, ReadOutcome, TapeDevice, TapePosition},
+    scsi::sense::{SenseData, TapeError},
+};
+
+pub const DEFAULT_RETRIES: u32 = 3;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OnFailure {
+    // Pass the error up, which will most likely end the load
+    #[default]
+    Fail,
+    // Fill the block with zeros and keep reading
+    Skip,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RecoveryPolicy {
+    // How many times to re-read a block before giving up on it
+    pub retries: u32,
+    // Retension the reel once we run out of retries, and then try them all again
+    pub retension: bool,
+    pub on_failure: OnFailure,
+}
+
+impl Default for RecoveryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: DEFAULT_RETRIES,
+            retension: false,
+            on_failure: OnFailure::Fail,
+        }
+    }
+}
+
+impl RecoveryPolicy {
+    // Parse a policy from a comma separated list of `retries=<n>`, `retension`, `skip`, and
+    // `fail`, anything not given is left at the default
+    pub fn parse(policy: &str) -> Option<Self> {
+        let mut parsed = Self::default();
+
+        for option in policy
+            .split(',')
+            .map(str::trim)
+            .filter(|opt| !opt.is_empty())
+        {
+            match option.split_once('=') {
+                Some(("retries", count)) => parsed.retries = count.trim().parse().ok()?,
+                None if option == "retension" => parsed.retension = true,
+                None if option == "skip" => parsed.on_failure = OnFailure::Skip,
+                None if option == "fail" => parsed.on_failure = OnFailure::Fail,
+                _ => return None,
+            }
+        }
+
+        Some(parsed)
+    }
+}
+
+impl fmt::Display for RecoveryPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "retries={}", self.retries)?;
+        if self.retension {
+            write!(f, ",retension")?;
+        }
+        match self.on_failure {
+            OnFailure::Fail => write!(f, ",fail"),
+            OnFailure::Skip => write!(f, ",skip"),
+        }
+    }
+}
+
+// How reading a tape file went
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FileErrors {
+    // `None` if we lost track of where we are on the tape
+    pub file: Option<u32>,
+    // Blocks that came back with a MEDIUM ERROR at least once
+    pub errors: u32,
+    // Re-reads it took, successful or not
+    pub retries: u32,
+    // Blocks that read fine in the end
+    pub recovered: u32,
+    // Blocks we gave up on and left zeroed
+    pub holes: u32,
+}
+
+impl FileErrors {
+    fn new(file: Option<u32>) -> Self {
+        Self {
+            file,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.errors == 0
+    }
+
+    fn add(&mut self, other: &Self) {
+        self.errors += other.errors;
+        self.retries += other.retries;
+        self.recovered += other.recovered;
+        self.holes += other.holes;
+    }
+}
+
+pub struct RecoveringTape<D: TapeDevice> {
+    device: D,
+    policy: RecoveryPolicy,
+    // Where we are, as far as we can tell from what has gone through us
+    file: Option<u32>,
+    block: u64,
+    // Length of the last good block, which is how big we make a hole, this carries over
+    // from the previous file so a bad first block still gets a sensible guess
+    last_len: Option<usize>,
+    current: FileErrors,
+    // Every file that had errors in it, in the order we read them
+    history: Vec<FileErrors>,
+}
+
+impl<D: TapeDevice> RecoveringTape<D> {
+    pub fn new(device: D, policy: RecoveryPolicy) -> Self {
+        Self {
+            device,
+            policy,
+            file: None,
+            block: 0,
+            last_len: None,
+            current: FileErrors::new(None),
+            history: Vec::new(),
+        }
+    }
+
+    // Every file that had errors, including the one we're in
+    pub fn summary(&self) -> Vec<FileErrors> {
+        let mut summary = self.history.clone();
+        if !self.current.is_clean() {
+            summary.push(self.current);
+        }
+
+        summary
+    }
+
+    // All of the errors on the tape so far, added up
+    pub fn totals(&self) -> FileErrors {
+        let mut totals = FileErrors::new(None);
+        for file in self.summary() {
+            totals.add(&file);
+        }
+
+        totals
+    }
+
+    // We've moved to a different file, so wrap up the errors from the last one
+    fn next_file(&mut self, file: Option<u32>) {
+        let done = core::mem::replace(&mut self.current, FileErrors::new(file));
+
+        if !done.is_clean() {
+            let device = self.device.name();
+            if done.holes != 0 {
+                warn!(
+                    device,
+                    file = ?done.file,
+                    errors = done.errors,
+                    retries = done.retries,
+                    recovered = done.recovered,
+                    holes = done.holes,
+                    "Tape file read with unrecovered errors"
+                );
+            } else {
+                info!(
+                    device,
+                    file = ?done.file,
+                    errors = done.errors,
+                    retries = done.retries,
+                    recovered = done.recovered,
+                    "Tape file read with recovered errors"
+                );
+            }
+
+            self.history.push(done);
+        }
+
+        self.file = file;
+        self.block = 0;
+    }
+
+    // Keep track of where a read left us
+    fn track(
+        &mut self,
+        result: Result<ReadOutcome, DeviceError>,
+    ) -> Result<ReadOutcome, DeviceError> {
+        match result {
+            Ok(ReadOutcome::Block(len)) => {
+                self.block += 1;
+                self.last_len = Some(len);
+            }
+            Ok(ReadOutcome::Filemark) => self.next_file(self.file.map(|file| file + 1)),
+            Ok(ReadOutcome::EndOfData) => self.next_file(None),
+            // The drive has moved past the block either way
+            Err(DeviceError::BlockTooLarge { .. }) => self.block += 1,
+            Err(_) => {}
+        }
+
+        result
+    }
+
+    // Re-read the block we just failed to read, the tape is just past it
+    //
+    // The inner result is the last MEDIUM ERROR if we never got it to read, anything else
+    // going wrong means we've lost our place.
+    async fn retry(
+        &mut self,
+        buffer: &mut [u8],
+        mut sense: SenseData,
+    ) -> Result<Result<ReadOutcome, SenseData>, DeviceError> {
+        for attempt in 1..=self.policy.retries {
+            self.current.retries += 1;
+
+            self.device.space_blocks(-1).await?;
+            match self.device.read_block(buffer).await {
+                Ok(outcome) => {
+                    info!(
+                        device = self.device.name(),
+                        file = ?self.file,
+                        block = self.block,
+                        attempt,
+                        "Read error recovered"
+                    );
+                    return Ok(Ok(outcome));
+                }
+                Err(DeviceError::Tape(TapeError::Medium(err))) => {
+                    debug!(
+                        device = self.device.name(),
+                        file = ?self.file,
+                        block = self.block,
+                        attempt,
+                        "Retry failed: {}",
+                        err
+                    );
+                    sense = err;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(Err(sense))
+    }
+
+    // Put the tape back to just past the block we were trying to read after a retension
+    async fn reposition(&mut self, file: u32) -> Result<(), DeviceError> {
+        self.device.retension().await?;
+        if file != 0 {
+            self.device.space_filemarks(file as i32).await?;
+        }
+        self.device.space_blocks(self.block as i32 + 1).await
+    }
+
+    async fn recover(
+        &mut self,
+        buffer: &mut [u8],
+        sense: SenseData,
+    ) -> Result<ReadOutcome, DeviceError> {
+        let device = self.device.name().to_string();
+        warn!(
+            device = device.as_str(),
+            file = ?self.file,
+            block = self.block,
+            retries = self.policy.retries,
+            "Read error: {}",
+            sense
+        );
+        self.current.errors += 1;
+
+        let mut result = self.retry(buffer, sense).await?;
+
+        if let Err(sense) = result
+            && self.policy.retension
+        {
+            match self.file {
+                Some(file) => {
+                    info!(
+                        device = device.as_str(),
+                        file, "Retensioning the reel and trying again"
+                    );
+                    self.reposition(file).await?;
+                    result = self.retry(buffer, sense).await?;
+                }
+                None => warn!(
+                    device = device.as_str(),
+                    "Lost track of the tape file, unable to retension"
+                ),
+            }
+        }
+
+        let sense = match result {
+            Ok(outcome) => {
+                self.current.recovered += 1;
+                return self.track(Ok(outcome));
+            }
+            Err(sense) => sense,
+        };
+
+        match self.policy.on_failure {
+            OnFailure::Fail => {
+                error!(
+                    device = device.as_str(),
+                    file = ?self.file,
+                    block = self.block,
+                    "Giving up on unreadable block"
+                );
+                Err(DeviceError::Tape(TapeError::Medium(sense)))
+            }
+            OnFailure::Skip => {
+                // NOTE(aki): Most tapes are written in fixed size blocks, so the last one we
+                // read is our best guess as to how much data we just lost. Without one, the
+                // buffer is as big as the caller expects blocks to get, which beats a hole
+                // that isn't there at all
+                let len = self.last_len.unwrap_or(buffer.len()).min(buffer.len());
+                buffer[..len].fill(0);
+                self.current.holes += 1;
+
+                warn!(
+                    device = device.as_str(),
+                    file = ?self.file,
+                    block = self.block,
+                    len,
+                    "Skipping unreadable block, leaving a hole"
+                );
+
+                self.block += 1;
+                Ok(ReadOutcome::Block(len))
+            }
+        }
+    }
+}
+
+impl<D: TapeDevice> TapeDevice for RecoveringTape<D> {
+    fn name(&self) -> &str {
+        self.device.name()
+    }
+
+    async fn read_block(&mut self, buffer: &mut [u8]) -> Result<ReadOutcome, DeviceError> {
+        match self.device.read_block(buffer).await {
+            Err(DeviceError::Tape(TapeError::Medium(sense))) => self.recover(buffer, sense).await,
+            result => self.track(result),
+        }
+    }
+
+    async fn space_filemarks(&mut self, count: i32) -> Result<(), DeviceError> {
+        let result = self.device.space_filemarks(count).await;
+
+        let file = match result {
+            Ok(()) => self.file.map(|file| file.saturating_add_signed(count)),
+            Err(_) => None,
+        };
+        self.next_file(file);
+
+        result
+    }
+
+    async fn space_blocks(&mut self, count: i32) -> Result<(), DeviceError> {
+        let result = self.device.space_blocks(count).await;
+
+        match result {
+            Ok(()) => self.block = self.block.saturating_add_signed(count as i64),
+            Err(_) => self.next_file(None),
+        }
+
+        result
+    }
+
+    async fn rewind(&mut self) -> Result<(), DeviceError> {
+        let result = self.device.rewind().await;
+        self.next_file(result.is_ok().then_some(0));
+        result
+    }
+
+    async fn retension(&mut self) -> Result<(), DeviceError> {
+        let result = self.device.retension().await;
+        self.next_file(result.is_ok().then_some(0));
+        result
+    }
+
+    async fn unload(&mut self) -> Result<(), DeviceError> {
+        let result = self.device.unload().await;
+        self.next_file(None);
+        result
+    }
+
+    // NOTE(aki): The errors carry on across reels, they're all part of the same set
+    async fn load(&mut self, timeout: Duration) -> Result<(), DeviceError> {
+        let result = self.device.load(timeout).await;
+        self.next_file(result.is_ok().then_some(0));
+        result
+    }
+
+    async fn position(&mut self) -> Result<TapePosition, DeviceError> {
+        self.device.position().await
+    }
+
+    async fn status(&mut self) -> Result<DeviceStatus, DeviceError> {
+        self.device.status().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tape::{
+        image,
+        testing::{MockTape, block_on},
+    };
+
+    const RETRY: [&str; 2] = ["space_blocks(-1)", "read"];
+
+    fn policy(retries: u32, retension: bool, on_failure: OnFailure) -> RecoveryPolicy {
+        RecoveryPolicy {
+            retries,
+            retension,
+            on_failure,
+        }
+    }
+
+    fn read(tape: &mut RecoveringTape<MockTape>) -> Result<Vec<u8>, DeviceError> {
+        let mut buffer = [0xFFu8; 16];
+        match block_on(tape.read_block(&mut buffer))? {
+            ReadOutcome::Block(len) => Ok(buffer[..len].to_vec()),
+            outcome => panic!("expected a block, got {outcome:?}"),
+        }
+    }
+
+    fn totals(errors: u32, retries: u32, recovered: u32, holes: u32) -> FileErrors {
+        FileErrors {
+            file: None,
+            errors,
+            retries,
+            recovered,
+            holes,
+        }
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(RecoveryPolicy::parse(""), Some(RecoveryPolicy::default()));
+        assert_eq!(
+            RecoveryPolicy::parse("retries=5, retension,skip"),
+            Some(policy(5, true, OnFailure::Skip))
+        );
+        assert_eq!(
+            RecoveryPolicy::parse("skip,fail"),
+            Some(policy(DEFAULT_RETRIES, false, OnFailure::Fail))
+        );
+        assert_eq!(RecoveryPolicy::parse("retries=-1"), None);
+        assert_eq!(RecoveryPolicy::parse("retry=1"), None);
+        assert_eq!(RecoveryPolicy::parse("rewind"), None);
+
+        let policy = policy(2, true, OnFailure::Skip);
+        assert_eq!(policy.to_string(), "retries=2,retension,skip");
+        assert_eq!(RecoveryPolicy::parse(&policy.to_string()), Some(policy));
+    }
+
+    #[test]
+    fn recovered() {
+        let mut device = MockTape::files(&[(3, 8)]);
+        device.fail(0, 1, 2);
+        let mut tape = RecoveringTape::new(device, policy(3, false, OnFailure::Fail));
+
+        assert_eq!(read(&mut tape), Ok(vec![0x00; 8]));
+        assert_eq!(read(&mut tape), Ok(vec![0x01; 8]));
+        assert_eq!(read(&mut tape), Ok(vec![0x02; 8]));
+
+        assert_eq!(
+            tape.device.log,
+            [["read", "read"].as_slice(), &RETRY, &RETRY, &["read"]].concat()
+        );
+        assert_eq!(tape.totals(), totals(1, 2, 1, 0));
+    }
+
+    #[test]
+    fn fail() {
+        let mut device = MockTape::files(&[(3, 8)]);
+        device.fail(0, 1, u32::MAX);
+        let mut tape = RecoveringTape::new(device, policy(2, false, OnFailure::Fail));
+
+        assert_eq!(read(&mut tape), Ok(vec![0x00; 8]));
+        assert_eq!(read(&mut tape), Err(image::medium_error()));
+        assert_eq!(
+            tape.device.log,
+            [["read", "read"].as_slice(), &RETRY, &RETRY].concat()
+        );
+        assert_eq!(tape.totals(), totals(1, 2, 0, 0));
+    }
+
+    #[test]
+    fn skip() {
+        let mut device = MockTape::files(&[(3, 8), (2, 12)]);
+        device.fail(0, 0, u32::MAX);
+        device.fail(0, 2, u32::MAX);
+        device.fail(1, 0, u32::MAX);
+        let mut tape = RecoveringTape::new(device, policy(1, false, OnFailure::Skip));
+
+        // Nothing has been read yet to say how big the blocks are, so it's the whole buffer
+        assert_eq!(read(&mut tape), Ok(vec![0x00; 16]));
+        assert_eq!(read(&mut tape), Ok(vec![0x01; 8]));
+        // Otherwise it's as big as the last one
+        assert_eq!(read(&mut tape), Ok(vec![0x00; 8]));
+        assert_eq!(
+            block_on(tape.read_block(&mut [0u8; 16])),
+            Ok(ReadOutcome::Filemark)
+        );
+        // Even if that was in the last file
+        assert_eq!(read(&mut tape), Ok(vec![0x00; 8]));
+        assert_eq!(read(&mut tape), Ok(vec![0x11; 12]));
+
+        assert_eq!(tape.totals(), totals(3, 3, 0, 3));
+        assert_eq!(tape.summary().len(), 2);
+        assert_eq!(tape.summary()[0].holes, 2);
+    }
+
+    #[test]
+    fn retension() {
+        let mut device = MockTape::files(&[(2, 8), (3, 8)]);
+        device.fail(1, 1, 2);
+        let mut tape = RecoveringTape::new(device, policy(1, true, OnFailure::Fail));
+
+        block_on(tape.rewind()).unwrap();
+        block_on(tape.space_filemarks(1)).unwrap();
+        assert_eq!(read(&mut tape), Ok(vec![0x10; 8]));
+        tape.device.log.clear();
+
+        // Retensioning leaves the reel at BOT, so it has to find its way back to the block
+        assert_eq!(read(&mut tape), Ok(vec![0x11; 8]));
+        assert_eq!(
+            tape.device.log,
+            [
+                ["read"].as_slice(),
+                &RETRY,
+                &["retension", "space_filemarks(1)", "space_blocks(2)"],
+                &RETRY
+            ]
+            .concat()
+        );
+        assert_eq!(read(&mut tape), Ok(vec![0x12; 8]));
+
+        assert_eq!(
+            tape.summary(),
+            [FileErrors {
+                file: Some(1),
+                errors: 1,
+                retries: 2,
+                recovered: 1,
+                holes: 0
+            }]
+        );
+    }
+
+    #[test]
+    fn lost() {
+        // Without knowing which file we're in there's no getting back after a retension
+        let mut device = MockTape::files(&[(2, 8)]);
+        device.fail(0, 1, u32::MAX);
+        let mut tape = RecoveringTape::new(device, policy(1, true, OnFailure::Fail));
+
+        assert_eq!(read(&mut tape), Ok(vec![0x00; 8]));
+        assert_eq!(read(&mut tape), Err(image::medium_error()));
+        assert!(!tape.device.log.iter().any(|op| op == "retension"));
+    }
+}
@@ -0,0 +1,253 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// CDB builders for the SSC/SPC commands we use.
+//
+// The timeouts are deliberately generous, a 9-track drive rewinding a full 2400ft reel
+// can take a couple of minutes, and some drives are *very* slow to load.
+
+use core::time::Duration;
+
+use crate::tape::scsi::{Cdb, Command, DataDirection, opcode, put_be24};
+
+const TIMEOUT_SHORT: Duration = Duration::from_secs(10);
+const TIMEOUT_READ: Duration = Duration::from_secs(60);
+const TIMEOUT_MOTION: Duration = Duration::from_mins(10);
+
+// The largest transfer length READ(6) can express
+pub const READ6_MAX_LEN: u32 = 0x00FF_FFFF;
+
+// Standard INQUIRY data is 36 bytes, with everything after that vendor specific
+pub const INQUIRY_LEN: u8 = 36;
+// Fixed format sense data is 18 bytes, but some drives have more to say
+pub const SENSE_LEN: u8 = 252;
+pub const BLOCK_LIMITS_LEN: usize = 6;
+pub const READ_POSITION_SHORT_LEN: usize = 20;
+// Room for the header and a good handful of density descriptors
+pub const DENSITY_SUPPORT_LEN: u16 = 4 + 52 * 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpaceCode {
+    Blocks,
+    Filemarks,
+}
+
+impl SpaceCode {
+    fn code(&self) -> u8 {
+        match self {
+            SpaceCode::Blocks => 0b000,
+            SpaceCode::Filemarks => 0b001,
+        }
+    }
+}
+
+fn cdb6(op: u8) -> Cdb {
+    let mut cdb = Cdb::new(6);
+    cdb.as_bytes_mut()[0] = op;
+    cdb
+}
+
+fn cdb10(op: u8) -> Cdb {
+    let mut cdb = Cdb::new(10);
+    cdb.as_bytes_mut()[0] = op;
+    cdb
+}
+
+fn no_data(cdb: Cdb, timeout: Duration) -> Command {
+    Command {
+        cdb,
+        direction: DataDirection::None,
+        transfer_len: 0,
+        timeout,
+    }
+}
+
+fn data_in(cdb: Cdb, transfer_len: usize, timeout: Duration) -> Command {
+    Command {
+        cdb,
+        direction: DataDirection::In,
+        transfer_len,
+        timeout,
+    }
+}
+
+pub fn test_unit_ready() -> Command {
+    no_data(cdb6(opcode::TEST_UNIT_READY), TIMEOUT_SHORT)
+}
+
+pub fn inquiry(alloc_len: u8) -> Command {
+    let mut cdb = cdb6(opcode::INQUIRY);
+    cdb.as_bytes_mut()[4] = alloc_len;
+    data_in(cdb, alloc_len as usize, TIMEOUT_SHORT)
+}
+
+pub fn request_sense(alloc_len: u8) -> Command {
+    let mut cdb = cdb6(opcode::REQUEST_SENSE);
+    cdb.as_bytes_mut()[4] = alloc_len;
+    data_in(cdb, alloc_len as usize, TIMEOUT_SHORT)
+}
+
+pub fn rewind(immed: bool) -> Command {
+    let mut cdb = cdb6(opcode::REWIND);
+    cdb.as_bytes_mut()[1] = immed as u8;
+    no_data(cdb, TIMEOUT_MOTION)
+}
+
+pub fn read_block_limits() -> Command {
+    data_in(
+        cdb6(opcode::READ_BLOCK_LIMITS),
+        BLOCK_LIMITS_LEN,
+        TIMEOUT_SHORT,
+    )
+}
+
+// READ(6) in fixed block mode, `blocks` blocks of `block_len` bytes each
+pub fn read_fixed(blocks: u32, block_len: u32, sili: bool) -> Command {
+    assert!(blocks <= READ6_MAX_LEN, "READ(6) transfer length too large");
+
+    let mut cdb = cdb6(opcode::READ_6);
+    let bytes = cdb.as_bytes_mut();
+    bytes[1] = 0x01 | ((sili as u8) << 1); // FIXED | SILI
+    put_be24(&mut bytes[2..5], blocks);
+
+    // NOTE(aki): Both can be up to 24 bits, too much for a `u32` once multiplied
+    let transfer_len = (blocks as usize)
+        .checked_mul(block_len as usize)
+        .expect("READ(6) transfer length too large");
+
+    data_in(cdb, transfer_len, TIMEOUT_READ)
+}
+
+// READ(6) in variable block mode, reading a single block of up to `max_len` bytes
+pub fn read_variable(max_len: u32, sili: bool) -> Command {
+    assert!(
+        max_len <= READ6_MAX_LEN,
+        "READ(6) transfer length too large"
+    );
+
+    let mut cdb = cdb6(opcode::READ_6);
+    let bytes = cdb.as_bytes_mut();
+    bytes[1] = (sili as u8) << 1; // SILI
+    put_be24(&mut bytes[2..5], max_len);
+
+    data_in(cdb, max_len as usize, TIMEOUT_READ)
+}
+
+// SPACE(6), negative counts move towards BOT
+pub fn space(code: SpaceCode, count: i32) -> Command {
+    assert!(
+        (-0x80_0000..=0x7F_FFFF).contains(&count),
+        "SPACE(6) count out of range"
+    );
+
+    let mut cdb = cdb6(opcode::SPACE_6);
+    let bytes = cdb.as_bytes_mut();
+    bytes[1] = code.code();
+    // The count is a 24-bit two's complement number
+    put_be24(&mut bytes[2..5], (count as u32) & 0x00FF_FFFF);
+
+    no_data(cdb, TIMEOUT_MOTION)
+}
+
+// READ POSITION, short form
+pub fn read_position() -> Command {
+    data_in(
+        cdb10(opcode::READ_POSITION),
+        READ_POSITION_SHORT_LEN,
+        TIMEOUT_SHORT,
+    )
+}
+
+pub fn load(immed: bool, retension: bool) -> Command {
+    let mut cdb = cdb6(opcode::LOAD_UNLOAD);
+    let bytes = cdb.as_bytes_mut();
+    bytes[1] = immed as u8;
+    bytes[4] = 0x01 | ((retension as u8) << 1); // LOAD | RETEN
+    no_data(cdb, TIMEOUT_MOTION)
+}
+
+pub fn unload(immed: bool) -> Command {
+    let mut cdb = cdb6(opcode::LOAD_UNLOAD);
+    cdb.as_bytes_mut()[1] = immed as u8;
+    no_data(cdb, TIMEOUT_MOTION)
+}
+
+// MODE SENSE(6) for the current values of `page`
+pub fn mode_sense6(dbd: bool, page: u8, alloc_len: u8) -> Command {
+    let mut cdb = cdb6(opcode::MODE_SENSE_6);
+    let bytes = cdb.as_bytes_mut();
+    bytes[1] = (dbd as u8) << 3;
+    bytes[2] = page & 0x3F;
+    bytes[4] = alloc_len;
+    data_in(cdb, alloc_len as usize, TIMEOUT_SHORT)
+}
+
+// MODE SELECT(6), `param_len` bytes of mode parameters follow in the data-out phase
+pub fn mode_select6(page_format: bool, param_len: u8) -> Command {
+    let mut cdb = cdb6(opcode::MODE_SELECT_6);
+    let bytes = cdb.as_bytes_mut();
+    bytes[1] = (page_format as u8) << 4;
+    bytes[4] = param_len;
+
+    Command {
+        cdb,
+        direction: DataDirection::Out,
+        transfer_len: param_len as usize,
+        timeout: TIMEOUT_SHORT,
+    }
+}
+
+// REPORT DENSITY SUPPORT, with `media` set it only reports what the loaded reel could be
+pub fn report_density_support(media: bool, alloc_len: u16) -> Command {
+    let mut cdb = cdb10(opcode::REPORT_DENSITY_SUPPORT);
+    let bytes = cdb.as_bytes_mut();
+    bytes[1] = media as u8;
+    bytes[7..9].copy_from_slice(&alloc_len.to_be_bytes());
+    data_in(cdb, alloc_len as usize, TIMEOUT_SHORT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read() {
+        let cmd = read_fixed(4, 512, true);
+        assert_eq!(cmd.cdb.as_bytes(), [0x08, 0x03, 0x00, 0x00, 0x04, 0x00]);
+        assert_eq!(cmd.direction, DataDirection::In);
+        assert_eq!(cmd.transfer_len, 2048);
+
+        let cmd = read_fixed(0x1000, 0x10_0000, false);
+        assert_eq!(cmd.cdb.as_bytes(), [0x08, 0x01, 0x00, 0x10, 0x00, 0x00]);
+        assert_eq!(cmd.transfer_len, 0x1_0000_0000);
+
+        let cmd = read_variable(0x01_0000, false);
+        assert_eq!(cmd.cdb.as_bytes(), [0x08, 0x00, 0x01, 0x00, 0x00, 0x00]);
+        assert_eq!(cmd.transfer_len, 0x01_0000);
+    }
+
+    #[test]
+    fn space_backwards() {
+        let cmd = space(SpaceCode::Filemarks, -2);
+        assert_eq!(cmd.cdb.as_bytes(), [0x11, 0x01, 0xFF, 0xFF, 0xFE, 0x00]);
+        assert_eq!(cmd.direction, DataDirection::None);
+    }
+
+    #[test]
+    fn data_in_lengths() {
+        let cmd = inquiry(INQUIRY_LEN);
+        assert_eq!(cmd.cdb.as_bytes(), [0x12, 0x00, 0x00, 0x00, 36, 0x00]);
+
+        let cmd = read_position();
+        assert_eq!(cmd.cdb.as_bytes().len(), 10);
+        assert_eq!(cmd.cdb.opcode(), opcode::READ_POSITION);
+        assert_eq!(cmd.transfer_len, READ_POSITION_SHORT_LEN);
+
+        let cmd = mode_sense6(false, 0x10, 0xFF);
+        assert_eq!(cmd.cdb.as_bytes(), [0x1A, 0x00, 0x10, 0x00, 0xFF, 0x00]);
+
+        let cmd = report_density_support(true, DENSITY_SUPPORT_LEN);
+        assert_eq!(
+            cmd.cdb.as_bytes(),
+            [0x44, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x44, 0x00]
+        );
+    }
+}
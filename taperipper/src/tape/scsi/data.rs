@@ -0,0 +1,558 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// Parsers for the data-in responses of the SSC/SPC commands we use, and the
+// few data-out parameter lists we need to build.
+
+use core::fmt;
+
+use crate::tape::scsi::{DEVICE_TYPE_SEQUENTIAL, ParseError, be16, be24, be32, need, put_be24};
+
+// Turn a space-padded ASCII field into something printable
+fn ascii_field(field: &[u8]) -> &str {
+    str::from_utf8(field)
+        .unwrap_or("<INVALID>")
+        .trim_end_matches([' ', '\0'])
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct InquiryData {
+    pub qualifier: u8,
+    pub device_type: u8,
+    pub removable: bool,
+    pub version: u8,
+    pub response_format: u8,
+    vendor: [u8; 8],
+    product: [u8; 16],
+    revision: [u8; 4],
+}
+
+impl InquiryData {
+    pub const LEN: usize = 36;
+
+    pub fn parse(data: &[u8]) -> Result<Self, ParseError> {
+        need(data, Self::LEN)?;
+
+        Ok(Self {
+            qualifier: data[0] >> 5,
+            device_type: data[0] & 0x1F,
+            removable: (data[1] & 0x80) != 0,
+            version: data[2],
+            response_format: data[3] & 0x0F,
+            vendor: data[8..16].try_into().unwrap(),
+            product: data[16..32].try_into().unwrap(),
+            revision: data[32..36].try_into().unwrap(),
+        })
+    }
+
+    // Is this a sequential-access device that's actually connected?
+    pub fn is_tape(&self) -> bool {
+        self.qualifier == 0 && self.device_type == DEVICE_TYPE_SEQUENTIAL
+    }
+
+    pub fn vendor(&self) -> &str {
+        ascii_field(&self.vendor)
+    }
+
+    pub fn product(&self) -> &str {
+        ascii_field(&self.product)
+    }
+
+    pub fn revision(&self) -> &str {
+        ascii_field(&self.revision)
+    }
+}
+
+impl fmt::Debug for InquiryData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InquiryData")
+            .field("qualifier", &self.qualifier)
+            .field("device_type", &self.device_type)
+            .field("removable", &self.removable)
+            .field("version", &self.version)
+            .field("response_format", &self.response_format)
+            .field("vendor", &self.vendor())
+            .field("product", &self.product())
+            .field("revision", &self.revision())
+            .finish()
+    }
+}
+
+impl fmt::Display for InquiryData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {}",
+            self.vendor(),
+            self.product(),
+            self.revision()
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockLimits {
+    // Block lengths must be a multiple of 2^granularity
+    pub granularity: u8,
+    pub max_len: u32,
+    pub min_len: u16,
+}
+
+impl BlockLimits {
+    pub const LEN: usize = 6;
+
+    pub fn parse(data: &[u8]) -> Result<Self, ParseError> {
+        need(data, Self::LEN)?;
+
+        Ok(Self {
+            granularity: data[0] & 0x1F,
+            max_len: be24(&data[1..4]),
+            min_len: be16(&data[4..6]),
+        })
+    }
+
+    // Fixed block mode only, drives report this by making min == max
+    pub fn is_fixed(&self) -> bool {
+        self.max_len != 0 && self.max_len == self.min_len as u32
+    }
+}
+
+// Short form READ POSITION data
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Position {
+    pub beginning_of_partition: bool,
+    pub end_of_partition: bool,
+    pub partition: u8,
+    // `None` if the drive doesn't know where it is
+    pub first_block: Option<u32>,
+    pub last_block: Option<u32>,
+    pub blocks_in_buffer: Option<u32>,
+    pub bytes_in_buffer: Option<u32>,
+    pub position_error: bool,
+}
+
+impl Position {
+    pub const LEN: usize = 20;
+
+    const BOP: u8 = 1 << 7;
+    const EOP: u8 = 1 << 6;
+    const LOCU: u8 = 1 << 5;
+    const BYCU: u8 = 1 << 4;
+    const LOLU: u8 = 1 << 2;
+    const PERR: u8 = 1 << 1;
+
+    pub fn parse(data: &[u8]) -> Result<Self, ParseError> {
+        need(data, Self::LEN)?;
+
+        let flags = data[0];
+        let location_unknown = (flags & Self::LOLU) != 0;
+        let block_count_unknown = (flags & Self::LOCU) != 0;
+        let byte_count_unknown = (flags & Self::BYCU) != 0;
+
+        Ok(Self {
+            beginning_of_partition: (flags & Self::BOP) != 0,
+            end_of_partition: (flags & Self::EOP) != 0,
+            partition: data[1],
+            first_block: (!location_unknown).then(|| be32(&data[4..8])),
+            last_block: (!location_unknown).then(|| be32(&data[8..12])),
+            blocks_in_buffer: (!block_count_unknown).then(|| be24(&data[13..16])),
+            bytes_in_buffer: (!byte_count_unknown).then(|| be32(&data[16..20])),
+            position_error: (flags & Self::PERR) != 0,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct BlockDescriptor {
+    pub density: u8,
+    pub blocks: u32,
+    // Zero means variable block mode
+    pub block_len: u32,
+}
+
+impl BlockDescriptor {
+    pub const LEN: usize = 8;
+
+    pub fn parse(data: &[u8]) -> Result<Self, ParseError> {
+        need(data, Self::LEN)?;
+
+        Ok(Self {
+            density: data[0],
+            blocks: be24(&data[1..4]),
+            block_len: be24(&data[5..8]),
+        })
+    }
+
+    pub fn write(&self, data: &mut [u8]) {
+        data[0] = self.density;
+        put_be24(&mut data[1..4], self.blocks);
+        data[4] = 0;
+        put_be24(&mut data[5..8], self.block_len);
+    }
+}
+
+// The MODE SENSE(6)/MODE SELECT(6) parameter list
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct ModeParameters {
+    pub medium_type: u8,
+    pub write_protected: bool,
+    pub buffered_mode: u8,
+    pub speed: u8,
+    pub block_descriptor: Option<BlockDescriptor>,
+    // Any mode pages, raw
+    pub pages: Vec<u8>,
+}
+
+impl ModeParameters {
+    pub const HEADER_LEN: usize = 4;
+
+    pub fn parse(data: &[u8]) -> Result<Self, ParseError> {
+        need(data, Self::HEADER_LEN)?;
+
+        // The mode data length doesn't count itself
+        let total_len = (data[0] as usize + 1).min(data.len());
+        let desc_len = data[3] as usize;
+        let device_specific = data[2];
+
+        need(data, Self::HEADER_LEN + desc_len)?;
+
+        let block_descriptor = match desc_len {
+            0 => None,
+            len if len >= BlockDescriptor::LEN => Some(BlockDescriptor::parse(
+                &data[Self::HEADER_LEN..Self::HEADER_LEN + BlockDescriptor::LEN],
+            )?),
+            _ => return Err(ParseError::Invalid("short block descriptor")),
+        };
+
+        let pages_start = (Self::HEADER_LEN + desc_len).min(total_len);
+
+        Ok(Self {
+            medium_type: data[1],
+            write_protected: (device_specific & 0x80) != 0,
+            buffered_mode: (device_specific >> 4) & 0x07,
+            speed: device_specific & 0x0F,
+            block_descriptor,
+            pages: data[pages_start..total_len].to_vec(),
+        })
+    }
+
+    // Serialize for MODE SELECT(6)
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let desc_len = if self.block_descriptor.is_some() {
+            BlockDescriptor::LEN
+        } else {
+            0
+        };
+
+        let mut data = vec![0; Self::HEADER_LEN + desc_len + self.pages.len()];
+        // Mode data length is reserved for MODE SELECT, as is the WP bit
+        data[1] = self.medium_type;
+        data[2] = ((self.buffered_mode & 0x07) << 4) | (self.speed & 0x0F);
+        data[3] = desc_len as u8;
+
+        if let Some(desc) = self.block_descriptor {
+            desc.write(&mut data[Self::HEADER_LEN..Self::HEADER_LEN + BlockDescriptor::LEN]);
+        }
+
+        data[Self::HEADER_LEN + desc_len..].copy_from_slice(&self.pages);
+
+        data
+    }
+
+    pub fn density(&self) -> Option<u8> {
+        self.block_descriptor.map(|desc| desc.density)
+    }
+
+    pub fn block_len(&self) -> Option<u32> {
+        self.block_descriptor.map(|desc| desc.block_len)
+    }
+}
+
+// A density descriptor from REPORT DENSITY SUPPORT
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct DensityDescriptor {
+    pub primary_code: u8,
+    pub secondary_code: u8,
+    // The drive can write this density, not just read it
+    pub writable: bool,
+    // This is the density the drive uses if it isn't told otherwise
+    pub default: bool,
+    pub bits_per_mm: u32,
+    // In tenths of a millimetre
+    pub media_width: u16,
+    pub tracks: u16,
+    // In megabytes
+    pub capacity: u32,
+    organization: [u8; 8],
+    name: [u8; 8],
+    description: [u8; 20],
+}
+
+impl DensityDescriptor {
+    pub const LEN: usize = 52;
+
+    const WRTOK: u8 = 1 << 7;
+    const DEFLT: u8 = 1 << 5;
+
+    pub fn parse(data: &[u8]) -> Result<Self, ParseError> {
+        need(data, Self::LEN)?;
+
+        Ok(Self {
+            primary_code: data[0],
+            secondary_code: data[1],
+            writable: (data[2] & Self::WRTOK) != 0,
+            default: (data[2] & Self::DEFLT) != 0,
+            bits_per_mm: be24(&data[5..8]),
+            media_width: be16(&data[8..10]),
+            tracks: be16(&data[10..12]),
+            capacity: be32(&data[12..16]),
+            organization: data[16..24].try_into().unwrap(),
+            name: data[24..32].try_into().unwrap(),
+            description: data[32..52].try_into().unwrap(),
+        })
+    }
+
+    pub fn organization(&self) -> &str {
+        ascii_field(&self.organization)
+    }
+
+    pub fn name(&self) -> &str {
+        ascii_field(&self.name)
+    }
+
+    pub fn description(&self) -> &str {
+        ascii_field(&self.description)
+    }
+}
+
+impl fmt::Debug for DensityDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DensityDescriptor")
+            .field("primary_code", &self.primary_code)
+            .field("secondary_code", &self.secondary_code)
+            .field("writable", &self.writable)
+            .field("default", &self.default)
+            .field("bits_per_mm", &self.bits_per_mm)
+            .field("media_width", &self.media_width)
+            .field("tracks", &self.tracks)
+            .field("capacity", &self.capacity)
+            .field("organization", &self.organization())
+            .field("name", &self.name())
+            .field("description", &self.description())
+            .finish()
+    }
+}
+
+// The REPORT DENSITY SUPPORT parameter list
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct DensitySupport {
+    pub descriptors: Vec<DensityDescriptor>,
+}
+
+impl DensitySupport {
+    pub const HEADER_LEN: usize = 4;
+
+    pub fn parse(data: &[u8]) -> Result<Self, ParseError> {
+        need(data, Self::HEADER_LEN)?;
+
+        // The available length doesn't count itself, and the drive may have had more to say
+        // than we gave it room for
+        let total_len = (be16(&data[0..2]) as usize + 2).min(data.len());
+
+        let descriptors = data[Self::HEADER_LEN..total_len.max(Self::HEADER_LEN)]
+            .as_chunks::<{ DensityDescriptor::LEN }>()
+            .0
+            .iter()
+            .map(|desc| DensityDescriptor::parse(desc))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self { descriptors })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // What an HP LTO-5 drive sends back for a standard INQUIRY
+    const INQUIRY: [u8; 36] = [
+        0x01, 0x80, 0x06, 0x02, 0x5B, 0x00, 0x00, 0x02, // type, RMB, version, format, len
+        b'H', b'P', b' ', b' ', b' ', b' ', b' ', b' ', // vendor
+        b'U', b'l', b't', b'r', b'i', b'u', b'm', b' ', // product
+        b'5', b'-', b'S', b'C', b'S', b'I', b' ', b' ', //
+        b'Z', b'6', b'I', b'D', // revision
+    ];
+
+    #[test]
+    fn inquiry() {
+        let inquiry = InquiryData::parse(&INQUIRY).unwrap();
+
+        assert!(inquiry.is_tape());
+        assert!(inquiry.removable);
+        assert_eq!(inquiry.version, 0x06);
+        assert_eq!(inquiry.response_format, 0x02);
+        assert_eq!(inquiry.vendor(), "HP");
+        assert_eq!(inquiry.product(), "Ultrium 5-SCSI");
+        assert_eq!(inquiry.revision(), "Z6ID");
+        assert_eq!(inquiry.to_string(), "HP Ultrium 5-SCSI Z6ID");
+    }
+
+    #[test]
+    fn inquiry_not_a_tape() {
+        // A disk, and a tape drive that isn't connected
+        let mut disk = INQUIRY;
+        disk[0] = 0x00;
+        assert!(!InquiryData::parse(&disk).unwrap().is_tape());
+
+        let mut missing = INQUIRY;
+        missing[0] = 0x21;
+        assert!(!InquiryData::parse(&missing).unwrap().is_tape());
+    }
+
+    #[test]
+    fn inquiry_short() {
+        assert_eq!(
+            InquiryData::parse(&INQUIRY[..20]),
+            Err(ParseError::TooShort {
+                expected: 36,
+                actual: 20
+            })
+        );
+    }
+
+    #[test]
+    fn block_limits() {
+        // Variable, 1 byte to 16MiB - 1
+        let limits = BlockLimits::parse(&[0x00, 0xFF, 0xFF, 0xFF, 0x00, 0x01]).unwrap();
+        assert_eq!(
+            limits,
+            BlockLimits {
+                granularity: 0,
+                max_len: 0x00FF_FFFF,
+                min_len: 1
+            }
+        );
+        assert!(!limits.is_fixed());
+
+        // Fixed, 512 byte blocks only
+        let limits = BlockLimits::parse(&[0x00, 0x00, 0x02, 0x00, 0x02, 0x00]).unwrap();
+        assert_eq!(limits.max_len, 512);
+        assert!(limits.is_fixed());
+
+        // Granularity is only the low 5 bits
+        let limits = BlockLimits::parse(&[0xE2, 0x00, 0x10, 0x00, 0x00, 0x04]).unwrap();
+        assert_eq!(limits.granularity, 2);
+
+        assert!(BlockLimits::parse(&[0x00; 5]).is_err());
+    }
+
+    #[test]
+    fn read_position() {
+        let mut data = [0u8; 20];
+        data[0] = 0x80; // BOP
+        data[4..8].copy_from_slice(&0x0000_1234u32.to_be_bytes());
+        data[8..12].copy_from_slice(&0x0000_1230u32.to_be_bytes());
+        data[13..16].copy_from_slice(&[0x00, 0x00, 0x04]);
+        data[16..20].copy_from_slice(&0x0000_8000u32.to_be_bytes());
+
+        let position = Position::parse(&data).unwrap();
+        assert!(position.beginning_of_partition);
+        assert!(!position.end_of_partition);
+        assert_eq!(position.partition, 0);
+        assert_eq!(position.first_block, Some(0x1234));
+        assert_eq!(position.last_block, Some(0x1230));
+        assert_eq!(position.blocks_in_buffer, Some(4));
+        assert_eq!(position.bytes_in_buffer, Some(0x8000));
+        assert!(!position.position_error);
+    }
+
+    #[test]
+    fn read_position_unknown() {
+        let mut data = [0xFFu8; 20];
+        // EOP, LOCU, BYCU, LOLU, and PERR
+        data[0] = 0x40 | 0x20 | 0x10 | 0x04 | 0x02;
+        data[1] = 1;
+
+        let position = Position::parse(&data).unwrap();
+        assert!(!position.beginning_of_partition);
+        assert!(position.end_of_partition);
+        assert_eq!(position.partition, 1);
+        assert_eq!(position.first_block, None);
+        assert_eq!(position.last_block, None);
+        assert_eq!(position.blocks_in_buffer, None);
+        assert_eq!(position.bytes_in_buffer, None);
+        assert!(position.position_error);
+
+        assert!(Position::parse(&data[..19]).is_err());
+    }
+
+    #[test]
+    fn mode_sense() {
+        let data = [
+            0x1B, 0x00, 0x90, 0x08, // header, write protected, buffered
+            0x44, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // LTO-3, variable
+            0x10, 0x0E, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // device configuration page
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+
+        let params = ModeParameters::parse(&data).unwrap();
+        assert!(params.write_protected);
+        assert_eq!(params.buffered_mode, 1);
+        assert_eq!(params.speed, 0);
+        assert_eq!(params.density(), Some(0x44));
+        assert_eq!(params.block_len(), Some(0));
+        assert_eq!(params.pages.len(), 16);
+        assert_eq!(params.pages[0] & 0x3F, 0x10);
+    }
+
+    #[test]
+    fn mode_sense_fixed_blocks() {
+        let data = [
+            0x0B, 0x00, 0x10, 0x08, // header, buffered
+            0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x00, // 6250 BPI, 1024 byte blocks
+        ];
+
+        let params = ModeParameters::parse(&data).unwrap();
+        assert!(!params.write_protected);
+        assert_eq!(params.density(), Some(0x03));
+        assert_eq!(params.block_len(), Some(1024));
+        assert!(params.pages.is_empty());
+    }
+
+    #[test]
+    fn mode_sense_no_descriptor() {
+        let params = ModeParameters::parse(&[0x03, 0x00, 0x00, 0x00]).unwrap();
+        assert_eq!(params.block_descriptor, None);
+        assert_eq!(params.density(), None);
+
+        // A block descriptor length that isn't enough for one, or runs off the end
+        assert_eq!(
+            ModeParameters::parse(&[0x07, 0x00, 0x00, 0x04, 0, 0, 0, 0]),
+            Err(ParseError::Invalid("short block descriptor"))
+        );
+        assert!(ModeParameters::parse(&[0x0B, 0x00, 0x00, 0x08, 0, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn mode_select_round_trip() {
+        let params = ModeParameters {
+            medium_type: 0,
+            write_protected: false,
+            buffered_mode: 1,
+            speed: 0,
+            block_descriptor: Some(BlockDescriptor {
+                density: 0x02,
+                blocks: 0,
+                block_len: 0x0200,
+            }),
+            pages: Vec::new(),
+        };
+
+        let data = params.to_bytes();
+        assert_eq!(
+            data,
+            [
+                0x00, 0x00, 0x10, 0x08, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00
+            ]
+        );
+        assert_eq!(ModeParameters::parse(&data).unwrap(), params);
+    }
+}
@@ -0,0 +1,72 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// Density codes.
+//
+// The code in the MODE SENSE block descriptor is how the drive tells us how the reel was
+// recorded. Most of them are for one particular cartridge format, the ones we really care
+// about are the 9-track ones, as a drive that does 6250 GCR may well not do 800 NRZI.
+
+use core::fmt;
+
+// Let the drive pick, or the drive doesn't know
+pub const DEFAULT: u8 = 0x00;
+pub const NRZI_800: u8 = 0x01;
+pub const PE_1600: u8 = 0x02;
+pub const GCR_6250: u8 = 0x03;
+pub const PE_3200: u8 = 0x06;
+
+const KNOWN: &[(u8, &str)] = &[
+    (DEFAULT, "default"),
+    (NRZI_800, "800 bpi NRZI, 9 track"),
+    (PE_1600, "1600 bpi PE, 9 track"),
+    (GCR_6250, "6250 bpi GCR, 9 track"),
+    (0x05, "QIC-24"),
+    (PE_3200, "3200 bpi PE, 9 track"),
+    (0x09, "37871 bpi GCR, 18 track, 3480"),
+    (0x0F, "QIC-120"),
+    (0x10, "QIC-150"),
+    (0x11, "QIC-320"),
+    (0x13, "DDS"),
+    (0x14, "EXB-8200"),
+    (0x15, "EXB-8500"),
+    (0x28, "36 track, 3490E"),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Density(pub u8);
+
+impl Density {
+    pub fn description(&self) -> Option<&'static str> {
+        KNOWN
+            .iter()
+            .find(|(code, _)| *code == self.0)
+            .map(|(_, desc)| *desc)
+    }
+
+    // Parse a density someone gave us, either as a density code, or for 9-track reels the
+    // recording density in bpi or the recording method
+    pub fn parse(density: &str) -> Option<Self> {
+        let density = density.trim().to_ascii_lowercase();
+
+        let code = match density.as_str() {
+            "800" | "nrzi" => NRZI_800,
+            "1600" | "pe" => PE_1600,
+            "6250" | "gcr" => GCR_6250,
+            "3200" => PE_3200,
+            code => match code.strip_prefix("0x") {
+                Some(hex) => u8::from_str_radix(hex, 16).ok()?,
+                None => code.parse().ok()?,
+            },
+        };
+
+        Some(Self(code))
+    }
+}
+
+impl fmt::Display for Density {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.description() {
+            Some(desc) => write!(f, "{:#04x} ({desc})", self.0),
+            None => write!(f, "{:#04x}", self.0),
+        }
+    }
+}
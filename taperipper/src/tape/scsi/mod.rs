@@ -0,0 +1,217 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// This module implements the bits of the SCSI Stream Commands (SSC) command set we need
+// to get a kernel off of a sequential-access device.
+//
+// Everything in here is plain old bytes in and out, it doesn't know or care how the
+// commands actually make it to the drive, that is up to the transports.
+//
+// see: https://www.t10.org/drafts.htm#SSC_Family
+// and: https://www.t10.org/drafts.htm#SPC_Family
+
+use core::{fmt, time::Duration};
+
+pub mod commands;
+pub mod data;
+pub mod density;
+pub mod sense;
+
+pub mod opcode {
+    pub const TEST_UNIT_READY: u8 = 0x00;
+    pub const REWIND: u8 = 0x01;
+    pub const REQUEST_SENSE: u8 = 0x03;
+    pub const READ_BLOCK_LIMITS: u8 = 0x05;
+    pub const READ_6: u8 = 0x08;
+    pub const SPACE_6: u8 = 0x11;
+    pub const INQUIRY: u8 = 0x12;
+    pub const MODE_SELECT_6: u8 = 0x15;
+    pub const MODE_SENSE_6: u8 = 0x1A;
+    pub const LOAD_UNLOAD: u8 = 0x1B;
+    pub const READ_POSITION: u8 = 0x34;
+    pub const REPORT_DENSITY_SUPPORT: u8 = 0x44;
+}
+
+// Peripheral device type for sequential-access devices (tape drives)
+pub const DEVICE_TYPE_SEQUENTIAL: u8 = 0x01;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Cdb {
+    bytes: [u8; Cdb::MAX_LEN],
+    len: usize,
+}
+
+impl Cdb {
+    pub const MAX_LEN: usize = 16;
+
+    pub const fn new(len: usize) -> Self {
+        assert!(len <= Self::MAX_LEN, "CDB is too long");
+        Self {
+            bytes: [0; Self::MAX_LEN],
+            len,
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let mut cdb = Self::new(bytes.len());
+        cdb.bytes[..bytes.len()].copy_from_slice(bytes);
+        cdb
+    }
+
+    pub fn opcode(&self) -> u8 {
+        self.bytes[0]
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.bytes[..self.len]
+    }
+}
+
+impl fmt::Debug for Cdb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cdb [")?;
+        for (idx, byte) in self.as_bytes().iter().enumerate() {
+            if idx != 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{byte:02x}")?;
+        }
+        write!(f, "]")
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DataDirection {
+    None,
+    In,
+    Out,
+}
+
+// A CDB along with everything a transport needs to know to send it
+#[derive(Clone, Copy, Debug)]
+pub struct Command {
+    pub cdb: Cdb,
+    pub direction: DataDirection,
+    // The number of bytes we expect to move in `direction`
+    pub transfer_len: usize,
+    pub timeout: Duration,
+}
+
+impl Command {
+    pub fn name(&self) -> &'static str {
+        match self.cdb.opcode() {
+            opcode::TEST_UNIT_READY => "TEST UNIT READY",
+            opcode::REWIND => "REWIND",
+            opcode::REQUEST_SENSE => "REQUEST SENSE",
+            opcode::READ_BLOCK_LIMITS => "READ BLOCK LIMITS",
+            opcode::READ_6 => "READ(6)",
+            opcode::SPACE_6 => "SPACE(6)",
+            opcode::INQUIRY => "INQUIRY",
+            opcode::MODE_SELECT_6 => "MODE SELECT(6)",
+            opcode::MODE_SENSE_6 => "MODE SENSE(6)",
+            opcode::LOAD_UNLOAD => "LOAD/UNLOAD",
+            opcode::READ_POSITION => "READ POSITION",
+            opcode::REPORT_DENSITY_SUPPORT => "REPORT DENSITY SUPPORT",
+            _ => "<UNKNOWN>",
+        }
+    }
+}
+
+// SCSI status byte
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScsiStatus {
+    Good,
+    CheckCondition,
+    ConditionMet,
+    Busy,
+    ReservationConflict,
+    TaskSetFull,
+    AcaActive,
+    TaskAborted,
+    Other(u8),
+}
+
+impl From<u8> for ScsiStatus {
+    fn from(status: u8) -> Self {
+        match status {
+            0x00 => ScsiStatus::Good,
+            0x02 => ScsiStatus::CheckCondition,
+            0x04 => ScsiStatus::ConditionMet,
+            0x08 => ScsiStatus::Busy,
+            0x18 => ScsiStatus::ReservationConflict,
+            0x28 => ScsiStatus::TaskSetFull,
+            0x30 => ScsiStatus::AcaActive,
+            0x40 => ScsiStatus::TaskAborted,
+            other => ScsiStatus::Other(other),
+        }
+    }
+}
+
+impl From<ScsiStatus> for u8 {
+    fn from(status: ScsiStatus) -> Self {
+        match status {
+            ScsiStatus::Good => 0x00,
+            ScsiStatus::CheckCondition => 0x02,
+            ScsiStatus::ConditionMet => 0x04,
+            ScsiStatus::Busy => 0x08,
+            ScsiStatus::ReservationConflict => 0x18,
+            ScsiStatus::TaskSetFull => 0x28,
+            ScsiStatus::AcaActive => 0x30,
+            ScsiStatus::TaskAborted => 0x40,
+            ScsiStatus::Other(other) => other,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    // The response was shorter than the fixed part of the structure
+    TooShort { expected: usize, actual: usize },
+    // The response had something in it we don't understand
+    Invalid(&'static str),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::TooShort { expected, actual } => {
+                write!(f, "response too short ({actual} < {expected} bytes)")
+            }
+            ParseError::Invalid(what) => write!(f, "invalid response: {what}"),
+        }
+    }
+}
+
+#[inline]
+pub(crate) fn need(data: &[u8], len: usize) -> Result<(), ParseError> {
+    if data.len() < len {
+        Err(ParseError::TooShort {
+            expected: len,
+            actual: data.len(),
+        })
+    } else {
+        Ok(())
+    }
+}
+
+#[inline]
+pub(crate) fn be16(data: &[u8]) -> u16 {
+    u16::from_be_bytes([data[0], data[1]])
+}
+
+#[inline]
+pub(crate) fn be24(data: &[u8]) -> u32 {
+    u32::from_be_bytes([0, data[0], data[1], data[2]])
+}
+
+#[inline]
+pub(crate) fn be32(data: &[u8]) -> u32 {
+    u32::from_be_bytes([data[0], data[1], data[2], data[3]])
+}
+
+#[inline]
+pub(crate) fn put_be24(data: &mut [u8], value: u32) {
+    data.copy_from_slice(&value.to_be_bytes()[1..]);
+}
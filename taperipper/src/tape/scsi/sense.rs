@@ -0,0 +1,593 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// Sense data decoding.
+//
+// Tape drives do most of their talking through CHECK CONDITION, hitting a filemark, running off
+// the end of the data, or reading a block that is shorter than asked for are all reported as
+// sense data. So we split it into the things a read loop is expected to deal with (`TapeCondition`)
+// and the things that actually mean something went wrong (`TapeError`).
+
+use core::fmt;
+
+use tracing::{debug, warn};
+
+use crate::{
+    display,
+    tape::scsi::{ParseError, be32, need},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SenseKey {
+    NoSense,
+    RecoveredError,
+    NotReady,
+    MediumError,
+    HardwareError,
+    IllegalRequest,
+    UnitAttention,
+    DataProtect,
+    BlankCheck,
+    VendorSpecific,
+    CopyAborted,
+    AbortedCommand,
+    VolumeOverflow,
+    Miscompare,
+    Completed,
+    Reserved,
+}
+
+impl From<u8> for SenseKey {
+    fn from(key: u8) -> Self {
+        match key & 0x0F {
+            0x0 => SenseKey::NoSense,
+            0x1 => SenseKey::RecoveredError,
+            0x2 => SenseKey::NotReady,
+            0x3 => SenseKey::MediumError,
+            0x4 => SenseKey::HardwareError,
+            0x5 => SenseKey::IllegalRequest,
+            0x6 => SenseKey::UnitAttention,
+            0x7 => SenseKey::DataProtect,
+            0x8 => SenseKey::BlankCheck,
+            0x9 => SenseKey::VendorSpecific,
+            0xA => SenseKey::CopyAborted,
+            0xB => SenseKey::AbortedCommand,
+            0xD => SenseKey::VolumeOverflow,
+            0xE => SenseKey::Miscompare,
+            0xF => SenseKey::Completed,
+            _ => SenseKey::Reserved,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SenseData {
+    // This is about an earlier command that the drive already said was fine
+    pub deferred: bool,
+    pub key: SenseKey,
+    pub asc: u8,
+    pub ascq: u8,
+    pub filemark: bool,
+    pub eom: bool,
+    pub ili: bool,
+    // For reads and spaces this is the residue, requested minus actual
+    pub information: Option<i64>,
+}
+
+impl SenseData {
+    const FIXED_LEN: usize = 14;
+    const DESCRIPTOR_LEN: usize = 8;
+
+    const DESC_INFORMATION: u8 = 0x00;
+    const DESC_STREAM_COMMANDS: u8 = 0x04;
+
+    pub fn parse(data: &[u8]) -> Result<Self, ParseError> {
+        need(data, 1)?;
+
+        match data[0] & 0x7F {
+            0x70 | 0x71 => Self::parse_fixed(data),
+            0x72 | 0x73 => Self::parse_descriptor(data),
+            _ => Err(ParseError::Invalid("unknown sense response code")),
+        }
+    }
+
+    fn parse_fixed(data: &[u8]) -> Result<Self, ParseError> {
+        need(data, Self::FIXED_LEN)?;
+
+        let code = data[0] & 0x7F;
+        let valid = (data[0] & 0x80) != 0;
+
+        Ok(Self {
+            deferred: code == 0x71,
+            key: data[2].into(),
+            asc: data[12],
+            ascq: data[13],
+            filemark: (data[2] & 0x80) != 0,
+            eom: (data[2] & 0x40) != 0,
+            ili: (data[2] & 0x20) != 0,
+            // The information field is signed for tape, as the residue can be negative
+            information: valid.then(|| be32(&data[3..7]) as i32 as i64),
+        })
+    }
+
+    fn parse_descriptor(data: &[u8]) -> Result<Self, ParseError> {
+        need(data, Self::DESCRIPTOR_LEN)?;
+
+        let code = data[0] & 0x7F;
+        let mut sense = Self {
+            deferred: code == 0x73,
+            key: data[1].into(),
+            asc: data[2],
+            ascq: data[3],
+            filemark: false,
+            eom: false,
+            ili: false,
+            information: None,
+        };
+
+        let end = (Self::DESCRIPTOR_LEN + data[7] as usize).min(data.len());
+        let mut descriptors = &data[Self::DESCRIPTOR_LEN..end];
+
+        while descriptors.len() >= 2 {
+            let desc_len = (descriptors[1] as usize + 2).min(descriptors.len());
+            let desc = &descriptors[..desc_len];
+
+            match desc[0] {
+                // Only if the VALID bit is set
+                Self::DESC_INFORMATION if desc.len() >= 12 && (desc[2] & 0x80) != 0 => {
+                    sense.information = Some(i64::from_be_bytes(desc[4..12].try_into().unwrap()));
+                }
+                Self::DESC_STREAM_COMMANDS if desc.len() >= 4 => {
+                    sense.filemark = (desc[3] & 0x80) != 0;
+                    sense.eom = (desc[3] & 0x40) != 0;
+                    sense.ili = (desc[3] & 0x20) != 0;
+                }
+                _ => {}
+            }
+
+            descriptors = &descriptors[desc_len..];
+        }
+
+        Ok(sense)
+    }
+
+    // Make up sense data for things that aren't a real drive but want to act like one
+    pub fn synthetic(key: SenseKey, asc: u8, ascq: u8) -> Self {
+        Self {
+            deferred: false,
+            key,
+            asc,
+            ascq,
+            filemark: false,
+            eom: false,
+            ili: false,
+            information: None,
+        }
+    }
+
+    pub fn residue(&self) -> i64 {
+        self.information.unwrap_or(0)
+    }
+
+    pub fn description(&self) -> &'static str {
+        describe_asc(self.asc, self.ascq)
+    }
+
+    // Sort the sense data into either something the read loop should handle, or an error
+    pub fn classify(&self) -> Result<Option<TapeCondition>, TapeError> {
+        let residue = self.residue();
+
+        match self.key {
+            SenseKey::NoSense | SenseKey::RecoveredError | SenseKey::Completed => {
+                if self.filemark {
+                    Ok(Some(TapeCondition::Filemark { residue }))
+                } else if self.ili {
+                    Ok(Some(TapeCondition::IncorrectLength { residue }))
+                } else if self.eom {
+                    if self.asc == 0x00 && self.ascq == 0x04 {
+                        Ok(Some(TapeCondition::BeginningOfMedium))
+                    } else {
+                        Ok(Some(TapeCondition::EndOfMedium { residue }))
+                    }
+                } else if self.key == SenseKey::RecoveredError {
+                    Ok(Some(TapeCondition::Recovered))
+                } else {
+                    Ok(None)
+                }
+            }
+            SenseKey::BlankCheck => Ok(Some(TapeCondition::EndOfData)),
+            SenseKey::MediumError if self.eom => Err(TapeError::EndOfMedium),
+            SenseKey::NotReady => Err(TapeError::NotReady {
+                becoming_ready: self.asc == 0x04 && self.ascq == 0x01,
+                no_medium: self.asc == 0x3A,
+            }),
+            SenseKey::UnitAttention => Err(TapeError::UnitAttention {
+                medium_changed: self.asc == 0x28,
+            }),
+            SenseKey::MediumError => Err(TapeError::Medium(*self)),
+            SenseKey::HardwareError => Err(TapeError::Hardware(*self)),
+            SenseKey::IllegalRequest => Err(TapeError::IllegalRequest(*self)),
+            SenseKey::DataProtect => Err(TapeError::DataProtect),
+            SenseKey::VolumeOverflow => Err(TapeError::EndOfMedium),
+            SenseKey::AbortedCommand => Err(TapeError::Aborted),
+            _ => Err(TapeError::Sense(*self)),
+        }
+    }
+
+    // Emit the sense data as a tracing event, at a level fitting to how bad it is
+    pub fn trace(&self, command: &'static str) {
+        match self.classify() {
+            Ok(condition) => debug!(
+                command,
+                key = ?self.key,
+                asc = display::fmt::hex(self.asc),
+                ascq = display::fmt::hex(self.ascq),
+                residue = self.residue(),
+                deferred = self.deferred,
+                condition = ?condition,
+                "{}",
+                self.description()
+            ),
+            Err(err) => warn!(
+                command,
+                key = ?self.key,
+                asc = display::fmt::hex(self.asc),
+                ascq = display::fmt::hex(self.ascq),
+                residue = self.residue(),
+                deferred = self.deferred,
+                "{}: {}",
+                err,
+                self.description()
+            ),
+        }
+    }
+}
+
+impl fmt::Display for SenseData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} [{:02x}/{:02x}] {}",
+            self.key,
+            self.asc,
+            self.ascq,
+            self.description()
+        )?;
+
+        if self.filemark {
+            write!(f, " FILEMARK")?;
+        }
+        if self.eom {
+            write!(f, " EOM")?;
+        }
+        if self.ili {
+            write!(f, " ILI")?;
+        }
+        if let Some(info) = self.information {
+            write!(f, " (info {info})")?;
+        }
+        if self.deferred {
+            write!(f, " DEFERRED")?;
+        }
+
+        Ok(())
+    }
+}
+
+// Things that come back as CHECK CONDITION but aren't really errors when reading
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TapeCondition {
+    // We hit a filemark, this is the end of the current tape file
+    Filemark { residue: i64 },
+    // Early warning that we're near the end of the reel
+    EndOfMedium { residue: i64 },
+    // Spacing backwards ran into BOT
+    BeginningOfMedium,
+    // The block was a different length than asked for, positive means it was shorter
+    IncorrectLength { residue: i64 },
+    // Blank tape, there is nothing more recorded past here
+    EndOfData,
+    // The drive had to work for it, but the data is good
+    Recovered,
+}
+
+impl fmt::Display for TapeCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TapeCondition::Filemark { .. } => write!(f, "filemark"),
+            TapeCondition::EndOfMedium { .. } => write!(f, "end of medium"),
+            TapeCondition::BeginningOfMedium => write!(f, "beginning of medium"),
+            TapeCondition::IncorrectLength { residue } => {
+                write!(f, "incorrect length (residue {residue})")
+            }
+            TapeCondition::EndOfData => write!(f, "end of data"),
+            TapeCondition::Recovered => write!(f, "recovered error"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TapeError {
+    NotReady {
+        becoming_ready: bool,
+        no_medium: bool,
+    },
+    // The drive was reset or the reel was changed under us
+    UnitAttention {
+        medium_changed: bool,
+    },
+    // We ran off the physical end of the reel
+    EndOfMedium,
+    Medium(SenseData),
+    Hardware(SenseData),
+    IllegalRequest(SenseData),
+    DataProtect,
+    Aborted,
+    // Sense data we don't have a better idea about
+    Sense(SenseData),
+    // The drive gave us sense data we couldn't make heads or tails of
+    InvalidSense(ParseError),
+}
+
+impl TapeError {
+    // Is it worth trying the command again?
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            TapeError::NotReady {
+                becoming_ready: true,
+                ..
+            } | TapeError::UnitAttention { .. }
+                | TapeError::Aborted
+        )
+    }
+}
+
+impl fmt::Display for TapeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TapeError::NotReady {
+                becoming_ready: true,
+                ..
+            } => write!(f, "drive is becoming ready"),
+            TapeError::NotReady {
+                no_medium: true, ..
+            } => write!(f, "no reel loaded"),
+            TapeError::NotReady { .. } => write!(f, "drive not ready"),
+            TapeError::UnitAttention {
+                medium_changed: true,
+            } => write!(f, "reel changed"),
+            TapeError::UnitAttention { .. } => write!(f, "unit attention"),
+            TapeError::EndOfMedium => write!(f, "physical end of medium"),
+            TapeError::Medium(sense) => write!(f, "medium error: {}", sense.description()),
+            TapeError::Hardware(sense) => write!(f, "hardware error: {}", sense.description()),
+            TapeError::IllegalRequest(sense) => {
+                write!(f, "illegal request: {}", sense.description())
+            }
+            TapeError::DataProtect => write!(f, "data protect"),
+            TapeError::Aborted => write!(f, "command aborted"),
+            TapeError::Sense(sense) => write!(f, "{sense}"),
+            TapeError::InvalidSense(err) => write!(f, "invalid sense data: {err}"),
+        }
+    }
+}
+
+impl From<ParseError> for TapeError {
+    fn from(err: ParseError) -> Self {
+        TapeError::InvalidSense(err)
+    }
+}
+
+// NOTE(aki): This is far from all of them, just the ones a tape drive is likely to throw at us
+pub fn describe_asc(asc: u8, ascq: u8) -> &'static str {
+    match (asc, ascq) {
+        (0x00, 0x00) => "No additional sense information",
+        (0x00, 0x01) => "Filemark detected",
+        (0x00, 0x02) => "End-of-partition/medium detected",
+        (0x00, 0x03) => "Setmark detected",
+        (0x00, 0x04) => "Beginning-of-partition/medium detected",
+        (0x00, 0x05) => "End-of-data detected",
+        (0x03, 0x02) => "Excessive write errors",
+        (0x04, 0x00) => "Logical unit not ready, cause not reportable",
+        (0x04, 0x01) => "Logical unit is in process of becoming ready",
+        (0x04, 0x02) => "Logical unit not ready, initializing command required",
+        (0x04, 0x03) => "Logical unit not ready, manual intervention required",
+        (0x0C, 0x00) => "Write error",
+        (0x11, 0x00) => "Unrecovered read error",
+        (0x11, 0x01) => "Read retries exhausted",
+        (0x11, 0x08) => "Incomplete block read",
+        (0x14, 0x00) => "Recorded entity not found",
+        (0x14, 0x01) => "Record not found",
+        (0x14, 0x02) => "Filemark or setmark not found",
+        (0x14, 0x03) => "End-of-data not found",
+        (0x14, 0x04) => "Block sequence error",
+        (0x15, 0x00) => "Random positioning error",
+        (0x15, 0x02) => "Positioning error detected by read of medium",
+        (0x1A, 0x00) => "Parameter list length error",
+        (0x20, 0x00) => "Invalid command operation code",
+        (0x24, 0x00) => "Invalid field in CDB",
+        (0x25, 0x00) => "Logical unit not supported",
+        (0x26, 0x00) => "Invalid field in parameter list",
+        (0x27, 0x00) => "Write protected",
+        (0x28, 0x00) => "Not ready to ready change, medium may have changed",
+        (0x29, 0x00) => "Power on, reset, or bus device reset occurred",
+        (0x2A, 0x01) => "Mode parameters changed",
+        (0x30, 0x00) => "Incompatible medium installed",
+        (0x30, 0x01) => "Cannot read medium, unknown format",
+        (0x30, 0x02) => "Cannot read medium, incompatible format",
+        (0x30, 0x03) => "Cleaning cartridge installed",
+        (0x31, 0x00) => "Medium format corrupted",
+        (0x33, 0x00) => "Tape length error",
+        (0x3A, 0x00) => "Medium not present",
+        (0x3B, 0x00) => "Sequential positioning error",
+        (0x3B, 0x01) => "Tape position error at beginning-of-medium",
+        (0x3B, 0x02) => "Tape position error at end-of-medium",
+        (0x3B, 0x08) => "Reposition error",
+        (0x40, _) => "Diagnostic failure",
+        (0x44, 0x00) => "Internal target failure",
+        (0x47, _) => "SCSI parity error",
+        (0x48, 0x00) => "Initiator detected error message received",
+        (0x4E, 0x00) => "Overlapped commands attempted",
+        (0x50, 0x00) => "Write append error",
+        (0x51, 0x00) => "Erase failure",
+        (0x52, 0x00) => "Cartridge fault",
+        (0x53, 0x00) => "Media load or eject failed",
+        (0x53, 0x01) => "Unload tape failure",
+        (0x53, 0x02) => "Medium removal prevented",
+        (0x5A, 0x01) => "Operator medium removal request",
+        (0x5D, _) => "Failure prediction threshold exceeded",
+        _ => "Unknown sense code",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Fixed format sense, with the VALID bit and information field set from `info`
+    fn fixed(flags_key: u8, info: Option<i32>, asc: u8, ascq: u8) -> [u8; 18] {
+        let mut data = [0u8; 18];
+        data[0] = 0x70 | if info.is_some() { 0x80 } else { 0 };
+        data[2] = flags_key;
+        data[3..7].copy_from_slice(&info.unwrap_or(0).to_be_bytes());
+        data[7] = 10;
+        data[12] = asc;
+        data[13] = ascq;
+        data
+    }
+
+    #[test]
+    fn fixed_filemark() {
+        // Read a filemark with 32KiB still to go
+        let sense = SenseData::parse(&fixed(0x80, Some(0x8000), 0x00, 0x01)).unwrap();
+
+        assert!(!sense.deferred);
+        assert_eq!(sense.key, SenseKey::NoSense);
+        assert!(sense.filemark);
+        assert!(!sense.eom);
+        assert!(!sense.ili);
+        assert_eq!(sense.information, Some(0x8000));
+        assert_eq!(
+            sense.classify(),
+            Ok(Some(TapeCondition::Filemark { residue: 0x8000 }))
+        );
+    }
+
+    #[test]
+    fn fixed_negative_residue() {
+        // A block 64 bytes longer than asked for
+        let sense = SenseData::parse(&fixed(0x20, Some(-64), 0x00, 0x00)).unwrap();
+
+        assert!(sense.ili);
+        assert_eq!(sense.residue(), -64);
+        assert_eq!(
+            sense.classify(),
+            Ok(Some(TapeCondition::IncorrectLength { residue: -64 }))
+        );
+    }
+
+    #[test]
+    fn fixed_without_information() {
+        // Without the VALID bit the information field is garbage
+        let mut data = fixed(0x08, None, 0x00, 0x05);
+        data[3..7].copy_from_slice(&[0xDE, 0xAD, 0xBE, 0xEF]);
+
+        let sense = SenseData::parse(&data).unwrap();
+        assert_eq!(sense.key, SenseKey::BlankCheck);
+        assert_eq!(sense.information, None);
+        assert_eq!(sense.classify(), Ok(Some(TapeCondition::EndOfData)));
+    }
+
+    #[test]
+    fn fixed_errors() {
+        let sense = SenseData::parse(&fixed(0x02, None, 0x3A, 0x00)).unwrap();
+        assert_eq!(
+            sense.classify(),
+            Err(TapeError::NotReady {
+                becoming_ready: false,
+                no_medium: true
+            })
+        );
+
+        let sense = SenseData::parse(&fixed(0x02, None, 0x04, 0x01)).unwrap();
+        assert!(sense.classify().unwrap_err().is_transient());
+
+        let sense = SenseData::parse(&fixed(0x03, None, 0x11, 0x00)).unwrap();
+        assert_eq!(sense.classify(), Err(TapeError::Medium(sense)));
+        assert_eq!(sense.description(), "Unrecovered read error");
+
+        let sense = SenseData::parse(&fixed(0x43, None, 0x00, 0x02)).unwrap();
+        assert_eq!(sense.classify(), Err(TapeError::EndOfMedium));
+    }
+
+    #[test]
+    fn fixed_deferred() {
+        let mut data = fixed(0x06, None, 0x28, 0x00);
+        data[0] = 0x71;
+
+        let sense = SenseData::parse(&data).unwrap();
+        assert!(sense.deferred);
+        assert_eq!(
+            sense.classify(),
+            Err(TapeError::UnitAttention {
+                medium_changed: true
+            })
+        );
+    }
+
+    #[test]
+    fn descriptor() {
+        let data = [
+            0x72, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x14, // NO SENSE, filemark detected
+            0x00, 0x0A, 0x80, 0x00, // information, VALID
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, //
+            0x04, 0x02, 0x00, 0x80, // stream commands, FILEMARK
+        ];
+
+        let sense = SenseData::parse(&data).unwrap();
+        assert_eq!(sense.key, SenseKey::NoSense);
+        assert_eq!((sense.asc, sense.ascq), (0x00, 0x01));
+        assert!(sense.filemark);
+        assert!(!sense.eom);
+        assert!(!sense.ili);
+        assert_eq!(sense.information, Some(0x100));
+        assert_eq!(
+            sense.classify(),
+            Ok(Some(TapeCondition::Filemark { residue: 0x100 }))
+        );
+    }
+
+    #[test]
+    fn descriptor_truncated() {
+        // The additional length says there's more than there is, and the information
+        // descriptor doesn't have its VALID bit set
+        let data = [
+            0x73, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF, //
+            0x04, 0x02, 0x00, 0x60, // stream commands, EOM and ILI
+            0x00, 0x0A, 0x00, 0x00, // information, not valid
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, //
+            0x00, 0x0A, // cut off
+        ];
+
+        let sense = SenseData::parse(&data).unwrap();
+        assert!(sense.deferred);
+        assert!(sense.eom);
+        assert!(sense.ili);
+        assert_eq!(sense.information, None);
+    }
+
+    #[test]
+    fn bad_sense() {
+        assert_eq!(
+            SenseData::parse(&[]),
+            Err(ParseError::TooShort {
+                expected: 1,
+                actual: 0
+            })
+        );
+        assert_eq!(
+            SenseData::parse(&[0x7F; 18]),
+            Err(ParseError::Invalid("unknown sense response code"))
+        );
+        assert!(SenseData::parse(&fixed(0x00, None, 0, 0)[..13]).is_err());
+        assert!(SenseData::parse(&[0x72, 0x00, 0x00]).is_err());
+    }
+}
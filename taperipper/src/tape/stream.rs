@@ -0,0 +1,214 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// Turning tape records back into a stream of bytes.
+//
+// Most things written to tape don't care about how they were blocked, a tar archive is
+// just a byte stream chopped up into 10240 byte records. `FileStream` reads one tape file
+// a record at a time and hands it out however much the caller asks for, stopping at the
+// next filemark.
+//
+// On an unlabelled volume set there's nothing to say a file carries on on another reel other
+// than running out of tape part way through it. If the stream is given a `ReelChanger` and
+// the file ends with the drive past the early warning while the caller still wants more, the
+// reel is changed and the stream carries on from the start of the next one.
+
+use tracing::{debug, trace};
+
+use crate::tape::{
+    device::{DeviceError, ReadOutcome, TapeDevice},
+    volume::ReelChanger,
+};
+
+// Large enough for any sensible block size, the drive tells us if we're wrong
+pub const DEFAULT_RECORD_LEN: usize = 256 * 1024;
+
+pub struct FileStream<'d, D: TapeDevice> {
+    device: &'d mut D,
+    // Set if the file is allowed to carry on onto another reel
+    reels: Option<&'d mut ReelChanger>,
+    record: Vec<u8>,
+    // The unread part of `record`
+    start: usize,
+    end: usize,
+    // We've hit the filemark (or end of data) at the end of this file
+    done: bool,
+    records: u64,
+    bytes: u64,
+    // Length of the first record read, which is the blocking for most formats
+    first_record_len: Option<usize>,
+}
+
+impl<'d, D: TapeDevice> FileStream<'d, D> {
+    pub fn new(device: &'d mut D) -> Self {
+        Self::with_record_len(device, DEFAULT_RECORD_LEN)
+    }
+
+    pub fn with_record_len(device: &'d mut D, max_record_len: usize) -> Self {
+        Self {
+            device,
+            reels: None,
+            record: vec![0u8; max_record_len],
+            start: 0,
+            end: 0,
+            done: false,
+            records: 0,
+            bytes: 0,
+            first_record_len: None,
+        }
+    }
+
+    // Let the file carry on onto the next reel if it runs off the end of this one
+    pub fn spanning(self, reels: &'d mut ReelChanger) -> Self {
+        Self {
+            reels: Some(reels),
+            ..self
+        }
+    }
+
+    // Which reel of the set we're on, starting from one
+    pub fn reel(&self) -> u32 {
+        self.reels.as_ref().map_or(1, |reels| reels.reel())
+    }
+
+    // How many records we've read out of this file so far
+    pub fn records(&self) -> u64 {
+        self.records
+    }
+
+    // How many bytes of records we've read out of this file so far
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    pub fn first_record_len(&self) -> Option<usize> {
+        self.first_record_len
+    }
+
+    // Whether the file we just ran out of carries on on another reel
+    async fn end_of_volume(&mut self) -> Result<bool, DeviceError> {
+        if self.reels.is_none() {
+            return Ok(false);
+        }
+
+        Ok(self.device.position().await?.end_of_partition)
+    }
+
+    // Swap reels and carry on with the file from the start of the next one
+    async fn next_reel(&mut self) -> Result<(), DeviceError> {
+        let Some(reels) = self.reels.as_deref_mut() else {
+            return Err(DeviceError::EndOfData);
+        };
+
+        debug!(
+            device = self.device.name(),
+            records = self.records,
+            bytes = self.bytes,
+            "Tape file runs off the end of the reel"
+        );
+
+        // NOTE(aki): Unlabelled reels have nothing on them to say which one they are, so
+        // whatever gets mounted is taken on trust
+        reels.mount_next(self.device, None).await?;
+        reels.mounted(None);
+
+        Ok(())
+    }
+
+    // Read the next record off of the tape, returns `false` at the end of the file
+    //
+    // With `span` set, running off the end of the reel moves on to the next one rather than
+    // ending the file.
+    async fn fill(&mut self, span: bool) -> Result<bool, DeviceError> {
+        if self.done {
+            return Ok(false);
+        }
+
+        let mut outcome = self.device.read_block(&mut self.record).await?;
+        if span
+            && matches!(outcome, ReadOutcome::Filemark | ReadOutcome::EndOfData)
+            && self.end_of_volume().await?
+        {
+            self.next_reel().await?;
+            outcome = self.device.read_block(&mut self.record).await?;
+        }
+
+        match outcome {
+            ReadOutcome::Block(len) => {
+                trace!(len, record = self.records, "Read record");
+                self.first_record_len.get_or_insert(len);
+                self.records += 1;
+                self.bytes += len as u64;
+                self.start = 0;
+                self.end = len;
+                Ok(true)
+            }
+            ReadOutcome::Filemark | ReadOutcome::EndOfData => {
+                debug!(
+                    device = self.device.name(),
+                    records = self.records,
+                    bytes = self.bytes,
+                    "End of tape file"
+                );
+                self.done = true;
+                Ok(false)
+            }
+        }
+    }
+
+    // Read up to `buffer.len()` bytes, returns 0 at the end of the file
+    pub async fn read(&mut self, buffer: &mut [u8]) -> Result<usize, DeviceError> {
+        while self.start == self.end {
+            if !self.fill(true).await? {
+                return Ok(0);
+            }
+        }
+
+        let len = buffer.len().min(self.end - self.start);
+        buffer[..len].copy_from_slice(&self.record[self.start..self.start + len]);
+        self.start += len;
+
+        Ok(len)
+    }
+
+    // Fill all of `buffer`, returns `false` if the file ended first
+    pub async fn read_exact(&mut self, buffer: &mut [u8]) -> Result<bool, DeviceError> {
+        let mut filled = 0;
+
+        while filled < buffer.len() {
+            match self.read(&mut buffer[filled..]).await? {
+                0 => return Ok(false),
+                len => filled += len,
+            }
+        }
+
+        Ok(true)
+    }
+
+    // Throw away `count` bytes, returns how many were actually skipped
+    pub async fn skip(&mut self, count: u64) -> Result<u64, DeviceError> {
+        let mut skipped = 0;
+
+        while skipped < count {
+            if self.start == self.end && !self.fill(true).await? {
+                break;
+            }
+
+            let len = ((count - skipped) as usize).min(self.end - self.start);
+            self.start += len;
+            skipped += len as u64;
+        }
+
+        Ok(skipped)
+    }
+
+    // Read out whatever is left in the file, leaving the tape at the start of the next one
+    //
+    // The caller is done with the file, so this never goes looking for another reel.
+    pub async fn finish(&mut self) -> Result<(), DeviceError> {
+        self.start = self.end;
+        while self.fill(false).await? {
+            self.start = self.end;
+        }
+
+        Ok(())
+    }
+}
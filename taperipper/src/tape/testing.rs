@@ -0,0 +1,202 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// Bits and pieces for testing things that sit on top of a `TapeDevice`.
+
+use core::{
+    pin::pin,
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use crate::tape::{
+    device::{DeviceError, DeviceStatus, ReadOutcome, TapeDevice, TapePosition},
+    image,
+};
+
+// Run a future to completion, none of the devices here ever have to wait on anything so
+// there's no need for a real executor
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let mut cx = Context::from_waker(Waker::noop());
+
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Item {
+    Block(Vec<u8>),
+    Filemark,
+}
+
+// A tape in memory that keeps a log of everything done to it, with blocks that can be made
+// to fail to read for a while
+pub struct MockTape {
+    items: Vec<Item>,
+    // How many more times each item will fail to read, `u32::MAX` for never
+    failures: Vec<u32>,
+    pos: usize,
+    pub log: Vec<String>,
+}
+
+impl MockTape {
+    pub fn new(items: Vec<Item>) -> Self {
+        Self {
+            failures: vec![0; items.len()],
+            items,
+            pos: 0,
+            log: Vec::new(),
+        }
+    }
+
+    // A tape file per entry, each one `count` blocks of `len` bytes
+    pub fn files(files: &[(usize, usize)]) -> Self {
+        let mut items = Vec::new();
+
+        for (file, &(count, len)) in files.iter().enumerate() {
+            for block in 0..count {
+                items.push(Item::Block(vec![(file * 16 + block) as u8; len]));
+            }
+            items.push(Item::Filemark);
+        }
+
+        Self::new(items)
+    }
+
+    // Make the `block`th block of `file` fail to read the next `times` times
+    pub fn fail(&mut self, file: usize, block: usize, times: u32) {
+        let (mut at_file, mut at_block) = (0, 0);
+
+        for (idx, item) in self.items.iter().enumerate() {
+            match item {
+                Item::Block(_) if (at_file, at_block) == (file, block) => {
+                    self.failures[idx] = times;
+                    return;
+                }
+                Item::Block(_) => at_block += 1,
+                Item::Filemark => (at_file, at_block) = (at_file + 1, 0),
+            }
+        }
+
+        panic!("No block {block} in file {file}");
+    }
+}
+
+impl TapeDevice for MockTape {
+    fn name(&self) -> &str {
+        "mock"
+    }
+
+    async fn read_block(&mut self, buffer: &mut [u8]) -> Result<ReadOutcome, DeviceError> {
+        self.log.push("read".to_string());
+
+        let Some(item) = self.items.get(self.pos) else {
+            return Ok(ReadOutcome::EndOfData);
+        };
+        let failures = &mut self.failures[self.pos];
+        self.pos += 1;
+
+        if *failures != 0 {
+            if *failures != u32::MAX {
+                *failures -= 1;
+            }
+            return Err(image::medium_error());
+        }
+
+        match item {
+            Item::Block(data) if data.len() > buffer.len() => {
+                Err(DeviceError::BlockTooLarge { len: data.len() })
+            }
+            Item::Block(data) => {
+                buffer[..data.len()].copy_from_slice(data);
+                Ok(ReadOutcome::Block(data.len()))
+            }
+            Item::Filemark => Ok(ReadOutcome::Filemark),
+        }
+    }
+
+    async fn space_filemarks(&mut self, count: i32) -> Result<(), DeviceError> {
+        self.log.push(format!("space_filemarks({count})"));
+
+        for _ in 0..count.unsigned_abs() {
+            loop {
+                let item = if count > 0 {
+                    let item = self.items.get(self.pos).ok_or(DeviceError::EndOfData)?;
+                    self.pos += 1;
+                    item
+                } else {
+                    let Some(pos) = self.pos.checked_sub(1) else {
+                        return Ok(());
+                    };
+                    self.pos = pos;
+                    &self.items[pos]
+                };
+
+                if *item == Item::Filemark {
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn space_blocks(&mut self, count: i32) -> Result<(), DeviceError> {
+        self.log.push(format!("space_blocks({count})"));
+
+        for _ in 0..count.unsigned_abs() {
+            let item = if count > 0 {
+                let item = self.items.get(self.pos).ok_or(DeviceError::EndOfData)?;
+                self.pos += 1;
+                item
+            } else {
+                let Some(pos) = self.pos.checked_sub(1) else {
+                    return Ok(());
+                };
+                self.pos = pos;
+                &self.items[pos]
+            };
+
+            if *item == Item::Filemark {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn rewind(&mut self) -> Result<(), DeviceError> {
+        self.log.push("rewind".to_string());
+        self.pos = 0;
+        Ok(())
+    }
+
+    async fn retension(&mut self) -> Result<(), DeviceError> {
+        self.log.push("retension".to_string());
+        self.pos = 0;
+        Ok(())
+    }
+
+    async fn unload(&mut self) -> Result<(), DeviceError> {
+        self.log.push("unload".to_string());
+        self.pos = 0;
+        Ok(())
+    }
+
+    async fn load(&mut self, _timeout: Duration) -> Result<(), DeviceError> {
+        Err(DeviceError::NotReady)
+    }
+
+    async fn position(&mut self) -> Result<TapePosition, DeviceError> {
+        Ok(TapePosition::default())
+    }
+
+    async fn status(&mut self) -> Result<DeviceStatus, DeviceError> {
+        Ok(DeviceStatus {
+            ready: true,
+            ..Default::default()
+        })
+    }
+}
@@ -0,0 +1,42 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// What can go wrong getting a command to a drive and back, no matter the transport.
+
+use core::fmt;
+
+use crate::platform::uefi::scsi::describe_host_adapter_status;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransportError {
+    // The firmware returned an error status
+    Uefi(uefi::Status),
+    // The host adapter had a problem getting the command to the drive
+    HostAdapter(u8),
+    // The command didn't complete within its timeout
+    Timeout,
+    // The drive has gone away
+    NoDevice,
+    // The drive (or whatever is between us and it) said something we didn't expect
+    Protocol(&'static str),
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransportError::Uefi(status) => write!(f, "firmware error: {status:?}"),
+            TransportError::HostAdapter(status) => write!(
+                f,
+                "host adapter error: {}",
+                describe_host_adapter_status(*status)
+            ),
+            TransportError::Timeout => write!(f, "command timed out"),
+            TransportError::NoDevice => write!(f, "device went away"),
+            TransportError::Protocol(what) => write!(f, "protocol error: {what}"),
+        }
+    }
+}
+
+impl From<uefi::Error> for TransportError {
+    fn from(err: uefi::Error) -> Self {
+        TransportError::Uefi(err.status())
+    }
+}
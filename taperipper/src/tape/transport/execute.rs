@@ -0,0 +1,142 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// The `Transport` trait, and what comes back from the drive when a command is run.
+
+use tracing::warn;
+use uefi::Event;
+
+use crate::tape::{
+    scsi::{
+        Command, ParseError, ScsiStatus, commands,
+        data::InquiryData,
+        sense::{SenseData, TapeCondition, TapeError},
+    },
+    transport::TransportError,
+};
+
+// What came back from the drive after a command
+#[derive(Clone, Copy, Debug)]
+pub struct Response {
+    pub status: ScsiStatus,
+    // How many bytes actually made it across in the data phase
+    pub transferred: usize,
+    // If the status was CHECK CONDITION, the sense data that came with it
+    pub sense: Option<SenseData>,
+}
+
+impl Response {
+    pub fn is_good(&self) -> bool {
+        self.status == ScsiStatus::Good
+    }
+
+    // Turn the status and sense data into either a condition or an error for `command`
+    pub fn check(&self, command: &Command) -> Result<Option<TapeCondition>, TapeError> {
+        match self.status {
+            ScsiStatus::Good | ScsiStatus::ConditionMet => Ok(None),
+            ScsiStatus::CheckCondition => {
+                let Some(sense) = self.sense else {
+                    warn!(
+                        command = command.name(),
+                        "CHECK CONDITION without sense data"
+                    );
+                    return Err(TapeError::InvalidSense(ParseError::Invalid(
+                        "no sense data",
+                    )));
+                };
+
+                sense.trace(command.name());
+                sense.classify()
+            }
+            // The drive is busy doing something else, it's worth trying again later
+            ScsiStatus::Busy | ScsiStatus::TaskSetFull | ScsiStatus::TaskAborted => {
+                Err(TapeError::Aborted)
+            }
+            status => {
+                warn!(command = command.name(), status = ?status, "Unexpected SCSI status");
+                Err(TapeError::Aborted)
+            }
+        }
+    }
+}
+
+pub trait Transport: Send {
+    // Something to tell the user where the drive is
+    fn name(&self) -> &str;
+
+    // Send `command` to the drive, `data` is the data-in or data-out buffer and must be at
+    // least `command.transfer_len` bytes long
+    //
+    // This blocks until the command is done. If the drive returns CHECK CONDITION, the
+    // transport is responsible for getting the sense data, be it from autosense or by
+    // issuing a REQUEST SENSE itself.
+    fn execute(&mut self, command: &Command, data: &mut [u8]) -> Result<Response, TransportError>;
+
+    // Start `command` without waiting for it, for transports that can have one in flight
+    //
+    // Returns the event that gets signaled when it's done, at which point `complete` hands
+    // over how it went, or `None` if the transport can't do this and `execute` is the only
+    // way. Anything else sent before `complete` waits for this one to finish first.
+    fn submit(
+        &mut self,
+        _command: &Command,
+        _data: &[u8],
+    ) -> Result<Option<Event>, TransportError> {
+        Ok(None)
+    }
+
+    // Finish off the command `submit` started, copying any data-in into `data`
+    fn complete(
+        &mut self,
+        _command: &Command,
+        _data: &mut [u8],
+    ) -> Result<Response, TransportError> {
+        Err(TransportError::Protocol("no command in flight"))
+    }
+
+    fn inquiry(&mut self) -> Result<InquiryData, TransportError> {
+        let cmd = commands::inquiry(commands::INQUIRY_LEN);
+        let mut data = [0u8; commands::INQUIRY_LEN as usize];
+
+        let resp = self.execute(&cmd, &mut data)?;
+        if !resp.is_good() {
+            return Err(TransportError::Protocol("INQUIRY failed"));
+        }
+
+        InquiryData::parse(&data[..resp.transferred])
+            .map_err(|_| TransportError::Protocol("short INQUIRY data"))
+    }
+}
+
+// So `find_drives` results can be handed straight to a `ScsiTape`
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn name(&self) -> &str {
+        (**self).name()
+    }
+
+    fn execute(&mut self, command: &Command, data: &mut [u8]) -> Result<Response, TransportError> {
+        (**self).execute(command, data)
+    }
+
+    fn submit(&mut self, command: &Command, data: &[u8]) -> Result<Option<Event>, TransportError> {
+        (**self).submit(command, data)
+    }
+
+    fn complete(&mut self, command: &Command, data: &mut [u8]) -> Result<Response, TransportError> {
+        (**self).complete(command, data)
+    }
+}
+
+// Fetch the sense data the hard way, for transports that don't do autosense
+pub fn request_sense<T: Transport + ?Sized>(
+    transport: &mut T,
+) -> Result<Option<SenseData>, TransportError> {
+    let cmd = commands::request_sense(commands::SENSE_LEN);
+    let mut data = [0u8; commands::SENSE_LEN as usize];
+
+    let resp = transport.execute(&cmd, &mut data)?;
+    if !resp.is_good() {
+        warn!(status = ?resp.status, "REQUEST SENSE failed");
+        return Ok(None);
+    }
+
+    Ok(SenseData::parse(&data[..resp.transferred]).ok())
+}
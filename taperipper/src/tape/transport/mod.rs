@@ -0,0 +1,35 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// Transports are how SCSI commands actually make it to a drive.
+//
+// They don't interpret anything past the status byte, a transport takes a `Command` and a
+// buffer, gets it to the drive and back, and hands over whatever status and sense data
+// came back with it. Making sense of that is up to the caller.
+
+mod error;
+mod execute;
+pub mod passthru;
+#[cfg(feature = "squishy")]
+pub mod squishy;
+
+pub use error::TransportError;
+pub use execute::{Response, Transport, request_sense};
+
+// Find every tape drive reachable over any transport we know about
+pub fn find_drives() -> Vec<Box<dyn Transport>> {
+    let mut drives: Vec<Box<dyn Transport>> = Vec::new();
+
+    drives.extend(
+        passthru::find_drives()
+            .into_iter()
+            .map(|drive| Box::new(drive) as Box<dyn Transport>),
+    );
+
+    #[cfg(feature = "squishy")]
+    drives.extend(
+        squishy::find_drives()
+            .into_iter()
+            .map(|drive| Box::new(drive) as Box<dyn Transport>),
+    );
+
+    drives
+}
@@ -0,0 +1,454 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// A transport over `EFI_EXT_SCSI_PASS_THRU_PROTOCOL`, for drives on a SCSI HBA the
+// firmware already has a driver for.
+//
+// In QEMU, the easiest way to test this is passing a real drive through with `scsi-generic`
+// on a `virtio-scsi-pci` or `lsi53c895a` controller, see `cargo xtask run-qemu --scsi-tape`.
+
+use std::alloc::{self, Layout};
+
+use tracing::{debug, trace, warn};
+use uefi::{
+    Event, Handle, Status,
+    boot::{self, EventType, Tpl},
+};
+
+use crate::{
+    display,
+    platform::uefi::{
+        get_proto_handles, get_proto_shared,
+        scsi::{self, ExtScsiPassThru, ScsiRequestPacket, TARGET_MAX_BYTES},
+    },
+    tape::{
+        scsi::{Cdb, Command, DataDirection, ScsiStatus, commands, sense::SenseData},
+        transport::{Response, Transport, TransportError, request_sense},
+    },
+};
+
+// A zeroed heap buffer with a specific alignment, for controllers that have opinions on
+// where their DMA buffers live
+struct AlignedBuffer {
+    ptr: *mut u8,
+    layout: Layout,
+}
+
+impl AlignedBuffer {
+    fn new(len: usize, align: usize) -> Self {
+        let layout = Layout::from_size_align(len.max(1), align).unwrap();
+        let ptr = unsafe { alloc::alloc_zeroed(layout) };
+        if ptr.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+
+        Self { ptr, layout }
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.ptr, self.layout.size()) }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.ptr, self.layout.size()) }
+    }
+}
+
+impl Drop for AlignedBuffer {
+    fn drop(&mut self) {
+        unsafe { alloc::dealloc(self.ptr, self.layout) };
+    }
+}
+
+// A command the controller is working on in the background
+//
+// NOTE(aki): The controller has pointers into all of this until the event is signaled, so it
+// lives with the transport rather than whoever is waiting on it, in case they stop waiting
+struct InFlight {
+    packet: Box<ScsiRequestPacket>,
+    // Never looked at again, the packet just points at it
+    _cdb: Box<Cdb>,
+    data: AlignedBuffer,
+    sense: AlignedBuffer,
+    // What `pass_thru` said when it was sent
+    status: Status,
+}
+
+pub struct PassThruTransport {
+    proto: boot::ScopedProtocol<ExtScsiPassThru>,
+    target: [u8; TARGET_MAX_BYTES],
+    lun: u64,
+    name: String,
+    // Only there if the controller can run commands in the background
+    event: Option<Event>,
+    in_flight: Option<InFlight>,
+}
+
+// NOTE(aki): Boot services are only ever touched from whichever core is running the
+// executor, so handing this off between tasks is fine.
+unsafe impl Send for PassThruTransport {}
+
+impl PassThruTransport {
+    pub fn open(
+        handle: Handle,
+        target: [u8; TARGET_MAX_BYTES],
+        lun: u64,
+        index: usize,
+    ) -> Result<Self, uefi::Error> {
+        let proto = get_proto_shared::<ExtScsiPassThru>(handle)?;
+
+        let event = if (proto.mode().attributes & scsi::ATTRIBUTES_NONBLOCKING) != 0 {
+            Some(unsafe { boot::create_event(EventType::empty(), Tpl::CALLBACK, None, None) }?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            proto,
+            target,
+            lun,
+            // NOTE(aki): Target IDs are opaque bytes, but in practice they are little-endian numbers
+            name: format!("scsi{}:{}:{}", index, u128::from_le_bytes(target), lun),
+            event,
+            in_flight: None,
+        })
+    }
+
+    // Wait out a command that was submitted but never completed, so it's safe to let go of
+    fn drain(&mut self) {
+        if self.in_flight.is_none() {
+            return;
+        }
+
+        if let Some(event) = &self.event {
+            trace!(device = self.name.as_str(), "Waiting on abandoned command");
+            if let Err(err) = boot::wait_for_event(&mut [unsafe { event.unsafe_clone() }]) {
+                warn!(
+                    device = self.name.as_str(),
+                    "Unable to wait on command: {:?}", err
+                );
+            }
+        }
+
+        self.in_flight = None;
+    }
+
+    // Make sense of how `command` went, once the controller is done with it
+    fn response(
+        &mut self,
+        command: &Command,
+        status: Status,
+        packet: &ScsiRequestPacket,
+        sense: &[u8],
+    ) -> Result<Response, TransportError> {
+        match status {
+            // BAD_BUFFER_SIZE just means we got less than we asked for
+            Status::SUCCESS | Status::BAD_BUFFER_SIZE | Status::DEVICE_ERROR => {}
+            Status::NOT_READY => {
+                // The controller has too many commands in flight
+                return Ok(Response {
+                    status: ScsiStatus::Busy,
+                    transferred: 0,
+                    sense: None,
+                });
+            }
+            Status::TIMEOUT => return Err(TransportError::Timeout),
+            status => {
+                warn!(
+                    device = self.name.as_str(),
+                    status = ?status,
+                    "{} pass-thru failed",
+                    command.name()
+                );
+                return Err(TransportError::Uefi(status));
+            }
+        }
+
+        match packet.host_adapter_status {
+            // Short reads are expected when reading variable length blocks
+            scsi::HOST_ADAPTER_OK | scsi::HOST_ADAPTER_DATA_OVERRUN_UNDERRUN => {}
+            scsi::HOST_ADAPTER_TIMEOUT | scsi::HOST_ADAPTER_TIMEOUT_COMMAND => {
+                return Err(TransportError::Timeout);
+            }
+            scsi::HOST_ADAPTER_SELECTION_TIMEOUT => return Err(TransportError::NoDevice),
+            hs => {
+                warn!(
+                    device = self.name.as_str(),
+                    host_status = display::fmt::hex(hs),
+                    "{}: {}",
+                    command.name(),
+                    scsi::describe_host_adapter_status(hs)
+                );
+                return Err(TransportError::HostAdapter(hs));
+            }
+        }
+
+        let len = command.transfer_len;
+        let transferred = match command.direction {
+            DataDirection::None => 0,
+            DataDirection::In => (packet.in_transfer_length as usize).min(len),
+            DataDirection::Out => (packet.out_transfer_length as usize).min(len),
+        };
+
+        let target_status = ScsiStatus::from(packet.target_status);
+        let sense = if target_status == ScsiStatus::CheckCondition {
+            match packet.sense_data_length as usize {
+                // No autosense, go and ask for it
+                0 => request_sense(self)?,
+                sense_len => SenseData::parse(&sense[..sense_len])
+                    .inspect_err(|err| {
+                        warn!(device = self.name.as_str(), "Bad sense data: {}", err)
+                    })
+                    .ok(),
+            }
+        } else {
+            None
+        };
+
+        Ok(Response {
+            status: target_status,
+            transferred,
+            sense,
+        })
+    }
+}
+
+impl Drop for PassThruTransport {
+    fn drop(&mut self) {
+        self.drain();
+
+        if let Some(event) = self.event.take()
+            && let Err(err) = boot::close_event(event)
+        {
+            warn!(
+                device = self.name.as_str(),
+                "Unable to close event: {:?}", err
+            );
+        }
+    }
+}
+
+// Fill out a request packet for `command`, with `data` as the data buffer
+fn request_packet(
+    command: &Command,
+    data: *mut u8,
+    sense: &AlignedBuffer,
+    cdb: &mut Cdb,
+) -> ScsiRequestPacket {
+    let len = command.transfer_len;
+
+    let mut packet = ScsiRequestPacket {
+        timeout: (command.timeout.as_nanos() / 100) as u64,
+        sense_data: sense.ptr.cast(),
+        cdb: cdb.as_bytes_mut().as_mut_ptr().cast(),
+        cdb_length: cdb.as_bytes().len() as u8,
+        sense_data_length: commands::SENSE_LEN,
+        ..Default::default()
+    };
+
+    match command.direction {
+        DataDirection::None => {}
+        DataDirection::In => {
+            packet.in_data_buffer = data.cast();
+            packet.in_transfer_length = len as u32;
+            packet.data_direction = scsi::DATA_DIRECTION_READ;
+        }
+        DataDirection::Out => {
+            packet.out_data_buffer = data.cast();
+            packet.out_transfer_length = len as u32;
+            packet.data_direction = scsi::DATA_DIRECTION_WRITE;
+        }
+    }
+
+    packet
+}
+
+impl Transport for PassThruTransport {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn execute(&mut self, command: &Command, data: &mut [u8]) -> Result<Response, TransportError> {
+        assert!(
+            data.len() >= command.transfer_len,
+            "Data buffer too small for {}",
+            command.name()
+        );
+        self.drain();
+
+        let align = self.proto.io_align();
+        let len = command.transfer_len;
+
+        // Only bounce the data if the controller can't deal with where it is
+        let mut bounce = (len != 0 && (data.as_ptr() as usize) % align != 0).then(|| {
+            trace!(
+                device = self.name.as_str(),
+                align, "Bouncing unaligned data buffer"
+            );
+            AlignedBuffer::new(len, align)
+        });
+
+        if let (Some(buffer), DataDirection::Out) = (bounce.as_mut(), command.direction) {
+            buffer.as_mut_slice()[..len].copy_from_slice(&data[..len]);
+        }
+
+        let data_ptr = match bounce.as_mut() {
+            Some(buffer) => buffer.ptr,
+            None => data.as_mut_ptr(),
+        };
+
+        let sense_buf = AlignedBuffer::new(commands::SENSE_LEN as usize, align);
+        let mut cdb = command.cdb;
+        let mut packet = request_packet(command, data_ptr, &sense_buf, &mut cdb);
+
+        trace!(device = self.name.as_str(), cdb = ?command.cdb, "{}", command.name());
+
+        let status = unsafe {
+            self.proto
+                .pass_thru(&self.target, self.lun, &mut packet, None)
+        };
+        let resp = self.response(command, status, &packet, sense_buf.as_slice())?;
+
+        if let (Some(buffer), DataDirection::In) = (bounce.as_ref(), command.direction) {
+            data[..resp.transferred].copy_from_slice(&buffer.as_slice()[..resp.transferred]);
+        }
+
+        Ok(resp)
+    }
+
+    fn submit(&mut self, command: &Command, data: &[u8]) -> Result<Option<Event>, TransportError> {
+        let Some(event) = self
+            .event
+            .as_ref()
+            .map(|event| unsafe { event.unsafe_clone() })
+        else {
+            return Ok(None);
+        };
+
+        assert!(
+            data.len() >= command.transfer_len,
+            "Data buffer too small for {}",
+            command.name()
+        );
+        self.drain();
+
+        // Don't let a signal nobody checked for make it look like this one is already done
+        boot::check_event(unsafe { event.unsafe_clone() })?;
+
+        // NOTE(aki): Always bounced, the caller's buffer may be gone by the time it's done
+        let align = self.proto.io_align();
+        let len = command.transfer_len;
+        let mut buffer = AlignedBuffer::new(len, align);
+        if command.direction == DataDirection::Out {
+            buffer.as_mut_slice()[..len].copy_from_slice(&data[..len]);
+        }
+
+        let sense = AlignedBuffer::new(commands::SENSE_LEN as usize, align);
+        let mut cdb = Box::new(command.cdb);
+        let mut packet = Box::new(request_packet(command, buffer.ptr, &sense, &mut cdb));
+
+        trace!(device = self.name.as_str(), cdb = ?command.cdb, "{}", command.name());
+
+        let status = unsafe {
+            self.proto
+                .pass_thru(&self.target, self.lun, &mut packet, Some(&event))
+        };
+
+        // It never made it to the controller, so nothing else is going to signal it
+        if status != Status::SUCCESS {
+            boot::signal_event(&event)?;
+        }
+
+        self.in_flight = Some(InFlight {
+            packet,
+            _cdb: cdb,
+            data: buffer,
+            sense,
+            status,
+        });
+
+        Ok(Some(event))
+    }
+
+    fn complete(&mut self, command: &Command, data: &mut [u8]) -> Result<Response, TransportError> {
+        let Some(in_flight) = self.in_flight.take() else {
+            return Err(TransportError::Protocol("no command in flight"));
+        };
+
+        let resp = self.response(
+            command,
+            in_flight.status,
+            &in_flight.packet,
+            in_flight.sense.as_slice(),
+        )?;
+
+        if command.direction == DataDirection::In {
+            data[..resp.transferred]
+                .copy_from_slice(&in_flight.data.as_slice()[..resp.transferred]);
+        }
+
+        Ok(resp)
+    }
+}
+
+// Walk every pass-thru controller and pick out anything that looks like a tape drive
+pub fn find_drives() -> Vec<PassThruTransport> {
+    let Ok(handles) = get_proto_handles::<ExtScsiPassThru>() else {
+        debug!("No SCSI pass-thru controllers found");
+        return Vec::new();
+    };
+
+    let mut drives = Vec::new();
+
+    for (index, handle) in handles.into_iter().enumerate() {
+        let Ok(proto) = get_proto_shared::<ExtScsiPassThru>(handle) else {
+            warn!("Unable to open SCSI pass-thru controller {}", index);
+            continue;
+        };
+
+        let mode = proto.mode();
+        debug!(
+            controller = index,
+            adapter_id = mode.adapter_id,
+            attributes = display::fmt::hex(mode.attributes),
+            io_align = mode.io_align,
+            "Found SCSI pass-thru controller"
+        );
+
+        // NOTE(aki): RAID controllers expose their disks twice, once on a physical channel
+        // and once on a logical one, plain HBAs set both bits on the one channel.
+        if (mode.attributes & scsi::ATTRIBUTES_LOGICAL) == 0 {
+            trace!(controller = index, "Skipping physical-only channel");
+            continue;
+        }
+
+        for (target, lun) in proto.targets() {
+            let Ok(mut drive) = PassThruTransport::open(handle, target, lun, index) else {
+                continue;
+            };
+
+            let inquiry = match drive.inquiry() {
+                Ok(inquiry) => inquiry,
+                Err(err) => {
+                    debug!(device = drive.name.as_str(), "INQUIRY failed: {}", err);
+                    continue;
+                }
+            };
+
+            debug!(
+                device = drive.name.as_str(),
+                device_type = display::fmt::hex(inquiry.device_type),
+                "{}",
+                inquiry
+            );
+
+            if inquiry.is_tape() {
+                debug!(
+                    device = drive.name.as_str(),
+                    "Found tape drive: {}", inquiry
+                );
+                drives.push(drive);
+            }
+        }
+    }
+
+    drives
+}
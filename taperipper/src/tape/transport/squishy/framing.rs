@@ -0,0 +1,706 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// The framing taperipper uses to get SCSI commands to Squishy and back over USB.
+//
+// NOTE(aki): This isn't something Squishy speaks, there's no USB protocol for its SCSI
+// initiator applet to follow, so this is one we made up. It's modeled on USB Mass Storage
+// Bulk-Only Transport, a command wrapper goes out on the bulk OUT endpoint, then the data
+// phase (if any) in the direction of the command, then a status wrapper comes back on the
+// bulk IN endpoint. Unlike BOT, the status wrapper carries the real SCSI status byte and
+// any autosense data, as the whole point is talking to a tape drive, and they say most of
+// what they have to say that way. Until the applet exists, the only thing on the other end
+// of this is the `MockEndpoint` in the tests.
+//
+// Command wrapper (little-endian):
+//   0..4   signature, "SQCB"
+//   4..8   tag, echoed back in the status wrapper
+//   8..12  data transfer length
+//   12     flags, bit 7 set for data-in
+//   13     SCSI target ID
+//   14     LUN
+//   15     CDB length
+//   16..32 CDB
+//   32..36 timeout in milliseconds
+//
+// Status wrapper (little-endian):
+//   0..4   signature, "SQSB"
+//   4..8   tag
+//   8..12  data residue
+//   12     SCSI status
+//   13     bus status, see `BUS_*`
+//   14     sense data length
+//   15     reserved
+//   16..   sense data
+
+use core::time::Duration;
+
+use tracing::{trace, warn};
+use uefi::Status;
+
+use crate::{
+    display,
+    platform::uefi::usb,
+    tape::{
+        scsi::{Cdb, Command, DataDirection, ScsiStatus, commands, sense::SenseData},
+        transport::{Response, Transport, TransportError},
+    },
+};
+
+pub const CBW_SIGNATURE: [u8; 4] = *b"SQCB";
+pub const CSW_SIGNATURE: [u8; 4] = *b"SQSB";
+pub const CBW_LEN: usize = 36;
+pub const CSW_HEADER_LEN: usize = 16;
+pub const CSW_MAX_LEN: usize = CSW_HEADER_LEN + commands::SENSE_LEN as usize;
+
+const CBW_FLAG_DATA_IN: u8 = 0x80;
+
+// Bus status codes, for things that went wrong between Squishy and the drive
+pub const BUS_OK: u8 = 0x00;
+pub const BUS_SELECTION_TIMEOUT: u8 = 0x01;
+pub const BUS_PHASE_ERROR: u8 = 0x02;
+pub const BUS_PARITY_ERROR: u8 = 0x03;
+pub const BUS_RESET: u8 = 0x04;
+pub const BUS_TIMEOUT: u8 = 0x05;
+
+// Extra time on top of the command timeout for USB to get the status back to us
+const USB_SLACK: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CommandWrapper {
+    pub tag: u32,
+    pub transfer_len: u32,
+    pub data_in: bool,
+    pub target: u8,
+    pub lun: u8,
+    pub cdb: Cdb,
+    pub timeout_ms: u32,
+}
+
+impl CommandWrapper {
+    pub fn to_bytes(self) -> [u8; CBW_LEN] {
+        let mut data = [0u8; CBW_LEN];
+
+        data[0..4].copy_from_slice(&CBW_SIGNATURE);
+        data[4..8].copy_from_slice(&self.tag.to_le_bytes());
+        data[8..12].copy_from_slice(&self.transfer_len.to_le_bytes());
+        data[12] = if self.data_in { CBW_FLAG_DATA_IN } else { 0 };
+        data[13] = self.target;
+        data[14] = self.lun;
+        let cdb = self.cdb.as_bytes();
+        data[15] = cdb.len() as u8;
+        data[16..16 + cdb.len()].copy_from_slice(cdb);
+        data[32..36].copy_from_slice(&self.timeout_ms.to_le_bytes());
+
+        data
+    }
+
+    // Only the other end needs to decode these
+    #[cfg(test)]
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() != CBW_LEN || data[0..4] != CBW_SIGNATURE {
+            return None;
+        }
+
+        let cdb_len = data[15] as usize;
+        if cdb_len == 0 || cdb_len > Cdb::MAX_LEN {
+            return None;
+        }
+
+        Some(Self {
+            tag: u32::from_le_bytes(data[4..8].try_into().unwrap()),
+            transfer_len: u32::from_le_bytes(data[8..12].try_into().unwrap()),
+            data_in: (data[12] & CBW_FLAG_DATA_IN) != 0,
+            target: data[13],
+            lun: data[14],
+            cdb: Cdb::from_bytes(&data[16..16 + cdb_len]),
+            timeout_ms: u32::from_le_bytes(data[32..36].try_into().unwrap()),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StatusWrapper {
+    pub tag: u32,
+    pub residue: u32,
+    pub status: u8,
+    pub bus_status: u8,
+    sense: [u8; commands::SENSE_LEN as usize],
+    sense_len: usize,
+}
+
+impl StatusWrapper {
+    pub fn new(tag: u32, residue: u32, status: u8, bus_status: u8, sense: &[u8]) -> Self {
+        let sense_len = sense.len().min(commands::SENSE_LEN as usize);
+        let mut wrapper = Self {
+            tag,
+            residue,
+            status,
+            bus_status,
+            sense: [0; commands::SENSE_LEN as usize],
+            sense_len,
+        };
+
+        wrapper.sense[..sense_len].copy_from_slice(&sense[..sense_len]);
+        wrapper
+    }
+
+    pub fn sense(&self) -> &[u8] {
+        &self.sense[..self.sense_len]
+    }
+
+    // Only the other end needs to encode these
+    #[cfg(test)]
+    pub fn to_bytes(self) -> Vec<u8> {
+        let mut data = vec![0u8; CSW_HEADER_LEN + self.sense_len];
+
+        data[0..4].copy_from_slice(&CSW_SIGNATURE);
+        data[4..8].copy_from_slice(&self.tag.to_le_bytes());
+        data[8..12].copy_from_slice(&self.residue.to_le_bytes());
+        data[12] = self.status;
+        data[13] = self.bus_status;
+        data[14] = self.sense_len as u8;
+        data[CSW_HEADER_LEN..].copy_from_slice(self.sense());
+
+        data
+    }
+
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < CSW_HEADER_LEN || data[0..4] != CSW_SIGNATURE {
+            return None;
+        }
+
+        let sense_len = (data[14] as usize).min(data.len() - CSW_HEADER_LEN);
+
+        Some(Self::new(
+            u32::from_le_bytes(data[4..8].try_into().unwrap()),
+            u32::from_le_bytes(data[8..12].try_into().unwrap()),
+            data[12],
+            data[13],
+            &data[CSW_HEADER_LEN..CSW_HEADER_LEN + sense_len],
+        ))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PipeError {
+    // The endpoint is halted, it needs clearing before it'll talk to us again
+    Stall,
+    Timeout,
+    Uefi(Status),
+    // Any other USB transfer error bits
+    Usb(u32),
+}
+
+impl From<PipeError> for TransportError {
+    fn from(err: PipeError) -> Self {
+        match err {
+            PipeError::Timeout => TransportError::Timeout,
+            PipeError::Uefi(status) => TransportError::Uefi(status),
+            PipeError::Stall => TransportError::Protocol("endpoint stalled"),
+            PipeError::Usb(result) => {
+                warn!(
+                    result = display::fmt::hex(result),
+                    "USB transfer error: {}",
+                    usb::describe_transfer_result(result)
+                );
+                TransportError::Protocol("USB transfer error")
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Endpoint {
+    In,
+    Out,
+}
+
+// A pair of bulk endpoints, so the framing can be pointed at something other than real
+// hardware
+pub trait BulkPipe: Send {
+    fn send(&mut self, data: &[u8], timeout: Duration) -> Result<usize, PipeError>;
+    fn receive(&mut self, data: &mut [u8], timeout: Duration) -> Result<usize, PipeError>;
+    fn clear_halt(&mut self, endpoint: Endpoint) -> Result<(), PipeError>;
+}
+
+pub struct SquishyTransport<P: BulkPipe> {
+    pipe: P,
+    target: u8,
+    lun: u8,
+    tag: u32,
+    name: String,
+}
+
+impl<P: BulkPipe> SquishyTransport<P> {
+    pub fn new(pipe: P, target: u8, lun: u8) -> Self {
+        Self {
+            pipe,
+            target,
+            lun,
+            tag: 0,
+            name: format!("squishy:{}:{}", target, lun),
+        }
+    }
+
+    pub fn into_pipe(self) -> P {
+        self.pipe
+    }
+
+    fn read_status(&mut self, timeout: Duration) -> Result<StatusWrapper, TransportError> {
+        let mut data = [0u8; CSW_MAX_LEN];
+
+        let len = match self.pipe.receive(&mut data, timeout) {
+            // BOT gives a stalled status read one more try after clearing it, so do we
+            Err(PipeError::Stall) => {
+                self.pipe.clear_halt(Endpoint::In)?;
+                self.pipe.receive(&mut data, timeout)?
+            }
+            res => res?,
+        };
+
+        let csw = StatusWrapper::parse(&data[..len])
+            .ok_or(TransportError::Protocol("bad status wrapper"))?;
+
+        if csw.tag != self.tag {
+            warn!(
+                device = self.name.as_str(),
+                expected = self.tag,
+                actual = csw.tag,
+                "Status wrapper tag mismatch"
+            );
+            return Err(TransportError::Protocol("status wrapper tag mismatch"));
+        }
+
+        Ok(csw)
+    }
+}
+
+impl<P: BulkPipe> Transport for SquishyTransport<P> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn execute(&mut self, command: &Command, data: &mut [u8]) -> Result<Response, TransportError> {
+        assert!(
+            data.len() >= command.transfer_len,
+            "Data buffer too small for {}",
+            command.name()
+        );
+
+        self.tag = self.tag.wrapping_add(1);
+        let len = command.transfer_len;
+        let usb_timeout = command.timeout + USB_SLACK;
+
+        let cbw = CommandWrapper {
+            tag: self.tag,
+            transfer_len: len as u32,
+            data_in: command.direction == DataDirection::In,
+            target: self.target,
+            lun: self.lun,
+            cdb: command.cdb,
+            timeout_ms: command.timeout.as_millis() as u32,
+        };
+
+        trace!(device = self.name.as_str(), tag = self.tag, cdb = ?command.cdb, "{}", command.name());
+
+        self.pipe.send(&cbw.to_bytes(), usb_timeout)?;
+
+        // A stall in the data phase just means the drive had less to say than we expected,
+        // the real story is in the status wrapper
+        let moved = match command.direction {
+            DataDirection::None => 0,
+            DataDirection::In if len != 0 => match self.pipe.receive(&mut data[..len], usb_timeout)
+            {
+                Ok(moved) => moved,
+                Err(PipeError::Stall) => {
+                    self.pipe.clear_halt(Endpoint::In)?;
+                    0
+                }
+                Err(err) => return Err(err.into()),
+            },
+            DataDirection::Out if len != 0 => match self.pipe.send(&data[..len], usb_timeout) {
+                Ok(moved) => moved,
+                Err(PipeError::Stall) => {
+                    self.pipe.clear_halt(Endpoint::Out)?;
+                    0
+                }
+                Err(err) => return Err(err.into()),
+            },
+            _ => 0,
+        };
+
+        let csw = self.read_status(usb_timeout)?;
+
+        let bus_error = match csw.bus_status {
+            BUS_OK => None,
+            BUS_SELECTION_TIMEOUT => return Err(TransportError::NoDevice),
+            BUS_TIMEOUT => return Err(TransportError::Timeout),
+            BUS_PHASE_ERROR => Some("SCSI bus phase error"),
+            BUS_PARITY_ERROR => Some("SCSI bus parity error"),
+            BUS_RESET => Some("SCSI bus reset"),
+            _ => Some("SCSI bus error"),
+        };
+
+        if let Some(what) = bus_error {
+            warn!(
+                device = self.name.as_str(),
+                bus_status = display::fmt::hex(csw.bus_status),
+                "{} failed on the SCSI bus",
+                command.name()
+            );
+            return Err(TransportError::Protocol(what));
+        }
+
+        // Trust whichever is smaller, what made it over USB or what the drive says it sent
+        let transferred = moved.min(len - (csw.residue as usize).min(len));
+        let status = ScsiStatus::from(csw.status);
+
+        let sense = if status == ScsiStatus::CheckCondition && !csw.sense().is_empty() {
+            SenseData::parse(csw.sense())
+                .inspect_err(|err| warn!(device = self.name.as_str(), "Bad sense data: {}", err))
+                .ok()
+        } else {
+            None
+        };
+
+        Ok(Response {
+            status,
+            transferred,
+            sense,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+    use crate::tape::scsi::{
+        commands::SpaceCode,
+        sense::{SenseKey, TapeCondition},
+    };
+
+    // A fake Squishy on the other end of a pipe
+    //
+    // `handler` gets the decoded command wrapper and any data-out bytes, and returns the
+    // data-in bytes, SCSI status and sense data.
+    struct MockEndpoint<F>
+    where
+        F: FnMut(&CommandWrapper, &[u8]) -> (Vec<u8>, ScsiStatus, Vec<u8>) + Send,
+    {
+        handler: F,
+        pending: Option<CommandWrapper>,
+        outgoing: VecDeque<Vec<u8>>,
+        // What to report in the bus status of every status wrapper
+        bus_status: u8,
+        // Stall the next data-in phase, like a drive that had nothing to say
+        stall_data_in: bool,
+        // Everything the host sent us, in order
+        sent: Vec<Vec<u8>>,
+        cleared: Vec<Endpoint>,
+    }
+
+    impl<F> MockEndpoint<F>
+    where
+        F: FnMut(&CommandWrapper, &[u8]) -> (Vec<u8>, ScsiStatus, Vec<u8>) + Send,
+    {
+        fn new(handler: F) -> Self {
+            Self {
+                handler,
+                pending: None,
+                outgoing: VecDeque::new(),
+                bus_status: BUS_OK,
+                stall_data_in: false,
+                sent: Vec::new(),
+                cleared: Vec::new(),
+            }
+        }
+
+        fn respond(&mut self, cbw: CommandWrapper, data_out: &[u8]) {
+            let (data_in, status, sense) = (self.handler)(&cbw, data_out);
+
+            let moved = if cbw.data_in {
+                let len = data_in.len().min(cbw.transfer_len as usize);
+                if !self.stall_data_in {
+                    self.outgoing.push_back(data_in[..len].to_vec());
+                }
+                len
+            } else {
+                data_out.len()
+            };
+
+            let csw = StatusWrapper::new(
+                cbw.tag,
+                cbw.transfer_len - moved as u32,
+                status.into(),
+                self.bus_status,
+                &sense,
+            );
+            self.outgoing.push_back(csw.to_bytes());
+        }
+    }
+
+    impl<F> BulkPipe for MockEndpoint<F>
+    where
+        F: FnMut(&CommandWrapper, &[u8]) -> (Vec<u8>, ScsiStatus, Vec<u8>) + Send,
+    {
+        fn send(&mut self, data: &[u8], _timeout: Duration) -> Result<usize, PipeError> {
+            self.sent.push(data.to_vec());
+
+            // Either this is the data-out phase for a command we're holding on to, or a new
+            // command
+            if let Some(cbw) = self.pending.take() {
+                self.respond(cbw, data);
+                return Ok(data.len());
+            }
+
+            let cbw = CommandWrapper::parse(data).ok_or(PipeError::Stall)?;
+            if !cbw.data_in && cbw.transfer_len != 0 {
+                self.pending = Some(cbw);
+            } else {
+                self.respond(cbw, &[]);
+            }
+
+            Ok(data.len())
+        }
+
+        fn receive(&mut self, data: &mut [u8], _timeout: Duration) -> Result<usize, PipeError> {
+            if self.stall_data_in {
+                self.stall_data_in = false;
+                return Err(PipeError::Stall);
+            }
+
+            let packet = self.outgoing.pop_front().ok_or(PipeError::Timeout)?;
+            if packet.len() > data.len() {
+                return Err(PipeError::Usb(usb::USB_ERR_BABBLE));
+            }
+
+            data[..packet.len()].copy_from_slice(&packet);
+            Ok(packet.len())
+        }
+
+        fn clear_halt(&mut self, endpoint: Endpoint) -> Result<(), PipeError> {
+            self.cleared.push(endpoint);
+            Ok(())
+        }
+    }
+
+    // Just enough INQUIRY data for a tape drive
+    fn tape_inquiry() -> Vec<u8> {
+        let mut data = vec![b' '; commands::INQUIRY_LEN as usize];
+        data[0..8].copy_from_slice(&[0x01, 0x80, 0x06, 0x02, 31, 0x00, 0x00, 0x00]);
+        data[8..10].copy_from_slice(b"HP");
+        data[16..30].copy_from_slice(b"Ultrium 5-SCSI");
+        data[32..36].copy_from_slice(b"Z6ID");
+        data
+    }
+
+    #[test]
+    fn command_wrapper() {
+        let cbw = CommandWrapper {
+            tag: 0x1234_5678,
+            transfer_len: 0x8000,
+            data_in: true,
+            target: 3,
+            lun: 1,
+            cdb: commands::read_variable(0x8000, true).cdb,
+            timeout_ms: 60_000,
+        };
+
+        let data = cbw.to_bytes();
+        assert_eq!(&data[0..4], b"SQCB");
+        assert_eq!(data[4..8], [0x78, 0x56, 0x34, 0x12]);
+        assert_eq!(data[8..12], [0x00, 0x80, 0x00, 0x00]);
+        assert_eq!(data[12..16], [0x80, 3, 1, 6]);
+        assert_eq!(data[16..22], [0x08, 0x02, 0x00, 0x80, 0x00, 0x00]);
+        assert!(data[22..32].iter().all(|&byte| byte == 0));
+        assert_eq!(data[32..36], 60_000u32.to_le_bytes());
+
+        assert_eq!(CommandWrapper::parse(&data), Some(cbw));
+    }
+
+    #[test]
+    fn status_wrapper() {
+        let sense = [0x70, 0x00, 0x80, 0x00, 0x00, 0x80, 0x00, 10];
+        let mut data = vec![0u8; CSW_HEADER_LEN];
+        data[0..4].copy_from_slice(b"SQSB");
+        data[4..8].copy_from_slice(&7u32.to_le_bytes());
+        data[8..12].copy_from_slice(&0x200u32.to_le_bytes());
+        data[12] = 0x02;
+        data[14] = sense.len() as u8;
+        data.extend_from_slice(&sense);
+
+        let csw = StatusWrapper::parse(&data).unwrap();
+        assert_eq!(csw.tag, 7);
+        assert_eq!(csw.residue, 0x200);
+        assert_eq!(csw.status, 0x02);
+        assert_eq!(csw.bus_status, BUS_OK);
+        assert_eq!(csw.sense(), sense);
+        assert_eq!(csw.to_bytes(), data);
+
+        // The sense length can't run past what actually came back
+        data[14] = 0xFF;
+        assert_eq!(StatusWrapper::parse(&data).unwrap().sense(), sense);
+
+        assert_eq!(StatusWrapper::parse(&data[..CSW_HEADER_LEN - 1]), None);
+        data[0..4].copy_from_slice(b"USBS");
+        assert_eq!(StatusWrapper::parse(&data), None);
+    }
+
+    #[test]
+    fn data_in() {
+        let mock = MockEndpoint::new(|cbw, _| {
+            assert_eq!(cbw.cdb.opcode(), 0x12);
+            (tape_inquiry(), ScsiStatus::Good, Vec::new())
+        });
+        let mut transport = SquishyTransport::new(mock, 3, 0);
+
+        let inquiry = transport.inquiry().unwrap();
+        assert!(inquiry.is_tape());
+        assert_eq!(inquiry.to_string(), "HP Ultrium 5-SCSI Z6ID");
+        assert_eq!(transport.name(), "squishy:3:0");
+
+        // One command wrapper out, and nothing else
+        let mock = transport.into_pipe();
+        assert_eq!(mock.sent.len(), 1);
+        let cbw = CommandWrapper::parse(&mock.sent[0]).unwrap();
+        assert_eq!(cbw.tag, 1);
+        assert!(cbw.data_in);
+        assert_eq!(cbw.transfer_len, commands::INQUIRY_LEN as u32);
+        assert_eq!((cbw.target, cbw.lun), (3, 0));
+    }
+
+    #[test]
+    fn data_out() {
+        let params = [
+            0x00, 0x00, 0x10, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x00,
+        ];
+        let mock = MockEndpoint::new(|cbw, data_out| {
+            assert!(!cbw.data_in);
+            assert_eq!(data_out, params);
+            (Vec::new(), ScsiStatus::Good, Vec::new())
+        });
+        let mut transport = SquishyTransport::new(mock, 0, 0);
+
+        let cmd = commands::mode_select6(true, params.len() as u8);
+        let mut data = params;
+        let resp = transport.execute(&cmd, &mut data).unwrap();
+        assert!(resp.is_good());
+        assert_eq!(resp.transferred, params.len());
+
+        let mock = transport.into_pipe();
+        assert_eq!(mock.sent.len(), 2);
+        assert_eq!(mock.sent[1], params);
+    }
+
+    #[test]
+    fn short_read() {
+        // A 512 byte block where we asked for up to 32KiB
+        let mock = MockEndpoint::new(|_, _| (vec![0xA5; 512], ScsiStatus::Good, Vec::new()));
+        let mut transport = SquishyTransport::new(mock, 0, 0);
+
+        let cmd = commands::read_variable(0x8000, true);
+        let mut data = vec![0u8; 0x8000];
+        let resp = transport.execute(&cmd, &mut data).unwrap();
+        assert!(resp.is_good());
+        assert_eq!(resp.transferred, 512);
+        assert!(data[..512].iter().all(|&byte| byte == 0xA5));
+    }
+
+    #[test]
+    fn autosense() {
+        // Read a filemark, with the sense data coming back in the status wrapper
+        let mock = MockEndpoint::new(|cbw, _| {
+            let mut sense = vec![0u8; 18];
+            sense[0] = 0xF0;
+            sense[2] = 0x80;
+            sense[3..7].copy_from_slice(&cbw.transfer_len.to_be_bytes());
+            sense[7] = 10;
+            sense[13] = 0x01;
+            (Vec::new(), ScsiStatus::CheckCondition, sense)
+        });
+        let mut transport = SquishyTransport::new(mock, 0, 0);
+
+        let cmd = commands::read_variable(0x8000, true);
+        let mut data = vec![0u8; 0x8000];
+        let resp = transport.execute(&cmd, &mut data).unwrap();
+        assert_eq!(resp.status, ScsiStatus::CheckCondition);
+        assert_eq!(resp.transferred, 0);
+
+        let sense = resp.sense.unwrap();
+        assert_eq!(sense.key, SenseKey::NoSense);
+        assert!(sense.filemark);
+        assert_eq!(
+            resp.check(&cmd),
+            Ok(Some(TapeCondition::Filemark { residue: 0x8000 }))
+        );
+    }
+
+    #[test]
+    fn stalled_data_phase() {
+        let mock = MockEndpoint::new(|_, _| (Vec::new(), ScsiStatus::Good, Vec::new()));
+        let mut transport = SquishyTransport::new(mock, 0, 0);
+        transport.pipe.stall_data_in = true;
+
+        let cmd = commands::read_variable(0x8000, true);
+        let mut data = vec![0u8; 0x8000];
+        let resp = transport.execute(&cmd, &mut data).unwrap();
+        assert!(resp.is_good());
+        assert_eq!(resp.transferred, 0);
+        assert_eq!(transport.into_pipe().cleared, [Endpoint::In]);
+    }
+
+    #[test]
+    fn bus_errors() {
+        let mock = MockEndpoint::new(|_, _| (Vec::new(), ScsiStatus::Good, Vec::new()));
+        let mut transport = SquishyTransport::new(mock, 5, 0);
+        let cmd = commands::space(SpaceCode::Filemarks, 1);
+
+        for (bus_status, err) in [
+            (BUS_SELECTION_TIMEOUT, TransportError::NoDevice),
+            (BUS_TIMEOUT, TransportError::Timeout),
+            (BUS_RESET, TransportError::Protocol("SCSI bus reset")),
+            (0x7F, TransportError::Protocol("SCSI bus error")),
+        ] {
+            transport.pipe.bus_status = bus_status;
+            assert_eq!(transport.execute(&cmd, &mut []).unwrap_err(), err);
+        }
+
+        // And everything is fine again once the bus is
+        transport.pipe.bus_status = BUS_OK;
+        assert!(transport.execute(&cmd, &mut []).unwrap().is_good());
+    }
+
+    #[test]
+    fn tag_mismatch() {
+        let mock = MockEndpoint::new(|_, _| (Vec::new(), ScsiStatus::Good, Vec::new()));
+        let mut transport = SquishyTransport::new(mock, 0, 0);
+        let cmd = commands::test_unit_ready();
+
+        // A status wrapper left over from some earlier command
+        let stale = StatusWrapper::new(0xDEAD, 0, 0x00, BUS_OK, &[]);
+        transport.pipe.outgoing.push_back(stale.to_bytes());
+
+        assert_eq!(
+            transport.execute(&cmd, &mut []).unwrap_err(),
+            TransportError::Protocol("status wrapper tag mismatch")
+        );
+    }
+
+    #[test]
+    fn overrun() {
+        // Something much too big for a status wrapper
+        let mock = MockEndpoint::new(|_, _| (Vec::new(), ScsiStatus::Good, Vec::new()));
+        let mut transport = SquishyTransport::new(mock, 0, 0);
+        let cmd = commands::test_unit_ready();
+
+        transport
+            .pipe
+            .outgoing
+            .push_back(vec![0u8; CSW_MAX_LEN + 1]);
+        assert_eq!(
+            transport.execute(&cmd, &mut []).unwrap_err(),
+            TransportError::Protocol("USB transfer error")
+        );
+    }
+}
@@ -0,0 +1,193 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// A transport over USB to a Squishy running its SCSI initiator applet.
+//
+// The framing is in `framing`, this is just what it takes to find a Squishy and push bytes
+// at its bulk endpoints. It's only built with the `squishy` feature, as nothing on the other
+// end speaks that framing yet.
+
+use core::time::Duration;
+
+use tracing::{debug, trace, warn};
+use uefi::{Handle, Status, boot};
+
+use crate::{
+    display,
+    platform::uefi::{
+        get_proto_handles, get_proto_shared,
+        usb::{self, UsbIo},
+    },
+    tape::transport::Transport,
+};
+
+mod framing;
+
+pub use framing::{BulkPipe, Endpoint, PipeError, SquishyTransport};
+
+// NOTE(aki): These are the pid.codes allocations for Squishy
+pub const SQUISHY_VID: u16 = 0x1209;
+pub const SQUISHY_PID: u16 = 0xCA70;
+
+// Squishy sits at ID 7 on the bus, as is tradition
+pub const INITIATOR_ID: u8 = 7;
+// We only care about narrow SCSI, so IDs 0 through 7
+pub const MAX_TARGETS: u8 = 8;
+
+const CONTROL_TIMEOUT_MS: u32 = 1000;
+
+pub struct UsbPipe {
+    proto: boot::ScopedProtocol<UsbIo>,
+    ep_in: u8,
+    ep_out: u8,
+}
+
+// NOTE(aki): Same deal as the pass-thru transport, boot services are only touched from
+// the core running the executor.
+unsafe impl Send for UsbPipe {}
+
+impl UsbPipe {
+    fn check(status: Status, result: u32) -> Result<(), PipeError> {
+        if !status.is_error() {
+            return Ok(());
+        }
+
+        if (result & usb::USB_ERR_STALL) != 0 {
+            Err(PipeError::Stall)
+        } else if (result & usb::USB_ERR_TIMEOUT) != 0 || status == Status::TIMEOUT {
+            Err(PipeError::Timeout)
+        } else if result != 0 {
+            Err(PipeError::Usb(result))
+        } else {
+            Err(PipeError::Uefi(status))
+        }
+    }
+}
+
+impl BulkPipe for UsbPipe {
+    fn send(&mut self, data: &[u8], timeout: Duration) -> Result<usize, PipeError> {
+        // NOTE(aki): UsbIo wants a mutable pointer, but doesn't write to OUT buffers
+        let (status, len, result) = self.proto.bulk_transfer(
+            self.ep_out,
+            data.as_ptr().cast_mut(),
+            data.len(),
+            timeout.as_millis() as usize,
+        );
+
+        Self::check(status, result).map(|_| len)
+    }
+
+    fn receive(&mut self, data: &mut [u8], timeout: Duration) -> Result<usize, PipeError> {
+        let (status, len, result) = self.proto.bulk_transfer(
+            self.ep_in,
+            data.as_mut_ptr(),
+            data.len(),
+            timeout.as_millis() as usize,
+        );
+
+        Self::check(status, result).map(|_| len)
+    }
+
+    fn clear_halt(&mut self, endpoint: Endpoint) -> Result<(), PipeError> {
+        let address = match endpoint {
+            Endpoint::In => self.ep_in,
+            Endpoint::Out => self.ep_out,
+        };
+
+        let (status, result) = self.proto.clear_halt(address, CONTROL_TIMEOUT_MS);
+        Self::check(status, result)
+    }
+}
+
+// Find any Squishy devices and probe their SCSI bus for tape drives
+pub fn find_drives() -> Vec<SquishyTransport<UsbPipe>> {
+    let Ok(handles) = get_proto_handles::<UsbIo>() else {
+        debug!("No USB devices found");
+        return Vec::new();
+    };
+
+    let mut drives = Vec::new();
+
+    for handle in handles {
+        let Some((ep_in, ep_out)) = probe(handle) else {
+            continue;
+        };
+
+        debug!(
+            ep_in = display::fmt::hex(ep_in),
+            ep_out = display::fmt::hex(ep_out),
+            "Found Squishy"
+        );
+
+        // Claim the interface, nobody else has any business talking to it
+        let Ok(proto) = boot::open_protocol_exclusive::<UsbIo>(handle) else {
+            warn!("Unable to claim Squishy interface");
+            continue;
+        };
+
+        let mut pipe = UsbPipe {
+            proto,
+            ep_in,
+            ep_out,
+        };
+
+        for target in (0..MAX_TARGETS).filter(|&id| id != INITIATOR_ID) {
+            let mut drive = SquishyTransport::new(pipe, target, 0);
+
+            match drive.inquiry() {
+                Ok(inquiry) if inquiry.is_tape() => {
+                    debug!(device = drive.name(), "Found tape drive: {}", inquiry);
+                    drives.push(drive);
+                    // NOTE(aki): The pipe now belongs to this drive, one drive per Squishy for now
+                    break;
+                }
+                Ok(inquiry) => {
+                    trace!(
+                        device = drive.name(),
+                        "Skipping non-tape device: {}", inquiry
+                    );
+                }
+                Err(err) => trace!(device = drive.name(), "No device: {}", err),
+            }
+
+            pipe = drive.into_pipe();
+        }
+    }
+
+    drives
+}
+
+// Check if `handle` is a Squishy interface we can use, and get its bulk endpoints
+fn probe(handle: Handle) -> Option<(u8, u8)> {
+    let mut proto = get_proto_shared::<UsbIo>(handle).ok()?;
+
+    let device = proto.device_descriptor().ok()?;
+    let (vid, pid) = (device.id_vendor, device.id_product);
+    if vid != SQUISHY_VID || pid != SQUISHY_PID {
+        return None;
+    }
+
+    let interface = proto.interface_descriptor().ok()?;
+    if interface.interface_class != usb::CLASS_VENDOR_SPECIFIC {
+        return None;
+    }
+
+    let mut ep_in = None;
+    let mut ep_out = None;
+
+    for index in 0..interface.num_endpoints {
+        let Ok(endpoint) = proto.endpoint_descriptor(index) else {
+            continue;
+        };
+
+        if !endpoint.is_bulk() {
+            continue;
+        }
+
+        if endpoint.is_in() {
+            ep_in.get_or_insert(endpoint.endpoint_address);
+        } else {
+            ep_out.get_or_insert(endpoint.endpoint_address);
+        }
+    }
+
+    Some((ep_in?, ep_out?))
+}
@@ -0,0 +1,98 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// Volume sets that span more than one reel.
+//
+// A 2400 ft reel only holds so much, so big payloads carry on onto another reel. When whatever
+// is reading the tape runs off the end of one, the `ReelChanger` unloads it, puts up a prompt
+// asking the operator to mount the next, and waits for the drive to come ready again. It is
+// up to the caller to check the reel that got mounted is the right one, as only it knows what
+// is meant to be on it.
+
+use core::time::Duration;
+
+use tracing::{info, warn};
+
+use crate::tape::device::{DeviceError, TapeDevice};
+
+// How long to wait for someone to swap the reel before giving up on them
+pub const MOUNT_TIMEOUT: Duration = Duration::from_hours(1);
+
+// Somewhere to ask the operator to go and swap the reel, the framebuffer outside of tests
+pub trait Operator: Send + Sync {
+    // Put up a prompt with `title` and `lines`, until `dismiss` is called
+    fn show(&self, title: &str, lines: &[String]);
+    fn dismiss(&self);
+}
+
+pub struct ReelChanger {
+    operator: Box<dyn Operator>,
+    // The volume serials of the reels in the set, in order, if we were told them
+    serials: Vec<String>,
+    // Which reel of the set is mounted, starting from one
+    reel: u32,
+}
+
+impl ReelChanger {
+    pub fn new(operator: impl Operator + 'static, serials: Vec<String>) -> Self {
+        Self {
+            operator: Box::new(operator),
+            serials,
+            reel: 1,
+        }
+    }
+
+    pub fn reel(&self) -> u32 {
+        self.reel
+    }
+
+    // The serial the next reel should have, if we know
+    pub fn next_serial(&self) -> Option<&str> {
+        self.serials.get(self.reel as usize).map(String::as_str)
+    }
+
+    // Unload the reel and wait for the operator to mount the next one
+    //
+    // `problem` is why the last reel they mounted was no good, if they've already had a go.
+    // This can be called as many times as it takes, the reel only counts as changed once the
+    // caller says it's happy with it by calling `mounted`.
+    pub async fn mount_next<D: TapeDevice>(
+        &mut self,
+        device: &mut D,
+        problem: Option<&str>,
+    ) -> Result<(), DeviceError> {
+        let reel = self.reel + 1;
+
+        device.unload().await?;
+
+        let mut lines = Vec::new();
+        if let Some(problem) = problem {
+            lines.push(problem.to_string());
+            lines.push(String::new());
+        }
+        lines.push(match self.next_serial() {
+            Some(serial) => format!("Mount reel {reel} (volume {serial}) on {}", device.name()),
+            None => format!("Mount reel {reel} on {}", device.name()),
+        });
+        lines.push("and bring the drive on-line to carry on".to_string());
+
+        // NOTE(aki): Log it before putting up the prompt, or the log will scribble over it
+        warn!(
+            device = device.name(),
+            reel,
+            serial = self.next_serial(),
+            problem,
+            "Waiting for the next reel to be mounted"
+        );
+        self.operator.show("END OF REEL", &lines);
+
+        let result = device.load(MOUNT_TIMEOUT).await;
+        self.operator.dismiss();
+
+        result
+    }
+
+    // The reel that was mounted is the one we wanted
+    pub fn mounted(&mut self, serial: Option<&str>) {
+        self.reel += 1;
+        info!(reel = self.reel, serial, "Carrying on from the next reel");
+    }
+}
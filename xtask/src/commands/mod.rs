@@ -6,6 +6,7 @@ use crate::utils;
 
 mod ovmf;
 mod qemu;
+mod tape;
 mod taperipper;
 
 pub type CmdExec = fn(&ArgMatches) -> utils::Result;
@@ -49,6 +50,9 @@ pub fn init() -> Vec<Command> {
         qemu::shell::init(),
         taperipper::build::init(),
         taperipper::check::init(),
+        tape::mktape::init(),
+        tape::inspect::init(),
+        tape::sign::init(),
     ]
 }
 
@@ -61,6 +65,9 @@ pub fn exec(command: &str) -> Option<CmdExec> {
         qemu::shell::COMMAND_NAME => Some(qemu::shell::exec),
         taperipper::build::COMMAND_NAME => Some(taperipper::build::exec),
         taperipper::check::COMMAND_NAME => Some(taperipper::check::exec),
+        tape::mktape::COMMAND_NAME => Some(tape::mktape::exec),
+        tape::inspect::COMMAND_NAME => Some(tape::inspect::exec),
+        tape::sign::COMMAND_NAME => Some(tape::sign::exec),
         _ => None,
     }
 }
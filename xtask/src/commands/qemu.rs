@@ -76,6 +76,22 @@ pub mod run {
                         .long("debug")
                         .action(ArgAction::SetTrue)
                         .help(""),
+                )
+                .arg(
+                    Arg::new("SCSI_TAPE")
+                        .long("scsi-tape")
+                        .action(ArgAction::Set)
+                        .value_name("SG_DEVICE")
+                        .help("Pass a host SCSI tape drive (e.g. /dev/sg0) through to the guest"),
+                )
+                .arg(
+                    Arg::new("SCSI_HBA")
+                        .long("scsi-hba")
+                        .action(ArgAction::Set)
+                        .value_name("HBA")
+                        .value_parser(["virtio", "lsi"])
+                        .default_value("virtio")
+                        .help("SCSI controller to attach the passed through tape drive to"),
                 ),
         )
     }
@@ -142,6 +158,25 @@ pub mod run {
             qemu.args(&["-S", "-s"]);
         }
 
+        if let Some(sg_dev) = args.get_one::<String>("SCSI_TAPE") {
+            // NOTE(aki): QEMU can't emulate a tape drive, this needs a real one via `scsi-generic`
+            let hba = match args.get_one::<String>("SCSI_HBA").map(String::as_str) {
+                Some("lsi") => "lsi53c895a,id=scsi0",
+                _ => "virtio-scsi-pci,id=scsi0",
+            };
+
+            debug!("Attaching SCSI tape drive {} to {}", sg_dev, hba);
+
+            qemu.args(&[
+                "-device",
+                hba,
+                "-drive",
+                format!("if=none,id=tape0,format=raw,file={}", sg_dev).as_str(),
+                "-device",
+                "scsi-generic,drive=tape0,bus=scsi0.0",
+            ]);
+        }
+
         if !qemu.status()?.success() {
             Err("QEMU Exited with an error condition!")?;
         }
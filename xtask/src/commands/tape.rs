@@ -0,0 +1,2220 @@
+// SPDX-License-Identifier: BSD-3-Clause
+// Tape images for Taperipper to boot from.
+//
+// These are the same SIMH `.tap` and AWSTAPE `.aws` images that Taperipper reads out of
+// the ESP when there's no drive attached, see `taperipper/src/tape/image`.
+//
+// see: https://simh.trailing-edge.com/docs/simh_magtape.pdf
+// see: https://www.hercules-390.eu/hercules/tapeconv.html
+
+use std::{io::Write, path::Path};
+
+use tracing::warn;
+
+use crate::utils;
+
+const SIMH_TAPE_MARK: u32 = 0x0000_0000;
+const SIMH_END_OF_MEDIUM: u32 = 0xFFFF_FFFF;
+const SIMH_ERASE_GAP: u32 = 0xFFFF_FFFE;
+const SIMH_HALF_GAP: u32 = 0xFFFE_FFFF;
+// Records the image was made with an error reading
+const SIMH_CLASS_BAD: u32 = 0x8000_0000;
+const SIMH_LENGTH_MASK: u32 = 0x0FFF_FFFF;
+
+const AWS_FLAG_NEW_RECORD: u8 = 0x80;
+const AWS_FLAG_TAPE_MARK: u8 = 0x40;
+const AWS_FLAG_END_RECORD: u8 = 0x20;
+const AWS_MAX_CHUNK_LEN: usize = u16::MAX as usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Simh,
+    Aws,
+}
+
+impl ImageFormat {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        match path.as_ref().extension()?.to_str()? {
+            "tap" => Some(ImageFormat::Simh),
+            "aws" => Some(ImageFormat::Aws),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Simh => "tap",
+            ImageFormat::Aws => "aws",
+        }
+    }
+}
+
+// Writes records and tape marks out in one of the image formats
+pub struct ImageWriter<W: Write> {
+    format: ImageFormat,
+    out: W,
+    // Length of the last AWSTAPE chunk, so it can be walked backwards
+    prev_len: u16,
+    // Records still to be flagged as bad, as (file, record in file)
+    bad: Vec<(u32, u64)>,
+    file: u32,
+    record: u64,
+    records: u64,
+    bytes: u64,
+}
+
+impl<W: Write> ImageWriter<W> {
+    pub fn new(format: ImageFormat, out: W) -> Self {
+        Self {
+            format,
+            out,
+            prev_len: 0,
+            bad: Vec::new(),
+            file: 0,
+            record: 0,
+            records: 0,
+            bytes: 0,
+        }
+    }
+
+    pub fn with_bad_records(mut self, bad: Vec<(u32, u64)>) -> Self {
+        self.bad = bad;
+        self
+    }
+
+    // The tape file we're writing
+    pub fn file(&self) -> u32 {
+        self.file
+    }
+
+    pub fn records(&self) -> u64 {
+        self.records
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    pub fn write_record(&mut self, data: &[u8]) -> utils::Result {
+        if data.is_empty() || data.len() > SIMH_LENGTH_MASK as usize {
+            Err(format!("Can't write a {} byte record", data.len()))?;
+        }
+
+        match self.format {
+            ImageFormat::Simh => {
+                let mut marker = data.len() as u32;
+                if let Some(idx) = self
+                    .bad
+                    .iter()
+                    .position(|&bad| bad == (self.file, self.record))
+                {
+                    self.bad.swap_remove(idx);
+                    warn!(
+                        file = self.file,
+                        record = self.record,
+                        "Injecting bad record"
+                    );
+                    marker |= SIMH_CLASS_BAD;
+                }
+
+                self.out.write_all(&marker.to_le_bytes())?;
+                self.out.write_all(data)?;
+                // Records are padded out to an even length
+                if data.len() & 1 != 0 {
+                    self.out.write_all(&[0])?;
+                }
+                self.out.write_all(&marker.to_le_bytes())?;
+            }
+            ImageFormat::Aws => {
+                let count = data.len().div_ceil(AWS_MAX_CHUNK_LEN);
+                for (idx, chunk) in data.chunks(AWS_MAX_CHUNK_LEN).enumerate() {
+                    let mut flags = 0;
+                    if idx == 0 {
+                        flags |= AWS_FLAG_NEW_RECORD;
+                    }
+                    if idx == count - 1 {
+                        flags |= AWS_FLAG_END_RECORD;
+                    }
+
+                    self.write_aws_header(chunk.len() as u16, flags)?;
+                    self.out.write_all(chunk)?;
+                }
+            }
+        }
+
+        self.record += 1;
+        self.records += 1;
+        self.bytes += data.len() as u64;
+
+        Ok(())
+    }
+
+    // Write `data` as `block_len` sized records, padding the last one out with zeros
+    pub fn write_blocks(&mut self, data: &[u8], block_len: usize) -> utils::Result {
+        let mut block = vec![0u8; block_len];
+
+        for chunk in data.chunks(block_len) {
+            if chunk.len() == block_len {
+                self.write_record(chunk)?;
+            } else {
+                block[..chunk.len()].copy_from_slice(chunk);
+                block[chunk.len()..].fill(0);
+                self.write_record(&block)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn write_tape_mark(&mut self) -> utils::Result {
+        match self.format {
+            ImageFormat::Simh => self.out.write_all(&SIMH_TAPE_MARK.to_le_bytes())?,
+            ImageFormat::Aws => self.write_aws_header(0, AWS_FLAG_TAPE_MARK)?,
+        }
+
+        self.file += 1;
+        self.record = 0;
+
+        Ok(())
+    }
+
+    fn write_aws_header(&mut self, len: u16, flags: u8) -> utils::Result {
+        self.out.write_all(&len.to_le_bytes())?;
+        self.out.write_all(&self.prev_len.to_le_bytes())?;
+        self.out.write_all(&[flags, 0])?;
+        self.prev_len = len;
+
+        Ok(())
+    }
+
+    // End the tape with a second tape mark after the last file
+    pub fn finish(mut self) -> core::result::Result<W, utils::Error> {
+        self.write_tape_mark()?;
+
+        for (file, record) in &self.bad {
+            warn!(file, record, "Bad record is past the end of the tape");
+        }
+
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TapeEnd {
+    // Two tape marks in a row, the usual end of data
+    DoubleTapeMark,
+    // A SIMH end of medium marker
+    EndOfMedium,
+    // Ran out of image without either of those
+    Truncated,
+}
+
+pub struct Record {
+    // Where the record starts in the image
+    pub offset: usize,
+    pub data: Vec<u8>,
+    // Flagged as read with an error when the image was made
+    pub bad: bool,
+}
+
+#[derive(Default)]
+pub struct TapeFile {
+    pub records: Vec<Record>,
+    pub errors: Vec<String>,
+    // Ended with a tape mark, rather than running into the end of the tape
+    pub terminated: bool,
+}
+
+impl TapeFile {
+    pub fn bytes(&self) -> usize {
+        self.records.iter().map(|record| record.data.len()).sum()
+    }
+
+    // Everything in the file, as it would be read off of the tape
+    pub fn data(&self) -> Vec<u8> {
+        self.records
+            .iter()
+            .map(|record| record.data.as_slice())
+            .collect::<Vec<_>>()
+            .concat()
+    }
+}
+
+// A whole tape image split up into files and records, in the same vein as goblin
+pub struct TapeImage {
+    pub format: ImageFormat,
+    pub files: Vec<TapeFile>,
+    pub end: TapeEnd,
+    // Bytes in the image past where we stopped
+    pub trailing: usize,
+}
+
+impl TapeImage {
+    pub fn parse(format: ImageFormat, image: &[u8]) -> Self {
+        let mut parser = Parser::default();
+
+        let (end, offset) = match format {
+            ImageFormat::Simh => parser.simh(image),
+            ImageFormat::Aws => parser.aws(image),
+        };
+
+        let mut files = parser.files;
+        if !parser.current.records.is_empty() || !parser.current.errors.is_empty() {
+            files.push(parser.current);
+        }
+
+        Self {
+            format,
+            files,
+            end,
+            trailing: image.len().saturating_sub(offset),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Parser {
+    files: Vec<TapeFile>,
+    current: TapeFile,
+    // Tape marks in a row
+    marks: usize,
+}
+
+impl Parser {
+    // Returns `true` at the end of data
+    fn tape_mark(&mut self) -> bool {
+        self.marks += 1;
+        if self.marks == 2 {
+            return true;
+        }
+
+        let mut file = std::mem::take(&mut self.current);
+        file.terminated = true;
+        self.files.push(file);
+
+        false
+    }
+
+    fn record(&mut self, record: Record) {
+        self.marks = 0;
+        self.current.records.push(record);
+    }
+
+    fn error(&mut self, offset: usize, what: &str) {
+        self.current.errors.push(format!("{what} at {offset:#x}"));
+    }
+
+    fn simh(&mut self, image: &[u8]) -> (TapeEnd, usize) {
+        let mut offset = 0;
+
+        while offset + 4 <= image.len() {
+            let marker = u32::from_le_bytes(image[offset..offset + 4].try_into().unwrap());
+
+            match marker {
+                SIMH_TAPE_MARK => {
+                    offset += 4;
+                    if self.tape_mark() {
+                        return (TapeEnd::DoubleTapeMark, offset);
+                    }
+                    continue;
+                }
+                SIMH_END_OF_MEDIUM => return (TapeEnd::EndOfMedium, offset + 4),
+                SIMH_ERASE_GAP => {
+                    offset += 4;
+                    continue;
+                }
+                SIMH_HALF_GAP => {
+                    offset += 2;
+                    continue;
+                }
+                _ => {}
+            }
+
+            let class = marker >> 28;
+            if matches!(class, 0x7 | 0xF) {
+                // Private markers, with no data
+                offset += 4;
+                continue;
+            }
+
+            let len = (marker & SIMH_LENGTH_MASK) as usize;
+            let start = offset + 4;
+            let trailer = start + len + (len & 1);
+
+            if trailer + 4 > image.len() {
+                self.error(offset, "truncated record");
+                return (TapeEnd::Truncated, offset);
+            }
+            if image[trailer..trailer + 4] != marker.to_le_bytes() {
+                // There's no telling where the next record starts
+                self.error(offset, "record length mismatch");
+                return (TapeEnd::Truncated, offset);
+            }
+
+            match class {
+                0x0 | 0x8 => self.record(Record {
+                    offset,
+                    data: image[start..start + len].to_vec(),
+                    bad: class == 0x8,
+                }),
+                _ => self.error(offset, &format!("private class {class:#x} record")),
+            }
+
+            offset = trailer + 4;
+        }
+
+        (TapeEnd::Truncated, offset)
+    }
+
+    fn aws(&mut self, image: &[u8]) -> (TapeEnd, usize) {
+        let mut offset = 0;
+        let mut prev_len = 0;
+        // The record being put back together from chunks
+        let mut pending: Option<Record> = None;
+
+        while offset + 6 <= image.len() {
+            let len = u16::from_le_bytes([image[offset], image[offset + 1]]) as usize;
+            let flags = image[offset + 4];
+
+            if u16::from_le_bytes([image[offset + 2], image[offset + 3]]) as usize != prev_len {
+                self.error(offset, "previous chunk length mismatch");
+            }
+
+            if flags & AWS_FLAG_TAPE_MARK != 0 {
+                if pending.take().is_some() {
+                    self.error(offset, "record cut short by a tape mark");
+                }
+
+                offset += 6;
+                prev_len = len;
+                if self.tape_mark() {
+                    return (TapeEnd::DoubleTapeMark, offset);
+                }
+                continue;
+            }
+
+            let start = offset + 6;
+            if start + len > image.len() {
+                self.error(offset, "truncated chunk");
+                return (TapeEnd::Truncated, offset);
+            }
+
+            if flags & AWS_FLAG_NEW_RECORD != 0 && pending.is_some() {
+                self.error(offset, "record cut short by the next one");
+                pending = None;
+            }
+            if flags & AWS_FLAG_NEW_RECORD == 0 && pending.is_none() {
+                self.error(offset, "chunk without the start of a record");
+            }
+
+            let record = pending.get_or_insert_with(|| Record {
+                offset,
+                data: Vec::new(),
+                bad: false,
+            });
+            record.data.extend_from_slice(&image[start..start + len]);
+
+            if flags & AWS_FLAG_END_RECORD != 0 {
+                let record = pending.take().unwrap();
+                self.record(record);
+            }
+
+            offset = start + len;
+            prev_len = len;
+        }
+
+        if pending.is_some() {
+            self.error(offset, "last record never ended");
+        }
+
+        (TapeEnd::Truncated, offset)
+    }
+}
+
+// Just enough of tar to put boot payloads into an archive, and see what is in one
+mod tar {
+    use crate::utils;
+
+    pub const BLOCK_LEN: usize = 512;
+
+    fn octal(field: &mut [u8], value: u64) -> utils::Result {
+        let digits = format!("{value:0width$o}", width = field.len() - 1);
+        if digits.len() >= field.len() {
+            Err(format!("{value} is too large for a tar header"))?;
+        }
+
+        field[..digits.len()].copy_from_slice(digits.as_bytes());
+        field[digits.len()] = 0;
+
+        Ok(())
+    }
+
+    fn header(
+        name: &str,
+        size: u64,
+        mtime: u64,
+    ) -> core::result::Result<[u8; BLOCK_LEN], utils::Error> {
+        let mut header = [0u8; BLOCK_LEN];
+
+        if name.len() > 100 {
+            Err(format!("'{name}' is too long for a tar header"))?;
+        }
+
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        octal(&mut header[100..108], 0o644)?;
+        octal(&mut header[108..116], 0)?;
+        octal(&mut header[116..124], 0)?;
+        octal(&mut header[124..136], size)?;
+        octal(&mut header[136..148], mtime)?;
+        header[156] = b'0';
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        header[265..269].copy_from_slice(b"root");
+        header[297..301].copy_from_slice(b"root");
+
+        // The checksum is taken with its own field as spaces
+        header[148..156].fill(b' ');
+        let checksum: u32 = header.iter().map(|&b| b as u32).sum();
+        octal(&mut header[148..155], checksum as u64)?;
+
+        Ok(header)
+    }
+
+    // Build an archive of `files`, padded out to a whole number of `record_len` records
+    pub fn archive(
+        files: &[(&str, &[u8])],
+        mtime: u64,
+        record_len: usize,
+    ) -> core::result::Result<Vec<u8>, utils::Error> {
+        let mut archive = Vec::new();
+
+        for (name, data) in files {
+            archive.extend_from_slice(&header(name, data.len() as u64, mtime)?);
+            archive.extend_from_slice(data);
+            archive.resize(archive.len().next_multiple_of(BLOCK_LEN), 0);
+        }
+
+        // Two zero blocks for the end of the archive
+        archive.resize(archive.len() + BLOCK_LEN * 2, 0);
+        archive.resize(archive.len().next_multiple_of(record_len), 0);
+
+        Ok(archive)
+    }
+    // Numeric fields are octal text, or big-endian binary with the top bit set
+    fn number(field: &[u8]) -> Option<u64> {
+        if field[0] & 0x80 != 0 {
+            return Some(
+                field[1..]
+                    .iter()
+                    .fold((field[0] & 0x7F) as u64, |acc, &b| (acc << 8) | b as u64),
+            );
+        }
+
+        let digits = str::from_utf8(field).ok()?.trim_matches(['\0', ' ']);
+        if digits.is_empty() {
+            return Some(0);
+        }
+
+        u64::from_str_radix(digits, 8).ok()
+    }
+
+    fn field_str(field: &[u8]) -> String {
+        let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+        String::from_utf8_lossy(&field[..len]).into_owned()
+    }
+
+    fn checksum_ok(header: &[u8]) -> bool {
+        let Some(expected) = number(&header[148..156]) else {
+            return false;
+        };
+
+        let sum = |signed: bool| {
+            header.iter().enumerate().fold(0i64, |acc, (idx, &b)| {
+                let b = if (148..156).contains(&idx) {
+                    b' ' as i64
+                } else if signed {
+                    b as i8 as i64
+                } else {
+                    b as i64
+                };
+                acc + b
+            })
+        };
+
+        sum(false) == expected as i64 || sum(true) == expected as i64
+    }
+
+    pub struct Entry {
+        pub path: String,
+        pub kind: u8,
+        pub size: u64,
+        // Where the entry's data starts in the archive
+        pub offset: usize,
+    }
+
+    // Whether `data` starts with something that looks like a tar header
+    pub fn is_archive(data: &[u8]) -> bool {
+        data.len() >= BLOCK_LEN
+            && !data[..BLOCK_LEN].iter().all(|&b| b == 0)
+            && checksum_ok(&data[..BLOCK_LEN])
+    }
+
+    // List the entries in an archive, along with anything wrong with it
+    pub fn entries(data: &[u8]) -> (Vec<Entry>, Option<String>) {
+        let mut entries = Vec::new();
+        let mut offset = 0;
+        // Names and sizes from GNU long name and pax extended headers
+        let mut long_name = None;
+        let mut pax_path = None;
+        let mut pax_size = None;
+
+        while offset + BLOCK_LEN <= data.len() {
+            let header = &data[offset..offset + BLOCK_LEN];
+            if header.iter().all(|&b| b == 0) {
+                return (entries, None);
+            }
+
+            if !checksum_ok(header) {
+                return (entries, Some(format!("bad header checksum at {offset:#x}")));
+            }
+
+            let Some(size) = pax_size.take().or_else(|| number(&header[124..136])) else {
+                return (entries, Some(format!("bad size field at {offset:#x}")));
+            };
+
+            let start = offset + BLOCK_LEN;
+            let Some(end) = start
+                .checked_add(size as usize)
+                .filter(|&end| end <= data.len())
+            else {
+                return (entries, Some(format!("entry at {offset:#x} is truncated")));
+            };
+            let body = &data[start..end];
+
+            match header[156] {
+                b'L' => long_name = Some(field_str(body)),
+                // The target of a link with a long one, which we don't show either
+                b'K' => {}
+                b'x' => {
+                    // "<len> <key>=<value>\n" records
+                    for record in String::from_utf8_lossy(body).lines() {
+                        let Some((_, pair)) = record.split_once(' ') else {
+                            continue;
+                        };
+                        match pair.split_once('=') {
+                            Some(("path", path)) => pax_path = Some(path.to_string()),
+                            Some(("size", size)) => pax_size = size.parse().ok(),
+                            _ => {}
+                        }
+                    }
+                }
+                // Global pax headers apply to everything, but nothing we show
+                b'g' => {}
+                kind => {
+                    let path = pax_path.take().or(long_name.take()).unwrap_or_else(|| {
+                        let name = field_str(&header[0..100]);
+                        match field_str(&header[345..500]) {
+                            prefix if &header[257..262] == b"ustar" && !prefix.is_empty() => {
+                                format!("{prefix}/{name}")
+                            }
+                            _ => name,
+                        }
+                    });
+
+                    entries.push(Entry {
+                        path,
+                        kind,
+                        size,
+                        offset: start,
+                    });
+                }
+            }
+
+            offset = start + (size as usize).next_multiple_of(BLOCK_LEN);
+        }
+
+        (entries, Some("archive has no end".to_string()))
+    }
+}
+
+// The taperipper boot tape manifest, see `taperipper/src/tape/format/manifest.rs`
+mod manifest {
+    pub const MAGIC: [u8; 8] = *b"TRIPBOOT";
+    pub const VERSION: u16 = 1;
+    pub const HEADER_LEN: usize = 16;
+    pub const ENTRY_LEN: usize = 64;
+
+    pub const KIND_KERNEL: u8 = 0x01;
+    pub const KIND_INITRD: u8 = 0x02;
+    pub const KIND_CMDLINE: u8 = 0x03;
+    pub const KIND_CONFIG: u8 = 0x04;
+    pub const KIND_SIGNATURE: u8 = 0x05;
+
+    // Which layer of the initramfs an initrd is
+    pub const LAYER_BASE: u8 = 0x00;
+    pub const LAYER_MICROCODE: u8 = 0x01;
+    pub const LAYER_OVERLAY: u8 = 0x02;
+
+    pub struct Entry {
+        pub kind: u8,
+        // Only for initrds, zero for everything else
+        pub layer: u8,
+        pub file: u32,
+        pub size: u64,
+        pub block_len: u32,
+        pub sha256: [u8; 32],
+    }
+
+    pub fn to_bytes(entries: &[Entry]) -> Vec<u8> {
+        let mut record = Vec::new();
+
+        record.extend_from_slice(&MAGIC);
+        record.extend_from_slice(&VERSION.to_le_bytes());
+        record.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        record.extend_from_slice(&[0u8; 4]);
+
+        for entry in entries {
+            let mut bytes = [0u8; ENTRY_LEN];
+            bytes[0] = entry.kind;
+            bytes[1] = entry.layer;
+            bytes[0x04..0x08].copy_from_slice(&entry.file.to_le_bytes());
+            bytes[0x08..0x10].copy_from_slice(&entry.size.to_le_bytes());
+            bytes[0x10..0x14].copy_from_slice(&entry.block_len.to_le_bytes());
+            bytes[0x20..0x40].copy_from_slice(&entry.sha256);
+            record.extend_from_slice(&bytes);
+        }
+
+        record
+    }
+    pub fn kind_name(kind: u8) -> &'static str {
+        match kind {
+            KIND_KERNEL => "kernel",
+            KIND_INITRD => "initrd",
+            KIND_CMDLINE => "command-line",
+            KIND_CONFIG => "config",
+            KIND_SIGNATURE => "signature",
+            _ => "unknown",
+        }
+    }
+
+    pub fn layer_name(layer: u8) -> &'static str {
+        match layer {
+            LAYER_BASE => "base",
+            LAYER_MICROCODE => "microcode",
+            LAYER_OVERLAY => "overlay",
+            _ => "unknown",
+        }
+    }
+
+    // Pull the entries out of a manifest record, without checking them any further
+    pub fn parse(record: &[u8]) -> Option<(u16, Vec<Entry>)> {
+        if !record.starts_with(&MAGIC) || record.len() < HEADER_LEN {
+            return None;
+        }
+
+        let version = u16::from_le_bytes([record[0x08], record[0x09]]);
+        let count = u16::from_le_bytes([record[0x0A], record[0x0B]]) as usize;
+
+        let entries = record[HEADER_LEN..]
+            .get(..count * ENTRY_LEN)?
+            .as_chunks::<ENTRY_LEN>()
+            .0
+            .iter()
+            .map(|entry| Entry {
+                kind: entry[0],
+                layer: entry[1],
+                file: u32::from_le_bytes(entry[0x04..0x08].try_into().unwrap()),
+                size: u64::from_le_bytes(entry[0x08..0x10].try_into().unwrap()),
+                block_len: u32::from_le_bytes(entry[0x10..0x14].try_into().unwrap()),
+                sha256: entry[0x20..0x40].try_into().unwrap(),
+            })
+            .collect();
+
+        Some((version, entries))
+    }
+}
+
+// Detached payload signatures, see `taperipper/src/tape/format/signature.rs`
+mod signature {
+    use std::{fs, io::Write, path::Path};
+
+    use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+    use sha2::{Digest, Sha256};
+
+    use crate::utils;
+
+    use super::manifest;
+
+    pub const MAGIC: [u8; 8] = *b"TRIPSIGN";
+    pub const SIGNED_MAGIC: [u8; 8] = *b"TRIPPAYL";
+    pub const VERSION: u16 = 1;
+    pub const RECORD_LEN: usize = 112;
+    pub const SIGNED_ENTRY_LEN: usize = 48;
+
+    // What the signature is called in a tar archive
+    pub const FILE_NAME: &str = "boot.sig";
+
+    pub fn to_hex(data: &[u8]) -> String {
+        data.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    // Signing keys are kept as the hex of the secret half, the public half goes next to it
+    // in `<KEY>.pub` to hand to whoever is building Taperipper
+    pub fn read_key(path: &Path) -> core::result::Result<SigningKey, utils::Error> {
+        let hex = fs::read_to_string(path)?;
+        let hex = hex.trim();
+        if hex.len() != 64 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            Err(format!("{} is not a signing key", path.display()))?;
+        }
+
+        let mut secret = [0u8; 32];
+        for (byte, idx) in secret.iter_mut().zip((0..hex.len()).step_by(2)) {
+            *byte = u8::from_str_radix(&hex[idx..idx + 2], 16)?;
+        }
+
+        Ok(SigningKey::from_bytes(&secret))
+    }
+
+    pub fn write_key(path: &Path, key: &SigningKey) -> utils::Result {
+        // NOTE(aki): Never clobber a key, and don't let anyone else read it
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        writeln!(options.open(path)?, "{}", to_hex(key.as_bytes()))?;
+
+        let mut public = path.as_os_str().to_owned();
+        public.push(".pub");
+        fs::write(
+            public,
+            format!("{}\n", to_hex(key.verifying_key().as_bytes())),
+        )?;
+
+        Ok(())
+    }
+
+    // The statement of the payloads that actually gets signed, an initrd in more than one
+    // part is signed as one as that's how it's booted
+    fn signed_message(kernel: &[u8], initrd: &[u8], cmdline: Option<&[u8]>) -> Vec<u8> {
+        let payloads: Vec<(u8, &[u8])> = [(manifest::KIND_KERNEL, kernel)]
+            .into_iter()
+            .chain((!initrd.is_empty()).then_some((manifest::KIND_INITRD, initrd)))
+            .chain(cmdline.map(|cmdline| (manifest::KIND_CMDLINE, cmdline)))
+            .collect();
+
+        let mut message = Vec::new();
+        message.extend_from_slice(&SIGNED_MAGIC);
+        message.extend_from_slice(&VERSION.to_le_bytes());
+        message.extend_from_slice(&(payloads.len() as u16).to_le_bytes());
+        message.extend_from_slice(&[0u8; 4]);
+
+        for (kind, data) in payloads {
+            let mut entry = [0u8; SIGNED_ENTRY_LEN];
+            entry[0] = kind;
+            entry[0x08..0x10].copy_from_slice(&(data.len() as u64).to_le_bytes());
+            entry[0x10..0x30].copy_from_slice(&Sha256::digest(data));
+            message.extend_from_slice(&entry);
+        }
+
+        message
+    }
+
+    pub fn sign(key: &SigningKey, kernel: &[u8], initrd: &[u8], cmdline: Option<&[u8]>) -> Vec<u8> {
+        let signature = key.sign(&signed_message(kernel, initrd, cmdline));
+
+        let mut record = vec![0u8; RECORD_LEN];
+        record[0x00..0x08].copy_from_slice(&MAGIC);
+        record[0x08..0x0A].copy_from_slice(&VERSION.to_le_bytes());
+        record[0x10..0x30].copy_from_slice(key.verifying_key().as_bytes());
+        record[0x30..0x70].copy_from_slice(&signature.to_bytes());
+
+        record
+    }
+
+    // Pull the version and key out of a signature record, without checking it any further
+    pub fn parse(record: &[u8]) -> Option<(u16, [u8; 32], [u8; 64])> {
+        if !record.starts_with(&MAGIC) || record.len() < RECORD_LEN {
+            return None;
+        }
+
+        Some((
+            u16::from_le_bytes([record[0x08], record[0x09]]),
+            record[0x10..0x30].try_into().unwrap(),
+            record[0x30..0x70].try_into().unwrap(),
+        ))
+    }
+
+    // Make sure a signature someone handed us is actually over these payloads
+    pub fn check(
+        record: &[u8],
+        kernel: &[u8],
+        initrd: &[u8],
+        cmdline: Option<&[u8]>,
+    ) -> utils::Result {
+        let (version, key, signature) = parse(record).ok_or("Not a payload signature")?;
+        if version != VERSION {
+            Err(format!("Unsupported signature version {version}"))?;
+        }
+
+        VerifyingKey::from_bytes(&key)?.verify_strict(
+            &signed_message(kernel, initrd, cmdline),
+            &Signature::from_bytes(&signature),
+        )?;
+
+        Ok(())
+    }
+}
+
+// The payloads that go onto a tape, and the arguments to say where they come from
+mod payloads {
+    use std::{fs, path::PathBuf};
+
+    use clap::{Arg, ArgAction, ArgMatches, Command};
+
+    use crate::utils;
+
+    use super::manifest;
+
+    pub struct Payloads {
+        pub kernel: Vec<u8>,
+        pub microcode: Vec<Vec<u8>>,
+        pub initrds: Vec<Vec<u8>>,
+        pub overlays: Vec<Vec<u8>>,
+        pub cmdline: Option<Vec<u8>>,
+    }
+
+    impl Payloads {
+        // Every part of the initrd with its layer, in the order they go on the tape
+        pub fn initrd_parts(&self) -> impl Iterator<Item = (u8, &Vec<u8>)> + Clone {
+            let microcode = self
+                .microcode
+                .iter()
+                .map(|part| (manifest::LAYER_MICROCODE, part));
+            let base = self.initrds.iter().map(|part| (manifest::LAYER_BASE, part));
+            let overlays = self
+                .overlays
+                .iter()
+                .map(|part| (manifest::LAYER_OVERLAY, part));
+
+            microcode.chain(base).chain(overlays)
+        }
+
+        // The whole initrd, as it gets signed
+        pub fn initrd(&self) -> Vec<u8> {
+            self.initrd_parts()
+                .flat_map(|(_, part)| part.iter().copied())
+                .collect()
+        }
+    }
+
+    fn read_all(args: &ArgMatches, id: &str, what: &str) -> Result<Vec<Vec<u8>>, utils::Error> {
+        let parts = args
+            .get_many::<PathBuf>(id)
+            .unwrap_or_default()
+            .map(fs::read)
+            .collect::<Result<Vec<_>, _>>()?;
+        if parts.iter().any(|part| part.is_empty()) {
+            Err(format!("{what} is empty"))?;
+        }
+
+        Ok(parts)
+    }
+
+    pub fn args(cmd: Command) -> Command {
+        cmd.arg(
+            Arg::new("KERNEL")
+                .short('k')
+                .long("kernel")
+                .action(ArgAction::Set)
+                .value_name("KERNEL")
+                .value_parser(clap::value_parser!(PathBuf))
+                .required(true)
+                .help("Kernel image to put on the tape"),
+        )
+        .arg(
+            Arg::new("INITRD")
+                .short('i')
+                .long("initrd")
+                .action(ArgAction::Append)
+                .value_name("INITRD")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Initrd to put on the tape, may be given more than once"),
+        )
+        .arg(
+            Arg::new("MICROCODE")
+                .long("microcode")
+                .action(ArgAction::Append)
+                .value_name("CPIO")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Uncompressed early microcode cpio to go ahead of the initrd, may be given more than once"),
+        )
+        .arg(
+            Arg::new("OVERLAY")
+                .long("overlay")
+                .action(ArgAction::Append)
+                .value_name("CPIO")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Cpio to unpack over the top of the initrd, may be given more than once"),
+        )
+        .arg(
+            Arg::new("CMDLINE")
+                .short('c')
+                .long("cmdline")
+                .action(ArgAction::Set)
+                .value_name("CMDLINE")
+                .help("Kernel command-line"),
+        )
+    }
+
+    pub fn read(args: &ArgMatches) -> core::result::Result<Payloads, utils::Error> {
+        let kernel = fs::read(args.get_one::<PathBuf>("KERNEL").unwrap())?;
+        if kernel.is_empty() {
+            Err("Kernel image is empty")?;
+        }
+
+        let microcode = read_all(args, "MICROCODE", "Microcode")?;
+        let initrds = read_all(args, "INITRD", "Initrd")?;
+        let overlays = read_all(args, "OVERLAY", "Overlay")?;
+
+        let cmdline = args
+            .get_one::<String>("CMDLINE")
+            .map(|cmdline| cmdline.trim())
+            .filter(|cmdline| !cmdline.is_empty())
+            .map(|cmdline| format!("{cmdline}\n").into_bytes());
+
+        Ok(Payloads {
+            kernel,
+            microcode,
+            initrds,
+            overlays,
+            cmdline,
+        })
+    }
+}
+
+// ANSI and IBM standard labels, see `taperipper/src/tape/format/ansi.rs`
+mod labels {
+    pub const LABEL_LEN: usize = 80;
+
+    const LABEL_IDS: &[&[u8; 3]] = &[b"VOL", b"HDR", b"EOF", b"EOV", b"UHL", b"UTL", b"UVL"];
+
+    // Just enough of EBCDIC (code page 037) to read labels
+    fn ebcdic_to_ascii(byte: u8) -> u8 {
+        match byte {
+            0x40 => b' ',
+            0x81..=0x89 => b'a' + (byte - 0x81),
+            0x91..=0x99 => b'j' + (byte - 0x91),
+            0xA2..=0xA9 => b's' + (byte - 0xA2),
+            0xC1..=0xC9 => b'A' + (byte - 0xC1),
+            0xD1..=0xD9 => b'J' + (byte - 0xD1),
+            0xE2..=0xE9 => b'S' + (byte - 0xE2),
+            0xF0..=0xF9 => b'0' + (byte - 0xF0),
+            0x4B => b'.',
+            0x4D => b'(',
+            0x4E => b'+',
+            0x50 => b'&',
+            0x5B => b'$',
+            0x5C => b'*',
+            0x5D => b')',
+            0x5E => b';',
+            0x60 => b'-',
+            0x61 => b'/',
+            0x6B => b',',
+            0x6D => b'_',
+            0x7A => b':',
+            0x7B => b'#',
+            0x7C => b'@',
+            0x7D => b'\'',
+            0x7E => b'=',
+            _ => b'?',
+        }
+    }
+
+    fn is_label(record: &[u8]) -> bool {
+        record.len() == LABEL_LEN
+            && LABEL_IDS.iter().any(|id| record.starts_with(*id))
+            && record[3].is_ascii_digit()
+    }
+
+    // Translate a label into ASCII, along with whether it was EBCDIC
+    pub fn decode(record: &[u8]) -> Option<(String, bool)> {
+        if is_label(record) {
+            return Some((String::from_utf8_lossy(record).into_owned(), false));
+        }
+
+        let translated: Vec<u8> = record.iter().map(|&b| ebcdic_to_ascii(b)).collect();
+        is_label(&translated).then(|| (String::from_utf8_lossy(&translated).into_owned(), true))
+    }
+
+    // Pick out the fields we care about from a label
+    pub fn describe(label: &str, ibm: bool) -> String {
+        let field = |range: std::ops::Range<usize>| label.get(range).unwrap_or("").trim();
+
+        match &label[0..4] {
+            "VOL1" => format!(
+                "VOL1 serial '{}' owner '{}'",
+                field(4..10),
+                if ibm { field(41..51) } else { field(37..51) }
+            ),
+            "HDR1" | "EOF1" | "EOV1" => format!(
+                "{} file '{}' set '{}' section {} sequence {} blocks {}",
+                &label[0..4],
+                field(4..21),
+                field(21..27),
+                field(27..31),
+                field(31..35),
+                field(54..60),
+            ),
+            "HDR2" | "EOF2" | "EOV2" => format!(
+                "{} format {} block {} record {}",
+                &label[0..4],
+                field(4..5),
+                field(5..10),
+                field(10..15),
+            ),
+            _ => label.trim_end().to_string(),
+        }
+    }
+}
+
+pub mod mktape {
+    use std::{
+        fs::{self, File},
+        io::BufWriter,
+        path::PathBuf,
+        time::{SystemTime, UNIX_EPOCH},
+    };
+
+    use clap::{Arg, ArgAction, ArgMatches, Command};
+    use sha2::{Digest, Sha256};
+    use tracing::{debug, info, warn};
+
+    use crate::utils;
+
+    use super::{ImageFormat, ImageWriter, manifest, payloads, signature, tar};
+
+    pub const COMMAND_NAME: &str = "mktape";
+
+    fn parse_bad_record(value: &str) -> Result<(u32, u64), String> {
+        let (file, record) = value
+            .split_once(':')
+            .ok_or("expected FILE:RECORD".to_string())?;
+
+        Ok((
+            file.parse()
+                .map_err(|err| format!("bad file number: {err}"))?,
+            record
+                .parse()
+                .map_err(|err| format!("bad record number: {err}"))?,
+        ))
+    }
+
+    pub fn init() -> Command {
+        payloads::args(
+            Command::new(COMMAND_NAME).about("Master a tape image to boot Taperipper from"),
+        )
+        .arg(
+            Arg::new("FORMAT")
+                .short('f')
+                .long("format")
+                .action(ArgAction::Set)
+                .value_name("FORMAT")
+                .value_parser(["simh", "aws"])
+                .default_value("simh")
+                .help("Tape image format"),
+        )
+        .arg(
+            Arg::new("LAYOUT")
+                .short('l')
+                .long("layout")
+                .action(ArgAction::Set)
+                .value_name("LAYOUT")
+                .value_parser(["files", "tar"])
+                .default_value("files")
+                .help("A manifest and one tape file per payload, or a single tar archive"),
+        )
+        .arg(
+            Arg::new("BLOCK_SIZE")
+                .short('b')
+                .long("block-size")
+                .action(ArgAction::Set)
+                .value_name("BYTES")
+                .value_parser(clap::value_parser!(u32).range(1..=0x0FFF_FFFF))
+                .default_value("10240")
+                .help("Size of the records written to tape"),
+        )
+        .arg(
+            Arg::new("BAD_RECORD")
+                .long("bad-record")
+                .action(ArgAction::Append)
+                .value_name("FILE:RECORD")
+                .value_parser(parse_bad_record)
+                .help("Flag a record as bad to test error handling, may be given more than once"),
+        )
+        .arg(
+            Arg::new("OUTPUT")
+                .short('o')
+                .long("output")
+                .action(ArgAction::Set)
+                .value_name("OUTPUT")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Where to write the image, defaults to the ESP for run-qemu"),
+        )
+        .arg(
+            Arg::new("SIGN")
+                .short('s')
+                .long("sign")
+                .action(ArgAction::Set)
+                .value_name("KEY")
+                .value_parser(clap::value_parser!(PathBuf))
+                .conflicts_with("SIGNATURE")
+                .help("Sign the payloads with a key made by sign-payloads"),
+        )
+        .arg(
+            Arg::new("SIGNATURE")
+                .long("signature")
+                .action(ArgAction::Set)
+                .value_name("SIGNATURE")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Put a signature made by sign-payloads on the tape"),
+        )
+    }
+
+    pub fn exec(args: &ArgMatches) -> utils::Result {
+        let format = match args.get_one::<String>("FORMAT").unwrap().as_str() {
+            "aws" => ImageFormat::Aws,
+            _ => ImageFormat::Simh,
+        };
+        let layout = args.get_one::<String>("LAYOUT").unwrap().as_str();
+        let block_len = *args.get_one::<u32>("BLOCK_SIZE").unwrap() as usize;
+        if layout == "tar" && !block_len.is_multiple_of(tar::BLOCK_LEN) {
+            Err("Block size must be a multiple of 512 for a tar archive")?;
+        }
+
+        let bad: Vec<_> = args
+            .get_many::<(u32, u64)>("BAD_RECORD")
+            .map(|bad| bad.copied().collect())
+            .unwrap_or_default();
+
+        let payloads = payloads::read(args)?;
+        let (kernel, cmdline) = (&payloads.kernel, &payloads.cmdline);
+
+        // NOTE(aki): Linux is happy with multiple cpio archives back to back, taperipper
+        // signs the initrd as one in the order the parts are on the tape
+        let initrd = payloads.initrd();
+
+        let signature = if let Some(key) = args.get_one::<PathBuf>("SIGN") {
+            let key = signature::read_key(key)?;
+            info!(
+                key = signature::to_hex(key.verifying_key().as_bytes()),
+                "Signing payloads"
+            );
+            Some(signature::sign(&key, kernel, &initrd, cmdline.as_deref()))
+        } else if let Some(path) = args.get_one::<PathBuf>("SIGNATURE") {
+            let record = fs::read(path)?;
+            signature::check(&record, kernel, &initrd, cmdline.as_deref()).map_err(|err| {
+                format!(
+                    "{} is not a signature of these payloads: {err}",
+                    path.display()
+                )
+            })?;
+            Some(record)
+        } else {
+            None
+        };
+
+        let output = match args.get_one::<PathBuf>("OUTPUT") {
+            Some(output) => output.clone(),
+            None => {
+                let dir = crate::paths::efi_tape_dir();
+                utils::need_dir(&dir)?;
+
+                // Taperipper boots the first one it finds, so the other one would get in the way
+                for other in ["boot.tap", "boot.aws"] {
+                    let other = dir.join(other);
+                    if other.extension().unwrap() != format.extension() && other.exists() {
+                        warn!("{} exists, and will be booted instead", other.display());
+                    }
+                }
+
+                dir.join(format!("boot.{}", format.extension()))
+            }
+        };
+
+        info!("Writing tape image to {}", output.display());
+
+        // NOTE(aki): AWSTAPE has no way to flag a record as bad
+        if format != ImageFormat::Simh && !bad.is_empty() {
+            Err("Bad records can only be injected into SIMH images")?;
+        }
+
+        let mut tape =
+            ImageWriter::new(format, BufWriter::new(File::create(&output)?)).with_bad_records(bad);
+
+        match layout {
+            "tar" => {
+                // Every layer goes in as one file, taperipper picks them out by name
+                let (microcode, base, overlay) = (
+                    payloads.microcode.concat(),
+                    payloads.initrds.concat(),
+                    payloads.overlays.concat(),
+                );
+
+                let mut files: Vec<(&str, &[u8])> = vec![("vmlinuz", kernel)];
+                for (name, data) in [
+                    ("microcode.cpio", &microcode),
+                    ("initrd.img", &base),
+                    ("overlay.cpio", &overlay),
+                ] {
+                    if !data.is_empty() {
+                        files.push((name, data));
+                    }
+                }
+                if let Some(cmdline) = cmdline {
+                    files.push(("cmdline", cmdline));
+                }
+                if let Some(signature) = &signature {
+                    files.push((signature::FILE_NAME, signature));
+                }
+
+                let mtime = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+                let archive = tar::archive(&files, mtime, block_len)?;
+                debug!(
+                    len = archive.len(),
+                    blocking_factor = block_len / tar::BLOCK_LEN,
+                    "Built tar archive"
+                );
+
+                tape.write_blocks(&archive, block_len)?;
+                tape.write_tape_mark()?;
+            }
+            _ => {
+                // The manifest is file 0, and every payload gets the next file along
+                let files = [(manifest::KIND_KERNEL, manifest::LAYER_BASE, kernel)]
+                    .into_iter()
+                    .chain(
+                        payloads
+                            .initrd_parts()
+                            .map(|(layer, initrd)| (manifest::KIND_INITRD, layer, initrd)),
+                    )
+                    .chain(
+                        cmdline
+                            .iter()
+                            .map(|cmdline| (manifest::KIND_CMDLINE, manifest::LAYER_BASE, cmdline)),
+                    )
+                    .chain(signature.iter().map(|signature| {
+                        (manifest::KIND_SIGNATURE, manifest::LAYER_BASE, signature)
+                    }));
+
+                let entries = files
+                    .clone()
+                    .enumerate()
+                    .map(|(idx, (kind, layer, data))| manifest::Entry {
+                        kind,
+                        layer,
+                        file: idx as u32 + 1,
+                        size: data.len() as u64,
+                        block_len: block_len as u32,
+                        sha256: Sha256::digest(data).into(),
+                    })
+                    .collect::<Vec<_>>();
+
+                tape.write_record(&manifest::to_bytes(&entries))?;
+                tape.write_tape_mark()?;
+
+                for (_, _, data) in files {
+                    debug!(file = tape.file(), len = data.len(), "Writing payload");
+                    tape.write_blocks(data, block_len)?;
+                    tape.write_tape_mark()?;
+                }
+            }
+        }
+
+        let (files, records, bytes) = (tape.file(), tape.records(), tape.bytes());
+        tape.finish()?;
+
+        info!("Wrote {files} files, {records} records, {bytes} bytes");
+
+        Ok(())
+    }
+}
+
+pub mod sign {
+    use std::{fs, path::PathBuf};
+
+    use clap::{Arg, ArgAction, ArgMatches, Command};
+    use ed25519_dalek::SigningKey;
+    use rand_core::OsRng;
+    use tracing::info;
+
+    use crate::utils;
+
+    use super::{payloads, signature};
+
+    pub const COMMAND_NAME: &str = "sign-payloads";
+
+    pub fn init() -> Command {
+        payloads::args(
+            Command::new(COMMAND_NAME)
+                .about("Sign boot payloads for mktape, or make a key to sign them with"),
+        )
+        .mut_arg("KERNEL", |arg| {
+            arg.required(false).required_unless_present("GENERATE")
+        })
+        .arg(
+            Arg::new("KEY")
+                .short('K')
+                .long("key")
+                .action(ArgAction::Set)
+                .value_name("KEY")
+                .value_parser(clap::value_parser!(PathBuf))
+                .required(true)
+                .help("Signing key"),
+        )
+        .arg(
+            Arg::new("GENERATE")
+                .long("generate")
+                .action(ArgAction::SetTrue)
+                .help("Make a new signing key at KEY, with the public key in KEY.pub"),
+        )
+        .arg(
+            Arg::new("OUTPUT")
+                .short('o')
+                .long("output")
+                .action(ArgAction::Set)
+                .value_name("OUTPUT")
+                .value_parser(clap::value_parser!(PathBuf))
+                .required_unless_present("GENERATE")
+                .help("Where to write the signature"),
+        )
+    }
+
+    pub fn exec(args: &ArgMatches) -> utils::Result {
+        let key_path = args.get_one::<PathBuf>("KEY").unwrap();
+
+        if args.get_flag("GENERATE") {
+            let key = SigningKey::generate(&mut OsRng);
+            signature::write_key(key_path, &key)?;
+
+            let public = signature::to_hex(key.verifying_key().as_bytes());
+            info!("Wrote signing key to {}", key_path.display());
+            info!("Public key: {public}");
+            info!(
+                "Trust it by building with TAPERIPPER_TRUSTED_KEYS set, or enrolling it in the boot services only TAPERIPPER_TRUSTED_KEYS variable"
+            );
+
+            return Ok(());
+        }
+
+        let key = signature::read_key(key_path)?;
+        let payloads = payloads::read(args)?;
+        let record = signature::sign(
+            &key,
+            &payloads.kernel,
+            &payloads.initrd(),
+            payloads.cmdline.as_deref(),
+        );
+
+        let output = args.get_one::<PathBuf>("OUTPUT").unwrap();
+        fs::write(output, &record)?;
+        info!(
+            key = signature::to_hex(key.verifying_key().as_bytes()),
+            "Wrote signature to {}",
+            output.display()
+        );
+
+        Ok(())
+    }
+}
+
+pub mod inspect {
+    use std::{collections::BTreeMap, fs, path::PathBuf};
+
+    use clap::{Arg, ArgAction, ArgMatches, Command};
+    use tracing::info;
+
+    use crate::utils;
+
+    use super::{ImageFormat, TapeEnd, TapeFile, TapeImage, labels, manifest, signature, tar};
+
+    pub const COMMAND_NAME: &str = "inspect-tape";
+
+    // Longest text preview to show
+    const PREVIEW_LEN: usize = 60;
+
+    pub fn init() -> Command {
+        Command::new(COMMAND_NAME)
+            .about("Dump the structure of a tape image")
+            .arg(
+                Arg::new("IMAGE")
+                    .value_name("IMAGE")
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("Tape image to inspect, defaults to the one in the ESP"),
+            )
+            .arg(
+                Arg::new("EXTRACT")
+                    .short('x')
+                    .long("extract")
+                    .action(ArgAction::Set)
+                    .value_name("FILE")
+                    .value_parser(clap::value_parser!(usize))
+                    .help("Extract a tape file, counting from 0"),
+            )
+            .arg(
+                Arg::new("OUTPUT")
+                    .short('o')
+                    .long("output")
+                    .action(ArgAction::Set)
+                    .value_name("OUTPUT")
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .requires("EXTRACT")
+                    .help("Where to write the extracted file, defaults to file<N>.bin"),
+            )
+    }
+
+    // Take a guess at what a payload is
+    fn describe_payload(data: &[u8]) -> String {
+        // The boot protocol header in a bzImage, which EFI stub kernels have too
+        let linux = data
+            .get(0x202..0x208)
+            .filter(|hdr| &hdr[0..4] == b"HdrS")
+            .map(|hdr| (hdr[5], hdr[4]));
+
+        if data.starts_with(b"MZ") {
+            return match goblin::pe::PE::parse(data) {
+                Ok(pe) => {
+                    let mut desc = format!(
+                        "PE32{} image, machine {}",
+                        if pe.is_64 { "+" } else { "" },
+                        goblin::pe::header::machine_to_str(pe.header.coff_header.machine)
+                    );
+                    if let Some(header) = pe.header.optional_header {
+                        desc += &format!(", subsystem {}", header.windows_fields.subsystem);
+                        if header.windows_fields.subsystem
+                            == goblin::pe::subsystem::IMAGE_SUBSYSTEM_EFI_APPLICATION
+                        {
+                            desc += " (EFI application)";
+                        }
+                    }
+                    if let Some((major, minor)) = linux {
+                        desc +=
+                            &format!(", Linux kernel with EFI stub, boot protocol {major}.{minor}");
+                    }
+                    desc
+                }
+                Err(err) => format!("broken PE image ({err})"),
+            };
+        }
+
+        if let Some((major, minor)) = linux {
+            return format!("Linux kernel (bzImage), boot protocol {major}.{minor}");
+        }
+
+        if data.starts_with(b"\x7FELF") {
+            return match goblin::elf::Elf::parse(data) {
+                Ok(elf) => format!(
+                    "ELF{} {}, machine {}",
+                    if elf.is_64 { 64 } else { 32 },
+                    goblin::elf::header::et_to_str(elf.header.e_type),
+                    goblin::elf::header::machine_to_str(elf.header.e_machine)
+                ),
+                Err(err) => format!("broken ELF image ({err})"),
+            };
+        }
+
+        const MAGICS: &[(&[u8], &str)] = &[
+            (b"070701", "cpio archive (newc)"),
+            (b"070702", "cpio archive (newc, with checksums)"),
+            (b"\x1F\x8B", "gzip compressed data"),
+            (b"\x28\xB5\x2F\xFD", "zstd compressed data"),
+            (b"\xFD7zXZ\x00", "xz compressed data"),
+            (b"BZh", "bzip2 compressed data"),
+            (b"\x02\x21\x4C\x18", "lz4 compressed data"),
+            (b"\x89LZO", "lzop compressed data"),
+        ];
+
+        if let Some((_, desc)) = MAGICS.iter().find(|(magic, _)| data.starts_with(magic)) {
+            return desc.to_string();
+        }
+
+        let text = data.split(|&b| b == 0).next().unwrap_or_default();
+        if !text.is_empty()
+            && text
+                .iter()
+                .all(|&b| b.is_ascii_graphic() || b.is_ascii_whitespace())
+        {
+            let line = String::from_utf8_lossy(text);
+            let line = line.lines().next().unwrap_or_default();
+            return match line.char_indices().nth(PREVIEW_LEN) {
+                Some((idx, _)) => format!("text: {:?}...", &line[..idx]),
+                None => format!("text: {line:?}"),
+            };
+        }
+
+        "data".to_string()
+    }
+
+    fn show_labels(file: &TapeFile) -> bool {
+        let Some(decoded) = file
+            .records
+            .iter()
+            .map(|record| labels::decode(&record.data))
+            .collect::<Option<Vec<_>>>()
+            .filter(|decoded| !decoded.is_empty())
+        else {
+            return false;
+        };
+
+        let ibm = decoded.iter().any(|(_, ibm)| *ibm);
+        println!("  {} labels", if ibm { "IBM" } else { "ANSI" });
+        for (label, ibm) in decoded {
+            println!("    {}", labels::describe(&label, ibm));
+        }
+
+        true
+    }
+
+    fn show_manifest(file: &TapeFile) -> bool {
+        let Some((version, entries)) = file
+            .records
+            .first()
+            .and_then(|record| manifest::parse(&record.data))
+        else {
+            return false;
+        };
+
+        println!("  Boot tape manifest, version {version}");
+        for entry in entries {
+            let kind = match entry.kind {
+                manifest::KIND_INITRD => {
+                    format!("initrd ({})", manifest::layer_name(entry.layer))
+                }
+                kind => manifest::kind_name(kind).to_string(),
+            };
+            println!(
+                "    {:<20} file {:<3} {:>10} bytes  block {:<6} sha256 {}",
+                kind,
+                entry.file,
+                entry.size,
+                entry.block_len,
+                entry
+                    .sha256
+                    .iter()
+                    .map(|b| format!("{b:02x}"))
+                    .collect::<String>()
+            );
+        }
+
+        true
+    }
+
+    fn show_signature(file: &TapeFile) -> bool {
+        let Some((version, key, _)) = file
+            .records
+            .first()
+            .and_then(|record| signature::parse(&record.data))
+        else {
+            return false;
+        };
+
+        println!("  Payload signature, version {version}");
+        println!("    key {}", signature::to_hex(&key));
+
+        true
+    }
+
+    fn show_tar(data: &[u8]) -> bool {
+        if !tar::is_archive(data) {
+            return false;
+        }
+
+        let (entries, err) = tar::entries(data);
+
+        println!("  tar archive, {} entries", entries.len());
+        for entry in entries {
+            let end = (entry.offset + entry.size as usize).min(data.len());
+            let desc = match entry.kind {
+                b'0' | b'\0' | b'7' => describe_payload(&data[entry.offset..end]),
+                b'1' => "hard link".to_string(),
+                b'2' => "symbolic link".to_string(),
+                b'5' => "directory".to_string(),
+                kind => format!("type '{}'", kind as char),
+            };
+            println!("    {:>10}  {}  ({desc})", entry.size, entry.path);
+        }
+
+        if let Some(err) = err {
+            println!("  ! {err}");
+        }
+
+        true
+    }
+
+    fn show_file(idx: usize, file: &TapeFile) {
+        println!(
+            "File {idx}: {} record{}, {} bytes{}",
+            file.records.len(),
+            if file.records.len() == 1 { "" } else { "s" },
+            file.bytes(),
+            if file.terminated {
+                ""
+            } else {
+                ", no tape mark"
+            }
+        );
+
+        let mut histogram = BTreeMap::new();
+        for record in &file.records {
+            *histogram.entry(record.data.len()).or_insert(0) += 1;
+        }
+        if !histogram.is_empty() {
+            println!(
+                "  Block sizes: {}",
+                histogram
+                    .iter()
+                    .map(|(len, count)| format!("{len} x{count}"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+
+        for (num, record) in file.records.iter().enumerate() {
+            if record.bad {
+                println!("  ! record {num} at {:#x} is flagged bad", record.offset);
+            }
+        }
+        for err in &file.errors {
+            println!("  ! {err}");
+        }
+
+        if file.records.is_empty() {
+            return;
+        }
+
+        if show_labels(file) || show_manifest(file) || show_signature(file) {
+            return;
+        }
+
+        let data = file.data();
+        if !show_tar(&data) {
+            println!("  {}", describe_payload(&data));
+        }
+    }
+
+    pub fn exec(args: &ArgMatches) -> utils::Result {
+        let path = match args.get_one::<PathBuf>("IMAGE") {
+            Some(path) => path.clone(),
+            None => ["boot.tap", "boot.aws"]
+                .iter()
+                .map(|name| crate::paths::efi_tape_dir().join(name))
+                .find(|path| path.exists())
+                .ok_or("No tape image given, and there's none in the ESP")?,
+        };
+
+        let format = ImageFormat::from_path(&path)
+            .ok_or("Unknown tape image format, expected a .tap or .aws file")?;
+        let image = TapeImage::parse(format, &fs::read(&path)?);
+
+        if let Some(&idx) = args.get_one::<usize>("EXTRACT") {
+            let file = image.files.get(idx).ok_or(format!(
+                "There are only {} files on the tape",
+                image.files.len()
+            ))?;
+            let mut data = file.data();
+
+            // Drop the padding off of the end of fixed block payloads
+            if let Some((_, entries)) = image
+                .files
+                .first()
+                .and_then(|file| file.records.first())
+                .and_then(|record| manifest::parse(&record.data))
+                && let Some(entry) = entries.iter().find(|entry| entry.file as usize == idx)
+            {
+                data.truncate(entry.size as usize);
+            }
+
+            let output = args
+                .get_one::<PathBuf>("OUTPUT")
+                .cloned()
+                .unwrap_or_else(|| PathBuf::from(format!("file{idx}.bin")));
+
+            fs::write(&output, &data)?;
+            info!("Wrote {} bytes to {}", data.len(), output.display());
+
+            return Ok(());
+        }
+
+        println!(
+            "{}: {} image, {} file{}",
+            path.display(),
+            match image.format {
+                ImageFormat::Simh => "SIMH",
+                ImageFormat::Aws => "AWSTAPE",
+            },
+            image.files.len(),
+            if image.files.len() == 1 { "" } else { "s" }
+        );
+
+        for (idx, file) in image.files.iter().enumerate() {
+            show_file(idx, file);
+        }
+
+        println!(
+            "{}",
+            match image.end {
+                TapeEnd::DoubleTapeMark => "End of data",
+                TapeEnd::EndOfMedium => "End of medium",
+                TapeEnd::Truncated => "End of image, without an end of data marker",
+            }
+        );
+        if image.trailing != 0 {
+            println!("  ! {} bytes of the image left unread", image.trailing);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use ed25519_dalek::SigningKey;
+
+    use super::*;
+
+    // The images and manifest Taperipper's own tests read, made by `mktape` with these payloads
+    const BOOT_TAP: &[u8] = include_bytes!("../../../taperipper/src/tape/image/testdata/boot.tap");
+    const BOOT_AWS: &[u8] = include_bytes!("../../../taperipper/src/tape/image/testdata/boot.aws");
+    const MANIFEST: &[u8] =
+        include_bytes!("../../../taperipper/src/tape/format/testdata/manifest.bin");
+    // And what GNU tar makes, for the tar reader there
+    const USTAR: &[u8] = include_bytes!("../../../taperipper/src/tape/format/testdata/ustar.tar");
+    const PAX: &[u8] = include_bytes!("../../../taperipper/src/tape/format/testdata/pax.tar");
+    const GNU: &[u8] = include_bytes!("../../../taperipper/src/tape/format/testdata/gnu.tar");
+
+    // A scratch directory with the payloads in it, gone again once the test is done
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("xtask-{}-{name}", std::process::id()));
+            fs::create_dir_all(&dir).unwrap();
+
+            fs::write(dir.join("vmlinuz"), "kernel image\n".repeat(100)).unwrap();
+            fs::write(dir.join("initrd.img"), "initrd\n").unwrap();
+            fs::write(dir.join("ucode.cpio"), "microcode").unwrap();
+
+            Self(dir)
+        }
+
+        fn path(&self, name: &str) -> String {
+            self.0.join(name).to_str().unwrap().to_string()
+        }
+
+        fn mktape(&self, output: &str, extra: &[&str]) -> Vec<u8> {
+            let output = self.path(output);
+            let args = [
+                mktape::COMMAND_NAME,
+                "-k",
+                &self.path("vmlinuz"),
+                "-i",
+                &self.path("initrd.img"),
+                "-c",
+                "console=ttyS0",
+                "-o",
+                &output,
+            ];
+
+            let matches = mktape::init()
+                .try_get_matches_from(args.iter().chain(extra))
+                .unwrap();
+            mktape::exec(&matches).unwrap();
+
+            fs::read(output).unwrap()
+        }
+
+        fn extract(&self, image: &str, file: usize) -> Vec<u8> {
+            let output = self.path("extracted");
+            let matches = inspect::init()
+                .try_get_matches_from([
+                    inspect::COMMAND_NAME,
+                    &self.path(image),
+                    "-x",
+                    &file.to_string(),
+                    "-o",
+                    &output,
+                ])
+                .unwrap();
+            inspect::exec(&matches).unwrap();
+
+            fs::read(output).unwrap()
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn records(file: &TapeFile) -> Vec<(usize, bool)> {
+        file.records
+            .iter()
+            .map(|record| (record.data.len(), record.bad))
+            .collect()
+    }
+
+    #[test]
+    fn mktape() {
+        let scratch = Scratch::new("mktape");
+
+        // Byte for byte what Taperipper is tested against
+        let simh = scratch.mktape("boot.tap", &["-b", "512", "--bad-record", "2:0"]);
+        assert!(simh == BOOT_TAP);
+        let aws = scratch.mktape("boot.aws", &["-b", "512", "-f", "aws"]);
+        assert!(aws == BOOT_AWS);
+
+        let microcode = scratch.mktape(
+            "microcode.tap",
+            &["-b", "512", "--microcode", &scratch.path("ucode.cpio")],
+        );
+        let image = TapeImage::parse(ImageFormat::Simh, &microcode);
+        assert!(image.files[0].records[0].data == MANIFEST);
+    }
+
+    #[test]
+    fn inspect() {
+        let scratch = Scratch::new("inspect");
+        scratch.mktape("boot.tap", &["-b", "512", "--bad-record", "2:0"]);
+        scratch.mktape("boot.aws", &["-b", "512", "-f", "aws"]);
+
+        for (name, format) in [
+            ("boot.tap", ImageFormat::Simh),
+            ("boot.aws", ImageFormat::Aws),
+        ] {
+            let image = TapeImage::parse(format, &fs::read(scratch.path(name)).unwrap());
+            assert_eq!(image.end, TapeEnd::DoubleTapeMark);
+            assert_eq!(image.trailing, 0);
+
+            let files: Vec<_> = image.files.iter().map(records).collect();
+            let bad = format == ImageFormat::Simh;
+            assert_eq!(
+                files,
+                [
+                    vec![(16 + 3 * manifest::ENTRY_LEN, false)],
+                    vec![(512, false); 3],
+                    vec![(512, bad)],
+                    vec![(512, false)],
+                ]
+            );
+            assert!(
+                image
+                    .files
+                    .iter()
+                    .all(|file| file.terminated && file.errors.is_empty())
+            );
+
+            let (version, entries) = manifest::parse(&image.files[0].records[0].data).unwrap();
+            assert_eq!(version, manifest::VERSION);
+            let entries: Vec<_> = entries
+                .iter()
+                .map(|entry| {
+                    (
+                        entry.kind,
+                        entry.layer,
+                        entry.file,
+                        entry.size,
+                        entry.block_len,
+                    )
+                })
+                .collect();
+            assert_eq!(
+                entries,
+                [
+                    (manifest::KIND_KERNEL, manifest::LAYER_BASE, 1, 1300, 512),
+                    (manifest::KIND_INITRD, manifest::LAYER_BASE, 2, 7, 512),
+                    (manifest::KIND_CMDLINE, manifest::LAYER_BASE, 3, 14, 512),
+                ]
+            );
+
+            // Extracting a payload leaves the padding behind
+            assert_eq!(
+                scratch.extract(name, 1),
+                "kernel image\n".repeat(100).as_bytes()
+            );
+            assert_eq!(scratch.extract(name, 2), b"initrd\n");
+            assert_eq!(scratch.extract(name, 3), b"console=ttyS0\n");
+        }
+    }
+
+    #[test]
+    fn tar_layout() {
+        let scratch = Scratch::new("tar");
+        let image = scratch.mktape(
+            "boot.tap",
+            &["-l", "tar", "--microcode", &scratch.path("ucode.cpio")],
+        );
+
+        let image = TapeImage::parse(ImageFormat::Simh, &image);
+        assert_eq!(image.files.len(), 1);
+        assert_eq!(records(&image.files[0]), [(10240, false)]);
+
+        let data = image.files[0].data();
+        assert!(tar::is_archive(&data));
+        let (entries, err) = tar::entries(&data);
+        assert_eq!(err, None);
+
+        let contents: Vec<_> = entries
+            .iter()
+            .map(|entry| {
+                let start = entry.offset;
+                (
+                    entry.path.as_str(),
+                    entry.kind,
+                    &data[start..start + entry.size as usize],
+                )
+            })
+            .collect();
+        assert_eq!(
+            contents,
+            [
+                ("vmlinuz", b'0', "kernel image\n".repeat(100).as_bytes()),
+                ("microcode.cpio", b'0', b"microcode".as_slice()),
+                ("initrd.img", b'0', b"initrd\n"),
+                ("cmdline", b'0', b"console=ttyS0\n"),
+            ]
+        );
+
+        // Record lengths that aren't a whole number of tar blocks are turned away
+        let matches = mktape::init()
+            .try_get_matches_from([
+                mktape::COMMAND_NAME,
+                "-k",
+                &scratch.path("vmlinuz"),
+                "-l",
+                "tar",
+                "-b",
+                "1000",
+                "-o",
+                &scratch.path("bad.tap"),
+            ])
+            .unwrap();
+        assert!(mktape::exec(&matches).is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        let big: Vec<u8> = (0..AWS_MAX_CHUNK_LEN * 2 + 10)
+            .map(|idx| idx as u8)
+            .collect();
+        // NOTE(aki): Not an empty file, that's two tape marks in a row and the end of data
+        let files: [&[&[u8]]; 3] = [&[b"odd", b"even"], &[b"z"], &[&big, b"x"]];
+
+        for format in [ImageFormat::Simh, ImageFormat::Aws] {
+            let bad = match format {
+                ImageFormat::Simh => vec![(2, 1)],
+                ImageFormat::Aws => Vec::new(),
+            };
+            let mut writer = ImageWriter::new(format, Vec::new()).with_bad_records(bad);
+            for file in files {
+                for record in file {
+                    writer.write_record(record).unwrap();
+                }
+                writer.write_tape_mark().unwrap();
+            }
+            assert_eq!((writer.file(), writer.records()), (3, 5));
+            assert!(writer.write_record(&[]).is_err());
+
+            let image = TapeImage::parse(format, &writer.finish().unwrap());
+            assert_eq!(image.format, format);
+            assert_eq!(image.end, TapeEnd::DoubleTapeMark);
+            assert_eq!(image.trailing, 0);
+            assert_eq!(image.files.len(), 3);
+
+            for (file, records) in image.files.iter().zip(files) {
+                assert!(file.errors.is_empty());
+                let data: Vec<_> = file
+                    .records
+                    .iter()
+                    .map(|record| record.data.as_slice())
+                    .collect();
+                assert_eq!(data, records);
+            }
+            assert_eq!(image.files[2].records[1].bad, format == ImageFormat::Simh);
+        }
+    }
+
+    #[test]
+    fn damaged() {
+        // Cut off part way through the kernel
+        let image = TapeImage::parse(ImageFormat::Simh, &BOOT_TAP[..1000]);
+        assert_eq!(image.end, TapeEnd::Truncated);
+        assert_eq!(image.files.len(), 2);
+        assert!(!image.files[1].terminated);
+        assert_eq!(image.files[1].errors, ["truncated record at 0x2e4"]);
+
+        let image = TapeImage::parse(ImageFormat::Aws, &BOOT_AWS[..1000]);
+        assert_eq!(image.end, TapeEnd::Truncated);
+        assert_eq!(image.files[1].errors, ["truncated chunk at 0x2e2"]);
+
+        // A trailing length that doesn't match the leading one
+        let mut bad = BOOT_TAP.to_vec();
+        let trailer = 4 + 16 + 3 * manifest::ENTRY_LEN;
+        bad[trailer] ^= 1;
+        let image = TapeImage::parse(ImageFormat::Simh, &bad);
+        assert_eq!(image.end, TapeEnd::Truncated);
+        assert_eq!(image.files[0].errors, ["record length mismatch at 0x0"]);
+
+        // Running off the end with a record still open
+        let mut bad = BOOT_AWS.to_vec();
+        bad[4] = AWS_FLAG_NEW_RECORD;
+        let image = TapeImage::parse(ImageFormat::Aws, &bad[..6 + 16 + 3 * manifest::ENTRY_LEN]);
+        assert_eq!(image.files[0].records.len(), 0);
+        assert_eq!(image.files[0].errors, ["last record never ended at 0xd6"]);
+    }
+
+    #[test]
+    fn gnu_tar() {
+        let vmlinuz = format!("boot/{}/vmlinuz", "d".repeat(90));
+        let initrd = format!(
+            "boot/{}/{}/{}/initrd.img",
+            "l".repeat(120),
+            "m".repeat(120),
+            "n".repeat(40)
+        );
+
+        let paths = |archive: &[u8]| {
+            assert!(tar::is_archive(archive));
+            let (entries, err) = tar::entries(archive);
+            assert_eq!(err, None);
+            entries
+                .into_iter()
+                .map(|entry| (entry.path, entry.kind, entry.size))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            paths(USTAR),
+            [
+                (vmlinuz, b'0', 7),
+                ("boot/cmdline".to_string(), b'0', 14),
+                ("boot/current".to_string(), b'2', 0),
+            ]
+        );
+        assert_eq!(
+            paths(PAX),
+            [
+                ("boot/cmdline".to_string(), b'0', 14),
+                (initrd.clone(), b'0', 7),
+            ]
+        );
+        assert_eq!(
+            paths(GNU),
+            [(initrd, b'0', 7), ("boot/longlink".to_string(), b'2', 0)]
+        );
+
+        let mut bad = USTAR.to_vec();
+        bad[1024] ^= 1;
+        let (entries, err) = tar::entries(&bad);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(err.as_deref(), Some("bad header checksum at 0x400"));
+
+        let (_, err) = tar::entries(&USTAR[..1024 + 512 + 3]);
+        assert_eq!(err.as_deref(), Some("entry at 0x400 is truncated"));
+        let (_, err) = tar::entries(&USTAR[..2560]);
+        assert_eq!(err.as_deref(), Some("archive has no end"));
+    }
+
+    #[test]
+    fn signature() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let kernel = b"kernel image\n";
+        let cmdline = Some(b"console=ttyS0\n".as_slice());
+
+        let record = signature::sign(&key, kernel, b"initrd\n", cmdline);
+        assert_eq!(record.len(), signature::RECORD_LEN);
+
+        let (version, public, _) = signature::parse(&record).unwrap();
+        assert_eq!(version, signature::VERSION);
+        assert_eq!(&public, key.verifying_key().as_bytes());
+
+        assert!(signature::check(&record, kernel, b"initrd\n", cmdline).is_ok());
+        assert!(signature::check(&record, kernel, b"initrd?", cmdline).is_err());
+        assert!(signature::check(&record, kernel, b"initrd\n", None).is_err());
+        assert!(signature::check(&record, kernel, b"", cmdline).is_err());
+        assert!(signature::check(&record[..100], kernel, b"initrd\n", cmdline).is_err());
+    }
+
+    #[test]
+    fn labels() {
+        // Laid out the same as the labels in `taperipper/src/tape/format/{ansi,ibm}.rs`
+        let label = |fields: &[(usize, &str)]| {
+            let mut label = vec![b' '; labels::LABEL_LEN];
+            for (offset, field) in fields {
+                label[*offset..*offset + field.len()].copy_from_slice(field.as_bytes());
+            }
+            label
+        };
+
+        let vol1 = label(&[(0, "VOL1"), (4, "TAPE01"), (37, "aki"), (79, "4")]);
+        let (decoded, ibm) = labels::decode(&vol1).unwrap();
+        assert!(!ibm);
+        assert_eq!(
+            labels::describe(&decoded, ibm),
+            "VOL1 serial 'TAPE01' owner 'aki'"
+        );
+
+        // The same in EBCDIC, which only has upper case here
+        let ebcdic = |ascii: &[u8]| -> Vec<u8> {
+            ascii
+                .iter()
+                .map(|&b| match b {
+                    b' ' => 0x40,
+                    b'0'..=b'9' => 0xF0 + (b - b'0'),
+                    b'A'..=b'I' => 0xC1 + (b - b'A'),
+                    b'J'..=b'R' => 0xD1 + (b - b'J'),
+                    b'S'..=b'Z' => 0xE2 + (b - b'S'),
+                    b'.' => 0x4B,
+                    _ => panic!("No EBCDIC for {b:#x}"),
+                })
+                .collect()
+        };
+
+        let vol1 = label(&[(0, "VOL1"), (4, "000123"), (10, "0"), (41, "SYSPROG")]);
+        let (decoded, ibm) = labels::decode(&ebcdic(&vol1)).unwrap();
+        assert!(ibm);
+        assert_eq!(
+            labels::describe(&decoded, ibm),
+            "VOL1 serial '000123' owner 'SYSPROG'"
+        );
+
+        let hdr1 = label(&[
+            (0, "HDR1"),
+            (4, "SYS1.LINUX.KERNEL"),
+            (21, "000123"),
+            (27, "0001"),
+            (31, "0001"),
+            (54, "000042"),
+        ]);
+        let (decoded, ibm) = labels::decode(&ebcdic(&hdr1)).unwrap();
+        assert_eq!(
+            labels::describe(&decoded, ibm),
+            "HDR1 file 'SYS1.LINUX.KERNEL' set '000123' section 0001 sequence 0001 blocks 000042"
+        );
+
+        let hdr2 = label(&[(0, "HDR2"), (4, "F"), (5, "32760"), (10, "00080")]);
+        let (decoded, ibm) = labels::decode(&hdr2).unwrap();
+        assert_eq!(
+            labels::describe(&decoded, ibm),
+            "HDR2 format F block 32760 record 00080"
+        );
+
+        // Not a label, in either character set
+        assert_eq!(labels::decode(&label(&[(0, "HDRX")])), None);
+        assert_eq!(labels::decode(&vol1[..79]), None);
+        assert_eq!(labels::decode(MANIFEST), None);
+    }
+}
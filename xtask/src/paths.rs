@@ -37,6 +37,11 @@ pub fn efi_boot_dir() -> PathBuf {
     efi_root().join("EFI").join("boot")
 }
 
+// Where taperipper looks for tape images to boot from
+pub fn efi_tape_dir() -> PathBuf {
+    efi_root().join("EFI").join("taperipper")
+}
+
 pub fn efi_root() -> PathBuf {
     target_dir().join("esp")
 }